@@ -0,0 +1,869 @@
+//! Command-line interface to KeePass databases, for scripts and terminal use
+//!
+//! Passwords are prompted for on the terminal. When stdin is not a terminal, they are read line by
+//! line from stdin instead, so that scripts can pipe them in: first the password of the database,
+//! then any further passwords a command asks for.
+
+mod vault;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Args, Parser, Subcommand};
+use omnikee_lib::generator::PasswordOptions;
+use omnikee_lib::{Group, OTPResponse, Value, ValueSet};
+use serde_json::json;
+
+use crate::vault::{Located, Vault};
+
+/// Access KeePass databases from scripts and the terminal
+#[derive(Parser)]
+#[command(name = "omnikee-cli", version)]
+struct Cli {
+    /// Path of the database file
+    #[arg(short, long, env = "OMNIKEE_DATABASE", global = true)]
+    database: Option<PathBuf>,
+
+    /// Key file to unlock the database with
+    #[arg(short, long, global = true)]
+    keyfile: Option<PathBuf>,
+
+    /// Unlock the database with the key file only, without asking for a password
+    #[arg(long, global = true)]
+    no_password: bool,
+
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the groups and entries in a group
+    Ls {
+        /// Path of the group, e.g. `Internet/Mail`, or the root group if left out
+        #[arg(default_value = "")]
+        group: String,
+
+        /// Also list the contents of all subgroups
+        #[arg(short, long)]
+        recursive: bool,
+    },
+
+    /// Show all fields of an entry
+    Show {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+
+        /// Show the values of protected fields like the password
+        #[arg(short, long)]
+        reveal: bool,
+    },
+
+    /// Find entries whose title, user name, URL or notes contain a text
+    Search {
+        /// Text to search for, ignoring case
+        query: String,
+    },
+
+    /// Print the value of a single field of an entry, with placeholders resolved
+    Get {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+
+        /// Name of the field
+        #[arg(short, long, default_value = "Password")]
+        field: String,
+
+        /// Print the value as stored, without resolving placeholders and field references
+        #[arg(long)]
+        raw: bool,
+    },
+
+    /// Generate the current one-time password of an entry
+    Totp {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+    },
+
+    /// Add a new entry
+    Add {
+        /// Path of the new entry like `Internet/Mail`, where the last part is its title
+        ///
+        /// Groups along the path are created if they are missing.
+        path: String,
+
+        #[command(flatten)]
+        fields: EntryFields,
+    },
+
+    /// Change the fields of an entry
+    Edit {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+
+        /// New title
+        #[arg(long)]
+        title: Option<String>,
+
+        #[command(flatten)]
+        fields: EntryFields,
+    },
+
+    /// Remove an entry, moving it to the recycle bin if the database has one
+    Rm {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+    },
+
+    /// Move an entry to another group
+    Mv {
+        /// Path like `Internet/Mail` or UUID of the entry
+        entry: String,
+
+        /// Path of the destination group, or an empty string for the root group
+        group: String,
+    },
+
+    /// Create a group and any missing groups along its path
+    Mkdir {
+        /// Path of the group, e.g. `Internet/Mail`
+        group: String,
+    },
+
+    /// Generate a random password
+    Generate {
+        #[command(flatten)]
+        options: GeneratorOptions,
+    },
+
+    /// Write all entries including their secrets as JSON
+    Export {
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Merge the changes of another copy of the database into it
+    Merge {
+        /// Path of the other database file
+        other: PathBuf,
+
+        /// Key file to unlock the other database with
+        #[arg(long)]
+        other_keyfile: Option<PathBuf>,
+
+        /// Unlock the other database with its key file only
+        #[arg(long)]
+        other_no_password: bool,
+    },
+
+    /// Create a new, empty database
+    DbCreate {
+        /// Name of the database, the file name if left out
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+/// Standard fields to set when adding or editing an entry
+#[derive(Args)]
+struct EntryFields {
+    /// User name
+    #[arg(short, long)]
+    username: Option<String>,
+
+    /// URL
+    #[arg(long)]
+    url: Option<String>,
+
+    /// Notes
+    #[arg(long)]
+    notes: Option<String>,
+
+    /// Ask for the password of the entry
+    #[arg(short = 'p', long, conflicts_with = "generate")]
+    ask_password: bool,
+
+    /// Set a newly generated password
+    #[arg(short, long)]
+    generate: bool,
+
+    #[command(flatten)]
+    generator: GeneratorOptions,
+
+    /// Set a custom text field, can be repeated
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_field)]
+    field: Vec<(String, String)>,
+}
+
+/// Settings for generated passwords
+#[derive(Args)]
+struct GeneratorOptions {
+    /// Length of generated passwords
+    #[arg(short, long, default_value_t = PasswordOptions::default().length)]
+    length: usize,
+
+    /// Include symbols in generated passwords
+    #[arg(long)]
+    symbols: bool,
+
+    /// Leave out digits from generated passwords
+    #[arg(long)]
+    no_digits: bool,
+
+    /// Leave out characters that are easily confused, like `l` and `1`
+    #[arg(long)]
+    exclude_ambiguous: bool,
+}
+
+impl From<&GeneratorOptions> for PasswordOptions {
+    fn from(options: &GeneratorOptions) -> Self {
+        PasswordOptions {
+            length: options.length,
+            digits: !options.no_digits,
+            symbols: options.symbols,
+            exclude_ambiguous: options.exclude_ambiguous,
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_field(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or("expected NAME=VALUE".to_string())
+}
+
+/// Ask for a password on the terminal, or read it from the next line of stdin if that is not a
+/// terminal
+fn read_password(prompt: &str) -> Result<String> {
+    let stdin = std::io::stdin();
+
+    if stdin.is_terminal() {
+        return rpassword::prompt_password(prompt).context("Reading the password");
+    }
+
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line)? == 0 {
+        bail!("Expected a password on stdin");
+    }
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Ask for a new password, twice if it is typed on the terminal
+fn read_new_password(prompt: &str) -> Result<String> {
+    let password = read_password(prompt)?;
+
+    if std::io::stdin().is_terminal() && read_password("Repeat the password: ")? != password {
+        bail!("The passwords do not match");
+    }
+
+    Ok(password)
+}
+
+/// Read the password and key file for a database
+fn read_key(
+    path: &Path,
+    keyfile: Option<&Path>,
+    no_password: bool,
+) -> Result<(Option<String>, Option<Vec<u8>>)> {
+    let keyfile = keyfile
+        .map(|p| std::fs::read(p).with_context(|| format!("Reading {}", p.display())))
+        .transpose()?;
+
+    let password = match no_password {
+        true => None,
+        false => Some(read_password(&format!(
+            "Password for {}: ",
+            path.display()
+        ))?),
+    };
+
+    Ok((password, keyfile))
+}
+
+fn print_json(value: &serde_json::Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Command::Generate { options } = &cli.command {
+        return generate(options, cli.json);
+    }
+
+    let path = cli.database.as_deref().ok_or(anyhow!(
+        "No database given, use --database or OMNIKEE_DATABASE"
+    ))?;
+
+    if let Command::DbCreate { name } = &cli.command {
+        let keyfile = cli
+            .keyfile
+            .as_deref()
+            .map(|p| std::fs::read(p).with_context(|| format!("Reading {}", p.display())))
+            .transpose()?;
+
+        let password = match cli.no_password {
+            true => None,
+            false => Some(read_new_password(&format!(
+                "New password for {}: ",
+                path.display()
+            ))?),
+        };
+
+        let name = name.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+
+        let mut vault = Vault::create(path, name, password, keyfile)?;
+        return vault.save();
+    }
+
+    let (password, keyfile) = read_key(path, cli.keyfile.as_deref(), cli.no_password)?;
+    let mut vault = Vault::open(path, password, keyfile)?;
+
+    match cli.command {
+        Command::Ls { group, recursive } => ls(&vault, &group, recursive, cli.json),
+        Command::Show { entry, reveal } => show(&vault, &entry, reveal, cli.json),
+        Command::Search { query } => search(&vault, &query, cli.json),
+        Command::Get { entry, field, raw } => get(&vault, &entry, &field, raw, cli.json),
+        Command::Totp { entry } => totp(&mut vault, &entry, cli.json),
+        Command::Add { path, fields } => add(&mut vault, &path, &fields, cli.json),
+        Command::Edit {
+            entry,
+            title,
+            fields,
+        } => {
+            let uuid = vault.find_entry(&entry)?.entry.uuid.to_string();
+            set_fields(&mut vault, &uuid, title, &fields)?;
+            vault.save()
+        }
+        Command::Rm { entry } => {
+            let uuid = vault.find_entry(&entry)?.entry.uuid.to_string();
+            vault
+                .state
+                .remove_entry(vault.index(), &uuid)
+                .map_err(anyhow::Error::msg)?;
+            vault.save()
+        }
+        Command::Mv { entry, group } => {
+            let uuid = vault.find_entry(&entry)?.entry.uuid.to_string();
+            let group = vault.find_group(&group)?.uuid.to_string();
+            vault
+                .state
+                .move_entry(vault.index(), &uuid, &group)
+                .map_err(anyhow::Error::msg)?;
+            vault.save()
+        }
+        Command::Mkdir { group } => {
+            let group = vault.create_group(&group)?;
+            vault.save()?;
+
+            match cli.json {
+                true => print_json(&json!({ "uuid": group.uuid })),
+                false => println!("{}", group.uuid),
+            }
+
+            Ok(())
+        }
+        Command::Export { output } => export(&vault, output.as_deref()),
+        Command::Merge {
+            other,
+            other_keyfile,
+            other_no_password,
+        } => {
+            let (password, keyfile) =
+                read_key(&other, other_keyfile.as_deref(), other_no_password)?;
+            let changes = vault.merge(&other, password, keyfile)?;
+            vault.save()?;
+
+            match cli.json {
+                true => print_json(&json!({ "changes": changes })),
+                false => println!("Merged {changes} changes"),
+            }
+
+            Ok(())
+        }
+        Command::Generate { .. } | Command::DbCreate { .. } => unreachable!(),
+    }
+}
+
+fn ls(vault: &Vault, group_path: &str, recursive: bool, json: bool) -> Result<()> {
+    let group = vault.find_group(group_path)?;
+
+    let mut groups = Vec::new();
+    collect_groups(&group, String::new(), recursive, &mut groups);
+
+    let entries = vault.entries(group_path, recursive)?;
+    let prefix = vault::normalize_path(group_path);
+
+    if json {
+        print_json(&json!({
+            "groups": groups
+                .iter()
+                .map(|(path, g)| json!({ "path": path, "uuid": g.uuid }))
+                .collect::<Vec<_>>(),
+            "entries": entries.iter().map(|e| entry_summary(e, &prefix)).collect::<Vec<_>>(),
+        }));
+    } else {
+        for (path, _) in &groups {
+            println!("{path}/");
+        }
+
+        for located in &entries {
+            println!("{}", relative(&located.path(), &prefix));
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect the subgroups of a group with their paths relative to it
+fn collect_groups<'a>(
+    group: &'a Group,
+    path: String,
+    recursive: bool,
+    out: &mut Vec<(String, &'a Group)>,
+) {
+    for child in &group.children {
+        let child_path = match path.is_empty() {
+            true => child.name.clone(),
+            false => format!("{path}/{}", child.name),
+        };
+
+        out.push((child_path.clone(), child));
+
+        if recursive {
+            collect_groups(child, child_path, true, out);
+        }
+    }
+}
+
+/// Strip the path of a listed group from the path of an entry within it
+fn relative(path: &str, prefix: &str) -> String {
+    match prefix.is_empty() {
+        true => path.to_string(),
+        false => path
+            .strip_prefix(prefix)
+            .map(|p| p.trim_start_matches('/').to_string())
+            .unwrap_or_else(|| path.to_string()),
+    }
+}
+
+fn entry_summary(located: &Located, prefix: &str) -> serde_json::Value {
+    json!({
+        "path": relative(&located.path(), prefix),
+        "uuid": located.entry.uuid,
+        "title": located.entry.name,
+        "username": located.entry.user_name,
+        "url": located.entry.url,
+    })
+}
+
+fn show(vault: &Vault, reference: &str, reveal: bool, json: bool) -> Result<()> {
+    let located = vault.find_entry(reference)?;
+    let uuid = located.entry.uuid.to_string();
+
+    let mut fields = BTreeMap::new();
+    for (name, value) in &located.entry.fields {
+        let protected = matches!(value, Value::Protected);
+
+        let value = match value {
+            Value::Unprotected(v) => Some(v.to_string()),
+            Value::Protected if reveal => Some(
+                vault
+                    .state
+                    .reveal_protected(vault.index(), &uuid, name)
+                    .map_err(anyhow::Error::msg)?,
+            ),
+            Value::Protected => None,
+            Value::Bytes(data) => Some(format!("<{} bytes>", data.len())),
+        };
+
+        fields.insert(name.to_string(), (value, protected));
+    }
+
+    if json {
+        print_json(&json!({
+            "path": located.path(),
+            "uuid": located.entry.uuid,
+            "fields": fields
+                .iter()
+                .map(|(name, (value, protected))| {
+                    json!({ "name": name, "value": value, "protected": protected })
+                })
+                .collect::<Vec<_>>(),
+        }));
+    } else {
+        println!("Path: {}", located.path());
+        println!("UUID: {}", located.entry.uuid);
+
+        for (name, (value, _)) in &fields {
+            println!("{name}: {}", value.as_deref().unwrap_or("********"));
+        }
+    }
+
+    Ok(())
+}
+
+fn search(vault: &Vault, query: &str, json: bool) -> Result<()> {
+    let query = query.to_lowercase();
+
+    let entries: Vec<Located> = vault
+        .entries("", true)?
+        .into_iter()
+        .filter(|located| {
+            let entry = &located.entry;
+            let notes = match entry.fields.get("Notes") {
+                Some(Value::Unprotected(notes)) => Some(notes),
+                _ => None,
+            };
+
+            [
+                entry.name.as_ref(),
+                entry.user_name.as_ref(),
+                entry.url.as_ref(),
+                notes,
+            ]
+            .into_iter()
+            .flatten()
+            .any(|text| text.to_lowercase().contains(&query))
+        })
+        .collect();
+
+    if json {
+        print_json(&json!(
+            entries
+                .iter()
+                .map(|e| entry_summary(e, ""))
+                .collect::<Vec<_>>()
+        ));
+    } else {
+        for located in &entries {
+            println!("{}", located.path());
+        }
+    }
+
+    Ok(())
+}
+
+fn get(vault: &Vault, reference: &str, field: &str, raw: bool, json: bool) -> Result<()> {
+    let uuid = vault.find_entry(reference)?.entry.uuid.to_string();
+
+    let value = vault
+        .state
+        .resolve_field(vault.index(), &uuid, field)
+        .map_err(anyhow::Error::msg)?;
+
+    let value = match raw {
+        true => value.raw,
+        false => value.resolved,
+    };
+
+    match json {
+        true => print_json(&json!({ "field": field, "value": value })),
+        false => println!("{value}"),
+    }
+
+    Ok(())
+}
+
+fn totp(vault: &mut Vault, reference: &str, json: bool) -> Result<()> {
+    let uuid = vault.find_entry(reference)?.entry.uuid.to_string();
+
+    let response = vault
+        .state
+        .get_otp(vault.index(), &uuid, now())
+        .map_err(anyhow::Error::msg)?;
+
+    // counter-based codes advance the counter stored in the entry
+    if let OTPResponse::Hotp { .. } = response {
+        vault.save()?;
+    }
+
+    match (&response, json) {
+        (_, true) => print_json(&otp_json(&response)),
+        (OTPResponse::Totp { code, .. } | OTPResponse::Hotp { code, .. }, false) => {
+            println!("{code}")
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe a one-time password for scripts, with durations in whole seconds
+fn otp_json(response: &OTPResponse) -> serde_json::Value {
+    match response {
+        OTPResponse::Totp {
+            code,
+            valid_for,
+            period,
+        } => json!({
+            "type": "Totp",
+            "code": code,
+            "valid_for": valid_for.as_secs(),
+            "period": period.as_secs(),
+        }),
+        OTPResponse::Hotp { code, counter } => json!({
+            "type": "Hotp",
+            "code": code,
+            "counter": counter,
+        }),
+    }
+}
+
+fn add(vault: &mut Vault, path: &str, fields: &EntryFields, json: bool) -> Result<()> {
+    let (group_path, title) = path
+        .trim_matches('/')
+        .rsplit_once('/')
+        .unwrap_or(("", path));
+
+    let group = vault.create_group(group_path)?.uuid.to_string();
+    let uuid = vault
+        .state
+        .add_entry(vault.index(), &group)
+        .map_err(anyhow::Error::msg)?;
+
+    for name in ["UserName", "URL", "Notes"] {
+        set_field(
+            vault,
+            &uuid,
+            name,
+            ValueSet::Unprotected {
+                data: String::new(),
+            },
+        )?;
+    }
+    set_field(
+        vault,
+        &uuid,
+        "Password",
+        ValueSet::Protected {
+            data: String::new(),
+        },
+    )?;
+
+    set_fields(
+        vault,
+        &uuid,
+        Some(title.trim_matches('/').to_string()),
+        fields,
+    )?;
+    vault.save()?;
+
+    match json {
+        true => print_json(&json!({ "uuid": uuid })),
+        false => println!("{uuid}"),
+    }
+
+    Ok(())
+}
+
+fn set_field(vault: &mut Vault, uuid: &str, name: &str, value: ValueSet) -> Result<()> {
+    vault
+        .state
+        .set_field(vault.index(), uuid, name.to_string(), value)
+        .map_err(anyhow::Error::msg)
+}
+
+/// Apply the fields given on the command line to an entry
+fn set_fields(
+    vault: &mut Vault,
+    uuid: &str,
+    title: Option<String>,
+    fields: &EntryFields,
+) -> Result<()> {
+    let standard = [
+        ("Title", title),
+        ("UserName", fields.username.clone()),
+        ("URL", fields.url.clone()),
+        ("Notes", fields.notes.clone()),
+    ];
+
+    for (name, value) in standard {
+        if let Some(data) = value {
+            set_field(vault, uuid, name, ValueSet::Unprotected { data })?;
+        }
+    }
+
+    for (name, value) in &fields.field {
+        set_field(
+            vault,
+            uuid,
+            name,
+            ValueSet::Unprotected {
+                data: value.to_string(),
+            },
+        )?;
+    }
+
+    let password = if fields.generate {
+        Some(omnikee_lib::generator::generate(
+            &(&fields.generator).into(),
+        )?)
+    } else if fields.ask_password {
+        Some(read_new_password("Password for the entry: ")?)
+    } else {
+        None
+    };
+
+    if let Some(data) = password {
+        set_field(vault, uuid, "Password", ValueSet::Protected { data })?;
+    }
+
+    Ok(())
+}
+
+fn generate(options: &GeneratorOptions, json: bool) -> Result<()> {
+    let options: PasswordOptions = options.into();
+    let password = omnikee_lib::generator::generate(&options)?;
+
+    match json {
+        true => print_json(&json!({
+            "password": password,
+            "entropy_bits": options.entropy_bits(),
+        })),
+        false => println!("{password}"),
+    }
+
+    Ok(())
+}
+
+/// Write all entries with their revealed fields as JSON
+fn export(vault: &Vault, output: Option<&Path>) -> Result<()> {
+    let mut entries = Vec::new();
+
+    for located in vault.entries("", true)? {
+        let uuid = located.entry.uuid.to_string();
+
+        let mut fields = BTreeMap::new();
+        for (name, value) in &located.entry.fields {
+            let value = match value {
+                Value::Unprotected(v) => v.to_string(),
+                Value::Protected => vault
+                    .state
+                    .reveal_protected(vault.index(), &uuid, name)
+                    .map_err(anyhow::Error::msg)?,
+                Value::Bytes(..) => continue,
+            };
+
+            fields.insert(name.to_string(), value);
+        }
+
+        entries.push(json!({
+            "path": located.path(),
+            "uuid": located.entry.uuid,
+            "fields": fields,
+        }));
+    }
+
+    let data = serde_json::to_string_pretty(&entries)? + "\n";
+
+    match output {
+        Some(path) => write_private(path, data.as_bytes()),
+        None => {
+            print!("{data}");
+            Ok(())
+        }
+    }
+}
+
+/// Write a file that only the current user can read, as it contains secrets
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("Writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_arguments() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "omnikee-cli",
+            "add",
+            "Internet/Mail",
+            "--json",
+            "--generate",
+            "--length",
+            "32",
+            "--field",
+            "Account=42",
+        ])
+        .unwrap();
+
+        let Command::Add { path, fields } = cli.command else {
+            panic!("expected the add command");
+        };
+
+        assert!(cli.json);
+        assert_eq!(path, "Internet/Mail");
+        assert_eq!(PasswordOptions::from(&fields.generator).length, 32);
+        assert_eq!(fields.field, [("Account".to_string(), "42".to_string())]);
+
+        assert!(Cli::try_parse_from(["omnikee-cli", "edit", "x", "--field", "=1"]).is_err());
+        assert!(Cli::try_parse_from(["omnikee-cli", "edit", "x", "-g", "-p"]).is_err());
+    }
+
+    #[test]
+    fn test_otp_json() {
+        let response = OTPResponse::Totp {
+            code: "123456".to_string(),
+            valid_for: std::time::Duration::from_secs(12),
+            period: std::time::Duration::from_secs(30),
+        };
+
+        assert_eq!(
+            otp_json(&response),
+            json!({ "type": "Totp", "code": "123456", "valid_for": 12, "period": 30 })
+        );
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        assert_eq!(relative("Internet/Mail", ""), "Internet/Mail");
+        assert_eq!(relative("Internet/Mail", "Internet"), "Mail");
+    }
+}
@@ -0,0 +1,426 @@
+//! Access to a single database file through the `AppState` of omnikee-lib
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use omnikee_lib::{AppState, DatabaseOverview, Entry, Group};
+use uuid::Uuid;
+
+/// Index of the opened database within the `AppState`
+const DATABASE: usize = 0;
+
+/// Index of a second database that is opened temporarily, e.g. for merging
+const OTHER_DATABASE: usize = 1;
+
+/// An unlocked database file
+pub struct Vault {
+    path: PathBuf,
+    pub state: AppState,
+}
+
+/// An entry together with the path of the group that contains it
+pub struct Located {
+    pub group_path: String,
+    pub entry: Entry,
+}
+
+impl Located {
+    /// The path of the entry, made up of its group path and title
+    pub fn path(&self) -> String {
+        join_path(
+            &self.group_path,
+            self.entry.name.as_deref().unwrap_or_default(),
+        )
+    }
+}
+
+impl Vault {
+    /// Read and unlock a database file
+    pub fn open(path: &Path, password: Option<String>, keyfile: Option<Vec<u8>>) -> Result<Self> {
+        let mut state = AppState::new();
+        load(&mut state, path, password, keyfile)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            state,
+        })
+    }
+
+    /// Create a new database that will be saved to a path
+    pub fn create(
+        path: &Path,
+        name: String,
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        if path.exists() {
+            bail!("{} already exists", path.display());
+        }
+
+        let mut state = AppState::new();
+        state
+            .create_database(name, password, keyfile)
+            .map_err(anyhow::Error::msg)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            state,
+        })
+    }
+
+    /// Write the database back to its file
+    ///
+    /// The data is written to a temporary file first, so that the database is not left truncated
+    /// if saving fails midway. The temporary file gets the permissions of the database file before
+    /// replacing it.
+    pub fn save(&mut self) -> Result<()> {
+        let state = RefCell::new(&mut self.state);
+        let data = pollster::block_on(AppState::save_database(&state, DATABASE))
+            .map_err(anyhow::Error::msg)?
+            .ok_or(anyhow!("Database was not saved to a buffer"))?;
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+
+        std::fs::write(&temp, data).with_context(|| format!("Writing {:?}", temp))?;
+
+        if let Ok(metadata) = std::fs::metadata(&self.path) {
+            std::fs::set_permissions(&temp, metadata.permissions())
+                .with_context(|| format!("Setting the permissions of {:?}", temp))?;
+        }
+
+        std::fs::rename(&temp, &self.path)
+            .with_context(|| format!("Replacing {}", self.path.display()))?;
+
+        Ok(())
+    }
+
+    /// Merge the changes of another database file into this database, returning the number of
+    /// changed entries and groups
+    pub fn merge(
+        &mut self,
+        path: &Path,
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<usize> {
+        load(&mut self.state, path, password, keyfile)?;
+
+        let res = self.state.merge_database(DATABASE, OTHER_DATABASE);
+        self.state
+            .close_database(OTHER_DATABASE)
+            .map_err(anyhow::Error::msg)?;
+
+        res.map_err(anyhow::Error::msg)
+    }
+
+    /// The index of the database to pass to `AppState` methods
+    pub fn index(&self) -> usize {
+        DATABASE
+    }
+
+    /// The root group of the database with all its subgroups
+    pub fn root(&self) -> Result<Group> {
+        match self.state.list_databases().into_iter().nth(DATABASE) {
+            Some(DatabaseOverview::Unlocked { root, .. }) => Ok(root),
+            _ => bail!("The database is not unlocked"),
+        }
+    }
+
+    /// Find a group by a path like `Internet/Mail`, where the empty path is the root group
+    pub fn find_group(&self, path: &str) -> Result<Group> {
+        let mut group = self.root()?;
+
+        for name in split_path(path) {
+            let mut matching = group.children.into_iter().filter(|g| g.name == name);
+
+            group = match (matching.next(), matching.next()) {
+                (Some(child), None) => child,
+                (Some(..), Some(..)) => bail!("There are multiple groups named {:?}", name),
+                (None, _) => bail!("No group {:?}", path),
+            };
+        }
+
+        Ok(group)
+    }
+
+    /// Find a group by its path, creating it and any missing groups along the way
+    pub fn create_group(&mut self, path: &str) -> Result<Group> {
+        let mut parent_path = String::new();
+
+        for name in split_path(path) {
+            let parent = self.find_group(&parent_path)?;
+
+            if !parent.children.iter().any(|g| g.name == name) {
+                self.state
+                    .add_group(DATABASE, &parent.uuid.to_string(), name.to_string())
+                    .map_err(anyhow::Error::msg)?;
+            }
+
+            parent_path = join_path(&parent_path, name);
+        }
+
+        self.find_group(path)
+    }
+
+    /// List the entries of a group and, if requested, of all its subgroups
+    pub fn entries(&self, group_path: &str, recursive: bool) -> Result<Vec<Located>> {
+        let group = self.find_group(group_path)?;
+        let group_path = normalize_path(group_path);
+
+        let mut out = Vec::new();
+        self.collect_entries(&group, group_path, recursive, &mut out)?;
+
+        Ok(out)
+    }
+
+    fn collect_entries(
+        &self,
+        group: &Group,
+        group_path: String,
+        recursive: bool,
+        out: &mut Vec<Located>,
+    ) -> Result<()> {
+        let entries = self
+            .state
+            .list_entries(DATABASE, group.uuid.to_string())
+            .map_err(anyhow::Error::msg)?;
+
+        out.extend(entries.into_iter().map(|entry| Located {
+            group_path: group_path.clone(),
+            entry,
+        }));
+
+        if recursive {
+            for child in &group.children {
+                self.collect_entries(child, join_path(&group_path, &child.name), true, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find an entry by its UUID or by a path like `Internet/Mail/Work`
+    pub fn find_entry(&self, reference: &str) -> Result<Located> {
+        let uuid = Uuid::parse_str(reference).ok();
+
+        let (group_path, title) = match reference.trim_matches('/').rsplit_once('/') {
+            Some((group_path, title)) => (group_path, title),
+            None => ("", reference.trim_matches('/')),
+        };
+
+        let candidates = match uuid {
+            Some(..) => self.entries("", true)?,
+            None => self.entries(group_path, false)?,
+        };
+
+        let mut matching = candidates.into_iter().filter(|located| match uuid {
+            Some(uuid) => located.entry.uuid == uuid,
+            None => located.entry.name.as_deref() == Some(title),
+        });
+
+        match (matching.next(), matching.next()) {
+            (Some(located), None) => Ok(located),
+            (Some(..), Some(..)) => {
+                bail!(
+                    "There are multiple entries at {:?}, use the UUID",
+                    reference
+                )
+            }
+            (None, _) => bail!("No entry {:?}", reference),
+        }
+    }
+}
+
+/// Load a database file into the state and unlock it
+fn load(
+    state: &mut AppState,
+    path: &Path,
+    password: Option<String>,
+    keyfile: Option<Vec<u8>>,
+) -> Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    state
+        .load_database_buffer(name, &data)
+        .map_err(anyhow::Error::msg)?;
+
+    let idx = state.list_databases().len() - 1;
+    pollster::block_on(AppState::unlock_database(
+        &RefCell::new(state),
+        idx,
+        password,
+        keyfile,
+    ))
+    .map_err(|e| anyhow!("Cannot unlock {}: {}", path.display(), e))?;
+
+    Ok(())
+}
+
+/// Split a group path into the names of its groups
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+/// Normalize a group path by removing leading, trailing and repeated slashes
+pub fn normalize_path(path: &str) -> String {
+    split_path(path).collect::<Vec<_>>().join("/")
+}
+
+fn join_path(group_path: &str, name: &str) -> String {
+    if group_path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{group_path}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use omnikee_lib::ValueSet;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("omnikee-cli-{}-{}.kdbx", std::process::id(), name))
+    }
+
+    fn add(vault: &mut Vault, group: &str, title: &str) -> String {
+        let group = vault.find_group(group).unwrap().uuid.to_string();
+        let uuid = vault.state.add_entry(DATABASE, &group).unwrap();
+
+        vault
+            .state
+            .set_field(
+                DATABASE,
+                &uuid,
+                "Title".to_string(),
+                ValueSet::Unprotected {
+                    data: title.to_string(),
+                },
+            )
+            .unwrap();
+
+        uuid
+    }
+
+    #[test]
+    fn test_create_save_and_reopen() {
+        let path = temp_path("reopen");
+        let password = Some("hunter2".to_string());
+
+        let mut vault = Vault::create(&path, "Test".to_string(), password.clone(), None).unwrap();
+        add(&mut vault, "", "Mail");
+        vault.save().unwrap();
+
+        assert!(Vault::create(&path, "Test".to_string(), password.clone(), None).is_err());
+        assert!(Vault::open(&path, Some("wrong".to_string()), None).is_err());
+
+        let vault = Vault::open(&path, password, None).unwrap();
+        assert_eq!(vault.root().unwrap().name, "Test");
+        assert_eq!(vault.find_entry("Mail").unwrap().path(), "Mail");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("permissions");
+        let password = Some("hunter2".to_string());
+
+        let mut vault = Vault::create(&path, "Test".to_string(), password.clone(), None).unwrap();
+        vault.save().unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let mut vault = Vault::open(&path, password, None).unwrap();
+        add(&mut vault, "", "Mail");
+        vault.save().unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_find_entries_by_path_and_uuid() {
+        let demo = Path::new(env!("CARGO_MANIFEST_DIR")).join("../lib/src/demo.kdbx");
+        let vault = Vault::open(&demo, Some("demopass".to_string()), None).unwrap();
+
+        let located = vault.find_entry("Demo group/Demo entry").unwrap();
+        assert_eq!(located.group_path, "Demo group");
+
+        let uuid = located.entry.uuid.to_string();
+        assert_eq!(
+            vault.find_entry(&uuid).unwrap().path(),
+            "Demo group/Demo entry"
+        );
+        assert_eq!(
+            vault.find_entry("/TOTP Test Entry").unwrap().path(),
+            "TOTP Test Entry"
+        );
+
+        assert!(vault.find_entry("Demo entry").is_err());
+        assert!(vault.find_entry("Missing group/Demo entry").is_err());
+
+        assert_eq!(vault.entries("Demo group/", false).unwrap().len(), 1);
+        assert!(vault.entries("", true).unwrap().len() > vault.entries("", false).unwrap().len());
+    }
+
+    #[test]
+    fn test_merge() {
+        let path = temp_path("merge");
+        let other = temp_path("merge-other");
+        let password = Some("hunter2".to_string());
+
+        let mut vault = Vault::create(&path, "Test".to_string(), password.clone(), None).unwrap();
+        add(&mut vault, "", "Mail");
+        vault.save().unwrap();
+        std::fs::copy(&path, &other).unwrap();
+
+        let mut copy = Vault::open(&other, password.clone(), None).unwrap();
+        add(&mut copy, "", "News");
+        copy.save().unwrap();
+
+        let mut vault = Vault::open(&path, password.clone(), None).unwrap();
+        assert!(vault.merge(&other, password, None).unwrap() > 0);
+        assert_eq!(vault.find_entry("News").unwrap().path(), "News");
+        assert_eq!(vault.entries("", false).unwrap().len(), 2);
+
+        // the other database was closed again
+        assert_eq!(vault.state.list_databases().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn test_create_group() {
+        let password = Some("hunter2".to_string());
+        let mut vault =
+            Vault::create(&temp_path("groups"), "Test".to_string(), password, None).unwrap();
+
+        assert!(vault.find_group("Internet/Mail").is_err());
+
+        let mail = vault.create_group("Internet/Mail/").unwrap();
+        assert_eq!(vault.find_group("Internet/Mail").unwrap().uuid, mail.uuid);
+
+        // existing groups are reused
+        assert_eq!(vault.create_group("Internet/Mail").unwrap().uuid, mail.uuid);
+        vault.create_group("Internet/News").unwrap();
+        assert_eq!(vault.find_group("Internet").unwrap().children.len(), 2);
+
+        assert_eq!(
+            vault.create_group("").unwrap().uuid,
+            vault.root().unwrap().uuid
+        );
+    }
+}
@@ -0,0 +1,964 @@
+//! Auto-Type: typing entry credentials into other applications
+//!
+//! Sequences use the KeePass syntax (`{USERNAME}{TAB}{PASSWORD}{ENTER}`, modifiers `+^%#`,
+//! `{DELAY 500}`, `{VKEY 13}`, ...) and are turned into a platform-neutral list of
+//! [`AutoTypeAction`]s, with placeholders resolved. An [`AutoTypeBackend`] then performs these
+//! actions on the current platform.
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, NodeRef};
+use regex::RegexBuilder;
+
+use crate::exchange::{AutoTypeAction, AutoTypeKey, AutoTypeMatch, AutoTypeModifiers};
+use crate::placeholder::{Resolver, matching_brace};
+
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+mod uinput;
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+mod x11;
+
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+pub use uinput::UinputBackend;
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+pub use x11::X11Backend;
+
+/// The sequence used when neither the entry nor any of its groups define one
+pub const DEFAULT_SEQUENCE: &str = "{USERNAME}{TAB}{PASSWORD}{ENTER}";
+
+/// Delay between keystrokes unless changed with `{DELAY=n}`
+pub const DEFAULT_KEY_DELAY_MS: u64 = 10;
+
+/// Maximum repetition count for codes like `{TAB 3}`
+pub const MAX_REPEAT: usize = 1000;
+
+/// Check whether Auto-Type is enabled for an entry and the groups containing it
+pub fn is_enabled(database: &KpDatabase, entry: &KpEntry) -> bool {
+    if let Some(autotype) = &entry.autotype
+        && !autotype.enabled
+    {
+        return false;
+    }
+
+    let groups = Resolver::new(database)
+        .parent_groups(entry)
+        .unwrap_or_default();
+
+    // the innermost group with an explicit setting wins
+    for group in groups.iter().rev() {
+        match group
+            .enable_autotype
+            .as_deref()
+            .map(str::to_ascii_lowercase)
+        {
+            Some(v) if v == "false" => return false,
+            Some(v) if v == "true" => return true,
+            _ => {}
+        }
+    }
+
+    true
+}
+
+/// Get the default sequence of an entry, inherited from its groups if not set
+pub fn default_sequence(database: &KpDatabase, entry: &KpEntry) -> String {
+    if let Some(sequence) = entry
+        .autotype
+        .as_ref()
+        .and_then(|autotype| autotype.sequence.as_deref())
+        .filter(|s| !s.trim().is_empty())
+    {
+        return sequence.to_string();
+    }
+
+    let groups = Resolver::new(database)
+        .parent_groups(entry)
+        .unwrap_or_default();
+
+    groups
+        .iter()
+        .rev()
+        .filter_map(|group| group.default_autotype_sequence.as_deref())
+        .find(|s| !s.trim().is_empty())
+        .unwrap_or(DEFAULT_SEQUENCE)
+        .to_string()
+}
+
+/// Find the sequence to type for an entry into a window, if the entry matches the window
+///
+/// Window associations are checked first. Like in KeePass, an entry also matches if its title is
+/// contained in the window title.
+pub fn sequence_for_window(
+    database: &KpDatabase,
+    entry: &KpEntry,
+    window_title: &str,
+) -> Option<String> {
+    let associations = entry
+        .autotype
+        .as_ref()
+        .map(|autotype| autotype.associations.as_slice())
+        .unwrap_or_default();
+
+    for association in associations {
+        let Some(window) = association.window.as_deref() else {
+            continue;
+        };
+
+        if window_matches(window, window_title) {
+            return Some(
+                association
+                    .sequence
+                    .clone()
+                    .filter(|s| !s.trim().is_empty())
+                    .unwrap_or_else(|| default_sequence(database, entry)),
+            );
+        }
+    }
+
+    let title = Resolver::new(database).field(entry, "Title")?;
+    let title = title.trim();
+
+    if !title.is_empty() && window_title.to_lowercase().contains(&title.to_lowercase()) {
+        return Some(default_sequence(database, entry));
+    }
+
+    None
+}
+
+/// Find all entries of a database that can be auto-typed into a window
+pub fn find_matches(database: &KpDatabase, window_title: &str) -> Vec<AutoTypeMatch> {
+    let resolver = Resolver::new(database);
+    let recycle_bin = database.meta.recyclebin_uuid;
+
+    database
+        .root
+        .iter()
+        .filter_map(|node| match node {
+            NodeRef::Entry(entry) => Some(entry),
+            NodeRef::Group(..) => None,
+        })
+        .filter(|entry| {
+            let in_recycle_bin = recycle_bin.is_some_and(|bin| {
+                resolver
+                    .parent_groups(entry)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|group| group.uuid == bin)
+            });
+
+            !in_recycle_bin && is_enabled(database, entry)
+        })
+        .filter_map(|entry| {
+            let sequence = sequence_for_window(database, entry, window_title)?;
+
+            Some(AutoTypeMatch {
+                entry_uuid: entry.uuid,
+                name: resolver.field(entry, "Title"),
+                sequence,
+            })
+        })
+        .collect()
+}
+
+/// Check whether a window title matches the window pattern of an association
+///
+/// Patterns are matched case-insensitively against the whole title and may contain `*`
+/// wildcards. Patterns enclosed in `//` are regular expressions that only need to match a part
+/// of the title.
+pub fn window_matches(pattern: &str, window_title: &str) -> bool {
+    let pattern = pattern.trim();
+
+    if pattern.len() > 4 && pattern.starts_with("//") && pattern.ends_with("//") {
+        return RegexBuilder::new(&pattern[2..pattern.len() - 2])
+            .case_insensitive(true)
+            .build()
+            .map(|re| re.is_match(window_title))
+            .unwrap_or(false);
+    }
+
+    let wildcard = pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+
+    RegexBuilder::new(&format!("^{wildcard}$"))
+        .case_insensitive(true)
+        .build()
+        .map(|re| re.is_match(window_title))
+        .unwrap_or(false)
+}
+
+/// Turn a sequence into the actions needed to type it, resolving placeholders in the context of
+/// an entry
+pub fn plan(resolver: &Resolver, entry: &KpEntry, sequence: &str) -> Result<Vec<AutoTypeAction>> {
+    let mut planner = Planner {
+        resolver,
+        entry,
+        actions: Vec::new(),
+    };
+
+    planner.parse(sequence, AutoTypeModifiers::default())?;
+
+    Ok(planner.actions)
+}
+
+struct Planner<'a, 'b> {
+    resolver: &'b Resolver<'a>,
+    entry: &'b KpEntry,
+    actions: Vec<AutoTypeAction>,
+}
+
+impl Planner<'_, '_> {
+    /// Parse a (part of a) sequence, holding `held` modifiers for every key in it
+    fn parse(&mut self, sequence: &str, held: AutoTypeModifiers) -> Result<()> {
+        let mut pending = held;
+        let mut rest = sequence;
+
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+
+            match c {
+                '+' => pending.shift = true,
+                '^' => pending.ctrl = true,
+                '%' => pending.alt = true,
+                '#' => pending.meta = true,
+                '~' => {
+                    self.key(AutoTypeKey::Enter, pending);
+                    pending = held;
+                }
+                '(' => {
+                    let end = matching_paren(rest)
+                        .ok_or_else(|| anyhow!("Unmatched '(' in Auto-Type sequence"))?;
+
+                    self.parse(&rest[..end], pending)?;
+                    rest = &rest[end + 1..];
+                    pending = held;
+                }
+                '{' => {
+                    // escaped braces cannot be matched up
+                    let (inner, len) = if rest.starts_with("{}") {
+                        ("{", 2)
+                    } else if rest.starts_with("}}") {
+                        ("}", 2)
+                    } else {
+                        let end = matching_brace(&format!("{{{rest}"))
+                            .ok_or_else(|| anyhow!("Unmatched '{{' in Auto-Type sequence"))?;
+                        (&rest[..end - 1], end)
+                    };
+
+                    self.code(inner, pending)?;
+                    rest = &rest[len..];
+                    pending = held;
+                }
+                ')' => bail!("Unmatched ')' in Auto-Type sequence"),
+                '}' => bail!("Unmatched '}}' in Auto-Type sequence"),
+                '\n' => {
+                    self.key(AutoTypeKey::Enter, pending);
+                    pending = held;
+                }
+                c => {
+                    self.char(c, pending);
+                    pending = held;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle the contents of a `{...}` code
+    fn code(&mut self, inner: &str, modifiers: AutoTypeModifiers) -> Result<()> {
+        let mut chars = inner.chars();
+        if let (Some(c), None) = (chars.next(), chars.next())
+            && "+^%~()[]{}#".contains(c)
+        {
+            self.char(c, modifiers);
+            return Ok(());
+        }
+
+        let upper = inner.trim().to_ascii_uppercase();
+
+        if let Some(ms) = upper.strip_prefix("DELAY=") {
+            let ms = ms
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid delay {{{}}}", inner))?;
+            self.actions.push(AutoTypeAction::SetKeyDelay { ms });
+            return Ok(());
+        }
+
+        let (name, arg) = match upper.split_once(' ') {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (upper.as_str(), None),
+        };
+
+        match name {
+            "DELAY" => {
+                let ms = arg
+                    .and_then(|arg| arg.parse().ok())
+                    .ok_or_else(|| anyhow!("Invalid delay {{{}}}", inner))?;
+                self.actions.push(AutoTypeAction::Delay { ms });
+                return Ok(());
+            }
+            "VKEY" => {
+                let code = arg
+                    .and_then(|arg| arg.split_whitespace().next())
+                    .and_then(parse_number)
+                    .ok_or_else(|| anyhow!("Invalid virtual key {{{}}}", inner))?;
+                self.key(AutoTypeKey::Virtual { code }, modifiers);
+                return Ok(());
+            }
+            "CLEARFIELD" if arg.is_none() => {
+                self.actions.push(AutoTypeAction::ClearField);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if let Some(key) = key_by_name(name) {
+            let count = match arg {
+                Some(arg) => arg
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n <= MAX_REPEAT)
+                    .ok_or_else(|| anyhow!("Invalid repetition count {{{}}}", inner))?,
+                None => 1,
+            };
+
+            for _ in 0..count {
+                self.key(key.clone(), modifiers);
+            }
+
+            return Ok(());
+        }
+
+        // everything else is a placeholder whose value is typed literally
+        let placeholder = format!("{{{inner}}}");
+        let value = self.resolver.resolve(self.entry, &placeholder);
+
+        if value == placeholder {
+            bail!("Unknown Auto-Type code {}", placeholder);
+        }
+
+        for c in value.chars() {
+            match c {
+                '\n' => self.key(AutoTypeKey::Enter, AutoTypeModifiers::default()),
+                '\r' => {}
+                c => self.char(c, AutoTypeModifiers::default()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key(&mut self, key: AutoTypeKey, modifiers: AutoTypeModifiers) {
+        self.actions.push(AutoTypeAction::Key { key, modifiers });
+    }
+
+    /// Type a character, merging unmodified characters into text actions
+    fn char(&mut self, c: char, modifiers: AutoTypeModifiers) {
+        if modifiers != AutoTypeModifiers::default() {
+            self.key(AutoTypeKey::Char { char: c }, modifiers);
+            return;
+        }
+
+        if let Some(AutoTypeAction::Text { text }) = self.actions.last_mut() {
+            text.push(c);
+        } else {
+            self.actions.push(AutoTypeAction::Text {
+                text: c.to_string(),
+            });
+        }
+    }
+}
+
+/// Find the index of the `)` that closes a group, skipping over `{...}` codes
+fn matching_paren(text: &str) -> Option<usize> {
+    let mut level = 1;
+    let mut braces = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => braces += 1,
+            '}' if braces > 0 => braces -= 1,
+            '(' if braces == 0 => level += 1,
+            ')' if braces == 0 => {
+                level -= 1;
+                if level == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0X") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Look up a key by its name in an Auto-Type sequence, e.g. `TAB`
+fn key_by_name(name: &str) -> Option<AutoTypeKey> {
+    let key = match name {
+        "TAB" => AutoTypeKey::Tab,
+        "ENTER" => AutoTypeKey::Enter,
+        "SPACE" => AutoTypeKey::Space,
+        "BS" | "BKSP" | "BACKSPACE" => AutoTypeKey::Backspace,
+        "DEL" | "DELETE" => AutoTypeKey::Delete,
+        "INS" | "INSERT" => AutoTypeKey::Insert,
+        "HOME" => AutoTypeKey::Home,
+        "END" => AutoTypeKey::End,
+        "PGUP" => AutoTypeKey::PageUp,
+        "PGDN" => AutoTypeKey::PageDown,
+        "UP" => AutoTypeKey::Up,
+        "DOWN" => AutoTypeKey::Down,
+        "LEFT" => AutoTypeKey::Left,
+        "RIGHT" => AutoTypeKey::Right,
+        "ESC" => AutoTypeKey::Escape,
+        "WIN" | "LWIN" | "RWIN" => AutoTypeKey::Win,
+        "APPS" => AutoTypeKey::Apps,
+        "CAPSLOCK" => AutoTypeKey::CapsLock,
+        "NUMLOCK" => AutoTypeKey::NumLock,
+        "SCROLLLOCK" => AutoTypeKey::ScrollLock,
+        "PRTSC" => AutoTypeKey::PrintScreen,
+        "BREAK" => AutoTypeKey::Break,
+        "HELP" => AutoTypeKey::Help,
+        "ADD" => AutoTypeKey::Add,
+        "SUBTRACT" => AutoTypeKey::Subtract,
+        "MULTIPLY" => AutoTypeKey::Multiply,
+        "DIVIDE" => AutoTypeKey::Divide,
+        _ => {
+            if let Some(digit) = name.strip_prefix("NUMPAD") {
+                let digit = digit.parse().ok().filter(|d| *d <= 9)?;
+                AutoTypeKey::Numpad { digit }
+            } else if let Some(number) = name.strip_prefix('F') {
+                let number = number.parse().ok().filter(|n| (1..=24).contains(n))?;
+                AutoTypeKey::Function { number }
+            } else {
+                return None;
+            }
+        }
+    };
+
+    Some(key)
+}
+
+/// A way to send keystrokes to the focused application
+pub trait AutoTypeBackend {
+    /// Type a single character, using whatever key combination produces it
+    fn send_char(&mut self, c: char) -> Result<()>;
+
+    /// Press and release a key while holding modifiers
+    fn send_key(&mut self, key: &AutoTypeKey, modifiers: &AutoTypeModifiers) -> Result<()>;
+
+    /// Wait before sending the next keystroke
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Perform a list of actions using a backend
+pub fn perform<B: AutoTypeBackend + ?Sized>(
+    backend: &mut B,
+    actions: &[AutoTypeAction],
+) -> Result<()> {
+    let mut key_delay = Duration::from_millis(DEFAULT_KEY_DELAY_MS);
+
+    for action in actions {
+        match action {
+            AutoTypeAction::Text { text } => {
+                for c in text.chars() {
+                    backend.send_char(c)?;
+                    backend.sleep(key_delay);
+                }
+            }
+            AutoTypeAction::Key { key, modifiers } => {
+                backend.send_key(key, modifiers)?;
+                backend.sleep(key_delay);
+            }
+            AutoTypeAction::Delay { ms } => backend.sleep(Duration::from_millis(*ms)),
+            AutoTypeAction::SetKeyDelay { ms } => key_delay = Duration::from_millis(*ms),
+            AutoTypeAction::ClearField => {
+                let select_all = AutoTypeModifiers {
+                    ctrl: !cfg!(target_os = "macos"),
+                    meta: cfg!(target_os = "macos"),
+                    ..Default::default()
+                };
+
+                backend.send_key(&AutoTypeKey::Char { char: 'a' }, &select_all)?;
+                backend.sleep(key_delay);
+                backend.send_key(&AutoTypeKey::Delete, &AutoTypeModifiers::default())?;
+                backend.sleep(key_delay);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Perform a list of actions with the native backend of the current platform
+pub fn perform_native(actions: &[AutoTypeAction]) -> Result<()> {
+    #[cfg(all(feature = "tauri", target_os = "linux"))]
+    {
+        // XTEST only reaches X11 clients, so Wayland sessions type through a virtual keyboard
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            let mut backend = UinputBackend::create()?;
+            return perform(&mut backend, actions);
+        }
+
+        let mut backend = X11Backend::connect()?;
+        perform(&mut backend, actions)
+    }
+
+    #[cfg(not(all(feature = "tauri", target_os = "linux")))]
+    {
+        let _ = actions;
+        bail!("Auto-Type is not supported on this platform")
+    }
+}
+
+/// What a Windows virtual key code used by `{VKEY n}` refers to
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+enum VirtualKey {
+    Key(AutoTypeKey),
+    Shift,
+    Ctrl,
+    Alt,
+}
+
+/// Translate a Windows virtual key code for the native backends
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+fn virtual_key(code: u16) -> Option<VirtualKey> {
+    let key = match code {
+        0x08 => AutoTypeKey::Backspace,
+        0x09 => AutoTypeKey::Tab,
+        0x0d => AutoTypeKey::Enter,
+        0x13 => AutoTypeKey::Break,
+        0x14 => AutoTypeKey::CapsLock,
+        0x1b => AutoTypeKey::Escape,
+        0x20 => AutoTypeKey::Space,
+        0x21 => AutoTypeKey::PageUp,
+        0x22 => AutoTypeKey::PageDown,
+        0x23 => AutoTypeKey::End,
+        0x24 => AutoTypeKey::Home,
+        0x25 => AutoTypeKey::Left,
+        0x26 => AutoTypeKey::Up,
+        0x27 => AutoTypeKey::Right,
+        0x28 => AutoTypeKey::Down,
+        0x2c => AutoTypeKey::PrintScreen,
+        0x2d => AutoTypeKey::Insert,
+        0x2e => AutoTypeKey::Delete,
+        0x2f => AutoTypeKey::Help,
+        // digits and letters share their codes with ASCII
+        0x30..=0x39 | 0x41..=0x5a => AutoTypeKey::Char {
+            char: (code as u8 as char).to_ascii_lowercase(),
+        },
+        0x5b | 0x5c => AutoTypeKey::Win,
+        0x5d => AutoTypeKey::Apps,
+        0x60..=0x69 => AutoTypeKey::Numpad {
+            digit: (code - 0x60) as u8,
+        },
+        0x6a => AutoTypeKey::Multiply,
+        0x6b => AutoTypeKey::Add,
+        0x6d => AutoTypeKey::Subtract,
+        0x6f => AutoTypeKey::Divide,
+        0x70..=0x87 => AutoTypeKey::Function {
+            number: (code - 0x6f) as u8,
+        },
+        0x90 => AutoTypeKey::NumLock,
+        0x91 => AutoTypeKey::ScrollLock,
+        0xa0 | 0xa1 => return Some(VirtualKey::Shift),
+        0xa2 | 0xa3 => return Some(VirtualKey::Ctrl),
+        0xa4 | 0xa5 => return Some(VirtualKey::Alt),
+        _ => return None,
+    };
+
+    Some(VirtualKey::Key(key))
+}
+
+/// A keystroke sent to a [`RecordingBackend`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedEvent {
+    Char(char),
+    Key(AutoTypeKey, AutoTypeModifiers),
+    Sleep(Duration),
+}
+
+/// A backend that records keystrokes instead of sending them, e.g. for testing
+#[derive(Default)]
+pub struct RecordingBackend {
+    pub events: Vec<RecordedEvent>,
+}
+
+impl RecordingBackend {
+    /// The recorded keystrokes without any delays
+    pub fn keystrokes(&self) -> impl Iterator<Item = &RecordedEvent> {
+        self.events
+            .iter()
+            .filter(|event| !matches!(event, RecordedEvent::Sleep(..)))
+    }
+}
+
+impl AutoTypeBackend for RecordingBackend {
+    fn send_char(&mut self, c: char) -> Result<()> {
+        self.events.push(RecordedEvent::Char(c));
+        Ok(())
+    }
+
+    fn send_key(&mut self, key: &AutoTypeKey, modifiers: &AutoTypeModifiers) -> Result<()> {
+        self.events
+            .push(RecordedEvent::Key(key.clone(), *modifiers));
+        Ok(())
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        if !duration.is_zero() {
+            self.events.push(RecordedEvent::Sleep(duration));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::{Group as KpGroup, Node, Value as KpValue};
+
+    use RecordedEvent::{Char, Key, Sleep};
+
+    const NONE: AutoTypeModifiers = AutoTypeModifiers {
+        shift: false,
+        ctrl: false,
+        alt: false,
+        meta: false,
+    };
+
+    const CTRL: AutoTypeModifiers = AutoTypeModifiers { ctrl: true, ..NONE };
+
+    fn entry(fields: &[(&str, &str)]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        for (name, value) in fields {
+            entry
+                .fields
+                .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+        }
+        entry
+    }
+
+    fn example() -> KpEntry {
+        entry(&[
+            ("Title", "Example"),
+            ("UserName", "alice"),
+            ("Password", "p+^%~w"),
+            ("Notes", "one\r\ntwo"),
+        ])
+    }
+
+    /// Plan a sequence for the example entry and perform it, recording the keystrokes
+    fn record(sequence: &str) -> Result<Vec<RecordedEvent>> {
+        let entry = example();
+        let mut database = KpDatabase::new(Default::default());
+        database.root.children.push(Node::Entry(entry.clone()));
+
+        let actions = plan(&Resolver::new(&database), &entry, sequence)?;
+
+        let mut backend = RecordingBackend::default();
+        perform(&mut backend, &actions)?;
+
+        Ok(backend.events)
+    }
+
+    fn keystrokes(sequence: &str) -> Vec<RecordedEvent> {
+        record(sequence)
+            .unwrap()
+            .into_iter()
+            .filter(|event| !matches!(event, Sleep(..)))
+            .collect()
+    }
+
+    fn chars(text: &str) -> Vec<RecordedEvent> {
+        text.chars().map(Char).collect()
+    }
+
+    #[test]
+    fn default_sequence_types_credentials() {
+        let mut expected = chars("alice");
+        expected.push(Key(AutoTypeKey::Tab, NONE));
+        expected.extend(chars("p+^%~w"));
+        expected.push(Key(AutoTypeKey::Enter, NONE));
+
+        assert_eq!(keystrokes(DEFAULT_SEQUENCE), expected);
+    }
+
+    #[test]
+    fn merges_plain_characters_into_text() {
+        let entry = example();
+        let mut database = KpDatabase::new(Default::default());
+        database.root.children.push(Node::Entry(entry.clone()));
+
+        assert_eq!(
+            plan(&Resolver::new(&database), &entry, "ab{USERNAME}{TAB}c").unwrap(),
+            vec![
+                AutoTypeAction::Text {
+                    text: "abalice".to_string()
+                },
+                AutoTypeAction::Key {
+                    key: AutoTypeKey::Tab,
+                    modifiers: NONE
+                },
+                AutoTypeAction::Text {
+                    text: "c".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_keys() {
+        assert_eq!(
+            keystrokes("{TAB 3}{left 2}"),
+            vec![
+                Key(AutoTypeKey::Tab, NONE),
+                Key(AutoTypeKey::Tab, NONE),
+                Key(AutoTypeKey::Tab, NONE),
+                Key(AutoTypeKey::Left, NONE),
+                Key(AutoTypeKey::Left, NONE),
+            ]
+        );
+        assert_eq!(keystrokes("{TAB 0}"), vec![]);
+
+        assert!(record("{TAB 1001}").is_err());
+        assert!(record("{TAB x}").is_err());
+    }
+
+    #[test]
+    fn delays() {
+        let key_delay = Sleep(Duration::from_millis(DEFAULT_KEY_DELAY_MS));
+
+        assert_eq!(
+            record("a{DELAY 500}b").unwrap(),
+            vec![
+                Char('a'),
+                key_delay.clone(),
+                Sleep(Duration::from_millis(500)),
+                Char('b'),
+                key_delay.clone(),
+            ]
+        );
+
+        assert_eq!(
+            record("a{DELAY=50}b{TAB}{DELAY=0}c").unwrap(),
+            vec![
+                Char('a'),
+                key_delay,
+                Char('b'),
+                Sleep(Duration::from_millis(50)),
+                Key(AutoTypeKey::Tab, NONE),
+                Sleep(Duration::from_millis(50)),
+                Char('c'),
+            ]
+        );
+
+        assert!(record("{DELAY}").is_err());
+        assert!(record("{DELAY x}").is_err());
+        assert!(record("{DELAY=-1}").is_err());
+    }
+
+    #[test]
+    fn modifiers() {
+        let shift = AutoTypeModifiers {
+            shift: true,
+            ..NONE
+        };
+        let shift_ctrl = AutoTypeModifiers {
+            shift: true,
+            ctrl: true,
+            ..NONE
+        };
+        let alt = AutoTypeModifiers { alt: true, ..NONE };
+        let meta = AutoTypeModifiers { meta: true, ..NONE };
+
+        assert_eq!(
+            keystrokes("^a+b%{F4}#r~c"),
+            vec![
+                Key(AutoTypeKey::Char { char: 'a' }, CTRL),
+                Key(AutoTypeKey::Char { char: 'b' }, shift),
+                Key(AutoTypeKey::Function { number: 4 }, alt),
+                Key(AutoTypeKey::Char { char: 'r' }, meta),
+                Key(AutoTypeKey::Enter, NONE),
+                Char('c'),
+            ]
+        );
+
+        // modifiers before parentheses are held for the whole group
+        assert_eq!(
+            keystrokes("^(a+b{TAB})c"),
+            vec![
+                Key(AutoTypeKey::Char { char: 'a' }, CTRL),
+                Key(AutoTypeKey::Char { char: 'b' }, shift_ctrl),
+                Key(AutoTypeKey::Tab, CTRL),
+                Char('c'),
+            ]
+        );
+
+        assert_eq!(
+            keystrokes("+^(x(y))"),
+            vec![
+                Key(AutoTypeKey::Char { char: 'x' }, shift_ctrl),
+                Key(AutoTypeKey::Char { char: 'y' }, shift_ctrl),
+            ]
+        );
+
+        // a group without modifiers types normally
+        assert_eq!(keystrokes("(ab)"), chars("ab"));
+    }
+
+    #[test]
+    fn placeholder_values_are_typed_literally() {
+        let mut expected = chars("p+^%~w");
+        expected.extend(chars("one"));
+        expected.push(Key(AutoTypeKey::Enter, NONE));
+        expected.extend(chars("two"));
+
+        assert_eq!(keystrokes("{PASSWORD}{NOTES}"), expected);
+
+        // modifiers are not applied to the typed values
+        assert_eq!(keystrokes("^{USERNAME}"), chars("alice"));
+    }
+
+    #[test]
+    fn virtual_keys() {
+        assert_eq!(
+            keystrokes("{VKEY 13}^{VKEY 0x41}{vkey 0x0d}"),
+            vec![
+                Key(AutoTypeKey::Virtual { code: 13 }, NONE),
+                Key(AutoTypeKey::Virtual { code: 0x41 }, CTRL),
+                Key(AutoTypeKey::Virtual { code: 13 }, NONE),
+            ]
+        );
+
+        assert!(record("{VKEY}").is_err());
+        assert!(record("{VKEY x}").is_err());
+        assert!(record("{VKEY 70000}").is_err());
+    }
+
+    #[test]
+    fn clear_field() {
+        let select_all = AutoTypeModifiers {
+            ctrl: !cfg!(target_os = "macos"),
+            meta: cfg!(target_os = "macos"),
+            ..NONE
+        };
+
+        let mut expected = vec![
+            Key(AutoTypeKey::Char { char: 'a' }, select_all),
+            Key(AutoTypeKey::Delete, NONE),
+        ];
+        expected.extend(chars("alice"));
+
+        assert_eq!(keystrokes("{CLEARFIELD}{USERNAME}"), expected);
+    }
+
+    #[test]
+    fn escaped_characters() {
+        assert_eq!(keystrokes("{{}x{}}"), chars("{x}"));
+        assert_eq!(
+            keystrokes("{+}{^}{%}{~}{#}{(}{)}{[}{]}"),
+            chars("+^%~#()[]")
+        );
+        assert_eq!(
+            keystrokes("^{{}"),
+            vec![Key(AutoTypeKey::Char { char: '{' }, CTRL)]
+        );
+    }
+
+    #[test]
+    fn named_keys() {
+        assert_eq!(
+            keystrokes("{ENTER}{bs}{NUMPAD7}{F12}{ESC}"),
+            vec![
+                Key(AutoTypeKey::Enter, NONE),
+                Key(AutoTypeKey::Backspace, NONE),
+                Key(AutoTypeKey::Numpad { digit: 7 }, NONE),
+                Key(AutoTypeKey::Function { number: 12 }, NONE),
+                Key(AutoTypeKey::Escape, NONE),
+            ]
+        );
+
+        assert!(record("{F25}").is_err());
+        assert!(record("{NUMPAD10}").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_sequences() {
+        for sequence in ["(a", "a)", "{TAB", "a}", "{UNKNOWN}", "{S:Missing}"] {
+            assert!(record(sequence).is_err(), "{sequence} was accepted");
+        }
+    }
+
+    #[test]
+    fn wildcard_window_patterns() {
+        assert!(window_matches("*Firefox*", "Login - Mozilla Firefox"));
+        assert!(window_matches("*firefox", "Login - Mozilla FIREFOX"));
+        assert!(window_matches("Login*", "Login - Mozilla Firefox"));
+        assert!(window_matches("Login*Firefox", "Login - Mozilla Firefox"));
+        assert!(window_matches("*", "anything"));
+        assert!(window_matches(" Notepad ", "notepad"));
+
+        // without wildcards, the whole title has to match
+        assert!(!window_matches("Notepad", "Untitled - Notepad"));
+        assert!(!window_matches("Login*", "My Login"));
+
+        // other characters are not special
+        assert!(window_matches("a.b (1) [x]*", "a.b (1) [x] - Editor"));
+        assert!(!window_matches("a.b", "axb"));
+    }
+
+    #[test]
+    fn regex_window_patterns() {
+        assert!(window_matches("//fox//", "Login - Mozilla Firefox"));
+        assert!(window_matches(
+            "//^login.*fire//",
+            "Login - Mozilla Firefox"
+        ));
+        assert!(window_matches(
+            "//(Chrome|Firefox)$//",
+            "Login - Mozilla Firefox"
+        ));
+        assert!(!window_matches("//^Firefox//", "Login - Mozilla Firefox"));
+
+        // invalid expressions never match
+        assert!(!window_matches("//(//", "("));
+
+        // too short to be an expression, so matched literally
+        assert!(window_matches("////", "////"));
+    }
+
+    #[test]
+    fn matches_windows_by_title() {
+        let mut group = KpGroup::new("Group");
+        group.default_autotype_sequence = Some("{PASSWORD}{ENTER}".to_string());
+
+        let entry = example();
+        group.children.push(Node::Entry(entry.clone()));
+
+        let mut database = KpDatabase::new(Default::default());
+        database.root.children.push(Node::Group(group));
+
+        assert_eq!(
+            sequence_for_window(&database, &entry, "example.com - Login"),
+            Some("{PASSWORD}{ENTER}".to_string())
+        );
+        assert_eq!(sequence_for_window(&database, &entry, "Other"), None);
+
+        let matches = find_matches(&database, "Login to EXAMPLE");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry_uuid, entry.uuid);
+    }
+}
@@ -0,0 +1,271 @@
+//! Auto-Type backend for Wayland using a virtual keyboard created through `/dev/uinput`
+//!
+//! Wayland does not let applications send input to other windows, but a virtual keyboard is
+//! handled by the compositor like a physical one. The compositor applies its own keyboard layout
+//! to the key codes, so characters are mapped to keys assuming a US layout and characters
+//! without a key on it cannot be typed. Writing to `/dev/uinput` usually needs a udev rule or
+//! membership in the `input` group.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+
+use super::{AutoTypeBackend, VirtualKey, virtual_key};
+use crate::exchange::{AutoTypeKey, AutoTypeModifiers};
+
+// ioctl requests from linux/uinput.h
+const UI_DEV_CREATE: u32 = 0x5501;
+const UI_DEV_DESTROY: u32 = 0x5502;
+const UI_DEV_SETUP: u32 = 0x405c_5503;
+const UI_SET_EVBIT: u32 = 0x4004_5564;
+const UI_SET_KEYBIT: u32 = 0x4004_5565;
+
+// event types and key codes from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const SYN_REPORT: u16 = 0;
+const BUS_VIRTUAL: u16 = 0x06;
+
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_LEFTALT: u16 = 56;
+const KEY_LEFTMETA: u16 = 125;
+
+/// The highest key code the virtual keyboard can send (`KEY_F24`)
+const MAX_KEY: u16 = 194;
+
+/// Sends keystrokes through a virtual keyboard
+pub struct UinputBackend {
+    device: File,
+}
+
+impl UinputBackend {
+    /// Create a virtual keyboard
+    pub fn create() -> Result<Self> {
+        let device = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")
+            .context("Opening /dev/uinput to create a virtual keyboard")?;
+
+        let backend = Self { device };
+
+        backend.ioctl(UI_SET_EVBIT, EV_KEY as libc::c_ulong)?;
+        for code in 1..=MAX_KEY {
+            backend.ioctl(UI_SET_KEYBIT, code as libc::c_ulong)?;
+        }
+
+        let mut setup = libc::uinput_setup {
+            id: libc::input_id {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 1,
+            },
+            name: [0; libc::UINPUT_MAX_NAME_SIZE],
+            ff_effects_max: 0,
+        };
+        for (c, b) in setup.name.iter_mut().zip(b"OmniKee Auto-Type") {
+            *c = *b as libc::c_char;
+        }
+
+        backend.ioctl(UI_DEV_SETUP, &setup as *const _ as libc::c_ulong)?;
+        backend.ioctl(UI_DEV_CREATE, 0)?;
+
+        // give the compositor a moment to pick up the new keyboard
+        std::thread::sleep(Duration::from_millis(200));
+
+        Ok(backend)
+    }
+
+    fn ioctl(&self, request: u32, arg: libc::c_ulong) -> Result<()> {
+        // SAFETY: the requests only read their argument, which is an integer or points to a
+        // `uinput_setup` that outlives the call
+        let res = unsafe { libc::ioctl(self.device.as_raw_fd(), request as _, arg) };
+
+        if res < 0 {
+            return Err(std::io::Error::last_os_error()).context("Setting up the virtual keyboard");
+        }
+
+        Ok(())
+    }
+
+    fn event(&mut self, type_: u16, code: u16, value: i32) -> Result<()> {
+        let event = libc::input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_,
+            code,
+            value,
+        };
+
+        // SAFETY: `input_event` is a plain C struct without padding between its fields
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const _ as *const u8,
+                std::mem::size_of::<libc::input_event>(),
+            )
+        };
+
+        self.device
+            .write_all(bytes)
+            .context("Sending a keystroke to the virtual keyboard")
+    }
+
+    fn key(&mut self, code: u16, press: bool) -> Result<()> {
+        self.event(EV_KEY, code, press as i32)?;
+        self.event(EV_SYN, SYN_REPORT, 0)
+    }
+
+    /// Press and release a key code while holding modifiers
+    fn tap(&mut self, code: u16, modifiers: &AutoTypeModifiers) -> Result<()> {
+        let held: Vec<_> = [
+            (modifiers.shift, KEY_LEFTSHIFT),
+            (modifiers.ctrl, KEY_LEFTCTRL),
+            (modifiers.alt, KEY_LEFTALT),
+            (modifiers.meta, KEY_LEFTMETA),
+        ]
+        .into_iter()
+        .filter_map(|(active, code)| active.then_some(code))
+        .collect();
+
+        for modifier in held.iter() {
+            self.key(*modifier, true)?;
+        }
+
+        self.key(code, true)?;
+        self.key(code, false)?;
+
+        for modifier in held.iter().rev() {
+            self.key(*modifier, false)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for UinputBackend {
+    fn drop(&mut self) {
+        let _ = self.ioctl(UI_DEV_DESTROY, 0);
+    }
+}
+
+impl AutoTypeBackend for UinputBackend {
+    fn send_char(&mut self, c: char) -> Result<()> {
+        let (code, shift) =
+            char_key(c).ok_or_else(|| anyhow!("Cannot type {:?} with a virtual keyboard", c))?;
+
+        let modifiers = AutoTypeModifiers {
+            shift,
+            ..Default::default()
+        };
+
+        self.tap(code, &modifiers)
+    }
+
+    fn send_key(&mut self, key: &AutoTypeKey, modifiers: &AutoTypeModifiers) -> Result<()> {
+        let code = match key {
+            // shortcuts like Ctrl+A refer to the key, not the uppercase character
+            AutoTypeKey::Char { char } => char_key(char.to_ascii_lowercase()).map(|(c, _)| c),
+            AutoTypeKey::Virtual { code } => virtual_key_code(*code),
+            key => key_code(key),
+        }
+        .ok_or_else(|| anyhow!("Cannot press {:?} with a virtual keyboard", key))?;
+
+        self.tap(code, modifiers)
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Get the key code producing a character on a US layout and whether Shift is needed for it
+fn char_key(c: char) -> Option<(u16, bool)> {
+    const ROWS: [(&str, &str, u16); 4] = [
+        ("1234567890-=", "!@#$%^&*()_+", 2),
+        ("qwertyuiop[]", "QWERTYUIOP{}", 16),
+        ("asdfghjkl;'`", "ASDFGHJKL:\"~", 30),
+        ("\\zxcvbnm,./", "|ZXCVBNM<>?", 43),
+    ];
+
+    match c {
+        '\t' => return Some((15, false)),
+        '\n' => return Some((28, false)),
+        ' ' => return Some((57, false)),
+        _ => {}
+    }
+
+    ROWS.iter().find_map(|(plain, shifted, first)| {
+        if let Some(i) = plain.chars().position(|p| p == c) {
+            Some((first + i as u16, false))
+        } else {
+            let i = shifted.chars().position(|s| s == c)?;
+            Some((first + i as u16, true))
+        }
+    })
+}
+
+/// Get the key code of a special key
+fn key_code(key: &AutoTypeKey) -> Option<u16> {
+    let code = match key {
+        AutoTypeKey::Tab => 15,
+        AutoTypeKey::Enter => 28,
+        AutoTypeKey::Space => 57,
+        AutoTypeKey::Backspace => 14,
+        AutoTypeKey::Delete => 111,
+        AutoTypeKey::Insert => 110,
+        AutoTypeKey::Home => 102,
+        AutoTypeKey::End => 107,
+        AutoTypeKey::PageUp => 104,
+        AutoTypeKey::PageDown => 109,
+        AutoTypeKey::Up => 103,
+        AutoTypeKey::Down => 108,
+        AutoTypeKey::Left => 105,
+        AutoTypeKey::Right => 106,
+        AutoTypeKey::Escape => 1,
+        AutoTypeKey::Win => KEY_LEFTMETA,
+        AutoTypeKey::Apps => 127,
+        AutoTypeKey::CapsLock => 58,
+        AutoTypeKey::NumLock => 69,
+        AutoTypeKey::ScrollLock => 70,
+        AutoTypeKey::PrintScreen => 99,
+        AutoTypeKey::Break => 119,
+        AutoTypeKey::Help => 138,
+        AutoTypeKey::Add => 78,
+        AutoTypeKey::Subtract => 74,
+        AutoTypeKey::Multiply => 55,
+        AutoTypeKey::Divide => 98,
+        AutoTypeKey::Numpad { digit } => {
+            *[82, 79, 80, 81, 75, 76, 77, 71, 72, 73].get(*digit as usize)?
+        }
+        AutoTypeKey::Function { number: n @ 1..=10 } => 58 + *n as u16,
+        AutoTypeKey::Function {
+            number: n @ 11..=12,
+        } => 76 + *n as u16,
+        AutoTypeKey::Function {
+            number: n @ 13..=24,
+        } => 170 + *n as u16,
+        AutoTypeKey::Function { .. } => return None,
+        AutoTypeKey::Char { char } => char_key(*char)?.0,
+        AutoTypeKey::Virtual { code } => virtual_key_code(*code)?,
+    };
+
+    Some(code)
+}
+
+/// Translate a Windows virtual key code into a key code
+fn virtual_key_code(code: u16) -> Option<u16> {
+    match virtual_key(code)? {
+        VirtualKey::Key(key) => key_code(&key),
+        VirtualKey::Shift => Some(KEY_LEFTSHIFT),
+        VirtualKey::Ctrl => Some(KEY_LEFTCTRL),
+        VirtualKey::Alt => Some(KEY_LEFTALT),
+    }
+}
@@ -0,0 +1,253 @@
+//! Auto-Type backend for X11 using the XTEST extension
+//!
+//! Characters that are not on the current keyboard layout are typed by temporarily mapping them
+//! to an unused keycode, like `xdotool` does.
+
+use anyhow::{Context, Result, anyhow};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ConnectionExt as _, KEY_PRESS_EVENT, KEY_RELEASE_EVENT, Keycode, Keysym, Window,
+};
+use x11rb::protocol::xtest::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use super::{AutoTypeBackend, VirtualKey, virtual_key};
+use crate::exchange::{AutoTypeKey, AutoTypeModifiers};
+
+const XK_SHIFT_L: Keysym = 0xffe1;
+const XK_CONTROL_L: Keysym = 0xffe3;
+const XK_ALT_L: Keysym = 0xffe9;
+const XK_SUPER_L: Keysym = 0xffeb;
+
+/// Sends keystrokes to the focused X11 window
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    min_keycode: Keycode,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<Keysym>,
+
+    /// a keycode without any keysyms that can be remapped to type arbitrary characters
+    scratch: Option<Keycode>,
+}
+
+impl X11Backend {
+    /// Connect to the X server given by the `DISPLAY` environment variable
+    pub fn connect() -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(None).context("Connecting to the X server")?;
+
+        conn.xtest_get_version(2, 2)?
+            .reply()
+            .context("The XTEST extension is not available")?;
+
+        let setup = conn.setup();
+        let root = setup.roots[screen_num].root;
+        let min_keycode = setup.min_keycode;
+        let max_keycode = setup.max_keycode;
+
+        let mapping = conn
+            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+            .reply()
+            .context("Reading the keyboard mapping")?;
+
+        let per = mapping.keysyms_per_keycode;
+        let scratch = mapping
+            .keysyms
+            .chunks(per as usize)
+            .rposition(|syms| syms.iter().all(|s| *s == 0))
+            .map(|i| min_keycode + i as u8);
+
+        Ok(Self {
+            conn,
+            root,
+            min_keycode,
+            keysyms_per_keycode: per,
+            keysyms: mapping.keysyms,
+            scratch,
+        })
+    }
+
+    /// Find a keycode producing a keysym and whether Shift is needed for it
+    fn keycode_for(&mut self, keysym: Keysym) -> Result<(Keycode, bool)> {
+        let per = self.keysyms_per_keycode as usize;
+
+        for (i, syms) in self.keysyms.chunks(per).enumerate() {
+            let code = self.min_keycode + i as u8;
+            if Some(code) == self.scratch {
+                continue;
+            }
+
+            if syms.first() == Some(&keysym) {
+                return Ok((code, false));
+            }
+            if syms.get(1) == Some(&keysym) {
+                return Ok((code, true));
+            }
+        }
+
+        let code = self
+            .scratch
+            .ok_or_else(|| anyhow!("No free keycode to type keysym {:#x}", keysym))?;
+
+        self.remap(code, keysym)?;
+
+        // give clients a moment to process the mapping change
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        Ok((code, false))
+    }
+
+    fn remap(&mut self, code: Keycode, keysym: Keysym) -> Result<()> {
+        let per = self.keysyms_per_keycode as usize;
+        let syms = vec![keysym; per];
+
+        self.conn
+            .change_keyboard_mapping(1, code, self.keysyms_per_keycode, &syms)?
+            .check()
+            .context("Changing the keyboard mapping")?;
+
+        let offset = (code - self.min_keycode) as usize * per;
+        self.keysyms[offset..offset + per].copy_from_slice(&syms);
+
+        Ok(())
+    }
+
+    fn fake_key(&self, code: Keycode, press: bool) -> Result<()> {
+        let event = if press {
+            KEY_PRESS_EVENT
+        } else {
+            KEY_RELEASE_EVENT
+        };
+
+        self.conn
+            .xtest_fake_input(event, code, x11rb::CURRENT_TIME, self.root, 0, 0, 0)?;
+
+        Ok(())
+    }
+
+    /// Press and release a keysym while holding modifiers
+    fn tap(&mut self, keysym: Keysym, modifiers: &AutoTypeModifiers) -> Result<()> {
+        let (code, shift) = self.keycode_for(keysym)?;
+
+        let mut held = Vec::new();
+        for (active, modifier) in [
+            (modifiers.shift || shift, XK_SHIFT_L),
+            (modifiers.ctrl, XK_CONTROL_L),
+            (modifiers.alt, XK_ALT_L),
+            (modifiers.meta, XK_SUPER_L),
+        ] {
+            if active {
+                held.push(self.keycode_for(modifier)?.0);
+            }
+        }
+
+        for modifier in held.iter() {
+            self.fake_key(*modifier, true)?;
+        }
+
+        self.fake_key(code, true)?;
+        self.fake_key(code, false)?;
+
+        for modifier in held.iter().rev() {
+            self.fake_key(*modifier, false)?;
+        }
+
+        self.conn.sync().context("Sending keystrokes")?;
+
+        Ok(())
+    }
+}
+
+impl Drop for X11Backend {
+    fn drop(&mut self) {
+        // restore the scratch keycode to be unmapped
+        if let Some(code) = self.scratch {
+            let _ = self.remap(code, 0);
+            let _ = self.conn.flush();
+        }
+    }
+}
+
+impl AutoTypeBackend for X11Backend {
+    fn send_char(&mut self, c: char) -> Result<()> {
+        self.tap(char_keysym(c), &AutoTypeModifiers::default())
+    }
+
+    fn send_key(&mut self, key: &AutoTypeKey, modifiers: &AutoTypeModifiers) -> Result<()> {
+        let keysym = match key {
+            // shortcuts like Ctrl+A refer to the key, not the uppercase character
+            AutoTypeKey::Char { char } => char_keysym(char.to_ascii_lowercase()),
+            AutoTypeKey::Virtual { code } => {
+                virtual_keysym(*code).ok_or_else(|| anyhow!("Unsupported virtual key {}", code))?
+            }
+            key => key_keysym(key),
+        };
+
+        self.tap(keysym, modifiers)
+    }
+
+    fn sleep(&mut self, duration: std::time::Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Get the keysym producing a character
+fn char_keysym(c: char) -> Keysym {
+    match c {
+        '\t' => 0xff09,
+        '\n' => 0xff0d,
+        // Latin-1 characters map directly, everything else uses the Unicode keysym range
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as Keysym,
+        c => 0x0100_0000 + c as Keysym,
+    }
+}
+
+/// Get the keysym of a special key
+fn key_keysym(key: &AutoTypeKey) -> Keysym {
+    match key {
+        AutoTypeKey::Tab => 0xff09,
+        AutoTypeKey::Enter => 0xff0d,
+        AutoTypeKey::Space => 0x20,
+        AutoTypeKey::Backspace => 0xff08,
+        AutoTypeKey::Delete => 0xffff,
+        AutoTypeKey::Insert => 0xff63,
+        AutoTypeKey::Home => 0xff50,
+        AutoTypeKey::End => 0xff57,
+        AutoTypeKey::PageUp => 0xff55,
+        AutoTypeKey::PageDown => 0xff56,
+        AutoTypeKey::Up => 0xff52,
+        AutoTypeKey::Down => 0xff54,
+        AutoTypeKey::Left => 0xff51,
+        AutoTypeKey::Right => 0xff53,
+        AutoTypeKey::Escape => 0xff1b,
+        AutoTypeKey::Win => XK_SUPER_L,
+        AutoTypeKey::Apps => 0xff67,
+        AutoTypeKey::CapsLock => 0xffe5,
+        AutoTypeKey::NumLock => 0xff7f,
+        AutoTypeKey::ScrollLock => 0xff14,
+        AutoTypeKey::PrintScreen => 0xff61,
+        AutoTypeKey::Break => 0xff6b,
+        AutoTypeKey::Help => 0xff6a,
+        AutoTypeKey::Add => 0xffab,
+        AutoTypeKey::Subtract => 0xffad,
+        AutoTypeKey::Multiply => 0xffaa,
+        AutoTypeKey::Divide => 0xffaf,
+        AutoTypeKey::Numpad { digit } => 0xffb0 + *digit as Keysym,
+        AutoTypeKey::Function { number } => 0xffbe + (*number as Keysym - 1),
+        AutoTypeKey::Char { char } => char_keysym(*char),
+        AutoTypeKey::Virtual { code } => virtual_keysym(*code).unwrap_or(0),
+    }
+}
+
+/// Translate a Windows virtual key code into a keysym
+fn virtual_keysym(code: u16) -> Option<Keysym> {
+    let keysym = match virtual_key(code)? {
+        VirtualKey::Key(key) => key_keysym(&key),
+        VirtualKey::Shift => XK_SHIFT_L,
+        VirtualKey::Ctrl => XK_CONTROL_L,
+        VirtualKey::Alt => XK_ALT_L,
+    };
+
+    Some(keysym)
+}
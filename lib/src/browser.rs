@@ -0,0 +1,1382 @@
+//! Browser integration compatible with the KeePassXC-Browser extension
+//!
+//! The extension talks to a native messaging host (`omnikee-proxy`) that relays its messages over
+//! a local socket to the running application. Except for the initial key exchange, messages are
+//! encrypted with NaCl `crypto_box` using keys that are exchanged for every connection.
+//!
+//! Browsers are associated with a database by storing their identity key in the custom data of
+//! the database, using the same `KPXC_BROWSER_` keys as KeePassXC so that existing associations
+//! keep working.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crypto_box::aead::Aead;
+use crypto_box::aead::generic_array::GenericArray;
+use crypto_box::{PublicKey, SalsaBox, SecretKey};
+use keepass::db::{
+    CustomDataItem, Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node,
+    Value as KpValue,
+};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::placeholder::Resolver;
+
+/// The KeePassXC version reported to the extension, which enables features by version
+pub const KEEPASSXC_VERSION: &str = "2.7.10";
+
+/// Prefix of the custom data keys that store browser associations
+pub const ASSOCIATION_PREFIX: &str = "KPXC_BROWSER_";
+
+/// Key in the database's custom data that saves the database after changes from the browser
+pub const AUTO_SAVE_KEY: &str = "OmniKee/BrowserAutoSave";
+
+/// Group that receives new logins saved from the browser
+pub const DEFAULT_GROUP: &str = "KeePassXC-Browser Passwords";
+
+/// Maximum size of a message, as imposed by browsers for messages to extensions
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+const NONCE_SIZE: usize = 24;
+
+// error codes as defined by the KeePassXC-Browser protocol
+const ERROR_DATABASE_NOT_OPENED: u8 = 1;
+const ERROR_CLIENT_PUBLIC_KEY_NOT_RECEIVED: u8 = 3;
+const ERROR_CANNOT_DECRYPT_MESSAGE: u8 = 4;
+const ERROR_ACTION_CANCELLED_OR_DENIED: u8 = 6;
+const ERROR_CANNOT_ENCRYPT_MESSAGE: u8 = 7;
+const ERROR_ASSOCIATION_FAILED: u8 = 8;
+const ERROR_KEY_CHANGE_FAILED: u8 = 9;
+const ERROR_INCORRECT_ACTION: u8 = 12;
+const ERROR_EMPTY_MESSAGE_RECEIVED: u8 = 13;
+const ERROR_NO_URL_PROVIDED: u8 = 14;
+const ERROR_NO_LOGINS_FOUND: u8 = 15;
+const ERROR_NO_VALID_UUID_PROVIDED: u8 = 18;
+
+/// Access to the application from a browser connection
+pub trait BrowserContext {
+    /// Run a function with exclusive access to the application state
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R;
+
+    /// Ask the user whether a browser may access a database
+    ///
+    /// This is called without holding the application state.
+    fn confirm_association(&self, database_name: &str) -> bool;
+
+    /// Save a database after a browser request changed it
+    ///
+    /// This is only called for databases that were opted in with [`set_auto_save`], otherwise
+    /// changes wait for the user to save the database.
+    fn database_changed(&self, database_idx: usize);
+}
+
+/// An error that is reported to the browser extension
+struct BrowserError {
+    code: u8,
+    message: String,
+}
+
+impl BrowserError {
+    fn new(code: u8, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+type BrowserResult<T> = std::result::Result<T, BrowserError>;
+
+/// The state of a single connection from a browser extension
+pub struct BrowserSession {
+    secret: SecretKey,
+    client: Option<PublicKey>,
+
+    /// identity keys that were associated or tested on this connection, as (id, key) pairs
+    associations: Vec<(String, String)>,
+}
+
+impl BrowserSession {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            secret: generate_secret()?,
+            client: None,
+            associations: Vec::new(),
+        })
+    }
+
+    /// Handle a request from the extension, returning the response to send back
+    pub fn handle<C: BrowserContext>(&mut self, ctx: &C, request: &str) -> String {
+        let Ok(request) = serde_json::from_str::<Value>(request) else {
+            return error_response("", ERROR_EMPTY_MESSAGE_RECEIVED, "Invalid message");
+        };
+
+        let action = request["action"].as_str().unwrap_or_default().to_string();
+
+        match self.handle_request(ctx, &action, &request) {
+            Ok(response) => response.to_string(),
+            Err(e) => error_response(&action, e.code, &e.message),
+        }
+    }
+
+    fn handle_request<C: BrowserContext>(
+        &mut self,
+        ctx: &C,
+        action: &str,
+        request: &Value,
+    ) -> BrowserResult<Value> {
+        let nonce = decode_nonce(&request["nonce"])?;
+
+        if action == "change-public-keys" {
+            let client = request["publicKey"]
+                .as_str()
+                .and_then(|key| BASE64.decode(key).ok())
+                .and_then(|key| PublicKey::from_slice(&key).ok())
+                .ok_or_else(|| {
+                    BrowserError::new(ERROR_KEY_CHANGE_FAILED, "Invalid client public key")
+                })?;
+
+            self.secret = generate_secret()
+                .map_err(|e| BrowserError::new(ERROR_KEY_CHANGE_FAILED, &format!("{}", e)))?;
+            self.client = Some(client);
+
+            return Ok(json!({
+                "action": action,
+                "version": KEEPASSXC_VERSION,
+                "publicKey": BASE64.encode(self.secret.public_key().as_bytes()),
+                "nonce": BASE64.encode(increment_nonce(&nonce)),
+                "success": "true",
+            }));
+        }
+
+        let client = self.client.clone().ok_or_else(|| {
+            BrowserError::new(
+                ERROR_CLIENT_PUBLIC_KEY_NOT_RECEIVED,
+                "Client public key not received",
+            )
+        })?;
+        let salsa_box = SalsaBox::new(&client, &self.secret);
+
+        let message = request["message"]
+            .as_str()
+            .and_then(|m| BASE64.decode(m).ok())
+            .and_then(|m| {
+                salsa_box
+                    .decrypt(GenericArray::from_slice(&nonce), &m[..])
+                    .ok()
+            })
+            .and_then(|m| serde_json::from_slice::<Value>(&m).ok())
+            .ok_or_else(|| {
+                BrowserError::new(ERROR_CANNOT_DECRYPT_MESSAGE, "Cannot decrypt message")
+            })?;
+
+        // only the encrypted action is authenticated, so the cleartext one must agree with it
+        if message["action"].as_str() != Some(action) {
+            return Err(BrowserError::new(
+                ERROR_INCORRECT_ACTION,
+                "Action does not match the message",
+            ));
+        }
+
+        let mut response = match action {
+            "get-databasehash" => get_database_hash(ctx)?,
+            "associate" => {
+                let response = associate(ctx, &message)?;
+                self.remember_association(&response["id"], &message["idKey"]);
+                response
+            }
+            "test-associate" => {
+                let response = test_associate(ctx, &message)?;
+                self.remember_association(&message["id"], &message["key"]);
+                response
+            }
+            "get-logins" => get_logins(ctx, &message)?,
+            "set-login" => set_login(ctx, &message, &self.associations)?,
+            "generate-password" => generate_password()?,
+            "get-totp" => get_totp(ctx, &message, &self.associations)?,
+            _ => {
+                return Err(BrowserError::new(
+                    ERROR_INCORRECT_ACTION,
+                    "Action not supported",
+                ));
+            }
+        };
+
+        let response_nonce = increment_nonce(&nonce);
+
+        response.insert("version".into(), KEEPASSXC_VERSION.into());
+        response.insert("success".into(), "true".into());
+        response.insert("nonce".into(), BASE64.encode(response_nonce).into());
+
+        let plaintext = Value::Object(response).to_string();
+        let encrypted = salsa_box
+            .encrypt(
+                GenericArray::from_slice(&response_nonce),
+                plaintext.as_bytes(),
+            )
+            .map_err(|_| {
+                BrowserError::new(ERROR_CANNOT_ENCRYPT_MESSAGE, "Cannot encrypt message")
+            })?;
+
+        Ok(json!({
+            "action": action,
+            "message": BASE64.encode(encrypted),
+            "nonce": BASE64.encode(response_nonce),
+        }))
+    }
+
+    /// Remember an identity key that was confirmed to be associated with a database
+    fn remember_association(&mut self, id: &Value, key: &Value) {
+        let (Some(id), Some(key)) = (id.as_str(), key.as_str()) else {
+            return;
+        };
+
+        let association = (id.to_string(), key.to_string());
+        if !self.associations.contains(&association) {
+            self.associations.push(association);
+        }
+    }
+}
+
+/// Check whether a database is saved after changes from the browser
+pub fn is_auto_save_enabled(database: &KpDatabase) -> bool {
+    crate::database::is_option_enabled(database, AUTO_SAVE_KEY)
+}
+
+/// Enable or disable saving a database after changes from the browser
+pub fn set_auto_save(database: &mut KpDatabase, enabled: bool) {
+    crate::database::set_option_enabled(database, AUTO_SAVE_KEY, enabled)
+}
+
+/// Let the application save a database changed by a request, if it was opted in
+fn changed<C: BrowserContext>(ctx: &C, idx: usize) {
+    let auto_save = ctx.with_state(|state| {
+        state.databases[idx]
+            .get_database()
+            .is_ok_and(is_auto_save_enabled)
+    });
+
+    if auto_save {
+        ctx.database_changed(idx);
+    }
+}
+
+/// Respond with the hash identifying the active database
+fn get_database_hash<C: BrowserContext>(ctx: &C) -> BrowserResult<Map<String, Value>> {
+    let hash = ctx.with_state(|state| {
+        let (_, database) = active_database(state)?;
+        Ok(database_hash(database))
+    })?;
+
+    Ok(response_map(json!({ "hash": hash })))
+}
+
+/// Associate a new browser with the active database after confirmation by the user
+fn associate<C: BrowserContext>(ctx: &C, message: &Value) -> BrowserResult<Map<String, Value>> {
+    let id_key = message["idKey"]
+        .as_str()
+        .filter(|key| BASE64.decode(key).is_ok_and(|k| k.len() == 32))
+        .ok_or_else(|| BrowserError::new(ERROR_ASSOCIATION_FAILED, "Invalid identity key"))?
+        .to_string();
+
+    let (hash, name) = ctx.with_state(|state| {
+        let (idx, database) = active_database(state)?;
+        Ok((
+            database_hash(database),
+            state.databases[idx].get_name().to_string(),
+        ))
+    })?;
+
+    if !ctx.confirm_association(&name) {
+        return Err(BrowserError::new(
+            ERROR_ACTION_CANCELLED_OR_DENIED,
+            "Association was denied",
+        ));
+    }
+
+    let id = format!("OmniKee {}", &Uuid::new_v4().simple().to_string()[..8]);
+
+    let idx = ctx.with_state(|state| {
+        let (idx, database) = active_database(state)?;
+
+        // the active database may have changed while waiting for the user
+        if database_hash(database) != hash {
+            return Err(BrowserError::new(
+                ERROR_ASSOCIATION_FAILED,
+                "The active database changed",
+            ));
+        }
+
+        let database = state.databases[idx]
+            .get_database_mut()
+            .map_err(|e| BrowserError::new(ERROR_DATABASE_NOT_OPENED, &format!("{}", e)))?;
+
+        database.meta.custom_data.items.insert(
+            format!("{ASSOCIATION_PREFIX}{id}"),
+            CustomDataItem {
+                value: Some(KpValue::Unprotected(id_key.clone())),
+                last_modification_time: Some(chrono::Utc::now().naive_utc()),
+            },
+        );
+
+        Ok(idx)
+    })?;
+
+    changed(ctx, idx);
+
+    Ok(response_map(json!({ "hash": hash, "id": id })))
+}
+
+/// Check whether the browser is still associated with the active database
+fn test_associate<C: BrowserContext>(
+    ctx: &C,
+    message: &Value,
+) -> BrowserResult<Map<String, Value>> {
+    let id = message["id"].as_str().unwrap_or_default();
+    let key = message["key"].as_str().unwrap_or_default();
+
+    ctx.with_state(|state| {
+        let (_, database) = active_database(state)?;
+
+        if !is_associated(database, id, key) {
+            return Err(BrowserError::new(
+                ERROR_ASSOCIATION_FAILED,
+                "Association not found",
+            ));
+        }
+
+        Ok(response_map(json!({
+            "hash": database_hash(database),
+            "id": id,
+        })))
+    })
+}
+
+/// Find logins for a URL in all unlocked databases the browser is associated with
+fn get_logins<C: BrowserContext>(ctx: &C, message: &Value) -> BrowserResult<Map<String, Value>> {
+    let url = message["url"]
+        .as_str()
+        .filter(|url| !url.is_empty())
+        .ok_or_else(|| BrowserError::new(ERROR_NO_URL_PROVIDED, "No URL provided"))?;
+
+    let keys = message_keys(message);
+
+    ctx.with_state(|state| {
+        let (_, active) = active_database(state)?;
+        let hash = database_hash(active);
+
+        let databases: Vec<(usize, &KpDatabase)> = state
+            .databases
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, db)| db.get_database().ok().map(|db| (idx, db)))
+            .filter(|(_, db)| keys.iter().any(|(id, key)| is_associated(db, id, key)))
+            .collect();
+
+        if databases.is_empty() {
+            return Err(BrowserError::new(
+                ERROR_ASSOCIATION_FAILED,
+                "Not associated with any open database",
+            ));
+        }
+
+        let matcher = crate::url_match::UrlMatcher::new(url)
+            .map_err(|e| BrowserError::new(ERROR_NO_URL_PROVIDED, &format!("{}", e)))?;
+
+        let entries: Vec<Value> = crate::url_match::find_entries(databases, &matcher)
+            .into_iter()
+            .map(|m| login_json(&Resolver::new(m.database), m.entry))
+            .collect();
+
+        if entries.is_empty() {
+            return Err(BrowserError::new(ERROR_NO_LOGINS_FOUND, "No logins found"));
+        }
+
+        Ok(response_map(json!({
+            "count": entries.len(),
+            "entries": entries,
+            "hash": hash,
+            "id": keys.first().map(|(id, _)| *id).unwrap_or_default(),
+        })))
+    })
+}
+
+/// Create or update a login from the browser
+///
+/// The browser has to prove its association with the active database, using the identity key
+/// for the association ID of the request from the request itself or from earlier on the same
+/// connection.
+fn set_login<C: BrowserContext>(
+    ctx: &C,
+    message: &Value,
+    associations: &[(String, String)],
+) -> BrowserResult<Map<String, Value>> {
+    let url = message["url"]
+        .as_str()
+        .filter(|url| !url.is_empty())
+        .ok_or_else(|| BrowserError::new(ERROR_NO_URL_PROVIDED, "No URL provided"))?
+        .to_string();
+
+    let login = message["login"].as_str().unwrap_or_default().to_string();
+    let password = message["password"].as_str().unwrap_or_default().to_string();
+
+    let parse_uuid = |field: &str| -> BrowserResult<Option<Uuid>> {
+        match message[field].as_str().filter(|s| !s.is_empty()) {
+            Some(s) => Uuid::parse_str(s).map(Some).map_err(|_| {
+                BrowserError::new(ERROR_NO_VALID_UUID_PROVIDED, "Invalid UUID provided")
+            }),
+            None => Ok(None),
+        }
+    };
+
+    let entry_uuid = parse_uuid("uuid")?;
+    let group_uuid = parse_uuid("groupUuid")?;
+
+    let id = message["id"].as_str().unwrap_or_default();
+    let keys = session_keys(message, associations);
+
+    let (idx, hash) = ctx.with_state(|state| {
+        let (idx, database) = active_database(state)?;
+        let hash = database_hash(database);
+
+        let associated = keys
+            .iter()
+            .any(|(key_id, key)| *key_id == id && is_associated(database, id, key));
+
+        if !associated {
+            return Err(BrowserError::new(
+                ERROR_ASSOCIATION_FAILED,
+                "Not associated with the active database",
+            ));
+        }
+
+        let database = &mut state.databases[idx];
+
+        if let Some(entry_uuid) = entry_uuid {
+            let entry = database
+                .entry_mut(&entry_uuid)
+                .map_err(|e| BrowserError::new(ERROR_DATABASE_NOT_OPENED, &format!("{}", e)))?
+                .ok_or_else(|| {
+                    BrowserError::new(ERROR_NO_VALID_UUID_PROVIDED, "No entry by that UUID")
+                })?;
+
+            entry
+                .fields
+                .insert("UserName".to_string(), KpValue::Unprotected(login));
+            entry
+                .fields
+                .insert("Password".to_string(), KpValue::Protected(password.into()));
+        } else {
+            let mut entry = KpEntry::new();
+
+            let title = url::Url::parse(&url)
+                .ok()
+                .and_then(|u| u.host_str().map(String::from))
+                .unwrap_or_else(|| url.clone());
+
+            entry
+                .fields
+                .insert("Title".to_string(), KpValue::Unprotected(title));
+            entry
+                .fields
+                .insert("URL".to_string(), KpValue::Unprotected(url));
+            entry
+                .fields
+                .insert("UserName".to_string(), KpValue::Unprotected(login));
+            entry
+                .fields
+                .insert("Password".to_string(), KpValue::Protected(password.into()));
+
+            let opened =
+                |e: anyhow::Error| BrowserError::new(ERROR_DATABASE_NOT_OPENED, &format!("{}", e));
+
+            let root_uuid = database.get_database().map_err(opened)?.root.uuid;
+
+            let group = match group_uuid {
+                Some(uuid) if uuid == root_uuid => {
+                    &mut database.get_database_mut().map_err(opened)?.root
+                }
+                Some(uuid) => database.group_mut(&uuid).map_err(opened)?.ok_or_else(|| {
+                    BrowserError::new(ERROR_NO_VALID_UUID_PROVIDED, "No group by that UUID")
+                })?,
+                None => default_group(&mut database.get_database_mut().map_err(opened)?.root),
+            };
+
+            group.children.push(Node::Entry(entry));
+        }
+
+        Ok((idx, hash))
+    })?;
+
+    changed(ctx, idx);
+
+    Ok(response_map(json!({
+        "count": null,
+        "entries": null,
+        "error": "",
+        "hash": hash,
+    })))
+}
+
+/// Generate a new random password
+fn generate_password() -> BrowserResult<Map<String, Value>> {
+    let options = crate::generator::PasswordOptions::default();
+
+    let password = crate::generator::generate(&options)
+        .map_err(|e| BrowserError::new(ERROR_ACTION_CANCELLED_OR_DENIED, &format!("{}", e)))?;
+
+    // older extension versions read the password from `entries`
+    Ok(response_map(json!({
+        "password": password,
+        "entries": [{
+            "login": (options.entropy_bits() as u32).to_string(),
+            "password": password,
+        }],
+    })))
+}
+
+/// Generate the current time-based one-time password for an entry
+///
+/// Only entries of databases that the browser is associated with are considered, using the keys
+/// sent with the request and those confirmed earlier on the same connection.
+fn get_totp<C: BrowserContext>(
+    ctx: &C,
+    message: &Value,
+    associations: &[(String, String)],
+) -> BrowserResult<Map<String, Value>> {
+    let uuid = message["uuid"]
+        .as_str()
+        .and_then(|s| Uuid::parse_str(s).ok())
+        .ok_or_else(|| BrowserError::new(ERROR_NO_VALID_UUID_PROVIDED, "Invalid UUID provided"))?;
+
+    let keys = session_keys(message, associations);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    ctx.with_state(|state| {
+        let databases: Vec<_> = state
+            .databases
+            .iter()
+            .filter(|db| {
+                db.get_database()
+                    .is_ok_and(|db| keys.iter().any(|(id, key)| is_associated(db, id, key)))
+            })
+            .collect();
+
+        if databases.is_empty() {
+            return Err(BrowserError::new(
+                ERROR_ASSOCIATION_FAILED,
+                "Not associated with any open database",
+            ));
+        }
+
+        let entry = databases
+            .into_iter()
+            .find_map(|db| db.entry(&uuid).ok().flatten())
+            .ok_or_else(|| {
+                BrowserError::new(ERROR_NO_VALID_UUID_PROVIDED, "No entry by that UUID")
+            })?;
+
+        let code = match crate::otp::OtpConfig::from_entry(entry) {
+            Ok(Some(crate::otp::OtpConfig::Totp(totp))) => match totp.value_at(now) {
+                crate::OTPResponse::Totp { code, .. } => code,
+                crate::OTPResponse::Hotp { code, .. } => code,
+            },
+            _ => String::new(),
+        };
+
+        Ok(response_map(json!({ "totp": code })))
+    })
+}
+
+/// Find the database that browser requests act on: the first unlocked database
+fn active_database(state: &AppState) -> BrowserResult<(usize, &KpDatabase)> {
+    state
+        .databases
+        .iter()
+        .enumerate()
+        .find_map(|(idx, db)| db.get_database().ok().map(|db| (idx, db)))
+        .ok_or_else(|| BrowserError::new(ERROR_DATABASE_NOT_OPENED, "Database not opened"))
+}
+
+/// The hash that identifies a database to the extension, like KeePassXC computes it
+fn database_hash(database: &KpDatabase) -> String {
+    let uuid = database.root.uuid.simple().to_string();
+    hex(&Sha256::digest(uuid.as_bytes()))
+}
+
+/// Get the identity keys sent with a request as (id, key) pairs
+fn message_keys(message: &Value) -> Vec<(&str, &str)> {
+    message["keys"]
+        .as_array()
+        .map(|keys| {
+            keys.iter()
+                .filter_map(|k| Some((k["id"].as_str()?, k["key"].as_str()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get the identity keys sent with a request along with those confirmed earlier on the connection
+fn session_keys<'a>(
+    message: &'a Value,
+    associations: &'a [(String, String)],
+) -> Vec<(&'a str, &'a str)> {
+    let mut keys = message_keys(message);
+    keys.extend(
+        associations
+            .iter()
+            .map(|(id, key)| (id.as_str(), key.as_str())),
+    );
+
+    keys
+}
+
+/// Check whether a browser identity key is associated with a database
+fn is_associated(database: &KpDatabase, id: &str, key: &str) -> bool {
+    if id.is_empty() || key.is_empty() {
+        return false;
+    }
+
+    database
+        .meta
+        .custom_data
+        .items
+        .get(&format!("{ASSOCIATION_PREFIX}{id}"))
+        .and_then(|item| item.value.as_ref())
+        .is_some_and(|value| matches!(value, KpValue::Unprotected(v) if v == key))
+}
+
+/// Describe an entry as a login for the extension
+fn login_json(resolver: &Resolver, entry: &KpEntry) -> Value {
+    let group = resolver
+        .parent_groups(entry)
+        .and_then(|groups| groups.last().map(|group| group.name.clone()))
+        .unwrap_or_default();
+
+    let mut login = json!({
+        "name": resolver.field(entry, "Title").unwrap_or_default(),
+        "login": resolver.field(entry, "UserName").unwrap_or_default(),
+        "password": resolver.field(entry, "Password").unwrap_or_default(),
+        "uuid": entry.uuid.simple().to_string(),
+        "group": group,
+        "expired": "false",
+        "stringFields": [],
+    });
+
+    if let Ok(Some(_)) = crate::otp::OtpConfig::from_entry(entry) {
+        login["totp"] = Value::String(String::new());
+    }
+
+    login
+}
+
+/// Find or create the group that receives new logins
+fn default_group(root: &mut KpGroup) -> &mut KpGroup {
+    let position = root
+        .children
+        .iter()
+        .position(|node| matches!(node, Node::Group(group) if group.name == DEFAULT_GROUP));
+
+    let position = match position {
+        Some(position) => position,
+        None => {
+            root.children.push(Node::Group(KpGroup::new(DEFAULT_GROUP)));
+            root.children.len() - 1
+        }
+    };
+
+    match &mut root.children[position] {
+        Node::Group(group) => group,
+        Node::Entry(..) => unreachable!(),
+    }
+}
+
+fn response_map(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+fn error_response(action: &str, code: u8, message: &str) -> String {
+    json!({
+        "action": action,
+        "errorCode": code.to_string(),
+        "error": message,
+    })
+    .to_string()
+}
+
+fn decode_nonce(nonce: &Value) -> BrowserResult<[u8; NONCE_SIZE]> {
+    nonce
+        .as_str()
+        .and_then(|n| BASE64.decode(n).ok())
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(|| BrowserError::new(ERROR_CANNOT_DECRYPT_MESSAGE, "Invalid nonce"))
+}
+
+/// Increment a nonce as a little-endian number, like `sodium_increment`
+fn increment_nonce(nonce: &[u8; NONCE_SIZE]) -> [u8; NONCE_SIZE] {
+    let mut out = *nonce;
+
+    for byte in out.iter_mut() {
+        let (value, overflow) = byte.overflowing_add(1);
+        *byte = value;
+
+        if !overflow {
+            break;
+        }
+    }
+
+    out
+}
+
+fn generate_secret() -> anyhow::Result<SecretKey> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    Ok(SecretKey::from(bytes))
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Directory holding the local sockets of the app
+///
+/// The sockets are kept in a directory that only the user can access, so that other users cannot
+/// connect before the permissions of a socket itself are restricted.
+#[cfg(unix)]
+pub fn runtime_dir() -> std::path::PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => std::path::PathBuf::from(dir).join("omnikee"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("omnikee-{user}"))
+        }
+    }
+}
+
+/// Path of the local socket that the native messaging host connects to
+///
+/// `omnikee-proxy` connects to the same path.
+#[cfg(unix)]
+pub fn socket_path() -> std::path::PathBuf {
+    runtime_dir().join("browser.sock")
+}
+
+/// Create a directory that only the user can access, or make sure an existing one is
+///
+/// Fails if the directory belongs to another user, who could then access its contents.
+#[cfg(all(feature = "tauri", unix))]
+pub(crate) fn create_private_dir(dir: &std::path::Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    if !std::fs::symlink_metadata(dir)?.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+
+    // only the owner can change the permissions
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+
+    Ok(())
+}
+
+/// Read a message framed by a 32-bit native-endian length prefix, as used by native messaging
+///
+/// Returns `None` when the stream was closed.
+pub fn read_message<R: std::io::Read>(reader: &mut R) -> anyhow::Result<Option<String>> {
+    let mut length = [0u8; 4];
+
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        anyhow::bail!("Message of {} bytes is too large", length);
+    }
+
+    let mut message = vec![0u8; length];
+    reader.read_exact(&mut message)?;
+
+    Ok(Some(String::from_utf8(message)?))
+}
+
+/// Write a message with a 32-bit native-endian length prefix
+pub fn write_message<W: std::io::Write>(writer: &mut W, message: &str) -> anyhow::Result<()> {
+    writer.write_all(&(message.len() as u32).to_ne_bytes())?;
+    writer.write_all(message.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Listen for connections from the native messaging host in the background
+#[cfg(all(feature = "tauri", unix))]
+pub fn listen<C: BrowserContext + Send + Sync + 'static>(
+    ctx: std::sync::Arc<C>,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    create_private_dir(&runtime_dir())?;
+    let path = socket_path();
+
+    // remove a stale socket left behind by a previous instance
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            anyhow::bail!(
+                "Another instance is already listening on {}",
+                path.display()
+            );
+        }
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+
+            let ctx = ctx.clone();
+            std::thread::spawn(move || {
+                let mut session = BrowserSession::new()?;
+
+                while let Some(request) = read_message(&mut stream)? {
+                    let response = session.handle(&*ctx, &request);
+                    write_message(&mut stream, &response)?;
+                }
+
+                Ok::<_, anyhow::Error>(())
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Listen for connections from the native messaging host in the background
+#[cfg(all(feature = "tauri", not(unix)))]
+pub fn listen<C: BrowserContext + Send + Sync + 'static>(
+    _ctx: std::sync::Arc<C>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("Browser integration is not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use keepass::db::NodeRef;
+
+    use super::*;
+    use crate::database::Database;
+
+    struct TestContext {
+        state: Mutex<AppState>,
+        allow: bool,
+        changed: Mutex<Vec<usize>>,
+    }
+
+    impl BrowserContext for TestContext {
+        fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+            f(&mut self.state.lock().unwrap())
+        }
+
+        fn confirm_association(&self, _database_name: &str) -> bool {
+            self.allow
+        }
+
+        fn database_changed(&self, database_idx: usize) {
+            self.changed.lock().unwrap().push(database_idx);
+        }
+    }
+
+    impl TestContext {
+        fn new(allow: bool) -> Self {
+            let mut login = KpEntry::new();
+            for (field, value) in [
+                ("Title", "Example"),
+                ("URL", "https://example.com/login"),
+                ("UserName", "alice"),
+            ] {
+                login
+                    .fields
+                    .insert(field.to_string(), KpValue::Unprotected(value.to_string()));
+            }
+            login.fields.insert(
+                "Password".to_string(),
+                KpValue::Protected("hunter2".as_bytes().into()),
+            );
+            login.fields.insert(
+                "otp".to_string(),
+                KpValue::Protected(
+                    "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP"
+                        .as_bytes()
+                        .into(),
+                ),
+            );
+
+            let mut database = KpDatabase::new(Default::default());
+            database.root.children.push(Node::Entry(login));
+
+            let mut state = AppState::default();
+            state.databases.push(Database::unlocked(database));
+
+            Self {
+                state: Mutex::new(state),
+                allow,
+                changed: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn entry_uuid(&self) -> Uuid {
+            self.with_state(|state| {
+                let database = state.databases[0].get_database().unwrap();
+                database
+                    .root
+                    .iter()
+                    .find_map(|node| match node {
+                        NodeRef::Entry(entry) => Some(entry.uuid),
+                        NodeRef::Group(_) => None,
+                    })
+                    .unwrap()
+            })
+        }
+
+        fn entry_count(&self) -> usize {
+            self.with_state(|state| {
+                let database = state.databases[0].get_database().unwrap();
+                database
+                    .root
+                    .iter()
+                    .filter(|node| matches!(node, NodeRef::Entry(_)))
+                    .count()
+            })
+        }
+    }
+
+    /// The extension side of a connection
+    struct TestClient {
+        secret: SecretKey,
+        server: Option<PublicKey>,
+        nonce: [u8; NONCE_SIZE],
+    }
+
+    impl TestClient {
+        fn connect(session: &mut BrowserSession, ctx: &TestContext) -> Self {
+            let mut client = Self {
+                secret: generate_secret().unwrap(),
+                server: None,
+                nonce: [0xff; NONCE_SIZE],
+            };
+
+            let request = json!({
+                "action": "change-public-keys",
+                "publicKey": BASE64.encode(client.secret.public_key().as_bytes()),
+                "nonce": BASE64.encode(client.nonce),
+                "clientID": "test",
+            });
+            let response: Value =
+                serde_json::from_str(&session.handle(ctx, &request.to_string())).unwrap();
+
+            assert_eq!(response["success"], "true");
+            assert_eq!(response["version"], KEEPASSXC_VERSION);
+
+            // the nonce wraps around as a little-endian number
+            assert_eq!(decode_nonce(&response["nonce"]).ok(), Some([0; NONCE_SIZE]));
+
+            let server = BASE64
+                .decode(response["publicKey"].as_str().unwrap())
+                .unwrap();
+            client.server = Some(PublicKey::from_slice(&server).unwrap());
+
+            client
+        }
+
+        /// Send an encrypted request and decrypt the response, or return the error response
+        fn send(
+            &mut self,
+            session: &mut BrowserSession,
+            ctx: &TestContext,
+            action: &str,
+            message: Value,
+        ) -> Value {
+            self.nonce = increment_nonce(&self.nonce);
+            let salsa_box = SalsaBox::new(self.server.as_ref().unwrap(), &self.secret);
+
+            let encrypted = salsa_box
+                .encrypt(
+                    GenericArray::from_slice(&self.nonce),
+                    message.to_string().as_bytes(),
+                )
+                .unwrap();
+
+            let request = json!({
+                "action": action,
+                "message": BASE64.encode(encrypted),
+                "nonce": BASE64.encode(self.nonce),
+                "clientID": "test",
+            });
+            let response: Value =
+                serde_json::from_str(&session.handle(ctx, &request.to_string())).unwrap();
+
+            if response.get("errorCode").is_some() {
+                return response;
+            }
+
+            let response_nonce = decode_nonce(&response["nonce"]).ok().unwrap();
+            assert_eq!(response_nonce, increment_nonce(&self.nonce));
+
+            let decrypted = salsa_box
+                .decrypt(
+                    GenericArray::from_slice(&response_nonce),
+                    &BASE64
+                        .decode(response["message"].as_str().unwrap())
+                        .unwrap()[..],
+                )
+                .unwrap();
+            let decrypted: Value = serde_json::from_slice(&decrypted).unwrap();
+
+            assert_eq!(decrypted["success"], "true");
+            assert_eq!(decrypted["nonce"], response["nonce"]);
+
+            decrypted
+        }
+    }
+
+    fn identity_key() -> String {
+        BASE64.encode(generate_secret().unwrap().public_key().as_bytes())
+    }
+
+    /// Associate a new identity key, returning the (id, key) pair
+    fn associate_new(
+        session: &mut BrowserSession,
+        client: &mut TestClient,
+        ctx: &TestContext,
+    ) -> (String, String) {
+        let key = identity_key();
+        let response = client.send(
+            session,
+            ctx,
+            "associate",
+            json!({ "action": "associate", "key": identity_key(), "idKey": key }),
+        );
+
+        (response["id"].as_str().unwrap().to_string(), key)
+    }
+
+    #[test]
+    fn requires_key_exchange() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+
+        let request = json!({
+            "action": "get-databasehash",
+            "message": "",
+            "nonce": BASE64.encode([0u8; NONCE_SIZE]),
+        });
+        let response: Value =
+            serde_json::from_str(&session.handle(&ctx, &request.to_string())).unwrap();
+
+        assert_eq!(response["action"], "get-databasehash");
+        assert_eq!(
+            response["errorCode"],
+            ERROR_CLIENT_PUBLIC_KEY_NOT_RECEIVED.to_string()
+        );
+    }
+
+    #[test]
+    fn rejects_messages_for_other_keys() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        client.secret = generate_secret().unwrap();
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-databasehash",
+            json!({ "action": "get-databasehash" }),
+        );
+
+        assert_eq!(
+            response["errorCode"],
+            ERROR_CANNOT_DECRYPT_MESSAGE.to_string()
+        );
+    }
+
+    #[test]
+    fn associates_and_tests_association() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let hash = client.send(
+            &mut session,
+            &ctx,
+            "get-databasehash",
+            json!({ "action": "get-databasehash" }),
+        )["hash"]
+            .clone();
+        let (id, key) = associate_new(&mut session, &mut client, &ctx);
+
+        // the association is only saved with the database by the user
+        assert!(ctx.changed.lock().unwrap().is_empty());
+
+        // a new connection proves the association with the stored key
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "test-associate",
+            json!({ "action": "test-associate", "id": id, "key": key }),
+        );
+        assert_eq!(response["id"], id.as_str());
+        assert_eq!(response["hash"], hash);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "test-associate",
+            json!({ "action": "test-associate", "id": id, "key": identity_key() }),
+        );
+        assert_eq!(response["errorCode"], ERROR_ASSOCIATION_FAILED.to_string());
+    }
+
+    #[test]
+    fn rejects_mismatched_actions() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-databasehash",
+            json!({ "action": "associate", "key": identity_key(), "idKey": identity_key() }),
+        );
+
+        assert_eq!(response["errorCode"], ERROR_INCORRECT_ACTION.to_string());
+        assert!(ctx.with_state(|state| {
+            let database = state.databases[0].get_database().unwrap();
+            database.meta.custom_data.items.is_empty()
+        }));
+    }
+
+    #[test]
+    fn saves_opted_in_databases() {
+        let ctx = TestContext::new(true);
+        ctx.with_state(|state| {
+            set_auto_save(state.databases[0].get_database_mut().unwrap(), true);
+        });
+
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+        associate_new(&mut session, &mut client, &ctx);
+
+        assert_eq!(*ctx.changed.lock().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn denied_association_is_not_stored() {
+        let ctx = TestContext::new(false);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "associate",
+            json!({ "action": "associate", "key": identity_key(), "idKey": identity_key() }),
+        );
+
+        assert_eq!(
+            response["errorCode"],
+            ERROR_ACTION_CANCELLED_OR_DENIED.to_string()
+        );
+        assert!(ctx.changed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn gets_logins_for_associated_keys() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+        let (id, key) = associate_new(&mut session, &mut client, &ctx);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-logins",
+            json!({
+                "action": "get-logins",
+                "url": "https://accounts.example.com/signin",
+                "keys": [{ "id": id, "key": key }],
+            }),
+        );
+
+        assert_eq!(response["count"], 1);
+        assert_eq!(response["id"], id.as_str());
+        assert_eq!(response["entries"][0]["login"], "alice");
+        assert_eq!(response["entries"][0]["password"], "hunter2");
+        assert_eq!(response["entries"][0]["totp"], "");
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-logins",
+            json!({
+                "action": "get-logins",
+                "url": "https://example.org/",
+                "keys": [{ "id": id, "key": key }],
+            }),
+        );
+        assert_eq!(response["errorCode"], ERROR_NO_LOGINS_FOUND.to_string());
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-logins",
+            json!({
+                "action": "get-logins",
+                "url": "https://example.com/",
+                "keys": [{ "id": id, "key": identity_key() }],
+            }),
+        );
+        assert_eq!(response["errorCode"], ERROR_ASSOCIATION_FAILED.to_string());
+    }
+
+    #[test]
+    fn set_login_requires_associated_key() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+        let (id, key) = associate_new(&mut session, &mut client, &ctx);
+
+        let login = |keys: Value| {
+            json!({
+                "action": "set-login",
+                "url": "https://new.example.net/",
+                "id": id,
+                "login": "mallory",
+                "password": "secret",
+                "keys": keys,
+            })
+        };
+
+        // knowing the association ID is not enough
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let response = client.send(&mut session, &ctx, "set-login", login(json!([])));
+        assert_eq!(response["errorCode"], ERROR_ASSOCIATION_FAILED.to_string());
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "set-login",
+            login(json!([{ "id": id, "key": identity_key() }])),
+        );
+        assert_eq!(response["errorCode"], ERROR_ASSOCIATION_FAILED.to_string());
+        assert_eq!(ctx.entry_count(), 1);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "set-login",
+            login(json!([{ "id": id, "key": key }])),
+        );
+        assert_eq!(response["error"], "");
+        assert_eq!(ctx.entry_count(), 2);
+    }
+
+    #[test]
+    fn gets_totp_after_test_associate() {
+        let ctx = TestContext::new(true);
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+        let (id, key) = associate_new(&mut session, &mut client, &ctx);
+        let uuid = ctx.entry_uuid().to_string();
+
+        let mut session = BrowserSession::new().unwrap();
+        let mut client = TestClient::connect(&mut session, &ctx);
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-totp",
+            json!({ "action": "get-totp", "uuid": uuid }),
+        );
+        assert_eq!(response["errorCode"], ERROR_ASSOCIATION_FAILED.to_string());
+
+        client.send(
+            &mut session,
+            &ctx,
+            "test-associate",
+            json!({ "action": "test-associate", "id": id, "key": key }),
+        );
+
+        let response = client.send(
+            &mut session,
+            &ctx,
+            "get-totp",
+            json!({ "action": "get-totp", "uuid": uuid }),
+        );
+        let code = response["totp"].as_str().unwrap();
+
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn increments_nonce_with_carry() {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[0] = 0xff;
+        nonce[1] = 0xff;
+
+        let incremented = increment_nonce(&nonce);
+
+        assert_eq!(incremented[..3], [0, 0, 1]);
+        assert_eq!(increment_nonce(&[0; NONCE_SIZE])[0], 1);
+    }
+
+    #[test]
+    fn frames_messages() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, "{\"action\":\"ping\"}").unwrap();
+
+        let mut reader = &buffer[..];
+        assert_eq!(
+            read_message(&mut reader).unwrap().as_deref(),
+            Some("{\"action\":\"ping\"}")
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let oversized = ((MAX_MESSAGE_SIZE + 1) as u32).to_ne_bytes();
+        assert!(read_message(&mut &oversized[..]).is_err());
+    }
+}
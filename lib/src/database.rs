@@ -2,11 +2,18 @@ use anyhow::{Context, Result, bail};
 
 use keepass::DatabaseKey as KpDatabaseKey;
 use keepass::db::{
-    Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node as KpNode, NodeRef,
+    CustomDataItem, Database as KpDatabase, DeletedObject, Entry as KpEntry, Group as KpGroup,
+    Node as KpNode, NodeRef, Value as KpValue,
 };
 use uuid::Uuid;
 
-use crate::source::DatabaseSource;
+use crate::attachment::Attachments;
+use crate::exchange::Icon;
+use crate::source::{DatabaseSource, SourceFuture};
+use crate::url_match::is_recycled;
+
+/// Index of the standard KeePass icon used for the recycle bin
+const RECYCLE_BIN_ICON: usize = 43;
 
 /// The state of an in-memory database
 pub(crate) enum DatabaseState {
@@ -14,35 +21,20 @@ pub(crate) enum DatabaseState {
     Unlocked {
         database: KpDatabase,
         key: KpDatabaseKey,
+        attachments: Attachments,
     },
 
     /// a marker that the database has not yet been unlocked
     Locked,
 }
 
-/// Internal state for an in-memory database
-pub(crate) struct Database {
-    pub(crate) state: DatabaseState,
-    pub(crate) source: Box<dyn DatabaseSource>,
-}
-
-impl Database {
-    /// Load a database source, but don't unlock the database yet
-    pub(crate) fn load<S: DatabaseSource + 'static>(source: S) -> Result<Self> {
-        Ok(Self {
-            state: DatabaseState::Locked,
-            source: Box::new(source),
-        })
-    }
-
-    /// Unlock a loaded database
-    pub(crate) fn unlock(
-        &mut self,
+impl DatabaseState {
+    /// Read and decrypt a database from its source
+    pub(crate) async fn open(
+        source: &dyn DatabaseSource,
         password: Option<String>,
         keyfile: Option<Vec<u8>>,
-
-        #[cfg(feature = "tauri")] app: tauri::AppHandle,
-    ) -> Result<()> {
+    ) -> Result<Self> {
         let mut key = KpDatabaseKey::new();
 
         if let Some(p) = password {
@@ -53,41 +45,128 @@ impl Database {
             key = key.with_keyfile(&mut &kf[..]).context("Reading keyfile")?;
         }
 
-        let mut reader = self.source.open(
-            #[cfg(feature = "tauri")]
-            app,
-        )?;
+        let data = source.open().await?;
+
+        let mut database = KpDatabase::open(&mut &data[..], key.clone())?;
+
+        // saving writes the attachments back, so a database whose attachments cannot be read
+        // must not unlock without them
+        let attachments =
+            Attachments::read(&data, key.clone(), &database).context("Reading the attachments")?;
+
+        // a broken KeeShare container must not keep the database locked, so problems are only
+        // reported when importing the containers explicitly
+        let _ = crate::keeshare::import_all(&mut database, source).await;
+
+        Ok(Self::Unlocked {
+            database,
+            key,
+            attachments,
+        })
+    }
+}
+
+/// Internal state for an in-memory database
+pub(crate) struct Database {
+    pub(crate) state: DatabaseState,
+    pub(crate) source: Box<dyn DatabaseSource>,
+
+    /// identifies the database while it is loaded, as indices change when closing databases
+    pub(crate) id: Uuid,
+
+    /// whether the database was exposed through the Secret Service when it was last locked
+    pub(crate) exposed_while_locked: bool,
+
+    /// whether the source is taken out by an operation waiting for it, see [`CheckedOut`]
+    checked_out: bool,
+}
+
+/// The source of a loaded database, taken out while an operation waits for it so that the
+/// database stays usable in the meantime
+pub(crate) struct CheckedOut {
+    /// the database to return the source to
+    pub(crate) id: Uuid,
+    pub(crate) source: Box<dyn DatabaseSource>,
+}
+
+/// Stands in for a source that is checked out
+struct CheckedOutSource {
+    name: String,
+}
 
-        let database = KpDatabase::open(&mut reader, key.clone())?;
+impl DatabaseSource for CheckedOutSource {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async { bail!("The database is busy with another operation") })
+    }
+
+    fn save<'a>(&'a mut self, _: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async { bail!("The database is busy with another operation") })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
 
-        self.state = DatabaseState::Unlocked { database, key };
+impl Database {
+    /// Load a database source, but don't unlock the database yet
+    pub(crate) fn load<S: DatabaseSource + 'static>(source: S) -> Result<Self> {
+        Ok(Self {
+            state: DatabaseState::Locked,
+            source: Box::new(source),
+            id: Uuid::new_v4(),
+            exposed_while_locked: false,
+            checked_out: false,
+        })
+    }
 
-        Ok(())
+    /// Wrap an in-memory database as if it had been loaded from an empty source and unlocked, for
+    /// tests
+    #[cfg(test)]
+    pub(crate) fn unlocked(database: KpDatabase) -> Self {
+        let mut db = Self::load(crate::source::BufferDatabaseSource::empty("test.kdbx")).unwrap();
+        db.state = DatabaseState::Unlocked {
+            database,
+            key: KpDatabaseKey::new(),
+            attachments: Attachments::default(),
+        };
+        db
     }
 
     /// Lock a database, discarding its key and unlocked data
     pub(crate) fn lock(&mut self) {
+        if let Ok(database) = self.get_database() {
+            self.exposed_while_locked = crate::secret_service::is_enabled(database);
+        }
+
         self.state = DatabaseState::Locked
     }
 
-    /// Save the database contents to its source
-    pub(crate) fn save(
-        &mut self,
-        #[cfg(feature = "tauri")] app: tauri::AppHandle,
-    ) -> Result<Option<Vec<u8>>> {
-        if let DatabaseState::Unlocked { database, key } = &self.state {
-            {
-                let mut writer = self.source.save(
-                    #[cfg(feature = "tauri")]
-                    app,
-                )?;
-
-                database.save(&mut writer, key.clone())?;
-            }
-            Ok(self.source.send_saved())
-        } else {
-            bail!("Database must be open to save")
+    /// Take the source out of the database, to wait for it without borrowing the database
+    pub(crate) fn check_out(&mut self) -> Result<CheckedOut> {
+        if self.checked_out {
+            bail!("The database is busy with another operation")
         }
+
+        let placeholder = CheckedOutSource {
+            name: self.source.get_name().to_string(),
+        };
+
+        self.checked_out = true;
+        Ok(CheckedOut {
+            id: self.id,
+            source: std::mem::replace(&mut self.source, Box::new(placeholder)),
+        })
+    }
+
+    /// Put a source taken out with [`Database::check_out`] back
+    pub(crate) fn check_in(&mut self, checked_out: CheckedOut) {
+        self.source = checked_out.source;
+        self.checked_out = false;
     }
 
     /// Convenience method to get an appropriate name for a database
@@ -121,18 +200,35 @@ impl Database {
         }
     }
 
+    /// Get the attachments of the entries of an unlocked database
+    pub(crate) fn attachments(&self) -> Result<&Attachments> {
+        if let DatabaseState::Unlocked { attachments, .. } = &self.state {
+            Ok(attachments)
+        } else {
+            bail!("Cannot access - database is locked")
+        }
+    }
+
     /// Convenience to get an icon
     pub(crate) fn get_icon(
         &self,
         uuid: Option<&Uuid>,
         index: Option<usize>,
-    ) -> Result<Option<String>> {
+    ) -> Result<Option<Icon>> {
         let database = self.get_database()?;
         let icon = crate::icon::get_icon(database, uuid, index);
 
         Ok(icon)
     }
 
+    /// Convenience to get the image data of a custom icon
+    pub(crate) fn get_custom_icon(&self, uuid: &Uuid) -> Result<Option<&[u8]>> {
+        let database = self.get_database()?;
+        let data = crate::icon::get_custom_icon(database, uuid);
+
+        Ok(data)
+    }
+
     /// Find a group by its UUID
     pub(crate) fn group(&self, uuid: &Uuid) -> Result<Option<&KpGroup>> {
         let database = self.get_database()?;
@@ -148,27 +244,18 @@ impl Database {
         Ok(None)
     }
 
-    /// Get a mutable reference to an entry by its UUID
+    /// Get a mutable reference to a group below the root group by its UUID
     pub(crate) fn group_mut(&mut self, uuid: &Uuid) -> Result<Option<&mut KpGroup>> {
         let database = self.get_database_mut()?;
 
-        fn inner<'a>(group: &'a mut KpGroup, uuid: &Uuid) -> Option<&'a mut KpGroup> {
-            for node in group.children.iter_mut() {
-                if let KpNode::Group(group) = node {
-                    if &group.uuid == uuid {
-                        return Some(group);
-                    }
-
-                    if let Some(group) = inner(group, uuid) {
-                        return Some(group);
-                    }
-                }
-            }
-
-            None
-        }
-
-        Ok(inner(&mut database.root, uuid))
+        Ok(database
+            .root
+            .children
+            .iter_mut()
+            .find_map(|node| match node {
+                KpNode::Group(group) => find_group_mut(group, uuid),
+                KpNode::Entry(..) => None,
+            }))
     }
 
     /// Get a reference to an entry by its UUID
@@ -211,4 +298,261 @@ impl Database {
 
         Ok(inner(&mut database.root, uuid))
     }
+
+    /// Add a new, empty entry to a group, which may be the root group, returning its UUID
+    pub(crate) fn add_entry(&mut self, group_uuid: &Uuid) -> Result<Option<Uuid>> {
+        let database = self.get_database_mut()?;
+
+        let Some(group) = find_group_mut(&mut database.root, group_uuid) else {
+            return Ok(None);
+        };
+
+        let mut entry = KpEntry::new();
+        entry
+            .fields
+            .insert("Title".to_string(), KpValue::Unprotected(String::new()));
+
+        let uuid = entry.uuid;
+        group.children.push(KpNode::Entry(entry));
+
+        Ok(Some(uuid))
+    }
+
+    /// Add a new, empty group to a group, which may be the root group, returning its UUID
+    pub(crate) fn add_group(&mut self, parent_uuid: &Uuid, name: &str) -> Result<Option<Uuid>> {
+        let database = self.get_database_mut()?;
+
+        let Some(parent) = find_group_mut(&mut database.root, parent_uuid) else {
+            return Ok(None);
+        };
+
+        let group = KpGroup::new(name);
+        let uuid = group.uuid;
+        parent.children.push(KpNode::Group(group));
+
+        Ok(Some(uuid))
+    }
+
+    /// Move an entry into another group
+    ///
+    /// Returns whether both the entry and the group were found.
+    pub(crate) fn move_entry(&mut self, uuid: &Uuid, group_uuid: &Uuid) -> Result<bool> {
+        let database = self.get_database_mut()?;
+
+        if find_group_mut(&mut database.root, group_uuid).is_none() {
+            return Ok(false);
+        }
+
+        let Some(mut entry) = take_entry(&mut database.root, uuid) else {
+            return Ok(false);
+        };
+
+        entry
+            .times
+            .set_location_changed(chrono::Utc::now().naive_utc());
+
+        find_group_mut(&mut database.root, group_uuid)
+            .expect("the group was found before")
+            .children
+            .push(KpNode::Entry(entry));
+
+        Ok(true)
+    }
+
+    /// Remove an entry, see [`recycle_entry`]
+    pub(crate) fn remove_entry(&mut self, uuid: &Uuid) -> Result<bool> {
+        let database = self.get_database_mut()?;
+
+        Ok(recycle_entry(database, uuid))
+    }
+}
+
+/// Find a group by its UUID, including the group itself
+pub(crate) fn find_group_mut<'a>(group: &'a mut KpGroup, uuid: &Uuid) -> Option<&'a mut KpGroup> {
+    if &group.uuid == uuid {
+        return Some(group);
+    }
+
+    group.children.iter_mut().find_map(|node| match node {
+        KpNode::Group(group) => find_group_mut(group, uuid),
+        KpNode::Entry(..) => None,
+    })
+}
+
+/// Detach an entry from the group that contains it
+fn take_entry(group: &mut KpGroup, uuid: &Uuid) -> Option<KpEntry> {
+    let position = group
+        .children
+        .iter()
+        .position(|node| matches!(node, KpNode::Entry(entry) if &entry.uuid == uuid));
+
+    if let Some(position) = position {
+        return match group.children.remove(position) {
+            KpNode::Entry(entry) => Some(entry),
+            KpNode::Group(..) => unreachable!(),
+        };
+    }
+
+    group.children.iter_mut().find_map(|node| match node {
+        KpNode::Group(group) => take_entry(group, uuid),
+        KpNode::Entry(..) => None,
+    })
+}
+
+/// Remove an entry like KeePass does: it is moved to the recycle bin, which is created if needed,
+/// unless the bin is disabled or the entry is already in there. Then it is deleted permanently.
+///
+/// Returns whether the entry was found.
+pub(crate) fn recycle_entry(database: &mut KpDatabase, uuid: &Uuid) -> bool {
+    let recycled = database.root.iter().find_map(|node| match node {
+        NodeRef::Entry(entry) if &entry.uuid == uuid => Some(is_recycled(database, entry)),
+        _ => None,
+    });
+
+    let Some(recycled) = recycled else {
+        return false;
+    };
+
+    let Some(mut entry) = take_entry(&mut database.root, uuid) else {
+        return false;
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+
+    if recycled || !database.meta.recyclebin_enabled.unwrap_or(true) {
+        database.deleted_objects.objects.push(DeletedObject {
+            uuid: *uuid,
+            deletion_time: now,
+        });
+
+        return true;
+    }
+
+    let bin = database
+        .meta
+        .recyclebin_uuid
+        .filter(|bin| find_group_mut(&mut database.root, bin).is_some());
+
+    let bin = match bin {
+        Some(bin) => bin,
+        None => {
+            let mut group = KpGroup::new("Recycle Bin");
+            group.icon_id = Some(RECYCLE_BIN_ICON);
+
+            let bin = group.uuid;
+            database.root.children.push(KpNode::Group(group));
+            database.meta.recyclebin_uuid = Some(bin);
+            database.meta.recyclebin_changed = Some(now);
+
+            bin
+        }
+    };
+
+    entry.times.set_location_changed(now);
+
+    find_group_mut(&mut database.root, &bin)
+        .expect("the recycle bin exists")
+        .children
+        .push(KpNode::Entry(entry));
+
+    true
+}
+
+/// Check whether an option stored as `"true"` in the custom data of a database is enabled
+pub(crate) fn is_option_enabled(database: &KpDatabase, key: &str) -> bool {
+    database
+        .meta
+        .custom_data
+        .items
+        .get(key)
+        .and_then(|item| item.value.as_ref())
+        .is_some_and(|value| matches!(value, KpValue::Unprotected(v) if v == "true"))
+}
+
+/// Enable or disable an option stored in the custom data of a database
+pub(crate) fn set_option_enabled(database: &mut KpDatabase, key: &str, enabled: bool) {
+    let items = &mut database.meta.custom_data.items;
+
+    if enabled {
+        items.insert(
+            key.to_string(),
+            CustomDataItem {
+                value: Some(KpValue::Unprotected("true".to_string())),
+                last_modification_time: Some(chrono::Utc::now().naive_utc()),
+            },
+        );
+    } else {
+        items.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_mut_excludes_root() {
+        let mut database = KpDatabase::new(Default::default());
+        database
+            .root
+            .children
+            .push(KpNode::Group(KpGroup::new("Internet")));
+
+        let root = database.root.uuid;
+        let child = database.root.groups()[0].uuid;
+        let mut db = Database::unlocked(database);
+
+        assert!(db.group_mut(&root).unwrap().is_none());
+        assert_eq!(db.group_mut(&child).unwrap().unwrap().name, "Internet");
+
+        // entries and groups can still be added to the root group
+        let uuid = db.add_entry(&root).unwrap().unwrap();
+        assert!(db.entry(&uuid).unwrap().is_some());
+        assert!(db.add_entry(&Uuid::new_v4()).unwrap().is_none());
+
+        let uuid = db.add_group(&root, "Mail").unwrap().unwrap();
+        assert_eq!(db.group_mut(&uuid).unwrap().unwrap().name, "Mail");
+        assert!(db.add_group(&Uuid::new_v4(), "Mail").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recycle_entry_creates_bin_then_deletes() {
+        let mut database = KpDatabase::new(Default::default());
+        let entry = KpEntry::new();
+        let uuid = entry.uuid;
+        database.root.children.push(KpNode::Entry(entry));
+
+        assert!(recycle_entry(&mut database, &uuid));
+
+        let bin = database
+            .meta
+            .recyclebin_uuid
+            .expect("a recycle bin was created");
+        let groups = database.root.groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].uuid, bin);
+        assert_eq!(groups[0].entries().len(), 1);
+        assert!(database.deleted_objects.objects.is_empty());
+
+        // removing a recycled entry deletes it for good
+        assert!(recycle_entry(&mut database, &uuid));
+        assert!(database.root.groups()[0].entries().is_empty());
+        assert!(database.deleted_objects.contains(uuid));
+
+        assert!(!recycle_entry(&mut database, &uuid));
+    }
+
+    #[test]
+    fn test_recycle_entry_without_bin() {
+        let mut database = KpDatabase::new(Default::default());
+        database.meta.recyclebin_enabled = Some(false);
+
+        let entry = KpEntry::new();
+        let uuid = entry.uuid;
+        database.root.children.push(KpNode::Entry(entry));
+
+        assert!(recycle_entry(&mut database, &uuid));
+        assert!(database.root.children.is_empty());
+        assert!(database.deleted_objects.contains(uuid));
+    }
 }
@@ -49,7 +49,7 @@ pub struct Group {
     pub name: String,
     pub uuid: Uuid,
     pub children: Vec<Group>,
-    pub icon: Option<String>,
+    pub icon: Option<Icon>,
 }
 
 impl Into<Group> for (&KpGroup, &KpDatabase) {
@@ -85,7 +85,52 @@ pub struct Entry {
 
     pub fields: HashMap<String, Value>,
 
-    pub icon: Option<String>,
+    /// The attachments of the entry. Their data can be requested by name.
+    pub attachments: Vec<Attachment>,
+
+    pub icon: Option<Icon>,
+}
+
+/// A file attached to an entry
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct Attachment {
+    pub name: String,
+    pub size: usize,
+}
+
+/// A reference to the icon of a group or entry
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum Icon {
+    /// One of the standard KeePass icons, with the name of the corresponding Material Design icon
+    Builtin { id: usize, name: String },
+
+    /// A custom icon stored in the database. Its data can be requested using the UUID.
+    Custom { uuid: Uuid },
+}
+
+/// An icon to assign to a group or entry
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum IconSet {
+    /// One of the standard KeePass icons by its index
+    Builtin { id: usize },
+
+    /// A custom icon that is already stored in the database
+    Custom { uuid: Uuid },
+}
+
+/// A custom icon stored in a database
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct CustomIcon {
+    pub uuid: Uuid,
+
+    /// Number of groups, entries and history items using this icon
+    pub uses: usize,
 }
 
 #[derive(Serialize, Deserialize, Tsify)]
@@ -125,10 +170,539 @@ impl Into<KpValue> for ValueSet {
     }
 }
 
+/// A generated one-time password
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum OTPResponse {
+    /// A time-based code and how long it remains valid
+    Totp {
+        code: String,
+        valid_for: Duration,
+        period: Duration,
+    },
+
+    /// A counter-based code and the counter value it was generated for
+    Hotp { code: String, counter: u64 },
+}
+
+/// Hash algorithm for one-time passwords
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum OTPAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// How a time-based one-time password is presented
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum OTPEncoder {
+    /// A decimal code, as used by most services
+    Digits,
+
+    /// A 5-character code using the Steam Guard alphabet
+    Steam,
+}
+
+/// A way to set up one-time passwords for an entry
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum OTPSetup {
+    /// An `otpauth://totp/` or `otpauth://hotp/` URI, e.g. as scanned from a QR code
+    Uri { uri: String },
+
+    /// A Base32-encoded secret for time-based codes with explicit parameters
+    Secret {
+        secret: String,
+        algorithm: OTPAlgorithm,
+        digits: u32,
+        period: u64,
+    },
+
+    /// A Base32-encoded secret for Steam Guard codes
+    Steam { secret: String },
+
+    /// A Base32-encoded secret for counter-based codes with the initial counter value
+    Hotp { secret: String, counter: u64 },
+}
+
+/// The one-time password settings of an entry, without the secret
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum OTPSettings {
+    Totp {
+        algorithm: OTPAlgorithm,
+        digits: u32,
+        period: u64,
+        encoder: OTPEncoder,
+        issuer: Option<String>,
+        account: Option<String>,
+    },
+    Hotp {
+        counter: u64,
+        issuer: Option<String>,
+        account: Option<String>,
+    },
+}
+
+/// A field value before and after resolving placeholders and field references
 #[derive(Serialize, Deserialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
-pub struct OTPResponse {
-    pub code: String,
-    pub valid_for: Duration,
-    pub period: Duration,
+pub struct ResolvedValue {
+    pub raw: String,
+    pub resolved: String,
+}
+
+/// An entry URL to download a favicon for
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct FaviconRequest {
+    pub entry_uuid: Uuid,
+    pub url: String,
+}
+
+/// The outcome of downloading favicons for a set of entries
+#[derive(Serialize, Deserialize, Tsify, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct FaviconSummary {
+    /// entries that received a new icon
+    pub downloaded: usize,
+
+    /// entries for which no icon could be found
+    pub not_found: usize,
+
+    /// entries for which downloading failed, with the error message
+    pub failed: HashMap<Uuid, String>,
+}
+
+/// A single step of an Auto-Type sequence, independent of the platform that performs it
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum AutoTypeAction {
+    /// Type a text character by character
+    Text { text: String },
+
+    /// Press and release a key while holding modifiers
+    Key {
+        key: AutoTypeKey,
+        modifiers: AutoTypeModifiers,
+    },
+
+    /// Wait for a number of milliseconds
+    Delay { ms: u64 },
+
+    /// Change the delay between keystrokes for the rest of the sequence
+    SetKeyDelay { ms: u64 },
+
+    /// Clear the contents of the focused input field
+    ClearField,
+}
+
+/// A key that can be pressed during Auto-Type
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "key")]
+pub enum AutoTypeKey {
+    Tab,
+    Enter,
+    Space,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+    Win,
+    Apps,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    PrintScreen,
+    Break,
+    Help,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+
+    /// A digit on the numeric keypad
+    Numpad {
+        digit: u8,
+    },
+
+    /// A function key `F1` to `F24`
+    Function {
+        number: u8,
+    },
+
+    /// The key that produces a character
+    Char {
+        char: char,
+    },
+
+    /// A key by its Windows virtual key code, as used by `{VKEY n}`
+    Virtual {
+        code: u16,
+    },
+}
+
+/// Modifier keys held down while pressing a key
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct AutoTypeModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+/// An entry that can be auto-typed into a window
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct AutoTypeMatch {
+    pub entry_uuid: Uuid,
+    pub name: Option<String>,
+
+    /// the sequence to type, from the matching window association or the entry default
+    pub sequence: String,
+}
+
+/// How closely the URL of an entry matches a page, from worst to best
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum UrlMatchLevel {
+    /// a `regex:` URL matches the page URL
+    Regex,
+
+    /// the page is on a subdomain of the entry's host
+    Subdomain,
+
+    /// the page is on the same host
+    Host,
+
+    /// the page is on the same host, below the path of the entry
+    Path,
+
+    /// the page has the same URL, ignoring the fragment
+    Exact,
+}
+
+/// An entry whose URL matches a page
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct UrlMatch {
+    pub database_idx: usize,
+    pub entry_uuid: Uuid,
+    pub name: Option<String>,
+    pub user_name: Option<String>,
+
+    /// the entry URL that matched
+    pub url: String,
+    pub level: UrlMatchLevel,
+}
+
+/// A key that is served by the SSH agent
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SshKey {
+    /// the database the key was loaded from, if it is still open
+    pub database_idx: Option<usize>,
+    pub entry_uuid: Uuid,
+    pub comment: String,
+    pub algorithm: String,
+    pub fingerprint: String,
+
+    /// whether every use of the key has to be confirmed
+    pub confirm: bool,
+
+    /// seconds until the key is removed from the agent
+    pub expires_in: Option<u64>,
+}
+
+/// How a CSV file is encoded and separated into fields
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct CsvFormat {
+    /// the character between fields, e.g. `,` or `;`
+    pub delimiter: char,
+
+    /// the character that encloses fields containing delimiters or line breaks
+    pub quote: char,
+
+    /// the name of the text encoding, e.g. `UTF-8` or `windows-1252`
+    pub encoding: String,
+
+    /// whether the first row contains column names
+    pub has_header: bool,
+}
+
+/// Which columns of a CSV file are imported into which fields of an entry, by column index
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Default, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct CsvMapping {
+    pub title: Option<usize>,
+    pub user_name: Option<usize>,
+    pub password: Option<usize>,
+    pub url: Option<usize>,
+    pub notes: Option<usize>,
+
+    /// a slash-separated path of groups below the target group, e.g. `Internet/Mail`
+    pub group: Option<usize>,
+
+    /// an `otpauth://` URI or a Base32-encoded TOTP secret
+    pub otp: Option<usize>,
+
+    /// tags separated by commas or semicolons
+    pub tags: Option<usize>,
+
+    pub created: Option<usize>,
+    pub modified: Option<usize>,
+    pub expires: Option<usize>,
+
+    /// further columns to store in custom fields
+    pub custom_fields: Vec<CsvCustomField>,
+}
+
+/// A CSV column that is stored in a custom field
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct CsvCustomField {
+    pub column: usize,
+    pub name: String,
+    pub protected: bool,
+}
+
+/// The first rows of a CSV file with the detected format and a suggested mapping
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct CsvPreview {
+    pub format: CsvFormat,
+
+    /// the column names, or empty if the file has no header
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+
+    /// the number of rows excluding the header
+    pub total_rows: usize,
+    pub mapping: CsvMapping,
+}
+
+/// The outcome of importing entries from another password manager
+#[derive(Serialize, Deserialize, Tsify, Debug, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ImportSummary {
+    /// number of imported entries
+    pub entries: usize,
+
+    /// number of created groups
+    pub groups: usize,
+
+    /// problems with individual records that were imported partially or skipped
+    pub warnings: Vec<String>,
+}
+
+/// A new database converted from another KeePass version
+#[derive(Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ImportedDatabase {
+    pub database: DatabaseOverview,
+
+    /// parts of the database that could not be converted
+    pub warnings: Vec<String>,
+}
+
+/// A file format for exporting a database or group
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum ExportFormat {
+    /// CSV with the columns of KeePassXC
+    Csv,
+
+    /// JSON following the schema described in the `export::json` module
+    Json,
+
+    /// the unencrypted XML document of KeePass 2.x
+    Xml,
+}
+
+impl ExportFormat {
+    /// The usual file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Xml => "xml",
+        }
+    }
+}
+
+/// Options for exporting a group into a new database
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct GroupExportOptions {
+    /// the name of the new database, defaulting to the name of the group
+    pub name: Option<String>,
+
+    /// keep the previous versions of entries
+    pub include_history: bool,
+}
+
+/// A column of a printable entry report
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type")]
+pub enum ReportColumn {
+    Title,
+    UserName,
+    Password,
+    Url,
+    Notes,
+
+    /// the `otpauth://` URI of the one-time password
+    Otp,
+    Tags,
+    Modified,
+    Expires,
+
+    /// a custom field by its name
+    Field {
+        name: String,
+    },
+}
+
+/// The content of a printable entry report
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ReportOptions {
+    /// the groups to list, or all groups of the database if empty
+    pub groups: Vec<Uuid>,
+
+    /// also list the subgroups of the selected groups
+    pub include_subgroups: bool,
+
+    pub columns: Vec<ReportColumn>,
+}
+
+/// How a group is shared with other databases through a KeeShare container
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum ShareType {
+    /// merge the container into the group when unlocking
+    Import,
+
+    /// write the group into the container when saving
+    Export,
+
+    /// both import and export
+    Synchronize,
+}
+
+/// The KeeShare container that a group is shared through
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ShareReference {
+    pub share_type: ShareType,
+
+    /// the path of the container, relative to the database
+    pub path: String,
+
+    /// the password of the container
+    pub password: String,
+}
+
+/// A shared group of a database
+#[derive(Serialize, Deserialize, Tsify, Debug)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct Share {
+    pub group_uuid: Uuid,
+    pub group_name: String,
+    pub reference: ShareReference,
+}
+
+/// An entry holding the user name and password to log in to a remote database with
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RemoteCredentials {
+    /// the unlocked database containing the entry
+    pub database_idx: usize,
+
+    pub entry_uuid: Uuid,
+}
+
+/// How to log in to a server over SSH
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone, Copy, PartialEq, Eq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum SftpAuth {
+    /// the password of the credentials entry
+    Password,
+
+    /// the SSH key of the credentials entry, as configured for the SSH agent
+    Key,
+}
+
+/// Where a database is stored on a server reachable over SSH
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SftpSettings {
+    pub host: String,
+    pub port: u16,
+
+    /// the path of the database, relative to the home directory unless absolute
+    pub path: String,
+
+    /// the entry with the user name, and the password or SSH key to log in with
+    pub credentials: RemoteCredentials,
+
+    pub auth: SftpAuth,
+
+    /// the SHA-256 fingerprint of the host key, to trust a server that is not in `known_hosts`
+    pub host_key_fingerprint: Option<String>,
+}
+
+/// Where a database is stored in an S3-compatible bucket
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct S3Settings {
+    /// the service endpoint, like `https://s3.eu-central-1.amazonaws.com` or a MinIO server
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+
+    /// the key of the database object
+    pub key: String,
+
+    /// address the bucket in the path instead of the host name, as needed for MinIO
+    pub path_style: bool,
+
+    /// the version of the object to open in a bucket with versioning, or the latest one
+    pub version_id: Option<String>,
+
+    /// the entry with the access key ID as user name and the secret access key as password
+    pub credentials: RemoteCredentials,
+}
+
+/// A database that the browser keeps between sessions
+#[derive(Serialize, Deserialize, Tsify, Debug, Clone)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct StoredDatabase {
+    /// identifies the database in the storage of the browser
+    pub id: String,
+
+    /// the file name of the database
+    pub name: String,
+
+    /// whether this is a file on disk that the browser was given access to, instead of a copy of
+    /// the database in the storage of the browser
+    pub linked: bool,
 }
@@ -0,0 +1,70 @@
+//! Export of databases and groups to other formats
+//!
+//! Exports cover a group with all of its subgroups, which is the root group to export a whole
+//! database. Protected values like passwords and one-time password secrets are only written to
+//! unencrypted formats when this is requested explicitly, and are left empty otherwise. A group
+//! can also be exported into a new encrypted database.
+
+pub mod csv;
+pub mod json;
+pub mod kdbx;
+pub mod xml;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Value as KpValue};
+
+use crate::exchange::ExportFormat;
+
+/// Export a group of a database with its subgroups and entries
+pub fn export(
+    database: &KpDatabase,
+    group: &KpGroup,
+    format: ExportFormat,
+    include_protected: bool,
+) -> Result<Vec<u8>> {
+    match format {
+        ExportFormat::Csv => csv::export(database, group, include_protected),
+        ExportFormat::Json => json::export(database, group, include_protected),
+        ExportFormat::Xml => xml::export(database, group, include_protected),
+    }
+}
+
+/// The text of a field, or `None` for attachments and for protected values that are excluded
+pub(crate) fn text_value(value: &KpValue, include_protected: bool) -> Option<String> {
+    match value {
+        KpValue::Unprotected(text) => Some(text.clone()),
+        KpValue::Protected(secret) if include_protected => {
+            Some(String::from_utf8_lossy(secret.unsecure()).into_owned())
+        }
+        KpValue::Protected(..) | KpValue::Bytes(..) => None,
+    }
+}
+
+/// Format a time as an ISO 8601 string in UTC
+pub(crate) fn format_time(time: &NaiveDateTime) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Call a function for each entry of a group and its subgroups with the slash-separated path of
+/// its group, leaving out the recycle bin
+fn for_each_entry<'a>(
+    database: &KpDatabase,
+    group: &'a KpGroup,
+    path: &str,
+    f: &mut impl FnMut(&str, &'a KpEntry) -> Result<()>,
+) -> Result<()> {
+    for entry in group.entries() {
+        f(path, entry)?;
+    }
+
+    for child in group.groups() {
+        if database.meta.recyclebin_uuid == Some(child.uuid) {
+            continue;
+        }
+
+        for_each_entry(database, child, &format!("{}/{}", path, child.name), f)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Export to CSV in the layout of KeePassXC
+//!
+//! KeePassXC writes one row per entry with the columns `Group`, `Title`, `Username`, `Password`,
+//! `URL`, `Notes`, `TOTP`, `Icon`, `Last Modified` and `Created`. The group is the path of groups
+//! starting with the exported group, separated by slashes. Custom fields, attachments and history
+//! are not part of the format.
+
+use anyhow::Result;
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, Group as KpGroup};
+
+use crate::otp::OtpConfig;
+
+const HEADER: [&str; 10] = [
+    "Group",
+    "Title",
+    "Username",
+    "Password",
+    "URL",
+    "Notes",
+    "TOTP",
+    "Icon",
+    "Last Modified",
+    "Created",
+];
+
+/// Export the entries of a group and its subgroups as CSV
+pub fn export(database: &KpDatabase, group: &KpGroup, include_protected: bool) -> Result<Vec<u8>> {
+    let mut writer = ::csv::WriterBuilder::new()
+        .quote_style(::csv::QuoteStyle::Always)
+        .from_writer(Vec::new());
+
+    writer.write_record(HEADER)?;
+
+    super::for_each_entry(database, group, &group.name, &mut |path, entry| {
+        writer.write_record(entry_to_row(path, entry, include_protected))?;
+        Ok(())
+    })?;
+
+    Ok(writer.into_inner()?)
+}
+
+fn entry_to_row(path: &str, entry: &KpEntry, include_protected: bool) -> [String; 10] {
+    let field = |name: &str| {
+        entry
+            .fields
+            .get(name)
+            .and_then(|value| super::text_value(value, include_protected))
+            .unwrap_or_default()
+    };
+
+    // the secret of a one-time password is as sensitive as the password
+    let totp = match include_protected {
+        true => OtpConfig::from_entry(entry)
+            .ok()
+            .flatten()
+            .map(|otp| otp.to_uri())
+            .unwrap_or_default(),
+        false => String::new(),
+    };
+
+    let time =
+        |time: Option<&chrono::NaiveDateTime>| time.map(super::format_time).unwrap_or_default();
+
+    [
+        path.to_string(),
+        field("Title"),
+        field("UserName"),
+        field("Password"),
+        field("URL"),
+        field("Notes"),
+        totp,
+        entry.icon_id.unwrap_or_default().to_string(),
+        time(entry.times.get_last_modification()),
+        time(entry.times.get_creation()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::{Node as KpNode, Value as KpValue};
+
+    fn database() -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database.root.name = "Root".to_string();
+
+        let mut mail = crate::import::new_entry(
+            "Mail",
+            "alice",
+            "p\"ss",
+            "https://mail.example.com",
+            "two\nlines",
+        );
+        mail.fields.insert(
+            "otp".to_string(),
+            KpValue::Unprotected("otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP".to_string()),
+        );
+
+        let mut internet = KpGroup::new("Internet");
+        internet.children.push(KpNode::Entry(mail));
+
+        let mut bin = KpGroup::new("Recycle Bin");
+        bin.children.push(KpNode::Entry(crate::import::new_entry(
+            "Old", "", "", "", "",
+        )));
+        database.meta.recyclebin_uuid = Some(bin.uuid);
+
+        database.root.children.push(KpNode::Group(internet));
+        database.root.children.push(KpNode::Group(bin));
+
+        database
+    }
+
+    fn rows(data: &[u8]) -> Vec<Vec<String>> {
+        ::csv::Reader::from_reader(data)
+            .records()
+            .map(|r| r.unwrap().iter().map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let database = database();
+        let data = export(&database, &database.root, true).unwrap();
+
+        assert!(data.starts_with(b"\"Group\",\"Title\",\"Username\",\"Password\""));
+
+        let rows = rows(&data);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][0], "Root/Internet");
+        assert_eq!(
+            rows[0][1..6],
+            [
+                "Mail",
+                "alice",
+                "p\"ss",
+                "https://mail.example.com",
+                "two\nlines"
+            ]
+        );
+        assert!(rows[0][6].starts_with("otpauth://totp/"));
+        assert_eq!(rows[0][7], "0");
+    }
+
+    #[test]
+    fn test_exclude_protected() {
+        let database = database();
+        let data = export(&database, database.root.groups()[0], false).unwrap();
+
+        let rows = rows(&data);
+        assert_eq!(rows[0][0], "Internet");
+        assert_eq!(rows[0][2], "alice");
+        assert_eq!(rows[0][3], "");
+        assert_eq!(rows[0][6], "");
+    }
+}
@@ -0,0 +1,246 @@
+//! Export to JSON
+//!
+//! The document holds the exported group as a tree of groups and entries, leaving out the recycle
+//! bin. Times are ISO 8601 strings in UTC, and `expires` is only set for groups and entries that
+//! expire. Fields map their names to the value and whether it is protected, with a `null` value
+//! for protected values that were not included. Attachments are encoded as Base64.
+//!
+//! ```json
+//! {
+//!   "format": "omnikee-export",
+//!   "version": 1,
+//!   "database": "Passwords",
+//!   "exported": "2025-01-01T12:00:00Z",
+//!   "includes_protected": false,
+//!   "root": {
+//!     "uuid": "9e6b8d4e-5d36-4a2e-9e0c-3b4f1a6c2d10",
+//!     "name": "Root",
+//!     "notes": null,
+//!     "icon": 48,
+//!     "custom_icon": null,
+//!     "times": { "created": "...", "modified": "...", "expires": null },
+//!     "entries": [
+//!       {
+//!         "uuid": "3f1c2a8b-7e4d-4c5a-8b9e-0d1f2a3b4c5d",
+//!         "fields": {
+//!           "Title": { "value": "Mail", "protected": false },
+//!           "Password": { "value": null, "protected": true }
+//!         },
+//!         "otp": null,
+//!         "tags": ["work"],
+//!         "attachments": { "notes.txt": "bm90ZXM=" },
+//!         "icon": 0,
+//!         "custom_icon": null,
+//!         "times": { "created": "...", "modified": "...", "expires": "2030-01-01T00:00:00Z" }
+//!       }
+//!     ],
+//!     "groups": []
+//!   }
+//! }
+//! ```
+//!
+//! `otp` is the `otpauth://` URI of the one-time password of an entry, which is only included
+//! together with protected values.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use keepass::db::{
+    Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Times, Value as KpValue,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::otp::OtpConfig;
+
+/// Identifies the format of the document
+const FORMAT: &str = "omnikee-export";
+
+/// Version of the schema, to be increased on incompatible changes
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document {
+    format: &'static str,
+    version: u32,
+    database: Option<String>,
+    exported: String,
+    includes_protected: bool,
+    root: Group,
+}
+
+#[derive(Serialize)]
+struct Group {
+    uuid: Uuid,
+    name: String,
+    notes: Option<String>,
+    icon: Option<usize>,
+    custom_icon: Option<Uuid>,
+    times: ExportTimes,
+    entries: Vec<Entry>,
+    groups: Vec<Group>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    uuid: Uuid,
+    fields: BTreeMap<String, Field>,
+    otp: Option<String>,
+    tags: Vec<String>,
+    attachments: BTreeMap<String, String>,
+    icon: Option<usize>,
+    custom_icon: Option<Uuid>,
+    times: ExportTimes,
+}
+
+#[derive(Serialize)]
+struct Field {
+    value: Option<String>,
+    protected: bool,
+}
+
+#[derive(Serialize)]
+struct ExportTimes {
+    created: Option<String>,
+    modified: Option<String>,
+    expires: Option<String>,
+}
+
+/// Export a group with its subgroups and entries as JSON
+pub fn export(database: &KpDatabase, group: &KpGroup, include_protected: bool) -> Result<Vec<u8>> {
+    let document = Document {
+        format: FORMAT,
+        version: VERSION,
+        database: database.meta.database_name.clone(),
+        exported: super::format_time(&chrono::Utc::now().naive_utc()),
+        includes_protected: include_protected,
+        root: convert_group(database, group, include_protected),
+    };
+
+    Ok(serde_json::to_vec_pretty(&document)?)
+}
+
+fn convert_group(database: &KpDatabase, group: &KpGroup, include_protected: bool) -> Group {
+    Group {
+        uuid: group.uuid,
+        name: group.name.clone(),
+        notes: group.notes.clone(),
+        icon: group.icon_id,
+        custom_icon: group.custom_icon_uuid,
+        times: convert_times(&group.times),
+        entries: group
+            .entries()
+            .into_iter()
+            .map(|entry| convert_entry(entry, include_protected))
+            .collect(),
+        groups: group
+            .groups()
+            .into_iter()
+            .filter(|child| database.meta.recyclebin_uuid != Some(child.uuid))
+            .map(|child| convert_group(database, child, include_protected))
+            .collect(),
+    }
+}
+
+fn convert_entry(entry: &KpEntry, include_protected: bool) -> Entry {
+    let mut fields = BTreeMap::new();
+    let mut attachments = BTreeMap::new();
+
+    for (name, value) in &entry.fields {
+        match value {
+            KpValue::Bytes(data) => {
+                attachments.insert(name.clone(), STANDARD.encode(data));
+            }
+            value => {
+                let field = Field {
+                    value: super::text_value(value, include_protected),
+                    protected: matches!(value, KpValue::Protected(..)),
+                };
+                fields.insert(name.clone(), field);
+            }
+        }
+    }
+
+    let otp = match include_protected {
+        true => OtpConfig::from_entry(entry)
+            .ok()
+            .flatten()
+            .map(|otp| otp.to_uri()),
+        false => None,
+    };
+
+    Entry {
+        uuid: entry.uuid,
+        fields,
+        otp,
+        tags: entry.tags.clone(),
+        attachments,
+        icon: entry.icon_id,
+        custom_icon: entry.custom_icon_uuid,
+        times: convert_times(&entry.times),
+    }
+}
+
+fn convert_times(times: &Times) -> ExportTimes {
+    ExportTimes {
+        created: times.get_creation().map(super::format_time),
+        modified: times.get_last_modification().map(super::format_time),
+        expires: times
+            .get_expiry()
+            .filter(|_| times.expires)
+            .map(super::format_time),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::Node as KpNode;
+    use serde_json::Value;
+
+    #[test]
+    fn test_export_json() {
+        let mut database = KpDatabase::new(Default::default());
+        database.meta.database_name = Some("Passwords".to_string());
+
+        let mut mail = crate::import::new_entry("Mail", "alice", "secret", "", "");
+        mail.tags.push("work".to_string());
+        mail.fields
+            .insert("notes.txt".to_string(), KpValue::Bytes(b"notes".to_vec()));
+
+        let mut internet = KpGroup::new("Internet");
+        internet.children.push(KpNode::Entry(mail));
+        database.root.children.push(KpNode::Group(internet));
+
+        let bin = KpGroup::new("Recycle Bin");
+        database.meta.recyclebin_uuid = Some(bin.uuid);
+        database.root.children.push(KpNode::Group(bin));
+
+        let document: Value =
+            serde_json::from_slice(&export(&database, &database.root, false).unwrap()).unwrap();
+
+        assert_eq!(document["format"], "omnikee-export");
+        assert_eq!(document["database"], "Passwords");
+        assert_eq!(document["includes_protected"], false);
+
+        let groups = document["root"]["groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 1);
+
+        let entry = &groups[0]["entries"][0];
+        assert_eq!(entry["fields"]["UserName"]["value"], "alice");
+        assert_eq!(entry["fields"]["Password"]["value"], Value::Null);
+        assert_eq!(entry["fields"]["Password"]["protected"], true);
+        assert_eq!(entry["attachments"]["notes.txt"], "bm90ZXM=");
+        assert_eq!(entry["tags"][0], "work");
+
+        let document: Value =
+            serde_json::from_slice(&export(&database, &database.root, true).unwrap()).unwrap();
+        assert_eq!(
+            document["root"]["groups"][0]["entries"][0]["fields"]["Password"]["value"],
+            "secret"
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Export of a group into a new KDBX database
+//!
+//! The exported group becomes the root group of the new database, keeping the UUIDs of its
+//! subgroups and entries. Only the custom icons that are used within the group are carried over.
+//! Attachments become binaries of the new database that its entries refer to, except for those of
+//! older versions of entries in opened databases, whose attachments are not read. The recycle bin
+//! is left out.
+
+mod references;
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+use keepass::DatabaseKey as KpDatabaseKey;
+use keepass::db::{
+    Database as KpDatabase, Entry as KpEntry, Group as KpGroup, HeaderAttachment, History,
+    Node as KpNode, Value as KpValue,
+};
+use uuid::Uuid;
+
+use self::references::EntryReferences;
+use crate::attachment::Attachments;
+use crate::exchange::GroupExportOptions;
+
+/// A group exported into a new database
+pub struct GroupExport {
+    pub database: KpDatabase,
+
+    /// the attachments of every entry and older version of an entry, in the order in which they
+    /// are saved
+    references: Vec<EntryReferences>,
+}
+
+/// Build a new database from a group of a database and its subgroups
+pub fn group_as_database(
+    database: &KpDatabase,
+    attachments: &Attachments,
+    group: &KpGroup,
+    options: &GroupExportOptions,
+) -> GroupExport {
+    let mut root = group.clone();
+    prepare_group(&mut root, database.meta.recyclebin_uuid, options);
+
+    let mut new = KpDatabase::new(database.config.clone());
+
+    let meta = &database.meta;
+    new.meta.database_name = Some(options.name.clone().unwrap_or_else(|| group.name.clone()));
+    new.meta.memory_protection = meta.memory_protection.clone();
+    new.meta.history_max_items = meta.history_max_items;
+    new.meta.history_max_size = meta.history_max_size;
+    new.meta.maintenance_history_days = meta.maintenance_history_days;
+
+    let mut icons = HashSet::new();
+    collect_icons(&root, &mut icons);
+
+    new.meta.custom_icons.icons = meta
+        .custom_icons
+        .icons
+        .iter()
+        .filter(|icon| icons.contains(&icon.uuid))
+        .cloned()
+        .collect();
+
+    let mut binaries = Binaries::default();
+    let mut references = Vec::new();
+    move_attachments(&mut root, attachments, &mut binaries, &mut references);
+
+    new.root = root;
+    new.header_attachments = binaries.attachments;
+
+    GroupExport {
+        database: new,
+        references,
+    }
+}
+
+impl GroupExport {
+    /// Save the new database with a password, a key file or both
+    pub fn save(&self, password: Option<&str>, keyfile: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut key = KpDatabaseKey::new();
+
+        if let Some(password) = password {
+            key = key.with_password(password);
+        }
+
+        if let Some(mut keyfile) = keyfile {
+            key = key.with_keyfile(&mut keyfile)?;
+        }
+
+        if key.is_empty() {
+            bail!("A password or key file is required");
+        }
+
+        let mut data = Vec::new();
+        self.database.save(&mut data, key)?;
+
+        if self.references.iter().all(Vec::is_empty) {
+            return Ok(data);
+        }
+
+        references::add_references(
+            &data,
+            &self.database.config,
+            password,
+            keyfile,
+            &self.references,
+        )
+    }
+}
+
+/// Remove the recycle bin and, unless requested, the history of entries from a copied group
+fn prepare_group(group: &mut KpGroup, recyclebin: Option<Uuid>, options: &GroupExportOptions) {
+    group
+        .children
+        .retain(|node| !matches!(node, KpNode::Group(g) if Some(g.uuid) == recyclebin));
+
+    for node in group.children.iter_mut() {
+        match node {
+            KpNode::Group(child) => prepare_group(child, recyclebin, options),
+            KpNode::Entry(entry) if !options.include_history => entry.history = None,
+            KpNode::Entry(_) => {}
+        }
+    }
+}
+
+/// The binaries of a new database, stored once for all attachments with the same data
+#[derive(Default)]
+struct Binaries {
+    attachments: Vec<HeaderAttachment>,
+    indices: HashMap<Vec<u8>, usize>,
+}
+
+impl Binaries {
+    fn add(&mut self, data: &[u8]) -> usize {
+        if let Some(index) = self.indices.get(data) {
+            return *index;
+        }
+
+        let index = self.attachments.len();
+        self.attachments.push(HeaderAttachment {
+            flags: 0,
+            content: data.to_vec(),
+        });
+        self.indices.insert(data.to_vec(), index);
+
+        index
+    }
+}
+
+/// Turn the attachments of the entries of a copied group into binaries, in the order in which
+/// the entries are saved
+///
+/// Attachments are either kept in the [`Attachments`] of the database, or among the fields of
+/// imported entries, which the keepass crate cannot save as binaries itself.
+fn move_attachments(
+    group: &mut KpGroup,
+    attachments: &Attachments,
+    binaries: &mut Binaries,
+    references: &mut Vec<EntryReferences>,
+) {
+    for node in group.children.iter_mut() {
+        match node {
+            KpNode::Group(child) => move_attachments(child, attachments, binaries, references),
+            KpNode::Entry(entry) => {
+                let mut current: EntryReferences = attachments
+                    .of_entry(&entry.uuid)
+                    .filter(|(name, _)| !entry.fields.contains_key(*name))
+                    .map(|(name, data)| (name.to_string(), binaries.add(data)))
+                    .collect();
+                current.extend(take_bytes(entry, binaries));
+                references.push(current);
+
+                // older versions can only be changed by building the history again
+                if let Some(history) = entry.history.take() {
+                    let mut old = history.get_entries().clone();
+                    references.extend(old.iter_mut().map(|old| take_bytes(old, binaries)));
+
+                    let mut rebuilt = History::default();
+                    for old in old.into_iter().rev() {
+                        rebuilt.add_entry(old);
+                    }
+                    entry.history = Some(rebuilt);
+                }
+            }
+        }
+    }
+}
+
+/// Remove the attachments that an entry keeps among its fields, adding them to the binaries
+fn take_bytes(entry: &mut KpEntry, binaries: &mut Binaries) -> EntryReferences {
+    let names = entry
+        .fields
+        .iter()
+        .filter(|(_, value)| matches!(value, KpValue::Bytes(_)))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    names
+        .into_iter()
+        .filter_map(|name| match entry.fields.remove(&name) {
+            Some(KpValue::Bytes(data)) => Some((name, binaries.add(&data))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The KDBX writer of the keepass crate stores attachments as text, which fails for binary data
+pub(crate) fn check_attachment(title: &str, name: &str, data: &[u8]) -> Result<()> {
+    if std::str::from_utf8(data).is_err() {
+        bail!(
+            "The attachment {:?} of {:?} contains binary data, which cannot be saved to KDBX yet",
+            name,
+            title
+        );
+    }
+
+    Ok(())
+}
+
+/// Collect the UUIDs of the custom icons of a group, its subgroups and entries
+fn collect_icons(group: &KpGroup, icons: &mut HashSet<Uuid>) {
+    icons.extend(group.custom_icon_uuid);
+
+    for node in &group.children {
+        match node {
+            KpNode::Group(child) => collect_icons(child, icons),
+            KpNode::Entry(entry) => {
+                let history = entry.history.iter().flat_map(|h| h.get_entries());
+
+                for entry in std::iter::once(entry).chain(history) {
+                    icons.extend(entry.custom_icon_uuid);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::{History, Icon};
+
+    fn icon(n: u8) -> Icon {
+        Icon {
+            uuid: Uuid::from_bytes([n; 16]),
+            data: vec![n],
+        }
+    }
+
+    fn database() -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database.meta.custom_icons.icons = vec![icon(1), icon(2), icon(3)];
+
+        // the exported database is decrypted again to add attachments, which is slow with Argon2
+        database.config.kdf_config = keepass::config::KdfConfig::Aes { rounds: 10 };
+
+        let mut mail = crate::import::new_entry("Mail", "alice", "secret", "", "");
+        mail.fields
+            .insert("notes.txt".to_string(), KpValue::Bytes(b"notes".to_vec()));
+
+        let mut old = mail.clone();
+        old.custom_icon_uuid = Some(Uuid::from_bytes([2; 16]));
+        let mut history = History::default();
+        history.add_entry(old);
+        mail.history = Some(history);
+
+        let mut shared = KpGroup::new("Shared");
+        shared.custom_icon_uuid = Some(Uuid::from_bytes([1; 16]));
+        shared.children.push(KpNode::Entry(mail));
+
+        let mut bin = KpGroup::new("Recycle Bin");
+        bin.custom_icon_uuid = Some(Uuid::from_bytes([3; 16]));
+        database.meta.recyclebin_uuid = Some(bin.uuid);
+
+        database.root.children.push(KpNode::Group(shared));
+        database.root.children.push(KpNode::Group(bin));
+
+        database
+    }
+
+    /// Save an exported group and open it again with its attachments
+    fn reopen(export: &GroupExport) -> (KpDatabase, Attachments) {
+        let data = export.save(Some("contractor"), None).unwrap();
+
+        let key = keepass::DatabaseKey::new().with_password("contractor");
+        let database = KpDatabase::open(&mut &data[..], key.clone()).unwrap();
+        let attachments = Attachments::read(&data, key, &database).unwrap();
+
+        (database, attachments)
+    }
+
+    #[test]
+    fn test_group_as_database() {
+        let database = database();
+        let shared = database.root.groups()[0];
+
+        let new = group_as_database(
+            &database,
+            &Attachments::default(),
+            shared,
+            &GroupExportOptions::default(),
+        );
+        assert_eq!(new.database.meta.database_name.as_deref(), Some("Shared"));
+        assert_eq!(new.database.root.uuid, shared.uuid);
+        assert_eq!(new.database.meta.custom_icons.icons, [icon(1)]);
+
+        let mail = new.database.root.entries()[0];
+        assert!(mail.history.is_none());
+
+        let (reopened, attachments) = reopen(&new);
+        let mail = reopened.root.entries()[0];
+        assert_eq!(mail.get_password(), Some("secret"));
+        assert_eq!(
+            reopened.root.custom_icon_uuid,
+            Some(Uuid::from_bytes([1; 16]))
+        );
+
+        // the attachment is a binary now, not a field
+        assert!(!mail.fields.contains_key("notes.txt"));
+        assert_eq!(attachments.get(mail, "notes.txt"), Some(&b"notes"[..]));
+    }
+
+    #[test]
+    fn test_include_history() {
+        let database = database();
+        let options = GroupExportOptions {
+            name: Some("Contractor".to_string()),
+            include_history: true,
+        };
+
+        let new = group_as_database(&database, &Attachments::default(), &database.root, &options);
+        assert_eq!(
+            new.database.meta.database_name.as_deref(),
+            Some("Contractor")
+        );
+        assert_eq!(new.database.root.groups().len(), 1);
+        assert_eq!(new.database.meta.custom_icons.icons, [icon(1), icon(2)]);
+
+        // the entry and its older version share a binary
+        assert_eq!(new.database.header_attachments.len(), 1);
+        assert_eq!(new.references, vec![vec![("notes.txt".to_string(), 0)]; 2]);
+
+        let (reopened, _) = reopen(&new);
+        let history = reopened.root.groups()[0].entries()[0]
+            .history
+            .clone()
+            .unwrap();
+        assert_eq!(history.get_entries().len(), 1);
+        assert_eq!(
+            history.get_entries()[0].custom_icon_uuid,
+            Some(Uuid::from_bytes([2; 16]))
+        );
+    }
+
+    #[test]
+    fn test_binary_attachments() {
+        let mut database = database();
+        let KpNode::Group(shared) = &mut database.root.children[0] else {
+            panic!("expected a group");
+        };
+        let KpNode::Entry(mail) = &mut shared.children[0] else {
+            panic!("expected an entry");
+        };
+        mail.fields.insert(
+            "key.bin".to_string(),
+            KpValue::Bytes(vec![0, 159, 146, 150]),
+        );
+
+        let options = GroupExportOptions::default();
+        let new = group_as_database(&database, &Attachments::default(), &database.root, &options);
+
+        let (reopened, attachments) = reopen(&new);
+        let mail = reopened.root.groups()[0].entries()[0];
+        assert_eq!(
+            attachments.get(mail, "key.bin"),
+            Some(&[0, 159, 146, 150][..])
+        );
+    }
+
+    #[test]
+    fn test_stored_attachments() {
+        // attachments of an opened database are kept apart from its entries
+        let data = include_bytes!("../keeagent.kdbx");
+        let key = keepass::DatabaseKey::new().with_password("keeagent");
+        let database = KpDatabase::open(&mut &data[..], key.clone()).unwrap();
+        let attachments = Attachments::read(data, key, &database).unwrap();
+
+        let options = GroupExportOptions::default();
+        let new = group_as_database(&database, &attachments, &database.root, &options);
+
+        let (reopened, reopened_attachments) = reopen(&new);
+        let entry = database.root.entries()[0];
+        let exported = reopened.root.entries()[0];
+        assert_eq!(exported.uuid, entry.uuid);
+
+        for name in ["KeeAgent.settings", "id_ed25519"] {
+            assert!(attachments.get(entry, name).is_some());
+            assert_eq!(
+                reopened_attachments.get(exported, name),
+                attachments.get(entry, name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ciphers_and_kdfs() {
+        use keepass::config::{CompressionConfig, DatabaseConfig, KdfConfig, OuterCipherConfig};
+
+        // the keepass crate does not export the version type of its Argon2 implementation
+        let KdfConfig::Argon2 { version, .. } = DatabaseConfig::default().kdf_config else {
+            panic!("expected Argon2 by default");
+        };
+
+        let kdfs = [
+            KdfConfig::Aes { rounds: 10 },
+            KdfConfig::Argon2 {
+                iterations: 2,
+                memory: 64 * 1024,
+                parallelism: 2,
+                version,
+            },
+            KdfConfig::Argon2id {
+                iterations: 2,
+                memory: 64 * 1024,
+                parallelism: 2,
+                version,
+            },
+        ];
+        let ciphers = [
+            OuterCipherConfig::AES256,
+            OuterCipherConfig::Twofish,
+            OuterCipherConfig::ChaCha20,
+        ];
+
+        for (kdf, cipher) in kdfs.into_iter().zip(ciphers) {
+            for compression in [CompressionConfig::None, CompressionConfig::GZip] {
+                let mut database = database();
+                database.config.kdf_config = kdf.clone();
+                database.config.outer_cipher_config = cipher.clone();
+                database.config.compression_config = compression;
+
+                let options = GroupExportOptions::default();
+                let new =
+                    group_as_database(&database, &Attachments::default(), &database.root, &options);
+
+                let (reopened, attachments) = reopen(&new);
+                let mail = reopened.root.groups()[0].entries()[0];
+                assert_eq!(attachments.get(mail, "notes.txt"), Some(&b"notes"[..]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyfile() {
+        let database = database();
+        let options = GroupExportOptions::default();
+        let new = group_as_database(&database, &Attachments::default(), &database.root, &options);
+
+        let keyfiles: [&[u8]; 3] = [
+            b"<?xml version=\"1.0\"?><KeyFile><Meta><Version>2.0</Version></Meta>\
+              <Key><Data>0123 4567 89AB CDEF 0123 4567 89AB CDEF \
+              0123 4567 89AB CDEF 0123 4567 89AB CDEF</Data></Key></KeyFile>",
+            &[7; 32],
+            b"any other file",
+        ];
+
+        for keyfile in keyfiles {
+            let data = new.save(Some("contractor"), Some(keyfile)).unwrap();
+
+            let key = keepass::DatabaseKey::new()
+                .with_password("contractor")
+                .with_keyfile(&mut &keyfile[..])
+                .unwrap();
+            let reopened = KpDatabase::open(&mut &data[..], key.clone()).unwrap();
+            let attachments = Attachments::read(&data, key, &reopened).unwrap();
+
+            let mail = reopened.root.groups()[0].entries()[0];
+            assert_eq!(attachments.get(mail, "notes.txt"), Some(&b"notes"[..]));
+        }
+    }
+}
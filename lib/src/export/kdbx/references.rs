@@ -0,0 +1,489 @@
+//! References from entries to the binaries of a KDBX 4 file
+//!
+//! The keepass crate writes the binaries of a database into the inner header of a KDBX 4 file,
+//! but no references from the entries to them. They are added to the XML document of the saved
+//! file, which is decrypted with its key and encrypted again with the same seeds.
+
+use std::io::{Read, Write};
+
+use aes::Aes256;
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use keepass::config::{CompressionConfig, DatabaseConfig, KdfConfig, OuterCipherConfig};
+use sha2::{Digest, Sha256, Sha512};
+use twofish::Twofish;
+
+/// Fields of the outer header
+const HEADER_END: u8 = 0;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_ENCRYPTION_IV: u8 = 7;
+const HEADER_KDF_PARAMS: u8 = 11;
+
+/// Size of the signature and version that the outer header starts with
+const SIGNATURE_SIZE: usize = 12;
+
+/// Size of the blocks that the encrypted payload is split into
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Attachments of an entry, by their names and the indices of their binaries
+pub type EntryReferences = Vec<(String, usize)>;
+
+/// The parts of the outer header that are needed to derive the keys of a file
+struct OuterHeader {
+    /// length of the header, which is followed by its SHA-256 hash and HMAC
+    len: usize,
+
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf_seed: Vec<u8>,
+}
+
+/// The keys of a file, derived from its composite key and the seeds in its header
+struct Keys {
+    master: [u8; 32],
+    hmac: [u8; 64],
+}
+
+/// Add references to the binaries of a saved file to its entries
+///
+/// The references are given for every entry, including older versions, in the order in which
+/// the entries appear in the XML document.
+pub fn add_references(
+    data: &[u8],
+    config: &DatabaseConfig,
+    password: Option<&str>,
+    keyfile: Option<&[u8]>,
+    references: &[EntryReferences],
+) -> Result<Vec<u8>> {
+    let header = OuterHeader::parse(data)?;
+    let keys = Keys::derive(&header, config, &composite_key(password, keyfile))?;
+
+    let header_data = &data[..header.len];
+    let header_hmac = &data[header.len + 32..header.len + 64];
+    if block_hmac(&keys.hmac, u64::MAX, header_data)[..] != header_hmac[..] {
+        bail!("The key of the saved database could not be derived");
+    }
+
+    let payload = decrypt(
+        &config.outer_cipher_config,
+        &keys.master,
+        &header.iv,
+        &read_blocks(&data[header.len + 64..])?,
+    )?;
+
+    let payload = match config.compression_config {
+        CompressionConfig::None => payload,
+        CompressionConfig::GZip => {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(&payload[..]).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+    };
+
+    let xml_start = inner_header_len(&payload)?;
+    let xml = std::str::from_utf8(&payload[xml_start..]).context("Decoding the database XML")?;
+
+    let mut payload = payload[..xml_start].to_vec();
+    payload.extend_from_slice(insert_references(xml, references)?.as_bytes());
+
+    let payload = match config.compression_config {
+        CompressionConfig::None => payload,
+        CompressionConfig::GZip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&payload)?;
+            encoder.finish()?
+        }
+    };
+
+    let encrypted = encrypt(
+        &config.outer_cipher_config,
+        &keys.master,
+        &header.iv,
+        &payload,
+    )?;
+
+    let mut out = data[..header.len + 64].to_vec();
+    write_blocks(&keys.hmac, &encrypted, &mut out);
+
+    Ok(out)
+}
+
+impl OuterHeader {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut pos = SIGNATURE_SIZE;
+        let mut master_seed = None;
+        let mut iv = None;
+        let mut kdf_seed = None;
+
+        loop {
+            let (id, value) = read_field(data, &mut pos)?;
+
+            match id {
+                HEADER_END => break,
+                HEADER_MASTER_SEED => master_seed = Some(value.to_vec()),
+                HEADER_ENCRYPTION_IV => iv = Some(value.to_vec()),
+                HEADER_KDF_PARAMS => kdf_seed = Some(kdf_seed_of(value)?),
+                _ => {}
+            }
+        }
+
+        if data.len() < pos + 64 {
+            bail!("The saved database is truncated");
+        }
+
+        Ok(Self {
+            len: pos,
+            master_seed: master_seed.ok_or(anyhow!("The saved database has no master seed"))?,
+            iv: iv.ok_or(anyhow!("The saved database has no encryption IV"))?,
+            kdf_seed: kdf_seed.ok_or(anyhow!("The saved database has no KDF seed"))?,
+        })
+    }
+}
+
+/// Read a field of the outer or inner header, which has a one-byte ID and a 32-bit length
+fn read_field<'a>(data: &'a [u8], pos: &mut usize) -> Result<(u8, &'a [u8])> {
+    let truncated = || anyhow!("The header of the saved database is truncated");
+
+    let id = *data.get(*pos).ok_or_else(truncated)?;
+    let len = data
+        .get(*pos + 1..*pos + 5)
+        .ok_or_else(truncated)?
+        .try_into()
+        .map(u32::from_le_bytes)? as usize;
+    let value = data.get(*pos + 5..*pos + 5 + len).ok_or_else(truncated)?;
+
+    *pos += 5 + len;
+
+    Ok((id, value))
+}
+
+/// Find the seed of the KDF in its parameters, which are a variant dictionary
+fn kdf_seed_of(params: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || anyhow!("The KDF parameters of the saved database are truncated");
+
+    // after the version of the dictionary, each item has a type, a name and a value
+    let mut pos = 2;
+    while *params.get(pos).ok_or_else(truncated)? != 0 {
+        let read_len = |pos: usize| -> Result<usize> {
+            Ok(params
+                .get(pos..pos + 4)
+                .ok_or_else(truncated)?
+                .try_into()
+                .map(u32::from_le_bytes)? as usize)
+        };
+
+        let name_len = read_len(pos + 1)?;
+        let name = params
+            .get(pos + 5..pos + 5 + name_len)
+            .ok_or_else(truncated)?;
+        let value_len = read_len(pos + 5 + name_len)?;
+        let value_start = pos + 9 + name_len;
+        let value = params
+            .get(value_start..value_start + value_len)
+            .ok_or_else(truncated)?;
+
+        if name == b"S" {
+            return Ok(value.to_vec());
+        }
+
+        pos = value_start + value_len;
+    }
+
+    bail!("The KDF parameters of the saved database have no seed")
+}
+
+impl Keys {
+    fn derive(header: &OuterHeader, config: &DatabaseConfig, composite: &[u8; 32]) -> Result<Self> {
+        let transformed = transform_key(&config.kdf_config, &header.kdf_seed, composite)?;
+
+        let master = Sha256::new()
+            .chain_update(&header.master_seed)
+            .chain_update(transformed)
+            .finalize()
+            .into();
+
+        let hmac = Sha512::new()
+            .chain_update(&header.master_seed)
+            .chain_update(transformed)
+            .chain_update([1])
+            .finalize()
+            .into();
+
+        Ok(Self { master, hmac })
+    }
+}
+
+/// Hash the password and the key file like the keepass crate does
+fn composite_key(password: Option<&str>, keyfile: Option<&[u8]>) -> [u8; 32] {
+    let mut composite = Sha256::new();
+
+    if let Some(password) = password {
+        composite.update(Sha256::digest(password.as_bytes()));
+    }
+
+    if let Some(keyfile) = keyfile {
+        composite.update(keyfile_key(keyfile));
+    }
+
+    composite.finalize().into()
+}
+
+/// The key contributed by a key file, which is the key in an XML key file, a 32-byte key, or the
+/// hash of the whole file
+fn keyfile_key(data: &[u8]) -> Vec<u8> {
+    if let Some(key) = xml_keyfile_key(data) {
+        return key;
+    }
+
+    if data.len() == 32 {
+        return data.to_vec();
+    }
+
+    Sha256::digest(data).to_vec()
+}
+
+fn xml_keyfile_key(data: &[u8]) -> Option<Vec<u8>> {
+    let xml = std::str::from_utf8(data).ok()?;
+    let document = roxmltree::Document::parse(xml).ok()?;
+
+    let root = document.root_element();
+    if !root.has_tag_name("KeyFile") {
+        return None;
+    }
+
+    let version = child(root, "Meta")
+        .and_then(|meta| child(meta, "Version"))
+        .and_then(|version| version.text());
+    let key = child(root, "Key")
+        .and_then(|key| child(key, "Data"))
+        .and_then(|data| data.text())?;
+
+    if version == Some("2.0") {
+        let hex: String = key.chars().filter(|c| !c.is_whitespace()).collect();
+        return Some(
+            data_encoding::HEXLOWER_PERMISSIVE
+                .decode(hex.as_bytes())
+                .unwrap_or_else(|_| key.as_bytes().to_vec()),
+        );
+    }
+
+    Some(
+        STANDARD
+            .decode(key)
+            .unwrap_or_else(|_| key.as_bytes().to_vec()),
+    )
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    tag: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+/// Transform the composite key with the KDF of the database
+fn transform_key(kdf: &KdfConfig, seed: &[u8], composite: &[u8; 32]) -> Result<[u8; 32]> {
+    let (algorithm, memory, iterations, parallelism, version) = match kdf {
+        KdfConfig::Aes { rounds } => {
+            use aes::cipher::{BlockEncrypt, KeyInit};
+
+            let cipher = Aes256::new_from_slice(seed).map_err(|e| anyhow!("{}", e))?;
+            let mut transformed = *composite;
+
+            for _ in 0..*rounds {
+                for block in transformed.chunks_exact_mut(16) {
+                    cipher.encrypt_block(block.into());
+                }
+            }
+
+            return Ok(Sha256::digest(transformed).into());
+        }
+        KdfConfig::Argon2 {
+            memory,
+            iterations,
+            parallelism,
+            version,
+        } => (
+            argon2::Algorithm::Argon2d,
+            memory,
+            iterations,
+            parallelism,
+            version,
+        ),
+        KdfConfig::Argon2id {
+            memory,
+            iterations,
+            parallelism,
+            version,
+        } => (
+            argon2::Algorithm::Argon2id,
+            memory,
+            iterations,
+            parallelism,
+            version,
+        ),
+    };
+
+    let version = argon2::Version::try_from(version.as_u32()).map_err(|e| anyhow!("{}", e))?;
+    let params = argon2::Params::new(
+        (memory / 1024).try_into()?,
+        (*iterations).try_into()?,
+        *parallelism,
+        Some(32),
+    )
+    .map_err(|e| anyhow!("{}", e))?;
+
+    let mut transformed = [0; 32];
+    argon2::Argon2::new(algorithm, version, params)
+        .hash_password_into(composite, seed, &mut transformed)
+        .map_err(|e| anyhow!("{}", e))?;
+
+    Ok(transformed)
+}
+
+fn decrypt(cipher: &OuterCipherConfig, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |_| anyhow!("The saved database cannot be decrypted");
+
+    match cipher {
+        OuterCipherConfig::AES256 => cbc::Decryptor::<Aes256>::new_from_slices(key, iv)?
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(invalid),
+        OuterCipherConfig::Twofish => cbc::Decryptor::<Twofish>::new_from_slices(key, iv)?
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(invalid),
+        OuterCipherConfig::ChaCha20 => {
+            let mut data = data.to_vec();
+            chacha20::ChaCha20::new_from_slices(key, iv)?.apply_keystream(&mut data);
+            Ok(data)
+        }
+    }
+}
+
+fn encrypt(cipher: &OuterCipherConfig, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    match cipher {
+        OuterCipherConfig::AES256 => Ok(cbc::Encryptor::<Aes256>::new_from_slices(key, iv)?
+            .encrypt_padded_vec_mut::<Pkcs7>(data)),
+        OuterCipherConfig::Twofish => Ok(cbc::Encryptor::<Twofish>::new_from_slices(key, iv)?
+            .encrypt_padded_vec_mut::<Pkcs7>(data)),
+        OuterCipherConfig::ChaCha20 => {
+            let mut data = data.to_vec();
+            chacha20::ChaCha20::new_from_slices(key, iv)?.apply_keystream(&mut data);
+            Ok(data)
+        }
+    }
+}
+
+/// The HMAC of a block of the payload, or of the header with the index `u64::MAX`
+fn block_hmac(hmac_key: &[u8; 64], index: u64, data: &[u8]) -> [u8; 32] {
+    let key = Sha512::new()
+        .chain_update(index.to_le_bytes())
+        .chain_update(hmac_key)
+        .finalize();
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Join the blocks of the encrypted payload, which the keepass crate has verified already
+fn read_blocks(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+
+    loop {
+        let len = data
+            .get(32..36)
+            .ok_or(anyhow!("The payload of the saved database is truncated"))?
+            .try_into()
+            .map(u32::from_le_bytes)? as usize;
+        if len == 0 {
+            return Ok(payload);
+        }
+
+        payload.extend_from_slice(
+            data.get(36..36 + len)
+                .ok_or(anyhow!("The payload of the saved database is truncated"))?,
+        );
+        data = &data[36 + len..];
+    }
+}
+
+/// Split the encrypted payload into blocks with their HMACs, ending with an empty block
+fn write_blocks(hmac_key: &[u8; 64], data: &[u8], out: &mut Vec<u8>) {
+    let blocks = data.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+
+    for (index, block) in blocks.enumerate() {
+        let len = (block.len() as u32).to_le_bytes();
+
+        let mut hmac_data = Vec::with_capacity(12 + block.len());
+        hmac_data.extend_from_slice(&(index as u64).to_le_bytes());
+        hmac_data.extend_from_slice(&len);
+        hmac_data.extend_from_slice(block);
+
+        out.extend_from_slice(&block_hmac(hmac_key, index as u64, &hmac_data));
+        out.extend_from_slice(&len);
+        out.extend_from_slice(block);
+    }
+}
+
+/// The length of the inner header, which is followed by the XML document
+fn inner_header_len(payload: &[u8]) -> Result<usize> {
+    let mut pos = 0;
+    while read_field(payload, &mut pos)?.0 != HEADER_END {}
+
+    Ok(pos)
+}
+
+/// Add the references to the entries of an XML document, after their UUIDs
+fn insert_references(xml: &str, references: &[EntryReferences]) -> Result<String> {
+    let document = roxmltree::Document::parse(xml).context("Parsing the database XML")?;
+    let entries = document
+        .descendants()
+        .filter(|n| n.has_tag_name("Entry"))
+        .collect::<Vec<_>>();
+
+    if entries.len() != references.len() {
+        bail!("The saved database does not contain the exported entries");
+    }
+
+    let mut out = String::with_capacity(xml.len());
+    let mut copied = 0;
+
+    for (entry, references) in entries.into_iter().zip(references) {
+        if references.is_empty() {
+            continue;
+        }
+
+        let uuid = entry
+            .children()
+            .find(|n| n.has_tag_name("UUID"))
+            .ok_or(anyhow!("An entry of the saved database has no UUID"))?;
+
+        let end = uuid.range().end;
+        out.push_str(&xml[copied..end]);
+        copied = end;
+
+        for (name, index) in references {
+            out.push_str(&format!(
+                "<Binary><Key>{}</Key><Value Ref=\"{}\"/></Binary>",
+                escape(name),
+                index
+            ));
+        }
+    }
+
+    out.push_str(&xml[copied..]);
+
+    Ok(out)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
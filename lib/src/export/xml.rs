@@ -0,0 +1,493 @@
+//! Export to the unencrypted XML document of KeePass 2.x
+//!
+//! This is the document that KeePass stores inside of KDBX files, with protected values in plain
+//! text and marked with `ProtectInMemory` so that they are protected again when importing the
+//! file. The whole metadata is written, and attachments are stored once in the gzip-compressed
+//! binary pool of the metadata. Deleted objects are only written when exporting the root group.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::NaiveDateTime;
+use keepass::db::{
+    CustomData, Database as KpDatabase, Entry as KpEntry, Group as KpGroup, NodeRef, Times,
+    Value as KpValue,
+};
+use uuid::Uuid;
+
+/// Attachments by their content, each stored once in the metadata
+struct Binaries<'a> {
+    ids: HashMap<&'a [u8], usize>,
+    data: Vec<&'a [u8]>,
+}
+
+impl<'a> Binaries<'a> {
+    /// Collect the attachments of the entries below a group, including their history
+    fn collect(group: &'a KpGroup) -> Self {
+        let mut binaries = Binaries {
+            ids: HashMap::new(),
+            data: Vec::new(),
+        };
+
+        for node in group.iter() {
+            let NodeRef::Entry(entry) = node else {
+                continue;
+            };
+
+            let history = entry.history.iter().flat_map(|h| h.get_entries());
+
+            for entry in std::iter::once(entry).chain(history) {
+                for value in entry.fields.values() {
+                    if let KpValue::Bytes(data) = value
+                        && !binaries.ids.contains_key(&data[..])
+                    {
+                        binaries.ids.insert(data.as_slice(), binaries.data.len());
+                        binaries.data.push(data);
+                    }
+                }
+            }
+        }
+
+        binaries
+    }
+}
+
+/// Writes indented XML elements
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push('\t');
+        }
+    }
+
+    fn start(&mut self, tag: &str) {
+        self.indent();
+        let _ = writeln!(self.out, "<{}>", tag);
+        self.depth += 1;
+    }
+
+    fn end(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.indent();
+        let _ = writeln!(self.out, "</{}>", tag);
+    }
+
+    /// Write an element with attributes and text content
+    fn element(&mut self, tag: &str, attributes: &[(&str, &str)], text: &str) {
+        self.indent();
+        let _ = write!(self.out, "<{}", tag);
+        for (name, value) in attributes {
+            let _ = write!(self.out, " {}=\"{}\"", name, escape(value));
+        }
+
+        if text.is_empty() {
+            self.out.push_str(" />\n");
+        } else {
+            let _ = writeln!(self.out, ">{}</{}>", escape(text), tag);
+        }
+    }
+
+    fn text(&mut self, tag: &str, text: &str) {
+        self.element(tag, &[], text);
+    }
+
+    fn optional(&mut self, tag: &str, value: Option<impl ToString>) {
+        if let Some(value) = value {
+            self.text(tag, &value.to_string());
+        }
+    }
+
+    fn bool(&mut self, tag: &str, value: bool) {
+        self.text(tag, if value { "True" } else { "False" });
+    }
+
+    fn uuid(&mut self, tag: &str, uuid: Option<Uuid>) {
+        if let Some(uuid) = uuid {
+            self.text(tag, &STANDARD.encode(uuid.as_bytes()));
+        }
+    }
+
+    fn time(&mut self, tag: &str, time: Option<&NaiveDateTime>) {
+        if let Some(time) = time {
+            self.text(tag, &super::format_time(time));
+        }
+    }
+}
+
+/// Escape text for element content and attribute values, dropping characters that XML forbids
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Export a group with its subgroups and entries as a KeePass 2.x XML document
+pub fn export(database: &KpDatabase, group: &KpGroup, include_protected: bool) -> Result<Vec<u8>> {
+    let binaries = Binaries::collect(group);
+
+    let mut w = XmlWriter {
+        out: String::from("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n"),
+        depth: 0,
+    };
+
+    w.start("KeePassFile");
+    write_meta(&mut w, database, &binaries, include_protected)?;
+
+    w.start("Root");
+    write_group(&mut w, group, &binaries, include_protected);
+
+    if group.uuid == database.root.uuid {
+        w.start("DeletedObjects");
+        for object in &database.deleted_objects.objects {
+            w.start("DeletedObject");
+            w.uuid("UUID", Some(object.uuid));
+            w.time("DeletionTime", Some(&object.deletion_time));
+            w.end("DeletedObject");
+        }
+        w.end("DeletedObjects");
+    }
+
+    w.end("Root");
+    w.end("KeePassFile");
+
+    Ok(w.out.into_bytes())
+}
+
+fn write_meta(
+    w: &mut XmlWriter,
+    database: &KpDatabase,
+    binaries: &Binaries,
+    include_protected: bool,
+) -> Result<()> {
+    let m = &database.meta;
+
+    w.start("Meta");
+    w.optional("Generator", m.generator.as_ref());
+    w.optional("DatabaseName", m.database_name.as_ref());
+    w.time("DatabaseNameChanged", m.database_name_changed.as_ref());
+    w.optional("DatabaseDescription", m.database_description.as_ref());
+    w.time(
+        "DatabaseDescriptionChanged",
+        m.database_description_changed.as_ref(),
+    );
+    w.optional("DefaultUserName", m.default_username.as_ref());
+    w.time(
+        "DefaultUserNameChanged",
+        m.default_username_changed.as_ref(),
+    );
+    w.optional("MaintenanceHistoryDays", m.maintenance_history_days);
+    w.optional("Color", m.color.as_ref());
+    w.time("MasterKeyChanged", m.master_key_changed.as_ref());
+    w.optional("MasterKeyChangeRec", m.master_key_change_rec);
+    w.optional("MasterKeyChangeForce", m.master_key_change_force);
+
+    if let Some(protection) = &m.memory_protection {
+        w.start("MemoryProtection");
+        w.bool("ProtectTitle", protection.protect_title);
+        w.bool("ProtectUserName", protection.protect_username);
+        w.bool("ProtectPassword", protection.protect_password);
+        w.bool("ProtectURL", protection.protect_url);
+        w.bool("ProtectNotes", protection.protect_notes);
+        w.end("MemoryProtection");
+    }
+
+    if !m.custom_icons.icons.is_empty() {
+        w.start("CustomIcons");
+        for icon in &m.custom_icons.icons {
+            w.start("Icon");
+            w.uuid("UUID", Some(icon.uuid));
+            w.text("Data", &STANDARD.encode(&icon.data));
+            w.end("Icon");
+        }
+        w.end("CustomIcons");
+    }
+
+    if let Some(enabled) = m.recyclebin_enabled {
+        w.bool("RecycleBinEnabled", enabled);
+    }
+    w.uuid("RecycleBinUUID", m.recyclebin_uuid);
+    w.time("RecycleBinChanged", m.recyclebin_changed.as_ref());
+    w.uuid("EntryTemplatesGroup", m.entry_templates_group);
+    w.time(
+        "EntryTemplatesGroupChanged",
+        m.entry_templates_group_changed.as_ref(),
+    );
+    w.uuid("LastSelectedGroup", m.last_selected_group);
+    w.uuid("LastTopVisibleGroup", m.last_top_visible_group);
+    w.optional("HistoryMaxItems", m.history_max_items);
+    w.optional("HistoryMaxSize", m.history_max_size);
+    w.time("SettingsChanged", m.settings_changed.as_ref());
+
+    if !binaries.data.is_empty() {
+        w.start("Binaries");
+        for (id, data) in binaries.data.iter().enumerate() {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            encoder.write_all(data)?;
+
+            w.element(
+                "Binary",
+                &[("ID", &id.to_string()), ("Compressed", "True")],
+                &STANDARD.encode(encoder.finish()?),
+            );
+        }
+        w.end("Binaries");
+    }
+
+    write_custom_data(w, &m.custom_data, include_protected);
+    w.end("Meta");
+
+    Ok(())
+}
+
+fn write_custom_data(w: &mut XmlWriter, custom_data: &CustomData, include_protected: bool) {
+    if custom_data.items.is_empty() {
+        return;
+    }
+
+    let mut items: Vec<_> = custom_data.items.iter().collect();
+    items.sort_by_key(|(key, _)| *key);
+
+    w.start("CustomData");
+    for (key, item) in items {
+        w.start("Item");
+        w.text("Key", key);
+        w.optional(
+            "Value",
+            item.value
+                .as_ref()
+                .and_then(|v| super::text_value(v, include_protected)),
+        );
+        w.time("LastModificationTime", item.last_modification_time.as_ref());
+        w.end("Item");
+    }
+    w.end("CustomData");
+}
+
+fn write_times(w: &mut XmlWriter, times: &Times) {
+    w.start("Times");
+    w.time("CreationTime", times.get_creation());
+    w.time("LastModificationTime", times.get_last_modification());
+    w.time("LastAccessTime", times.get_last_access());
+    w.time("ExpiryTime", times.get_expiry());
+    w.bool("Expires", times.expires);
+    w.text("UsageCount", &times.usage_count.to_string());
+    w.time("LocationChanged", times.get_location_changed());
+    w.end("Times");
+}
+
+fn write_group(w: &mut XmlWriter, group: &KpGroup, binaries: &Binaries, include_protected: bool) {
+    w.start("Group");
+    w.uuid("UUID", Some(group.uuid));
+    w.text("Name", &group.name);
+    w.optional("Notes", group.notes.as_ref());
+    w.optional("IconID", group.icon_id);
+    w.uuid("CustomIconUUID", group.custom_icon_uuid);
+    write_times(w, &group.times);
+    w.bool("IsExpanded", group.is_expanded);
+    w.optional(
+        "DefaultAutoTypeSequence",
+        group.default_autotype_sequence.as_ref(),
+    );
+    w.optional("EnableAutoType", group.enable_autotype.as_ref());
+    w.optional("EnableSearching", group.enable_searching.as_ref());
+    w.uuid("LastTopVisibleEntry", group.last_top_visible_entry);
+    write_custom_data(w, &group.custom_data, include_protected);
+
+    for entry in group.entries() {
+        write_entry(w, entry, binaries, include_protected, true);
+    }
+
+    for child in group.groups() {
+        write_group(w, child, binaries, include_protected);
+    }
+
+    w.end("Group");
+}
+
+fn write_entry(
+    w: &mut XmlWriter,
+    entry: &KpEntry,
+    binaries: &Binaries,
+    include_protected: bool,
+    with_history: bool,
+) {
+    w.start("Entry");
+    w.uuid("UUID", Some(entry.uuid));
+    w.optional("IconID", entry.icon_id);
+    w.uuid("CustomIconUUID", entry.custom_icon_uuid);
+    w.optional("ForegroundColor", entry.foreground_color.as_ref());
+    w.optional("BackgroundColor", entry.background_color.as_ref());
+    w.optional("OverrideURL", entry.override_url.as_ref());
+    if let Some(quality_check) = entry.quality_check {
+        w.bool("QualityCheck", quality_check);
+    }
+    if !entry.tags.is_empty() {
+        w.text("Tags", &entry.tags.join(";"));
+    }
+    write_times(w, &entry.times);
+
+    let mut fields: Vec<_> = entry.fields.iter().collect();
+    fields.sort_by_key(|(name, _)| *name);
+
+    for (name, value) in &fields {
+        let attributes: &[(&str, &str)] = match value {
+            KpValue::Bytes(..) => continue,
+            KpValue::Protected(..) => &[("ProtectInMemory", "True")],
+            KpValue::Unprotected(..) => &[],
+        };
+
+        w.start("String");
+        w.text("Key", name);
+        w.element(
+            "Value",
+            attributes,
+            &super::text_value(value, include_protected).unwrap_or_default(),
+        );
+        w.end("String");
+    }
+
+    for (name, value) in &fields {
+        if let KpValue::Bytes(data) = value {
+            w.start("Binary");
+            w.text("Key", name);
+            w.element(
+                "Value",
+                &[("Ref", &binaries.ids[&data[..]].to_string())],
+                "",
+            );
+            w.end("Binary");
+        }
+    }
+
+    if let Some(autotype) = &entry.autotype {
+        w.start("AutoType");
+        w.bool("Enabled", autotype.enabled);
+        w.optional("DefaultSequence", autotype.sequence.as_ref());
+        for association in &autotype.associations {
+            w.start("Association");
+            w.optional("Window", association.window.as_ref());
+            w.optional("KeystrokeSequence", association.sequence.as_ref());
+            w.end("Association");
+        }
+        w.end("AutoType");
+    }
+
+    write_custom_data(w, &entry.custom_data, include_protected);
+
+    if with_history && let Some(history) = &entry.history {
+        w.start("History");
+        for old in history.get_entries() {
+            write_entry(w, old, binaries, include_protected, false);
+        }
+        w.end("History");
+    }
+
+    w.end("Entry");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::{History, Icon, Node as KpNode};
+
+    fn database() -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database.meta.database_name = Some("Passwords <Home>".to_string());
+        database.meta.custom_icons.icons.push(Icon {
+            uuid: Uuid::from_bytes([1; 16]),
+            data: b"\x89PNG".to_vec(),
+        });
+
+        let mut old = crate::import::new_entry("Mail", "alice", "first", "", "");
+        old.uuid = Uuid::from_bytes([3; 16]);
+
+        let mut mail = crate::import::new_entry("Mail & Co", "alice", "new \"secret\"", "", "");
+        mail.uuid = Uuid::from_bytes([3; 16]);
+        mail.tags = vec!["work".to_string(), "mail".to_string()];
+        mail.custom_icon_uuid = Some(Uuid::from_bytes([1; 16]));
+        mail.fields.insert(
+            "key.bin".to_string(),
+            KpValue::Bytes(vec![0, 159, 146, 150]),
+        );
+        let mut history = History::default();
+        history.add_entry(old);
+        mail.history = Some(history);
+
+        let mut internet = KpGroup::new("Internet");
+        internet.children.push(KpNode::Entry(mail));
+        database.root.children.push(KpNode::Group(internet));
+
+        database
+    }
+
+    #[test]
+    fn test_export_xml() {
+        let database = database();
+        let data = export(&database, &database.root, true).unwrap();
+
+        let (imported, warnings) = crate::import::keepass_xml::import(&data).unwrap();
+        assert_eq!(
+            imported.meta.database_name.as_deref(),
+            Some("Passwords <Home>")
+        );
+        assert_eq!(imported.meta.custom_icons.icons.len(), 1);
+        assert_eq!(imported.root.uuid, database.root.uuid);
+
+        let mail = imported.root.groups()[0].entries()[0];
+        assert_eq!(mail.uuid, Uuid::from_bytes([3; 16]));
+        assert_eq!(mail.get_title(), Some("Mail & Co"));
+        assert_eq!(mail.get_password(), Some("new \"secret\""));
+        assert!(matches!(
+            mail.fields.get("Password"),
+            Some(KpValue::Protected(..))
+        ));
+        assert_eq!(mail.tags, ["work", "mail"]);
+
+        // the binary attachment is exported, but left out when importing as it cannot be saved
+        assert!(!mail.fields.contains_key("key.bin"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("key.bin"));
+
+        let history = mail.history.as_ref().unwrap().get_entries();
+        assert_eq!(history[0].get_password(), Some("first"));
+    }
+
+    #[test]
+    fn test_exclude_protected() {
+        let database = database();
+        let data = export(&database, database.root.groups()[0], false).unwrap();
+
+        let text = String::from_utf8(data.clone()).unwrap();
+        assert!(!text.contains("secret"));
+        assert!(!text.contains("first"));
+        assert!(!text.contains("DeletedObjects"));
+
+        let (imported, _) = crate::import::keepass_xml::import(&data).unwrap();
+        assert_eq!(imported.root.name, "Internet");
+        assert_eq!(imported.root.entries()[0].get_username(), Some("alice"));
+        assert_eq!(imported.root.entries()[0].get_password(), Some(""));
+    }
+}
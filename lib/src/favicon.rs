@@ -0,0 +1,533 @@
+//! Downloading of website icons for entries
+//!
+//! Fetching is abstracted over the [`FaviconFetcher`] trait so that the platform can decide how
+//! HTTP requests are made. Downloaded icons are converted into standard custom icons.
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use url::Url;
+
+/// Key in the database's custom data that enables favicon downloads
+pub const FAVICON_DOWNLOAD_KEY: &str = "OmniKee/FaviconDownload";
+
+/// Maximum number of bytes that are accepted for a page or an icon
+pub const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// A response to a HTTP GET request
+pub struct FetchResponse {
+    /// the HTTP status code
+    pub status: u16,
+
+    /// the final URL of the response, after following redirects
+    pub url: Url,
+
+    /// the value of the Content-Type header, if any
+    pub content_type: Option<String>,
+
+    /// the response body
+    pub body: Vec<u8>,
+}
+
+/// A way to perform HTTP GET requests for favicon downloads
+pub trait FaviconFetcher {
+    /// Fetch a URL, following redirects
+    fn get(&self, url: &Url) -> Result<FetchResponse>;
+}
+
+/// Check whether favicon downloads are enabled for a database
+pub fn is_enabled(database: &keepass::Database) -> bool {
+    crate::database::is_option_enabled(database, FAVICON_DOWNLOAD_KEY)
+}
+
+/// Enable or disable favicon downloads for a database
+pub fn set_enabled(database: &mut keepass::Database, enabled: bool) {
+    crate::database::set_option_enabled(database, FAVICON_DOWNLOAD_KEY, enabled)
+}
+
+/// Parse the URL of an entry into a URL that can be used to find a favicon
+///
+/// Like in other KeePass clients, URLs without a scheme are assumed to be HTTPS.
+pub fn parse_entry_url(url: &str) -> Result<Url> {
+    let url = url.trim();
+
+    let url = if url.contains("://") {
+        Url::parse(url)?
+    } else {
+        Url::parse(&format!("https://{url}"))?
+    };
+
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        bail!("Cannot download icons for URL {}", url)
+    }
+
+    Ok(url)
+}
+
+/// Download the favicon for a page and convert it into a custom icon
+///
+/// Icons referenced by `<link rel="icon">` tags of the page are preferred, falling back to
+/// `/favicon.ico` at the root of the site. Returns `None` if no icon could be found.
+pub fn fetch_favicon<F: FaviconFetcher + ?Sized>(
+    fetcher: &F,
+    page_url: &str,
+) -> Result<Option<Vec<u8>>> {
+    let page_url = parse_entry_url(page_url)?;
+
+    let mut candidates = Vec::new();
+
+    if let Ok(page) = fetcher.get(&page_url) {
+        let is_html = page
+            .content_type
+            .as_deref()
+            .map(|ct| ct.contains("html"))
+            .unwrap_or(true);
+
+        if is_success(page.status) && is_html {
+            let html =
+                String::from_utf8_lossy(&page.body[..page.body.len().min(MAX_RESPONSE_SIZE)]);
+            candidates.extend(find_icon_links(&html, &page.url));
+        }
+    }
+
+    let mut root = page_url.clone();
+    root.set_path("/favicon.ico");
+    root.set_query(None);
+    root.set_fragment(None);
+    candidates.push(root);
+
+    for candidate in candidates {
+        let data = if candidate.scheme() == "data" {
+            match decode_data_url(candidate.as_str()) {
+                Some(data) => data,
+                None => continue,
+            }
+        } else {
+            match fetcher.get(&candidate) {
+                Ok(res) if is_success(res.status) && res.body.len() <= MAX_RESPONSE_SIZE => {
+                    res.body
+                }
+                _ => continue,
+            }
+        };
+
+        if data.is_empty() {
+            continue;
+        }
+
+        if let Ok(icon) = crate::icon::convert_icon(&data) {
+            return Ok(Some(icon));
+        }
+    }
+
+    Ok(None)
+}
+
+fn is_success(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
+/// Find icon URLs referenced by `<link>` tags in a HTML document, best candidates first
+pub fn find_icon_links(html: &str, base: &Url) -> Vec<Url> {
+    let lower = html.to_ascii_lowercase();
+    let mut found: Vec<(u8, Url)> = Vec::new();
+
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<link") {
+        let start = pos + start;
+        let Some(end) = lower[start..].find('>') else {
+            break;
+        };
+        let end = start + end;
+        pos = end;
+
+        let attributes = parse_attributes(&html[start + "<link".len()..end]);
+
+        let Some(rel) = attributes.iter().find(|(k, _)| k == "rel").map(|(_, v)| v) else {
+            continue;
+        };
+        let Some(href) = attributes.iter().find(|(k, _)| k == "href").map(|(_, v)| v) else {
+            continue;
+        };
+
+        let rels: Vec<String> = rel
+            .split_ascii_whitespace()
+            .map(|r| r.to_ascii_lowercase())
+            .collect();
+
+        let priority = if rels.iter().any(|r| r == "icon") {
+            0
+        } else if rels.iter().any(|r| r == "apple-touch-icon") {
+            1
+        } else {
+            continue;
+        };
+
+        if let Ok(url) = base.join(href.trim()) {
+            found.push((priority, url));
+        }
+    }
+
+    // stable sort keeps document order within the same priority
+    found.sort_by_key(|(priority, _)| *priority);
+    found.into_iter().map(|(_, url)| url).collect()
+}
+
+/// Parse the attributes of a HTML tag into lowercase names and unquoted values
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut chars = tag.trim_end_matches('/').chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let name: String =
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != '=')).collect();
+
+        if name.is_empty() {
+            break;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let value = if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            match chars.next_if(|c| *c == '"' || *c == '\'') {
+                Some(quote) => {
+                    let value: String =
+                        std::iter::from_fn(|| chars.next_if(|c| *c != quote)).collect();
+                    chars.next();
+                    value
+                }
+                None => std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+            }
+        } else {
+            String::new()
+        };
+
+        out.push((name.to_ascii_lowercase(), value));
+    }
+
+    out
+}
+
+/// Decode an inline `data:` URL
+fn decode_data_url(url: &str) -> Option<Vec<u8>> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+
+    if header.ends_with(";base64") {
+        base64::engine::general_purpose::STANDARD
+            .decode(data.trim())
+            .ok()
+    } else {
+        Some(percent_encoding::percent_decode_str(data).collect::<Vec<u8>>())
+    }
+}
+
+/// A [`FaviconFetcher`] that uses a native HTTP client
+#[cfg(feature = "tauri")]
+pub struct HttpFaviconFetcher {
+    client: reqwest::blocking::Client,
+}
+
+#[cfg(feature = "tauri")]
+impl HttpFaviconFetcher {
+    pub fn new() -> Result<Self> {
+        use anyhow::Context;
+
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(concat!("OmniKee/", env!("CARGO_PKG_VERSION")))
+            .timeout(std::time::Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::limited(5))
+            .build()
+            .context("Creating HTTP client")?;
+
+        Ok(Self { client })
+    }
+}
+
+#[cfg(feature = "tauri")]
+impl FaviconFetcher for HttpFaviconFetcher {
+    fn get(&self, url: &Url) -> Result<FetchResponse> {
+        use anyhow::Context;
+        use std::io::Read;
+
+        let res = self.client.get(url.clone()).send()?;
+
+        let status = res.status().as_u16();
+        let url = res.url().clone();
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+
+        let mut body = Vec::new();
+        res.take(MAX_RESPONSE_SIZE as u64 + 1)
+            .read_to_end(&mut body)
+            .context("Reading response")?;
+
+        Ok(FetchResponse {
+            status,
+            url,
+            content_type,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use keepass::db::{Database as KpDatabase, Entry as KpEntry, Node, Value as KpValue};
+
+    use super::*;
+    use crate::AppState;
+    use crate::database::Database;
+
+    /// A stand-in for a web server, answering with canned responses and 404 otherwise
+    #[derive(Default)]
+    struct StubFetcher {
+        responses: HashMap<String, (u16, &'static str, Vec<u8>)>,
+        requested: RefCell<Vec<String>>,
+    }
+
+    impl StubFetcher {
+        fn with(mut self, url: &str, content_type: &'static str, body: &[u8]) -> Self {
+            self.responses
+                .insert(url.to_string(), (200, content_type, body.to_vec()));
+            self
+        }
+
+        fn requested(&self) -> Vec<String> {
+            self.requested.borrow().clone()
+        }
+    }
+
+    impl FaviconFetcher for StubFetcher {
+        fn get(&self, url: &Url) -> Result<FetchResponse> {
+            self.requested.borrow_mut().push(url.to_string());
+
+            let (status, content_type, body) = self
+                .responses
+                .get(url.as_str())
+                .cloned()
+                .unwrap_or((404, "text/plain", b"not found".to_vec()));
+
+            Ok(FetchResponse {
+                status,
+                url: url.clone(),
+                content_type: Some(content_type.to_string()),
+                body,
+            })
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let mut out = Vec::new();
+        image::RgbaImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut out), image::ImageFormat::Png)
+            .unwrap();
+        out
+    }
+
+    fn links(html: &str, base: &str) -> Vec<String> {
+        find_icon_links(html, &Url::parse(base).unwrap())
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn finds_icon_links() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <LINK REL="apple-touch-icon" HREF="/touch.png">
+            <link rel="shortcut icon" href='img/shortcut.ico'>
+            <link href=/icon.png rel=icon />
+            <link rel="icon">
+        </head></html>"#;
+
+        assert_eq!(
+            links(html, "https://example.com/app/login"),
+            vec![
+                "https://example.com/app/img/shortcut.ico",
+                "https://example.com/icon.png",
+                "https://example.com/touch.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_against_final_url() {
+        assert_eq!(
+            links(
+                r#"<link rel="icon" href="//cdn.example.net/icon.png">"#,
+                "http://example.com/"
+            ),
+            vec!["http://cdn.example.net/icon.png"]
+        );
+        assert_eq!(
+            links(
+                r#"<link rel="icon" href="https://other.example/icon.png">"#,
+                "https://example.com/"
+            ),
+            vec!["https://other.example/icon.png"]
+        );
+    }
+
+    #[test]
+    fn prefers_linked_icon() {
+        let fetcher = StubFetcher::default()
+            .with(
+                "https://example.com/login",
+                "text/html; charset=utf-8",
+                br#"<link rel="icon" href="/static/icon.png">"#,
+            )
+            .with("https://example.com/static/icon.png", "image/png", &png())
+            .with("https://example.com/favicon.ico", "image/png", &png());
+
+        let icon = fetch_favicon(&fetcher, "example.com/login").unwrap();
+
+        assert!(icon.is_some());
+        assert_eq!(
+            fetcher.requested(),
+            vec![
+                "https://example.com/login",
+                "https://example.com/static/icon.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_favicon_ico() {
+        let fetcher = StubFetcher::default()
+            .with(
+                "https://example.com/?q=1",
+                "text/html",
+                b"<html>no icons here</html>",
+            )
+            .with("https://example.com/favicon.ico", "image/x-icon", &png());
+
+        let icon = fetch_favicon(&fetcher, "https://example.com/?q=1#top").unwrap();
+
+        assert!(icon.is_some());
+        assert_eq!(
+            fetcher.requested().last().unwrap(),
+            "https://example.com/favicon.ico"
+        );
+    }
+
+    #[test]
+    fn decodes_data_urls() {
+        use base64::Engine;
+
+        let data_url = format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(png())
+        );
+        let html = format!(r#"<link rel="icon" href="{data_url}">"#);
+
+        let fetcher =
+            StubFetcher::default().with("https://example.com/", "text/html", html.as_bytes());
+
+        assert!(fetch_favicon(&fetcher, "example.com").unwrap().is_some());
+        assert_eq!(fetcher.requested(), vec!["https://example.com/"]);
+
+        assert_eq!(decode_data_url("data:,a%20b"), Some(b"a b".to_vec()));
+        assert_eq!(decode_data_url("data:image/png;base64,!!!"), None);
+    }
+
+    #[test]
+    fn skips_non_image_responses() {
+        let fetcher = StubFetcher::default()
+            .with(
+                "https://example.com/",
+                "text/html",
+                br#"<link rel="icon" href="/icon.png">"#,
+            )
+            .with(
+                "https://example.com/icon.png",
+                "text/html",
+                b"<html>soft 404</html>",
+            )
+            .with("https://example.com/favicon.ico", "image/x-icon", &png());
+
+        assert!(fetch_favicon(&fetcher, "example.com").unwrap().is_some());
+        assert_eq!(fetcher.requested().len(), 3);
+
+        let fetcher = StubFetcher::default()
+            .with("https://example.com/", "text/html", b"")
+            .with("https://example.com/favicon.ico", "text/plain", b"nope");
+
+        assert_eq!(fetch_favicon(&fetcher, "example.com").unwrap(), None);
+    }
+
+    #[test]
+    fn ignores_links_of_non_html_pages() {
+        let fetcher = StubFetcher::default().with(
+            "https://example.com/",
+            "application/json",
+            br#"{"x": "<link rel=icon href=/icon.png>"}"#,
+        );
+
+        assert_eq!(fetch_favicon(&fetcher, "example.com").unwrap(), None);
+        assert_eq!(
+            fetcher.requested(),
+            vec!["https://example.com/", "https://example.com/favicon.ico"]
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        assert!(parse_entry_url("ftp://example.com").is_err());
+        assert!(parse_entry_url("cmd://calc.exe").is_err());
+        assert_eq!(
+            parse_entry_url(" example.com/x ").unwrap().as_str(),
+            "https://example.com/x"
+        );
+    }
+
+    #[test]
+    fn opt_in_is_per_database() {
+        let database = || {
+            let mut database = KpDatabase::new(Default::default());
+            let mut entry = KpEntry::new();
+            entry.fields.insert(
+                "URL".to_string(),
+                KpValue::Unprotected("https://example.com".to_string()),
+            );
+            database.root.children.push(Node::Entry(entry));
+
+            Database::unlocked(database)
+        };
+
+        let mut state = AppState::default();
+        state.databases.push(database());
+        state.databases.push(database());
+
+        assert!(!state.get_favicon_download(0).unwrap());
+        assert!(state.list_favicon_requests(0, Vec::new()).is_err());
+
+        state.set_favicon_download(1, true).unwrap();
+
+        assert!(!state.get_favicon_download(0).unwrap());
+        assert!(state.get_favicon_download(1).unwrap());
+        assert!(state.list_favicon_requests(0, Vec::new()).is_err());
+        let requests = state.list_favicon_requests(1, Vec::new()).unwrap();
+        assert_eq!(requests.len(), 1);
+        let entry_uuid = requests[0].entry_uuid.to_string();
+
+        state.set_favicon_download(1, false).unwrap();
+
+        assert!(state.list_favicon_requests(1, Vec::new()).is_err());
+
+        // an icon that finished downloading after opting out is not stored
+        assert!(state.set_entry_favicon(1, &entry_uuid, png()).is_err());
+        let database = state.databases[1].get_database().unwrap();
+        assert!(database.meta.custom_icons.icons.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+//! Random password generation
+
+use anyhow::{Result, bail};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Characters that are easily confused with each other
+const AMBIGUOUS: &str = "Il1O0|`'\"";
+
+/// Which characters a generated password may contain
+#[derive(Debug, Clone)]
+pub struct PasswordOptions {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+
+    /// leave out characters like `l`, `1` and `I` that are hard to tell apart
+    pub exclude_ambiguous: bool,
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: false,
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl PasswordOptions {
+    /// The character classes to draw from
+    fn classes(&self) -> Vec<Vec<char>> {
+        [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| {
+            chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect()
+        })
+        .collect()
+    }
+
+    /// Estimated strength of passwords generated with these options, in bits
+    pub fn entropy_bits(&self) -> f64 {
+        let pool: usize = self.classes().iter().map(Vec::len).sum();
+
+        if pool == 0 {
+            return 0.0;
+        }
+
+        self.length as f64 * (pool as f64).log2()
+    }
+}
+
+/// Generate a random password that contains at least one character of every enabled class
+pub fn generate(options: &PasswordOptions) -> Result<String> {
+    let classes = options.classes();
+
+    if classes.is_empty() {
+        bail!("At least one character class must be enabled");
+    }
+
+    if options.length < classes.len() {
+        bail!(
+            "A password needs at least {} characters to contain every character class",
+            classes.len()
+        );
+    }
+
+    let pool: Vec<char> = classes.iter().flatten().copied().collect();
+
+    let mut out: Vec<char> = Vec::with_capacity(options.length);
+
+    for class in classes.iter() {
+        out.push(class[random_below(class.len())?]);
+    }
+
+    while out.len() < options.length {
+        out.push(pool[random_below(pool.len())?]);
+    }
+
+    // Fisher-Yates shuffle so that the guaranteed characters are not always at the start
+    for i in (1..out.len()).rev() {
+        out.swap(i, random_below(i + 1)?);
+    }
+
+    Ok(out.into_iter().collect())
+}
+
+/// Get a uniformly distributed random number in `0..n`
+fn random_below(n: usize) -> Result<usize> {
+    let n = n as u32;
+
+    // reject values from the incomplete last block to avoid modulo bias
+    let limit = u32::MAX - u32::MAX % n;
+
+    loop {
+        let value = getrandom::u32().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        if value < limit {
+            return Ok((value % n) as usize);
+        }
+    }
+}
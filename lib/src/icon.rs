@@ -1,6 +1,18 @@
-use base64::Engine;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+
+use anyhow::{Context, Result, bail};
+use keepass::db::{
+    Group as KpGroup, History as KpHistory, Icon as KpIcon, Node as KpNode, NodeRef,
+};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use crate::exchange::{CustomIcon, Icon, IconSet};
+
+/// Width and height that imported custom icons are scaled to
+pub const CUSTOM_ICON_SIZE: u32 = 64;
+
 pub const KEEPASS_ICONS_TO_MDI: [&'static str; 69] = [
     "mdi-key",
     "mdi-earth",
@@ -73,25 +85,589 @@ pub const KEEPASS_ICONS_TO_MDI: [&'static str; 69] = [
     "mdi-monitor-cellphone",
 ];
 
+/// Resolve the icon of a node into a reference that the frontend can display
+///
+/// Custom icons are only referenced by their UUID so that the (potentially large) image data does
+/// not need to be sent along with every group and entry. Use [`get_custom_icon`] to get the data.
 pub fn get_icon(
     database: &keepass::Database,
     uuid: Option<&Uuid>,
     index: Option<usize>,
-) -> Option<String> {
+) -> Option<Icon> {
     if let Some(ci) = uuid {
-        database.meta.custom_icons.icons.iter().find_map(|icon| {
-            if &icon.uuid == ci {
-                let engine = base64::engine::general_purpose::STANDARD;
-                let encoded = engine.encode(&icon.data);
-
-                Some(format!("data:image/png;base64,{encoded}"))
-            } else {
-                None
-            }
-        })
+        get_custom_icon(database, ci).map(|_| Icon::Custom { uuid: *ci })
     } else if let Some(i) = index {
-        KEEPASS_ICONS_TO_MDI.get(i).map(|v| v.to_string())
+        KEEPASS_ICONS_TO_MDI.get(i).map(|v| Icon::Builtin {
+            id: i,
+            name: v.to_string(),
+        })
     } else {
         None
     }
 }
+
+/// Get the image data of a custom icon by its UUID
+pub fn get_custom_icon<'a>(database: &'a keepass::Database, uuid: &Uuid) -> Option<&'a [u8]> {
+    database
+        .meta
+        .custom_icons
+        .icons
+        .iter()
+        .find(|icon| &icon.uuid == uuid)
+        .map(|icon| &icon.data[..])
+}
+
+/// List all custom icons of a database along with how often they are used
+pub fn list_custom_icons(database: &keepass::Database) -> Vec<CustomIcon> {
+    let usage = count_icon_usage(database);
+
+    database
+        .meta
+        .custom_icons
+        .icons
+        .iter()
+        .map(|icon| CustomIcon {
+            uuid: icon.uuid,
+            uses: usage.get(&icon.uuid).copied().unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Validate an icon to set and determine the new standard icon ID and custom icon UUID
+///
+/// The standard icon ID is `None` if the current one should be kept.
+pub fn resolve_icon_set(
+    database: &keepass::Database,
+    icon: IconSet,
+) -> Result<(Option<usize>, Option<Uuid>)> {
+    match icon {
+        IconSet::Builtin { id } => {
+            if id >= KEEPASS_ICONS_TO_MDI.len() {
+                bail!("No standard icon with ID {}", id)
+            }
+
+            Ok((Some(id), None))
+        }
+        IconSet::Custom { uuid } => {
+            if get_custom_icon(database, &uuid).is_none() {
+                bail!("No custom icon by that UUID")
+            }
+
+            // keep the standard icon as a fallback for clients without custom icon support
+            Ok((None, Some(uuid)))
+        }
+    }
+}
+
+/// Import image data as a new custom icon
+///
+/// The image is converted to a PNG of [`CUSTOM_ICON_SIZE`]. If an identical icon already exists in
+/// the database, its UUID is returned instead of adding a duplicate.
+pub fn add_custom_icon(database: &mut keepass::Database, data: &[u8]) -> Result<Uuid> {
+    Ok(add_converted_icon(database, convert_icon(data)?))
+}
+
+/// Add an icon that was already converted with [`convert_icon`], reusing an identical icon
+pub fn add_converted_icon(database: &mut keepass::Database, data: Vec<u8>) -> Uuid {
+    let hash = hash_icon(&data);
+
+    let icons = &mut database.meta.custom_icons.icons;
+
+    if let Some(existing) = icons.iter().find(|icon| hash_icon(&icon.data) == hash) {
+        return existing.uuid;
+    }
+
+    let uuid = Uuid::new_v4();
+    icons.push(KpIcon { uuid, data });
+
+    uuid
+}
+
+/// Replace the image data of an existing custom icon, keeping its UUID
+pub fn replace_custom_icon(
+    database: &mut keepass::Database,
+    uuid: &Uuid,
+    data: &[u8],
+) -> Result<()> {
+    let data = convert_icon(data)?;
+
+    let icon = database
+        .meta
+        .custom_icons
+        .icons
+        .iter_mut()
+        .find(|icon| &icon.uuid == uuid)
+        .context("No custom icon by that UUID")?;
+
+    icon.data = data;
+
+    Ok(())
+}
+
+/// Merge custom icons with identical image data, returning the number of icons removed
+///
+/// Groups, entries and entry history items using a duplicate are changed to use the first icon
+/// with the same data.
+pub fn deduplicate_custom_icons(database: &mut keepass::Database) -> usize {
+    let mut canonical: HashMap<[u8; 32], Uuid> = HashMap::new();
+    let mut replacements: HashMap<Uuid, Uuid> = HashMap::new();
+
+    for icon in database.meta.custom_icons.icons.iter() {
+        let hash = hash_icon(&icon.data);
+
+        if let Some(existing) = canonical.get(&hash) {
+            replacements.insert(icon.uuid, *existing);
+        } else {
+            canonical.insert(hash, icon.uuid);
+        }
+    }
+
+    if replacements.is_empty() {
+        return 0;
+    }
+
+    fn replace(uuid: &mut Option<Uuid>, replacements: &HashMap<Uuid, Uuid>) {
+        if let Some(replacement) = uuid.as_ref().and_then(|uuid| replacements.get(uuid)) {
+            *uuid = Some(*replacement);
+        }
+    }
+
+    fn inner(group: &mut KpGroup, replacements: &HashMap<Uuid, Uuid>) {
+        replace(&mut group.custom_icon_uuid, replacements);
+
+        for node in group.children.iter_mut() {
+            match node {
+                KpNode::Group(group) => inner(group, replacements),
+                KpNode::Entry(entry) => {
+                    replace(&mut entry.custom_icon_uuid, replacements);
+
+                    // history items cannot be changed in place, so rebuild the history
+                    if let Some(history) = entry.history.take() {
+                        let mut rebuilt = KpHistory::default();
+
+                        for mut item in history.get_entries().iter().rev().cloned() {
+                            replace(&mut item.custom_icon_uuid, replacements);
+                            rebuilt.add_entry(item);
+                        }
+
+                        entry.history = Some(rebuilt);
+                    }
+                }
+            }
+        }
+    }
+
+    inner(&mut database.root, &replacements);
+
+    let before = database.meta.custom_icons.icons.len();
+
+    database
+        .meta
+        .custom_icons
+        .icons
+        .retain(|icon| !replacements.contains_key(&icon.uuid));
+
+    before - database.meta.custom_icons.icons.len()
+}
+
+/// Remove custom icons that are not used by any group, entry or entry history item, returning the
+/// number of icons removed
+pub fn purge_unused_custom_icons(database: &mut keepass::Database) -> usize {
+    let used: HashSet<Uuid> = count_icon_usage(database).into_keys().collect();
+    let before = database.meta.custom_icons.icons.len();
+
+    database
+        .meta
+        .custom_icons
+        .icons
+        .retain(|icon| used.contains(&icon.uuid));
+
+    before - database.meta.custom_icons.icons.len()
+}
+
+/// Count how often each custom icon is referenced by groups, entries and entry history items
+fn count_icon_usage(database: &keepass::Database) -> HashMap<Uuid, usize> {
+    let mut usage = HashMap::new();
+
+    let mut count = |uuid: Option<&Uuid>| {
+        if let Some(uuid) = uuid {
+            *usage.entry(*uuid).or_default() += 1;
+        }
+    };
+
+    for node in database.root.iter() {
+        match node {
+            NodeRef::Group(group) => count(group.custom_icon_uuid.as_ref()),
+            NodeRef::Entry(entry) => {
+                count(entry.custom_icon_uuid.as_ref());
+
+                if let Some(history) = &entry.history {
+                    for item in history.get_entries() {
+                        count(item.custom_icon_uuid.as_ref());
+                    }
+                }
+            }
+        }
+    }
+
+    usage
+}
+
+/// Hash icon data to detect duplicates
+fn hash_icon(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Convert PNG, ICO, SVG and other common image formats into a PNG of standard size
+pub fn convert_icon(data: &[u8]) -> Result<Vec<u8>> {
+    if get_mime_type(data) == "image/svg+xml" {
+        return render_svg(data);
+    }
+
+    let image = image::load_from_memory(data).context("Decoding icon image")?;
+    let image = image.resize(
+        CUSTOM_ICON_SIZE,
+        CUSTOM_ICON_SIZE,
+        image::imageops::FilterType::Lanczos3,
+    );
+
+    let mut out = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut out), image::ImageFormat::Png)
+        .context("Encoding icon as PNG")?;
+
+    Ok(out)
+}
+
+/// Render an SVG image into a PNG of standard size
+fn render_svg(data: &[u8]) -> Result<Vec<u8>> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree =
+        usvg::Tree::from_data(data, &usvg::Options::default()).context("Parsing SVG icon")?;
+
+    let size = tree.size();
+    let scale =
+        (CUSTOM_ICON_SIZE as f32 / size.width()).min(CUSTOM_ICON_SIZE as f32 / size.height());
+
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).context("Allocating icon pixmap")?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap.encode_png().context("Encoding icon as PNG")
+}
+
+/// Guess the MIME type of icon data from its magic bytes
+pub fn get_mime_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
+        "image/vnd.microsoft.icon"
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if is_svg(data) {
+        "image/svg+xml"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Check whether data is an SVG document
+///
+/// SVG files may start with a byte order mark, whitespace, an XML declaration, comments or a
+/// doctype, so look for the root element within the first kilobyte instead of at the start.
+fn is_svg(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let head = &data[start..data.len().min(start + 1024)];
+
+    head.starts_with(b"<") && head.windows(4).any(|window| window == b"<svg")
+}
+
+/// Compute an entity tag for icon data so that clients can revalidate their caches
+#[cfg(feature = "tauri")]
+pub fn get_etag(data: &[u8]) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Build a response for the `omnikee-icon://` protocol
+///
+/// Icons are requested as `omnikee-icon://localhost/<database_idx>/<icon_uuid>`. Since database
+/// indices can be reused after closing a database, clients have to revalidate using the ETag.
+#[cfg(feature = "tauri")]
+pub fn protocol_response(
+    state: &crate::AppState,
+    request: &tauri::http::Request<Vec<u8>>,
+) -> tauri::http::Response<Vec<u8>> {
+    use tauri::http::{Response, StatusCode, header};
+
+    let mut segments = request.uri().path().trim_start_matches('/').split('/');
+
+    let (Some(database_idx), Some(icon_uuid), None) =
+        (segments.next(), segments.next(), segments.next())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Vec::new())
+            .unwrap();
+    };
+
+    let Ok(database_idx) = database_idx.parse::<usize>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Vec::new())
+            .unwrap();
+    };
+
+    let data = match state.get_custom_icon(database_idx, icon_uuid) {
+        Ok(data) => data,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(e.into_bytes())
+                .unwrap();
+        }
+    };
+
+    let etag = get_etag(&data);
+
+    let not_modified = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v == etag)
+        .unwrap_or(false);
+
+    let builder = Response::builder()
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::ETAG, &etag);
+
+    if not_modified {
+        builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Vec::new())
+            .unwrap()
+    } else {
+        builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, get_mime_type(&data))
+            .body(data)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keepass::db::Entry as KpEntry;
+
+    use super::*;
+
+    fn png(color: [u8; 4]) -> Vec<u8> {
+        let image = image::RgbaImage::from_pixel(2, 2, image::Rgba(color));
+
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+
+        data
+    }
+
+    fn icon(uuid: Uuid, data: &[u8]) -> KpIcon {
+        KpIcon {
+            uuid,
+            data: data.to_vec(),
+        }
+    }
+
+    fn entry_with_icon(uuid: Uuid, history: &[Uuid]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        entry.custom_icon_uuid = Some(uuid);
+
+        let mut items = KpHistory::default();
+        for uuid in history {
+            let mut item = KpEntry::new();
+            item.custom_icon_uuid = Some(*uuid);
+            items.add_entry(item);
+        }
+        entry.history = Some(items);
+
+        entry
+    }
+
+    fn entries(database: &keepass::Database) -> Vec<&KpEntry> {
+        database
+            .root
+            .iter()
+            .filter_map(|node| match node {
+                NodeRef::Entry(entry) => Some(entry),
+                NodeRef::Group(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adds_icons_as_png_without_duplicates() {
+        let mut database = keepass::Database::new(Default::default());
+
+        let red = add_custom_icon(&mut database, &png([255, 0, 0, 255])).unwrap();
+        let again = add_custom_icon(&mut database, &png([255, 0, 0, 255])).unwrap();
+        let blue = add_custom_icon(&mut database, &png([0, 0, 255, 255])).unwrap();
+
+        assert_eq!(red, again);
+        assert_ne!(red, blue);
+        assert_eq!(database.meta.custom_icons.icons.len(), 2);
+
+        let data = get_custom_icon(&database, &red).unwrap();
+        assert_eq!(get_mime_type(data), "image/png");
+
+        let image = image::load_from_memory(data).unwrap();
+        assert_eq!(image.width(), CUSTOM_ICON_SIZE);
+        assert_eq!(image.height(), CUSTOM_ICON_SIZE);
+
+        assert!(add_custom_icon(&mut database, b"not an image").is_err());
+    }
+
+    #[test]
+    fn replaces_icon_data_in_place() {
+        let mut database = keepass::Database::new(Default::default());
+
+        let uuid = add_custom_icon(&mut database, &png([255, 0, 0, 255])).unwrap();
+        let before = get_custom_icon(&database, &uuid).unwrap().to_vec();
+
+        replace_custom_icon(&mut database, &uuid, &png([0, 255, 0, 255])).unwrap();
+
+        assert_eq!(database.meta.custom_icons.icons.len(), 1);
+        assert_ne!(get_custom_icon(&database, &uuid).unwrap(), &before[..]);
+
+        assert!(replace_custom_icon(&mut database, &Uuid::new_v4(), &before).is_err());
+    }
+
+    #[test]
+    fn deduplicates_references_in_entries_and_history() {
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let mut database = keepass::Database::new(Default::default());
+        database.meta.custom_icons.icons = vec![
+            icon(first, b"same"),
+            icon(second, b"same"),
+            icon(third, b"other"),
+        ];
+
+        let mut group = KpGroup::new("Group");
+        group.custom_icon_uuid = Some(second);
+        group
+            .children
+            .push(KpNode::Entry(entry_with_icon(second, &[second, third])));
+        database.root.children.push(KpNode::Group(group));
+
+        assert_eq!(deduplicate_custom_icons(&mut database), 1);
+
+        let uuids: Vec<Uuid> = database
+            .meta
+            .custom_icons
+            .icons
+            .iter()
+            .map(|icon| icon.uuid)
+            .collect();
+        assert_eq!(uuids, vec![first, third]);
+
+        let entry = entries(&database)[0];
+        assert_eq!(entry.custom_icon_uuid, Some(first));
+
+        let history: Vec<Option<Uuid>> = entry
+            .history
+            .as_ref()
+            .unwrap()
+            .get_entries()
+            .iter()
+            .map(|item| item.custom_icon_uuid)
+            .collect();
+        assert_eq!(history, vec![Some(third), Some(first)]);
+
+        let NodeRef::Group(group) = database.root.iter().nth(1).unwrap() else {
+            panic!("expected the group");
+        };
+        assert_eq!(group.custom_icon_uuid, Some(first));
+
+        assert_eq!(deduplicate_custom_icons(&mut database), 0);
+    }
+
+    #[test]
+    fn purges_icons_unless_used_in_history() {
+        let (used, historic, unused) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let mut database = keepass::Database::new(Default::default());
+        database.meta.custom_icons.icons = vec![
+            icon(used, b"used"),
+            icon(historic, b"historic"),
+            icon(unused, b"unused"),
+        ];
+        database
+            .root
+            .children
+            .push(KpNode::Entry(entry_with_icon(used, &[historic])));
+
+        assert_eq!(purge_unused_custom_icons(&mut database), 1);
+        assert!(get_custom_icon(&database, &used).is_some());
+        assert!(get_custom_icon(&database, &historic).is_some());
+        assert!(get_custom_icon(&database, &unused).is_none());
+
+        let usage: Vec<(Uuid, usize)> = list_custom_icons(&database)
+            .into_iter()
+            .map(|icon| (icon.uuid, icon.uses))
+            .collect();
+        assert_eq!(usage, vec![(used, 1), (historic, 1)]);
+    }
+
+    #[test]
+    fn detects_svg_after_prolog() {
+        assert_eq!(
+            get_mime_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            "image/svg+xml"
+        );
+        assert_eq!(get_mime_type(b"\xef\xbb\xbf<svg/>"), "image/svg+xml");
+        assert_eq!(get_mime_type(b"\n  \t<svg/>"), "image/svg+xml");
+        assert_eq!(
+            get_mime_type(b"<?xml version=\"1.0\"?>\n<!-- icon -->\n<svg/>"),
+            "image/svg+xml"
+        );
+    }
+
+    #[test]
+    fn other_xml_is_not_svg() {
+        assert_eq!(
+            get_mime_type(b"<?xml version=\"1.0\"?><html/>"),
+            "application/octet-stream"
+        );
+        assert_eq!(get_mime_type(b"hello <svg/>"), "application/octet-stream");
+
+        let mut late = b"<?xml version=\"1.0\"?>".to_vec();
+        late.extend(std::iter::repeat_n(b' ', 2048));
+        late.extend(b"<svg/>");
+        assert_eq!(get_mime_type(&late), "application/octet-stream");
+    }
+
+    #[test]
+    fn detects_raster_formats() {
+        assert_eq!(get_mime_type(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(get_mime_type(&[0xff, 0xd8, 0xff, 0xe0]), "image/jpeg");
+        assert_eq!(get_mime_type(b"GIF89a"), "image/gif");
+    }
+}
@@ -0,0 +1,188 @@
+//! Import of entries from other password managers
+//!
+//! Importers add entries to a target group of an unlocked database, creating subgroups for the
+//! folders of the source, and report records they could only import partially. Databases of other
+//! KeePass versions are converted into new databases instead.
+
+pub mod bitwarden;
+pub mod csv;
+pub mod kdb;
+pub mod keepass_xml;
+pub mod lastpass;
+pub mod onepassword;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use keepass::db::{Entry as KpEntry, Group as KpGroup, Node as KpNode, Value as KpValue};
+
+use crate::exchange::ImportSummary;
+use crate::otp::{OtpConfig, TotpConfig, decode_base32};
+
+/// Find the group at a slash-separated path below a group, creating missing groups
+pub(crate) fn group_at_path<'a>(
+    group: &'a mut KpGroup,
+    path: &str,
+    summary: &mut ImportSummary,
+) -> &'a mut KpGroup {
+    let mut group = group;
+
+    for name in path.split('/').map(str::trim).filter(|n| !n.is_empty()) {
+        let position = group
+            .children
+            .iter()
+            .position(|node| matches!(node, KpNode::Group(g) if g.name == name));
+
+        let position = match position {
+            Some(position) => position,
+            None => {
+                group.children.push(KpNode::Group(KpGroup::new(name)));
+                summary.groups += 1;
+                group.children.len() - 1
+            }
+        };
+
+        group = match &mut group.children[position] {
+            KpNode::Group(child) => child,
+            KpNode::Entry(..) => unreachable!(),
+        };
+    }
+
+    group
+}
+
+/// Create an entry with the standard fields, leaving out empty values except for the title
+pub(crate) fn new_entry(
+    title: &str,
+    user_name: &str,
+    password: &str,
+    url: &str,
+    notes: &str,
+) -> KpEntry {
+    let mut entry = KpEntry::new();
+
+    entry
+        .fields
+        .insert("Title".to_string(), KpValue::Unprotected(title.to_string()));
+
+    for (name, value) in [("UserName", user_name), ("URL", url), ("Notes", notes)] {
+        if !value.is_empty() {
+            set_text(&mut entry, name, value);
+        }
+    }
+
+    if !password.is_empty() {
+        set_protected(&mut entry, "Password", password);
+    }
+
+    entry
+}
+
+/// Set an unprotected text field of an entry
+pub(crate) fn set_text(entry: &mut KpEntry, name: &str, value: &str) {
+    entry
+        .fields
+        .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+}
+
+/// Set a protected field of an entry
+pub(crate) fn set_protected(entry: &mut KpEntry, name: &str, value: &str) {
+    entry.fields.insert(
+        name.to_string(),
+        KpValue::Protected(value.as_bytes().into()),
+    );
+}
+
+/// Set up one-time passwords from an `otpauth://` URI, a `steam://` secret as used by Bitwarden or
+/// a Base32-encoded TOTP secret
+pub(crate) fn set_otp(entry: &mut KpEntry, value: &str) -> Result<()> {
+    let value = value.trim();
+
+    let mut config = if value.starts_with("otpauth://") {
+        OtpConfig::from_uri(value)?
+    } else if let Some(secret) = value.strip_prefix("steam://") {
+        let config = TotpConfig::steam(decode_base32(secret)?);
+        config.validate()?;
+        OtpConfig::Totp(config)
+    } else {
+        OtpConfig::Totp(TotpConfig::from_base32(value)?)
+    };
+
+    config.fill_label(entry);
+    config.write_to_entry(entry);
+
+    Ok(())
+}
+
+/// Parse a timestamp in one of the formats commonly found in exports
+///
+/// RFC 3339, `YYYY-MM-DD HH:MM[:SS]`, plain dates and UNIX timestamps in seconds or milliseconds
+/// are understood. Timestamps with an offset are converted to UTC.
+pub(crate) fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(time.naive_utc());
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(time);
+        }
+    }
+
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0);
+    }
+
+    let number = value.parse::<i64>().ok()?;
+
+    // anything beyond the year 5138 in seconds is more likely to be milliseconds
+    let time = if number > 100_000_000_000 {
+        chrono::DateTime::from_timestamp_millis(number)
+    } else {
+        chrono::DateTime::from_timestamp(number, 0)
+    };
+
+    time.map(|t| t.naive_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_at_path_creates_groups_once() {
+        let mut root = KpGroup::new("Root");
+        let mut summary = ImportSummary::default();
+
+        group_at_path(&mut root, "Internet/Mail", &mut summary)
+            .children
+            .push(KpNode::Entry(KpEntry::new()));
+        let group = group_at_path(&mut root, "/Internet/ Mail /", &mut summary);
+
+        assert_eq!(group.name, "Mail");
+        assert_eq!(group.entries().len(), 1);
+        assert_eq!(summary.groups, 2);
+
+        assert_eq!(group_at_path(&mut root, "", &mut summary).name, "Root");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 30, 0)
+            .unwrap();
+
+        assert_eq!(parse_timestamp("2024-03-01T12:30:00Z"), Some(expected));
+        assert_eq!(parse_timestamp("2024-03-01T14:30:00+02:00"), Some(expected));
+        assert_eq!(parse_timestamp("2024-03-01 12:30"), Some(expected));
+        assert_eq!(parse_timestamp("1709296200"), Some(expected));
+        assert_eq!(parse_timestamp("1709296200000"), Some(expected));
+        assert_eq!(
+            parse_timestamp("2024-03-01"),
+            expected.date().and_hms_opt(0, 0, 0)
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
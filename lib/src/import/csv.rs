@@ -0,0 +1,460 @@
+//! Import of CSV files as exported by spreadsheets and many password managers
+//!
+//! The encoding, delimiter and quote character are detected from the data, and a mapping of
+//! columns to fields is suggested from the column names. Both can be adjusted after previewing the
+//! file before importing it.
+
+use anyhow::{Result, anyhow, bail};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use keepass::db::{Entry as KpEntry, Group as KpGroup, Node as KpNode};
+
+use crate::exchange::{CsvCustomField, CsvFormat, CsvMapping, CsvPreview, ImportSummary};
+
+/// Candidate delimiters, in order of preference when they fit equally well
+const DELIMITERS: &[char] = &[',', ';', '\t', '|'];
+
+/// Number of rows used to detect the delimiter
+const SAMPLE_ROWS: usize = 20;
+
+/// Number of rows included in a preview
+const PREVIEW_ROWS: usize = 10;
+
+/// Column names recognized for the standard fields, in lowercase
+const TITLE_NAMES: &[&str] = &["title", "name", "account", "entry", "item"];
+const USER_NAME_NAMES: &[&str] = &[
+    "username",
+    "user name",
+    "user",
+    "login",
+    "login_username",
+    "email",
+    "e-mail",
+];
+const PASSWORD_NAMES: &[&str] = &["password", "pass", "pwd", "login_password"];
+const URL_NAMES: &[&str] = &["url", "website", "web site", "uri", "login_uri", "address"];
+const NOTES_NAMES: &[&str] = &["notes", "note", "comments", "comment", "extra"];
+const GROUP_NAMES: &[&str] = &["group", "folder", "grouping", "category", "path"];
+const OTP_NAMES: &[&str] = &["totp", "otp", "otpauth", "one-time password", "login_totp"];
+const TAGS_NAMES: &[&str] = &["tags", "tag", "labels"];
+const CREATED_NAMES: &[&str] = &["created", "creation time", "created at", "creation date"];
+const MODIFIED_NAMES: &[&str] = &[
+    "last modified",
+    "modified",
+    "modification time",
+    "updated",
+    "last modified at",
+];
+const EXPIRES_NAMES: &[&str] = &["expires", "expiry", "expiry time", "expiration"];
+
+/// Detect the format of a CSV file, or use the given one, and suggest a mapping of its columns
+pub fn preview(data: &[u8], format: Option<CsvFormat>) -> Result<CsvPreview> {
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(data)?,
+    };
+
+    let mut rows = read_rows(data, &format)?;
+
+    let header = match format.has_header && !rows.is_empty() {
+        true => rows.remove(0),
+        false => Vec::new(),
+    };
+
+    let mapping = suggest_mapping(&header);
+    let total_rows = rows.len();
+    rows.truncate(PREVIEW_ROWS);
+
+    Ok(CsvPreview {
+        format,
+        header,
+        rows,
+        total_rows,
+        mapping,
+    })
+}
+
+/// Import the rows of a CSV file as entries into a group
+///
+/// Group paths that start with the name of the database's root group, as exported by KeePassXC,
+/// are taken as relative to the root group.
+pub fn import(
+    group: &mut KpGroup,
+    root_name: &str,
+    data: &[u8],
+    format: &CsvFormat,
+    mapping: &CsvMapping,
+) -> Result<ImportSummary> {
+    let mut rows = read_rows(data, format)?;
+    if format.has_header && !rows.is_empty() {
+        rows.remove(0);
+    }
+
+    let mut summary = ImportSummary::default();
+
+    for (idx, row) in rows.iter().enumerate() {
+        if row.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+
+        let (path, entry) = row_to_entry(row, mapping, idx + 1, &mut summary.warnings);
+
+        let path = match path.split_once('/') {
+            Some((first, rest)) if first == root_name => rest,
+            _ if path == root_name => "",
+            _ => path,
+        };
+
+        super::group_at_path(group, path, &mut summary)
+            .children
+            .push(KpNode::Entry(entry));
+        summary.entries += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Convert a row into an entry and the path of the group to put it into
+fn row_to_entry<'a>(
+    row: &'a [String],
+    mapping: &CsvMapping,
+    row_number: usize,
+    warnings: &mut Vec<String>,
+) -> (&'a str, KpEntry) {
+    let cell = |column: Option<usize>| -> &str {
+        column
+            .and_then(|c| row.get(c))
+            .map(|v| v.as_str())
+            .unwrap_or_default()
+    };
+
+    let mut entry = super::new_entry(
+        cell(mapping.title),
+        cell(mapping.user_name),
+        cell(mapping.password),
+        cell(mapping.url),
+        cell(mapping.notes),
+    );
+
+    for CsvCustomField {
+        column,
+        name,
+        protected,
+    } in &mapping.custom_fields
+    {
+        let value = cell(Some(*column));
+
+        if value.is_empty() || name.is_empty() {
+            continue;
+        }
+
+        match protected {
+            true => super::set_protected(&mut entry, name, value),
+            false => super::set_text(&mut entry, name, value),
+        }
+    }
+
+    let otp = cell(mapping.otp);
+    if !otp.trim().is_empty()
+        && let Err(e) = super::set_otp(&mut entry, otp)
+    {
+        warnings.push(format!("Row {row_number}: invalid one-time password: {e}"));
+    }
+
+    entry.tags = cell(mapping.tags)
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+
+    let times = [
+        ("creation time", mapping.created),
+        ("modification time", mapping.modified),
+        ("expiry time", mapping.expires),
+    ];
+
+    for (name, column) in times {
+        let value = cell(column);
+        if value.trim().is_empty() {
+            continue;
+        }
+
+        let Some(time) = super::parse_timestamp(value) else {
+            warnings.push(format!("Row {row_number}: cannot read {name} {value:?}"));
+            continue;
+        };
+
+        match name {
+            "creation time" => entry.times.set_creation(time),
+            "modification time" => entry.times.set_last_modification(time),
+            _ => {
+                entry.times.set_expiry(time);
+                entry.times.expires = true;
+            }
+        }
+    }
+
+    (cell(mapping.group).trim(), entry)
+}
+
+/// Decode a CSV file and split it into rows of fields
+pub(super) fn read_rows(data: &[u8], format: &CsvFormat) -> Result<Vec<Vec<String>>> {
+    let text = decode(data, &format.encoding)?;
+    let mut reader = reader(&text, format.delimiter, format.quote)?;
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| anyhow!("Cannot read CSV: {}", e))?;
+        rows.push(record.iter().map(String::from).collect());
+    }
+
+    Ok(rows)
+}
+
+fn reader(text: &str, delimiter: char, quote: char) -> Result<::csv::Reader<&[u8]>> {
+    if !delimiter.is_ascii() || !quote.is_ascii() {
+        bail!("The delimiter and quote must be ASCII characters");
+    }
+
+    Ok(::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter as u8)
+        .quote(quote as u8)
+        .from_reader(text.as_bytes()))
+}
+
+/// Decode text in an encoding given by its name, skipping a byte order mark
+fn decode(data: &[u8], encoding: &str) -> Result<String> {
+    let encoding = Encoding::for_label(encoding.as_bytes())
+        .ok_or(anyhow!("Unknown encoding {:?}", encoding))?;
+
+    let (text, _) = encoding.decode_with_bom_removal(data);
+
+    Ok(text.into_owned())
+}
+
+/// Detect the encoding, delimiter, quote character and header of a CSV file
+pub(crate) fn detect_format(data: &[u8]) -> Result<CsvFormat> {
+    let encoding = detect_encoding(data);
+    let text = decode(data, encoding.name())?;
+
+    let quote = detect_quote(&text);
+    let delimiter = detect_delimiter(&text, quote);
+
+    let has_header = reader(&text, delimiter, quote)?
+        .records()
+        .next()
+        .and_then(|record| record.ok())
+        .map(|record| {
+            let header: Vec<String> = record.iter().map(String::from).collect();
+
+            // a header names at least one standard field, and all of its columns
+            let mut mapping = suggest_mapping(&header);
+            mapping.custom_fields.clear();
+
+            mapping != CsvMapping::default() && header.iter().all(|name| !name.trim().is_empty())
+        })
+        .unwrap_or_default();
+
+    Ok(CsvFormat {
+        delimiter,
+        quote,
+        encoding: encoding.name().to_string(),
+        has_header,
+    })
+}
+
+/// Detect the encoding from a byte order mark, falling back to Windows-1252 for text that is not
+/// valid UTF-8
+fn detect_encoding(data: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(data) {
+        return encoding;
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(..) => UTF_8,
+        Err(..) => WINDOWS_1252,
+    }
+}
+
+/// Use single quotes if fields start with them more often than with double quotes
+fn detect_quote(text: &str) -> char {
+    let starts = |quote: char| {
+        let mut previous = '\n';
+        let mut count = 0;
+
+        for c in text.chars() {
+            if c == quote && (previous == '\n' || DELIMITERS.contains(&previous)) {
+                count += 1;
+            }
+            previous = c;
+        }
+
+        count
+    };
+
+    match starts('\'') > starts('"') {
+        true => '\'',
+        false => '"',
+    }
+}
+
+/// Find the delimiter that splits the first rows into the same number of fields most consistently
+fn detect_delimiter(text: &str, quote: char) -> char {
+    let mut best = (DELIMITERS[0], 0, 0);
+
+    for &delimiter in DELIMITERS {
+        let Ok(mut reader) = reader(text, delimiter, quote) else {
+            continue;
+        };
+
+        let lengths: Vec<usize> = reader
+            .records()
+            .take(SAMPLE_ROWS)
+            .filter_map(|record| record.ok())
+            .map(|record| record.len())
+            .collect();
+
+        // the most common number of fields, and how many rows have it
+        let (columns, rows) = lengths
+            .iter()
+            .map(|&len| (len, lengths.iter().filter(|&&l| l == len).count()))
+            .max_by_key(|&(len, rows)| (rows, len))
+            .unwrap_or_default();
+
+        if columns > 1 && (rows, columns) > (best.1, best.2) {
+            best = (delimiter, rows, columns);
+        }
+    }
+
+    best.0
+}
+
+/// Suggest which columns go to which fields from their names
+///
+/// Columns with names that are not recognized are suggested as custom fields.
+fn suggest_mapping(header: &[String]) -> CsvMapping {
+    let mut mapping = CsvMapping::default();
+
+    for (column, name) in header.iter().enumerate() {
+        let normalized = name.trim().to_lowercase();
+        let known: [(&[&str], &mut Option<usize>); 11] = [
+            (TITLE_NAMES, &mut mapping.title),
+            (USER_NAME_NAMES, &mut mapping.user_name),
+            (PASSWORD_NAMES, &mut mapping.password),
+            (URL_NAMES, &mut mapping.url),
+            (NOTES_NAMES, &mut mapping.notes),
+            (GROUP_NAMES, &mut mapping.group),
+            (OTP_NAMES, &mut mapping.otp),
+            (TAGS_NAMES, &mut mapping.tags),
+            (CREATED_NAMES, &mut mapping.created),
+            (MODIFIED_NAMES, &mut mapping.modified),
+            (EXPIRES_NAMES, &mut mapping.expires),
+        ];
+
+        let field = known
+            .into_iter()
+            .find(|(names, field)| field.is_none() && names.contains(&normalized.as_str()));
+
+        match field {
+            Some((_, field)) => *field = Some(column),
+            None if !normalized.is_empty() => mapping.custom_fields.push(CsvCustomField {
+                column,
+                name: name.trim().to_string(),
+                protected: false,
+            }),
+            None => {}
+        }
+    }
+
+    mapping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_format_and_mapping() {
+        let data = "Name;Login;Password;Website;Folder;PIN\n\
+                    Mail;alice;\"se;cret\";https://mail.example.com;Internet/Mail;1234\n\
+                    Bank;bob;hunter2;https://bank.example.com;;\n";
+
+        let preview = preview(data.as_bytes(), None).unwrap();
+        assert_eq!(preview.format.delimiter, ';');
+        assert_eq!(preview.format.quote, '"');
+        assert_eq!(preview.format.encoding, "UTF-8");
+        assert!(preview.format.has_header);
+
+        assert_eq!(preview.total_rows, 2);
+        assert_eq!(preview.rows[0][2], "se;cret");
+
+        let mapping = preview.mapping;
+        assert_eq!(mapping.title, Some(0));
+        assert_eq!(mapping.user_name, Some(1));
+        assert_eq!(mapping.password, Some(2));
+        assert_eq!(mapping.url, Some(3));
+        assert_eq!(mapping.group, Some(4));
+        assert_eq!(mapping.custom_fields[0].name, "PIN");
+    }
+
+    #[test]
+    fn test_detects_encoding_without_header() {
+        let mut data = vec![0xef, 0xbb, 0xbf];
+        data.extend("'Caf\u{e9}','x'\n'Bar','y'\n".as_bytes());
+
+        let format = detect_format(&data).unwrap();
+        assert_eq!(format.encoding, "UTF-8");
+        assert_eq!(format.quote, '\'');
+        assert!(!format.has_header);
+        assert_eq!(read_rows(&data, &format).unwrap()[0][0], "Caf\u{e9}");
+
+        let latin1 = b"Title\tPassword\nCaf\xe9\tx\n";
+        let format = detect_format(latin1).unwrap();
+        assert_eq!(format.encoding, "windows-1252");
+        assert_eq!(format.delimiter, '\t');
+        assert_eq!(read_rows(latin1, &format).unwrap()[1][0], "Caf\u{e9}");
+    }
+
+    #[test]
+    fn test_import_creates_groups_and_fields() {
+        let data = "Group,Title,Username,Password,URL,Notes,TOTP,Tags,Last Modified,PIN\n\
+                    Root/Internet/Mail,Work,alice,secret,https://mail.example.com,,JBSWY3DPEHPK3PXP,\"work, mail\",2024-03-01 12:30,1234\n\
+                    ,Bank,bob,hunter2,,,not base32!,,sometime,\n\
+                    ,,,,,,,,,\n";
+
+        let format = detect_format(data.as_bytes()).unwrap();
+        let mut mapping = preview(data.as_bytes(), None).unwrap().mapping;
+        mapping.custom_fields[0].protected = true;
+
+        let mut root = KpGroup::new("Root");
+        let summary = import(&mut root, "Root", data.as_bytes(), &format, &mapping).unwrap();
+
+        assert_eq!(summary.entries, 2);
+        assert_eq!(summary.groups, 2);
+        assert_eq!(summary.warnings.len(), 2);
+        assert!(summary.warnings[0].starts_with("Row 2"));
+
+        let bank = root.entries()[0];
+        assert_eq!(bank.get_title(), Some("Bank"));
+        assert_eq!(bank.get_password(), Some("hunter2"));
+
+        let KpNode::Group(internet) = &root.children[0] else {
+            panic!("expected a group");
+        };
+        let work = internet.groups()[0].entries()[0];
+
+        assert_eq!(work.get_username(), Some("alice"));
+        assert_eq!(work.tags, ["work", "mail"]);
+        assert!(work.get_raw_otp_value().unwrap().starts_with("otpauth://"));
+        assert!(matches!(
+            work.fields.get("PIN"),
+            Some(keepass::db::Value::Protected(..))
+        ));
+        assert_eq!(
+            work.times.get_last_modification(),
+            super::super::parse_timestamp("2024-03-01 12:30").as_ref()
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! Import of KeePass 1.x databases (`.kdb`)
+//!
+//! The payload is encrypted with AES-256 or Twofish in CBC mode, with a key derived from the
+//! password and key file by repeatedly encrypting it with AES-256 in ECB mode and hashing it with
+//! SHA-256. It holds a flat list of groups, where each group's nesting is given by a level, and a
+//! list of entries referencing their group by ID. Meta-stream entries, which KeePass 1.x uses to
+//! store application state, are left out.
+
+use aes::Aes256;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit};
+use anyhow::{Context, Result, anyhow, bail};
+use cbc::cipher::block_padding::Pkcs7;
+use chrono::NaiveDateTime;
+use encoding_rs::WINDOWS_1252;
+use keepass::db::{
+    Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node as KpNode, Times,
+    Value as KpValue,
+};
+use sha2::{Digest, Sha256};
+use twofish::Twofish;
+use uuid::Uuid;
+
+/// File signatures of KeePass 1.x databases
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB65;
+
+/// Supported file version, ignoring the lowest byte
+const VERSION: u32 = 0x0003_0004;
+const VERSION_MASK: u32 = 0xFFFF_FF00;
+
+/// Flags selecting the cipher of the payload
+const FLAG_RIJNDAEL: u32 = 2;
+const FLAG_TWOFISH: u32 = 8;
+
+const HEADER_SIZE: usize = 124;
+
+/// Field type that ends a group or entry
+const FIELD_END: u16 = 0xFFFF;
+
+/// Expiry time that KeePass 1.x uses for items that never expire
+const NEVER_EXPIRES: (i32, u32, u32) = (2999, 12, 28);
+
+struct Header {
+    flags: u32,
+    master_seed: [u8; 16],
+    iv: [u8; 16],
+    num_groups: u32,
+    num_entries: u32,
+    contents_hash: [u8; 32],
+    transform_seed: [u8; 32],
+    transform_rounds: u32,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE {
+            bail!("The file is too short to be a KeePass 1.x database");
+        }
+
+        let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+
+        if u32_at(0) != SIGNATURE_1 || u32_at(4) != SIGNATURE_2 {
+            bail!("The file is not a KeePass 1.x database");
+        }
+
+        if u32_at(12) & VERSION_MASK != VERSION & VERSION_MASK {
+            bail!("Unsupported KeePass 1.x file version {:#x}", u32_at(12));
+        }
+
+        Ok(Self {
+            flags: u32_at(8),
+            master_seed: data[16..32].try_into()?,
+            iv: data[32..48].try_into()?,
+            num_groups: u32_at(48),
+            num_entries: u32_at(52),
+            contents_hash: data[56..88].try_into()?,
+            transform_seed: data[88..120].try_into()?,
+            transform_rounds: u32_at(120),
+        })
+    }
+}
+
+/// Decrypt a KeePass 1.x database and convert it into a database with the given name
+///
+/// KeePass 1.x hashed passwords in the Windows ANSI code page, so Windows-1252 is tried before
+/// UTF-8 for passwords that are not plain ASCII. Attachments that cannot be saved are left out,
+/// which is reported in the returned warnings.
+pub fn import(
+    name: &str,
+    data: &[u8],
+    password: Option<&str>,
+    keyfile: Option<&[u8]>,
+) -> Result<(KpDatabase, Vec<String>)> {
+    let header = Header::parse(data)?;
+
+    let mut passwords: Vec<Vec<u8>> = Vec::new();
+    if let Some(password) = password {
+        let (ansi, _, unmappable) = WINDOWS_1252.encode(password);
+        if !unmappable {
+            passwords.push(ansi.into_owned());
+        }
+        passwords.push(password.as_bytes().to_vec());
+        passwords.dedup();
+    }
+
+    let keyfile = keyfile.map(keyfile_key);
+
+    let payload = match (&passwords[..], keyfile) {
+        ([], None) => bail!("A password or key file is required"),
+        ([], Some(keyfile)) => decrypt(&header, &data[HEADER_SIZE..], &keyfile),
+        (passwords, keyfile) => passwords
+            .iter()
+            .find_map(|password| {
+                let mut key: [u8; 32] = Sha256::digest(password).into();
+                if let Some(keyfile) = keyfile {
+                    key = Sha256::new()
+                        .chain_update(key)
+                        .chain_update(keyfile)
+                        .finalize()
+                        .into();
+                }
+
+                decrypt(&header, &data[HEADER_SIZE..], &key).ok()
+            })
+            .ok_or(anyhow!("Incorrect password or key file")),
+    }?;
+
+    let mut reader = &payload[..];
+    let groups = (0..header.num_groups)
+        .map(|_| read_group(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+    let entries = (0..header.num_entries)
+        .map(|_| read_entry(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut warnings = Vec::new();
+    let root = build_tree(name, groups, entries, &mut warnings)?;
+
+    let mut database = KpDatabase::new(Default::default());
+    database.root = root;
+    database.meta.database_name = Some(name.to_string());
+
+    Ok((database, warnings))
+}
+
+/// Derive the key contributed by a key file, which is either a raw or hex-encoded 32-byte key or
+/// hashed as a whole
+fn keyfile_key(data: &[u8]) -> [u8; 32] {
+    if let Ok(key) = data.try_into() {
+        return key;
+    }
+
+    if data.len() == 64
+        && let Some(key) = std::str::from_utf8(data).ok().and_then(|hex| {
+            data_encoding::HEXLOWER_PERMISSIVE
+                .decode(hex.as_bytes())
+                .ok()
+        })
+    {
+        return key.try_into().unwrap();
+    }
+
+    Sha256::digest(data).into()
+}
+
+/// Decrypt the payload with a composite key, verifying it against the hash in the header
+fn decrypt(header: &Header, data: &[u8], composite_key: &[u8; 32]) -> Result<Vec<u8>> {
+    let cipher = Aes256::new(&header.transform_seed.into());
+    let mut transformed = *composite_key;
+
+    for _ in 0..header.transform_rounds {
+        for block in transformed.chunks_exact_mut(16) {
+            cipher.encrypt_block(block.into());
+        }
+    }
+
+    let transformed = Sha256::digest(transformed);
+    let master_key = Sha256::new()
+        .chain_update(header.master_seed)
+        .chain_update(transformed)
+        .finalize();
+
+    let payload = if header.flags & FLAG_RIJNDAEL != 0 {
+        cbc::Decryptor::<Aes256>::new(&master_key, &header.iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+    } else if header.flags & FLAG_TWOFISH != 0 {
+        cbc::Decryptor::<Twofish>::new_from_slices(&master_key, &header.iv)
+            .map_err(|e| anyhow!("{}", e))?
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+    } else {
+        bail!("Unsupported cipher in KeePass 1.x database");
+    }
+    .map_err(|_| anyhow!("Incorrect password or key file"))?;
+
+    if Sha256::digest(&payload)[..] != header.contents_hash {
+        bail!("Incorrect password or key file");
+    }
+
+    Ok(payload)
+}
+
+/// Read the next field of a group or entry
+fn read_field<'a>(data: &mut &'a [u8]) -> Result<(u16, &'a [u8])> {
+    if data.len() < 6 {
+        bail!("Unexpected end of KeePass 1.x database");
+    }
+
+    let field_type = u16::from_le_bytes([data[0], data[1]]);
+    let size = u32::from_le_bytes(data[2..6].try_into()?) as usize;
+
+    let value = data
+        .get(6..6 + size)
+        .ok_or(anyhow!("Unexpected end of KeePass 1.x database"))?;
+    *data = &data[6 + size..];
+
+    Ok((field_type, value))
+}
+
+fn read_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn read_u32(value: &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(
+        value.try_into().context("Invalid field length")?,
+    ))
+}
+
+/// Unpack a time stored in 5 bytes, returning `None` for the time of items that never expire
+fn read_time(value: &[u8]) -> Result<Option<NaiveDateTime>> {
+    let [b0, b1, b2, b3, b4] = value.try_into().context("Invalid time field")?;
+    let [b0, b1, b2, b3, b4] = [b0, b1, b2, b3, b4].map(u32::from);
+
+    let year = (b0 << 6) | (b1 >> 2);
+    let month = ((b1 & 0x03) << 2) | (b2 >> 6);
+    let day = (b2 >> 1) & 0x1F;
+    let hour = ((b2 & 0x01) << 4) | (b3 >> 4);
+    let minute = ((b3 & 0x0F) << 2) | (b4 >> 6);
+    let second = b4 & 0x3F;
+
+    if (year as i32, month, day) == NEVER_EXPIRES {
+        return Ok(None);
+    }
+
+    Ok(chrono::NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|date| date.and_hms_opt(hour, minute, second)))
+}
+
+/// Set a time field of a group or entry, where field types are relative to the creation time
+fn set_time(times: &mut Times, offset: u16, value: &[u8]) -> Result<()> {
+    let time = read_time(value)?;
+
+    match (offset, time) {
+        (0, Some(time)) => times.set_creation(time),
+        (1, Some(time)) => times.set_last_modification(time),
+        (2, Some(time)) => times.set_last_access(time),
+        (3, Some(time)) => {
+            times.set_expiry(time);
+            times.expires = true;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+struct KdbGroup {
+    id: u32,
+    level: u16,
+    group: KpGroup,
+}
+
+fn read_group(data: &mut &[u8]) -> Result<KdbGroup> {
+    let mut id = None;
+    let mut level = 0;
+    let mut group = KpGroup::new("");
+
+    loop {
+        let (field_type, value) = read_field(data)?;
+
+        match field_type {
+            0x0001 => id = Some(read_u32(value)?),
+            0x0002 => group.name = read_string(value),
+            0x0003..=0x0006 => set_time(&mut group.times, field_type - 0x0003, value)?,
+            0x0007 => group.icon_id = Some(read_u32(value)? as usize),
+            0x0008 => level = u16::from_le_bytes(value.try_into().context("Invalid group level")?),
+            FIELD_END => break,
+            // comments and flags
+            _ => {}
+        }
+    }
+
+    Ok(KdbGroup {
+        id: id.ok_or(anyhow!("Group without an ID in KeePass 1.x database"))?,
+        level,
+        group,
+    })
+}
+
+fn read_entry(data: &mut &[u8]) -> Result<(u32, KpEntry)> {
+    let mut group_id = None;
+    let mut entry = KpEntry::new();
+    let mut binary_name = String::new();
+    let mut binary_data = Vec::new();
+
+    loop {
+        let (field_type, value) = read_field(data)?;
+
+        let name = match field_type {
+            0x0004 => "Title",
+            0x0005 => "URL",
+            0x0006 => "UserName",
+            0x0008 => "Notes",
+            _ => "",
+        };
+
+        match field_type {
+            0x0001 => entry.uuid = Uuid::from_slice(value).context("Invalid entry UUID")?,
+            0x0002 => group_id = Some(read_u32(value)?),
+            0x0003 => entry.icon_id = Some(read_u32(value)? as usize),
+            0x0004..=0x0006 | 0x0008 => {
+                super::set_text(&mut entry, name, &read_string(value));
+            }
+            0x0007 => super::set_protected(&mut entry, "Password", &read_string(value)),
+            0x0009..=0x000C => set_time(&mut entry.times, field_type - 0x0009, value)?,
+            0x000D => binary_name = read_string(value),
+            0x000E => binary_data = value.to_vec(),
+            FIELD_END => break,
+            _ => {}
+        }
+    }
+
+    if !binary_name.is_empty() {
+        entry
+            .fields
+            .insert(binary_name, KpValue::Bytes(binary_data));
+    }
+
+    Ok((
+        group_id.ok_or(anyhow!("Entry without a group in KeePass 1.x database"))?,
+        entry,
+    ))
+}
+
+/// Whether an entry holds application state rather than user data
+fn is_meta_stream(entry: &KpEntry) -> bool {
+    entry.get("Title") == Some("Meta-Info")
+        && entry.get("UserName") == Some("SYSTEM")
+        && entry.get("URL") == Some("$")
+        && entry.fields.contains_key("bin-stream")
+        && entry.get("Notes").is_some_and(|notes| !notes.is_empty())
+}
+
+/// Nest the groups by their levels below a new root group, and put the entries into their groups
+fn build_tree(
+    name: &str,
+    groups: Vec<KdbGroup>,
+    entries: Vec<(u32, KpEntry)>,
+    warnings: &mut Vec<String>,
+) -> Result<KpGroup> {
+    let mut groups = groups;
+
+    for (group_id, mut entry) in entries {
+        if is_meta_stream(&entry) {
+            continue;
+        }
+
+        // binary attachments would keep the database from being saved
+        let title = entry.get_title().unwrap_or_default().to_string();
+        entry.fields.retain(|name, value| match value {
+            KpValue::Bytes(data) => crate::export::kdbx::check_attachment(&title, name, data)
+                .map_err(|e| warnings.push(format!("{}", e)))
+                .is_ok(),
+            _ => true,
+        });
+
+        groups
+            .iter_mut()
+            .find(|g| g.id == group_id)
+            .ok_or(anyhow!("Entry references unknown group {}", group_id))?
+            .group
+            .children
+            .push(KpNode::Entry(entry));
+    }
+
+    let mut root = KpGroup::new(name);
+
+    // the ancestors of the current group, with the top-level group first
+    let mut branch: Vec<KpGroup> = Vec::new();
+
+    fn collapse(branch: &mut Vec<KpGroup>, root: &mut KpGroup, level: usize) {
+        while branch.len() > level {
+            let group = branch.pop().unwrap();
+            branch
+                .last_mut()
+                .unwrap_or(&mut *root)
+                .children
+                .push(KpNode::Group(group));
+        }
+    }
+
+    for KdbGroup { level, group, .. } in groups {
+        let level = level as usize;
+        if level > branch.len() {
+            bail!(
+                "Group {:?} skips a level in KeePass 1.x database",
+                group.name
+            );
+        }
+
+        collapse(&mut branch, &mut root, level);
+        branch.push(group);
+    }
+
+    collapse(&mut branch, &mut root, 0);
+
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncryptMut;
+
+    fn field(out: &mut Vec<u8>, field_type: u16, value: &[u8]) {
+        out.extend(field_type.to_le_bytes());
+        out.extend((value.len() as u32).to_le_bytes());
+        out.extend(value);
+    }
+
+    fn text(value: &str) -> Vec<u8> {
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        value
+    }
+
+    fn time(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> [u8; 5] {
+        [
+            (year >> 6) as u8,
+            (((year & 0x3F) << 2) | (month >> 2)) as u8,
+            (((month & 0x03) << 6) | (day << 1) | (hour >> 4)) as u8,
+            (((hour & 0x0F) << 4) | (minute >> 2)) as u8,
+            (((minute & 0x03) << 6) | second) as u8,
+        ]
+    }
+
+    fn group(out: &mut Vec<u8>, id: u32, name: &str, level: u16) {
+        field(out, 0x0001, &id.to_le_bytes());
+        field(out, 0x0002, &text(name));
+        field(out, 0x0006, &time(2999, 12, 28, 23, 59, 59));
+        field(out, 0x0008, &level.to_le_bytes());
+        field(out, FIELD_END, &[]);
+    }
+
+    fn entry(out: &mut Vec<u8>, group_id: u32, fields: &[(u16, &str)], binary: Option<&[u8]>) {
+        field(out, 0x0001, Uuid::new_v4().as_bytes());
+        field(out, 0x0002, &group_id.to_le_bytes());
+        for (field_type, value) in fields {
+            field(out, *field_type, &text(value));
+        }
+        field(out, 0x0009, &time(2010, 5, 17, 8, 30, 15));
+        if let Some(binary) = binary {
+            field(out, 0x000E, binary);
+        }
+        field(out, FIELD_END, &[]);
+    }
+
+    /// Encrypt a payload of three groups and three entries, one of them a meta-stream
+    fn database(flags: u32, password: &[u8]) -> Vec<u8> {
+        database_with_attachment(flags, password, b"key data")
+    }
+
+    fn database_with_attachment(flags: u32, password: &[u8], attachment: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        group(&mut payload, 1, "Internet", 0);
+        group(&mut payload, 2, "Mail", 1);
+        group(&mut payload, 3, "Backup", 0);
+        entry(
+            &mut payload,
+            2,
+            &[(0x0004, "Work"), (0x0006, "alice"), (0x0007, "secret")],
+            None,
+        );
+        entry(
+            &mut payload,
+            1,
+            &[(0x0004, "Key"), (0x000D, "key.txt")],
+            Some(attachment),
+        );
+        entry(
+            &mut payload,
+            1,
+            &[
+                (0x0004, "Meta-Info"),
+                (0x0005, "$"),
+                (0x0006, "SYSTEM"),
+                (0x0008, "KPX_GROUP_TREE_STATE"),
+                (0x000D, "bin-stream"),
+            ],
+            Some(&[1, 0, 0, 0]),
+        );
+
+        let header = Header {
+            flags,
+            master_seed: [1; 16],
+            iv: [2; 16],
+            num_groups: 3,
+            num_entries: 3,
+            contents_hash: Sha256::digest(&payload).into(),
+            transform_seed: [3; 32],
+            transform_rounds: 10,
+        };
+
+        let cipher = Aes256::new(&header.transform_seed.into());
+        let mut key: [u8; 32] = Sha256::digest(password).into();
+        for _ in 0..header.transform_rounds {
+            for block in key.chunks_exact_mut(16) {
+                cipher.encrypt_block(block.into());
+            }
+        }
+        let master_key = Sha256::new()
+            .chain_update(header.master_seed)
+            .chain_update(Sha256::digest(key))
+            .finalize();
+
+        let encrypted = match flags {
+            FLAG_RIJNDAEL => cbc::Encryptor::<Aes256>::new(&master_key, &header.iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(&payload),
+            _ => cbc::Encryptor::<Twofish>::new_from_slices(&master_key, &header.iv)
+                .unwrap()
+                .encrypt_padded_vec_mut::<Pkcs7>(&payload),
+        };
+
+        let mut out = Vec::new();
+        for value in [SIGNATURE_1, SIGNATURE_2, header.flags | 1, VERSION] {
+            out.extend(value.to_le_bytes());
+        }
+        out.extend(header.master_seed);
+        out.extend(header.iv);
+        out.extend(header.num_groups.to_le_bytes());
+        out.extend(header.num_entries.to_le_bytes());
+        out.extend(header.contents_hash);
+        out.extend(header.transform_seed);
+        out.extend(header.transform_rounds.to_le_bytes());
+        out.extend(encrypted);
+
+        out
+    }
+
+    #[test]
+    fn test_import_kdb() {
+        for flags in [FLAG_RIJNDAEL, FLAG_TWOFISH] {
+            let data = database(flags, b"hunter2");
+
+            assert!(import("Old", &data, Some("wrong"), None).is_err());
+            assert!(import("Old", &data, None, None).is_err());
+
+            let (database, warnings) = import("Old", &data, Some("hunter2"), None).unwrap();
+            assert!(warnings.is_empty());
+            let root = &database.root;
+            assert_eq!(root.name, "Old");
+            assert_eq!(
+                root.groups().iter().map(|g| &g.name).collect::<Vec<_>>(),
+                ["Internet", "Backup"]
+            );
+
+            let internet = root.groups()[0];
+            assert_eq!(internet.entries().len(), 1);
+            assert!(matches!(
+                internet.entries()[0].fields.get("key.txt"),
+                Some(KpValue::Bytes(data)) if data == b"key data"
+            ));
+            assert!(!internet.times.expires);
+
+            let work = internet.groups()[0].entries()[0];
+            assert_eq!(internet.groups()[0].name, "Mail");
+            assert_eq!(work.get_username(), Some("alice"));
+            assert_eq!(work.get_password(), Some("secret"));
+            assert_eq!(
+                work.times.get_creation(),
+                chrono::NaiveDate::from_ymd_opt(2010, 5, 17)
+                    .unwrap()
+                    .and_hms_opt(8, 30, 15)
+                    .as_ref()
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_attachment_is_skipped() {
+        let data = database_with_attachment(FLAG_RIJNDAEL, b"hunter2", &[0xff, 0xfe, 0x00, 0x80]);
+
+        let mut state = crate::AppState::default();
+        let imported = state
+            .import_kdb("Old".to_string(), &data, Some("hunter2".to_string()), None)
+            .unwrap();
+        assert_eq!(imported.warnings.len(), 1);
+        assert!(imported.warnings[0].contains("key.txt"));
+
+        // the converted database can be saved and opened again
+        let pending = state.prepare_save(0).unwrap();
+        let saved = state
+            .finish_save(crate::source::block_on(pending.run()))
+            .unwrap()
+            .unwrap();
+        let key = keepass::DatabaseKey::new().with_password("hunter2");
+        let database = KpDatabase::open(&mut &saved[..], key).unwrap();
+
+        let internet = database.root.groups()[0];
+        assert_eq!(internet.entries()[0].get_title(), Some("Key"));
+        assert_eq!(
+            internet.groups()[0].entries()[0].get_password(),
+            Some("secret")
+        );
+    }
+
+    #[test]
+    fn test_ansi_password_and_truncated_file() {
+        let data = database(FLAG_RIJNDAEL, &WINDOWS_1252.encode("pässword").0);
+        assert!(import("Old", &data, Some("pässword"), None).is_ok());
+
+        assert!(import("Old", &data[..data.len() - 16], Some("pässword"), None).is_err());
+        assert!(import("Old", &data[..100], Some("pässword"), None).is_err());
+    }
+}
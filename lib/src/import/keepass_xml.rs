@@ -0,0 +1,628 @@
+//! Import of KeePass 2.x XML exports
+//!
+//! The export is the unencrypted XML document of a KDBX database, with protected values in plain
+//! text and marked with `ProtectInMemory`. Metadata, groups, entries with their history, custom
+//! icons, custom data and deleted objects are carried over. Binaries are stored once in the
+//! metadata, optionally compressed with gzip, and referenced by ID from the entries.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::NaiveDateTime;
+use keepass::db::{
+    AutoType, AutoTypeAssociation, Color, CustomData, CustomDataItem, Database as KpDatabase,
+    DeletedObject, Entry as KpEntry, Group as KpGroup, History, Icon, MemoryProtection,
+    Node as KpNode, Times, Value as KpValue,
+};
+use roxmltree::Node;
+use uuid::Uuid;
+
+/// Binaries of the metadata by their ID
+type Binaries = HashMap<String, Vec<u8>>;
+
+/// Read a KeePass 2.x XML export into a new database
+///
+/// Attachments that cannot be saved are left out, which is reported in the returned warnings.
+pub fn import(data: &[u8]) -> Result<(KpDatabase, Vec<String>)> {
+    let text = std::str::from_utf8(data).context("The XML file is not valid UTF-8")?;
+    let document = roxmltree::Document::parse_with_options(
+        text,
+        roxmltree::ParsingOptions {
+            allow_dtd: false,
+            ..Default::default()
+        },
+    )
+    .context("Reading XML")?;
+
+    let file = document.root_element();
+    if !file.has_tag_name("KeePassFile") {
+        bail!("The file is not a KeePass XML export");
+    }
+
+    let mut database = KpDatabase::new(Default::default());
+
+    let binaries = match child(file, "Meta") {
+        Some(meta) => read_meta(meta, &mut database)?,
+        None => Binaries::new(),
+    };
+
+    let root = child(file, "Root").ok_or(anyhow!("The XML file has no root group"))?;
+
+    let mut warnings = Vec::new();
+    database.root = child(root, "Group")
+        .map(|group| read_group(group, &binaries, &mut warnings))
+        .transpose()?
+        .ok_or(anyhow!("The XML file has no root group"))?;
+
+    if let Some(deleted) = child(root, "DeletedObjects") {
+        for object in children(deleted, "DeletedObject") {
+            let (Some(uuid), Some(deletion_time)) = (
+                uuid_value(object, "UUID")?,
+                time_value(object, "DeletionTime")?,
+            ) else {
+                continue;
+            };
+
+            database.deleted_objects.objects.push(DeletedObject {
+                uuid,
+                deletion_time,
+            });
+        }
+    }
+
+    // the versions in the history of an entry usually share its attachments
+    warnings.dedup();
+
+    Ok((database, warnings))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| c.has_tag_name(name))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |c| c.has_tag_name(name))
+}
+
+/// The text of a child element, with missing and empty elements as `None`
+fn text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|c| c.text())
+        .filter(|t| !t.is_empty())
+}
+
+fn string_value(node: Node, name: &str) -> Option<String> {
+    text(node, name).map(String::from)
+}
+
+fn bool_value(node: Node, name: &str) -> Option<bool> {
+    text(node, name).map(|v| v.eq_ignore_ascii_case("true"))
+}
+
+fn number_value<T: FromStr>(node: Node, name: &str) -> Result<Option<T>> {
+    text(node, name)
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid {}: {:?}", name, v))
+        })
+        .transpose()
+}
+
+fn uuid_value(node: Node, name: &str) -> Result<Option<Uuid>> {
+    text(node, name)
+        .map(|v| {
+            let bytes = STANDARD.decode(v.trim())?;
+            Uuid::from_slice(&bytes).map_err(anyhow::Error::from)
+        })
+        .transpose()
+        .with_context(|| format!("Invalid {}", name))
+}
+
+/// Read a time as an ISO 8601 string, or as Base64-encoded seconds since 0001-01-01 like in KDBX4
+fn time_value(node: Node, name: &str) -> Result<Option<NaiveDateTime>> {
+    let Some(value) = text(node, name).map(str::trim) else {
+        return Ok(None);
+    };
+
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.naive_utc()));
+    }
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(Some(time));
+    }
+
+    let seconds: [u8; 8] = STANDARD
+        .decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(anyhow!("Invalid {}: {:?}", name, value))?;
+
+    let epoch = chrono::NaiveDate::from_ymd_opt(1, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+
+    Ok(epoch.checked_add_signed(chrono::TimeDelta::seconds(i64::from_le_bytes(seconds))))
+}
+
+fn color_value(node: Node, name: &str) -> Option<Color> {
+    text(node, name).and_then(|v| Color::from_str(v).ok())
+}
+
+fn read_meta(meta: Node, database: &mut KpDatabase) -> Result<Binaries> {
+    let m = &mut database.meta;
+
+    m.generator = string_value(meta, "Generator");
+    m.database_name = string_value(meta, "DatabaseName");
+    m.database_name_changed = time_value(meta, "DatabaseNameChanged")?;
+    m.database_description = string_value(meta, "DatabaseDescription");
+    m.database_description_changed = time_value(meta, "DatabaseDescriptionChanged")?;
+    m.default_username = string_value(meta, "DefaultUserName");
+    m.default_username_changed = time_value(meta, "DefaultUserNameChanged")?;
+    m.maintenance_history_days = number_value(meta, "MaintenanceHistoryDays")?;
+    m.color = color_value(meta, "Color");
+    m.master_key_changed = time_value(meta, "MasterKeyChanged")?;
+    m.master_key_change_rec = number_value(meta, "MasterKeyChangeRec")?;
+    m.master_key_change_force = number_value(meta, "MasterKeyChangeForce")?;
+    m.recyclebin_enabled = bool_value(meta, "RecycleBinEnabled");
+    m.recyclebin_uuid = uuid_value(meta, "RecycleBinUUID")?;
+    m.recyclebin_changed = time_value(meta, "RecycleBinChanged")?;
+    m.entry_templates_group = uuid_value(meta, "EntryTemplatesGroup")?;
+    m.entry_templates_group_changed = time_value(meta, "EntryTemplatesGroupChanged")?;
+    m.last_selected_group = uuid_value(meta, "LastSelectedGroup")?;
+    m.last_top_visible_group = uuid_value(meta, "LastTopVisibleGroup")?;
+    m.history_max_items = number_value(meta, "HistoryMaxItems")?;
+    m.history_max_size = number_value(meta, "HistoryMaxSize")?;
+    m.settings_changed = time_value(meta, "SettingsChanged")?;
+
+    if let Some(protection) = child(meta, "MemoryProtection") {
+        m.memory_protection = Some(MemoryProtection {
+            protect_title: bool_value(protection, "ProtectTitle").unwrap_or_default(),
+            protect_username: bool_value(protection, "ProtectUserName").unwrap_or_default(),
+            protect_password: bool_value(protection, "ProtectPassword").unwrap_or(true),
+            protect_url: bool_value(protection, "ProtectURL").unwrap_or_default(),
+            protect_notes: bool_value(protection, "ProtectNotes").unwrap_or_default(),
+        });
+    }
+
+    if let Some(icons) = child(meta, "CustomIcons") {
+        for icon in children(icons, "Icon") {
+            let (Some(uuid), Some(data)) = (uuid_value(icon, "UUID")?, text(icon, "Data")) else {
+                continue;
+            };
+
+            let data = STANDARD
+                .decode(data.trim())
+                .context("Invalid custom icon data")?;
+            m.custom_icons.icons.push(Icon { uuid, data });
+        }
+    }
+
+    if let Some(custom_data) = child(meta, "CustomData") {
+        m.custom_data = read_custom_data(custom_data)?;
+    }
+
+    let mut binaries = Binaries::new();
+
+    if let Some(node) = child(meta, "Binaries") {
+        for binary in children(node, "Binary") {
+            let id = binary.attribute("ID").unwrap_or_default().to_string();
+            let data = read_binary(binary)?;
+            binaries.insert(id, data);
+        }
+    }
+
+    Ok(binaries)
+}
+
+/// Decode the content of a binary, which is Base64-encoded and may be compressed
+fn read_binary(binary: Node) -> Result<Vec<u8>> {
+    let data = STANDARD
+        .decode(binary.text().unwrap_or_default().trim())
+        .context("Invalid binary data")?;
+
+    if binary.attribute("Compressed") != Some("True") {
+        return Ok(data);
+    }
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(&data[..])
+        .read_to_end(&mut decompressed)
+        .context("Decompressing binary")?;
+
+    Ok(decompressed)
+}
+
+fn read_custom_data(node: Node) -> Result<CustomData> {
+    let mut custom_data = CustomData::default();
+
+    for item in children(node, "Item") {
+        let Some(key) = string_value(item, "Key") else {
+            continue;
+        };
+
+        custom_data.items.insert(
+            key,
+            CustomDataItem {
+                value: string_value(item, "Value").map(KpValue::Unprotected),
+                last_modification_time: time_value(item, "LastModificationTime")?,
+            },
+        );
+    }
+
+    Ok(custom_data)
+}
+
+fn read_times(node: Node) -> Result<Times> {
+    let mut times = Times::default();
+
+    let Some(node) = child(node, "Times") else {
+        return Ok(times);
+    };
+
+    if let Some(time) = time_value(node, "CreationTime")? {
+        times.set_creation(time);
+    }
+    if let Some(time) = time_value(node, "LastModificationTime")? {
+        times.set_last_modification(time);
+    }
+    if let Some(time) = time_value(node, "LastAccessTime")? {
+        times.set_last_access(time);
+    }
+    if let Some(time) = time_value(node, "ExpiryTime")? {
+        times.set_expiry(time);
+    }
+    if let Some(time) = time_value(node, "LocationChanged")? {
+        times.set_location_changed(time);
+    }
+
+    times.expires = bool_value(node, "Expires").unwrap_or_default();
+    times.usage_count = number_value(node, "UsageCount")?.unwrap_or_default();
+
+    Ok(times)
+}
+
+fn read_group(node: Node, binaries: &Binaries, warnings: &mut Vec<String>) -> Result<KpGroup> {
+    let mut group = KpGroup::new(text(node, "Name").unwrap_or_default());
+
+    if let Some(uuid) = uuid_value(node, "UUID")? {
+        group.uuid = uuid;
+    }
+
+    group.notes = string_value(node, "Notes");
+    group.icon_id = number_value(node, "IconID")?;
+    group.custom_icon_uuid = uuid_value(node, "CustomIconUUID")?;
+    group.times = read_times(node)?;
+    group.is_expanded = bool_value(node, "IsExpanded").unwrap_or_default();
+    group.default_autotype_sequence = string_value(node, "DefaultAutoTypeSequence");
+    group.enable_autotype = string_value(node, "EnableAutoType");
+    group.enable_searching = string_value(node, "EnableSearching");
+    group.last_top_visible_entry = uuid_value(node, "LastTopVisibleEntry")?;
+
+    if let Some(custom_data) = child(node, "CustomData") {
+        group.custom_data = read_custom_data(custom_data)?;
+    }
+
+    for element in node.children().filter(|c| c.is_element()) {
+        match element.tag_name().name() {
+            "Entry" => group
+                .children
+                .push(KpNode::Entry(read_entry(element, binaries, warnings)?)),
+            "Group" => group
+                .children
+                .push(KpNode::Group(read_group(element, binaries, warnings)?)),
+            _ => {}
+        }
+    }
+
+    Ok(group)
+}
+
+fn read_entry(node: Node, binaries: &Binaries, warnings: &mut Vec<String>) -> Result<KpEntry> {
+    let mut entry = KpEntry::new();
+
+    if let Some(uuid) = uuid_value(node, "UUID")? {
+        entry.uuid = uuid;
+    }
+
+    entry.icon_id = number_value(node, "IconID")?;
+    entry.custom_icon_uuid = uuid_value(node, "CustomIconUUID")?;
+    entry.foreground_color = color_value(node, "ForegroundColor");
+    entry.background_color = color_value(node, "BackgroundColor");
+    entry.override_url = string_value(node, "OverrideURL");
+    entry.quality_check = bool_value(node, "QualityCheck");
+    entry.times = read_times(node)?;
+
+    entry.tags = text(node, "Tags")
+        .unwrap_or_default()
+        .split([';', ','])
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+
+    for string in children(node, "String") {
+        let Some(key) = string_value(string, "Key") else {
+            continue;
+        };
+
+        let value = child(string, "Value");
+        let text = value.and_then(|v| v.text()).unwrap_or_default();
+
+        if value.and_then(|v| v.attribute("Protected")) == Some("True") {
+            bail!(
+                "The value of {:?} is encrypted, please export the database as plain XML",
+                key
+            );
+        }
+
+        match value.and_then(|v| v.attribute("ProtectInMemory")) {
+            Some("True") => super::set_protected(&mut entry, &key, text),
+            _ => super::set_text(&mut entry, &key, text),
+        }
+    }
+
+    for binary in children(node, "Binary") {
+        let Some(key) = string_value(binary, "Key") else {
+            continue;
+        };
+
+        let Some(value) = child(binary, "Value") else {
+            continue;
+        };
+
+        let data = match value.attribute("Ref") {
+            Some(id) => binaries.get(id).cloned().ok_or(anyhow!(
+                "Attachment {:?} references unknown binary {}",
+                key,
+                id
+            ))?,
+            None => read_binary(value)?,
+        };
+
+        // binary attachments would keep the database from being saved
+        let title = entry.get_title().unwrap_or_default();
+        if let Err(e) = crate::export::kdbx::check_attachment(title, &key, &data) {
+            warnings.push(format!("{}", e));
+            continue;
+        }
+
+        entry.fields.insert(key, KpValue::Bytes(data));
+    }
+
+    if let Some(autotype) = child(node, "AutoType") {
+        entry.autotype = Some(AutoType {
+            enabled: bool_value(autotype, "Enabled").unwrap_or(true),
+            sequence: string_value(autotype, "DefaultSequence"),
+            associations: children(autotype, "Association")
+                .map(|association| AutoTypeAssociation {
+                    window: string_value(association, "Window"),
+                    sequence: string_value(association, "KeystrokeSequence"),
+                })
+                .collect(),
+        });
+    }
+
+    if let Some(custom_data) = child(node, "CustomData") {
+        entry.custom_data = read_custom_data(custom_data)?;
+    }
+
+    if let Some(history) = child(node, "History") {
+        let mut entries = children(history, "Entry")
+            .map(|e| read_entry(e, binaries, warnings))
+            .collect::<Result<Vec<_>>>()?;
+
+        // adding to the history inserts at the front
+        let mut history = History::default();
+        while let Some(old) = entries.pop() {
+            history.add_entry(old);
+        }
+
+        entry.history = Some(history);
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn gzip_base64(data: &[u8]) -> String {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(data).unwrap();
+        STANDARD.encode(encoder.finish().unwrap())
+    }
+
+    fn export() -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <Generator>KeePass</Generator>
+        <DatabaseName>Archive</DatabaseName>
+        <RecycleBinEnabled>True</RecycleBinEnabled>
+        <RecycleBinUUID>AAAAAAAAAAAAAAAAAAAAAA==</RecycleBinUUID>
+        <HistoryMaxItems>10</HistoryMaxItems>
+        <CustomIcons>
+            <Icon><UUID>AQEBAQEBAQEBAQEBAQEBAQ==</UUID><Data>iVBORw0KGgo=</Data></Icon>
+        </CustomIcons>
+        <Binaries>
+            <Binary ID="0" Compressed="True">{compressed}</Binary>
+        </Binaries>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>AgICAgICAgICAgICAgICAg==</UUID>
+            <Name>Archive</Name>
+            <IconID>48</IconID>
+            <Times>
+                <CreationTime>2015-03-01T10:00:00Z</CreationTime>
+                <Expires>False</Expires>
+            </Times>
+            <Entry>
+                <UUID>AwMDAwMDAwMDAwMDAwMDAw==</UUID>
+                <IconID>0</IconID>
+                <CustomIconUUID>AQEBAQEBAQEBAQEBAQEBAQ==</CustomIconUUID>
+                <ForegroundColor>#FF0000</ForegroundColor>
+                <Tags>work;mail</Tags>
+                <Times>
+                    <LastModificationTime>2020-01-02T03:04:05Z</LastModificationTime>
+                    <ExpiryTime>2030-01-01T00:00:00Z</ExpiryTime>
+                    <Expires>True</Expires>
+                    <UsageCount>3</UsageCount>
+                </Times>
+                <String><Key>Title</Key><Value>Mail</Value></String>
+                <String><Key>UserName</Key><Value>alice</Value></String>
+                <String><Key>Password</Key><Value ProtectInMemory="True">new &amp; secret</Value></String>
+                <Binary><Key>notes.txt</Key><Value Ref="0" /></Binary>
+                <AutoType>
+                    <Enabled>True</Enabled>
+                    <DataTransferObfuscation>0</DataTransferObfuscation>
+                    <Association><Window>Mail*</Window><KeystrokeSequence>{{USERNAME}}{{ENTER}}</KeystrokeSequence></Association>
+                </AutoType>
+                <History>
+                    <Entry>
+                        <UUID>AwMDAwMDAwMDAwMDAwMDAw==</UUID>
+                        <String><Key>Password</Key><Value ProtectInMemory="True">first</Value></String>
+                    </Entry>
+                    <Entry>
+                        <UUID>AwMDAwMDAwMDAwMDAwMDAw==</UUID>
+                        <String><Key>Password</Key><Value ProtectInMemory="True">second</Value></String>
+                    </Entry>
+                </History>
+            </Entry>
+            <Group>
+                <UUID>BAQEBAQEBAQEBAQEBAQEBA==</UUID>
+                <Name>Old</Name>
+            </Group>
+        </Group>
+        <DeletedObjects>
+            <DeletedObject><UUID>BQUFBQUFBQUFBQUFBQUFBQ==</UUID><DeletionTime>ULx4zw4AAAA=</DeletionTime></DeletedObject>
+        </DeletedObjects>
+    </Root>
+</KeePassFile>"#,
+            compressed = gzip_base64(b"attached notes")
+        )
+    }
+
+    #[test]
+    fn test_import_keepass_xml() {
+        let (database, warnings) = import(export().as_bytes()).unwrap();
+        assert!(warnings.is_empty());
+
+        assert_eq!(database.meta.database_name.as_deref(), Some("Archive"));
+        assert_eq!(database.meta.recyclebin_enabled, Some(true));
+        assert_eq!(database.meta.history_max_items, Some(10));
+        assert_eq!(database.meta.custom_icons.icons.len(), 1);
+        assert_eq!(database.deleted_objects.objects.len(), 1);
+
+        let root = &database.root;
+        assert_eq!(root.uuid, Uuid::from_bytes([2; 16]));
+        assert_eq!(root.icon_id, Some(48));
+        assert_eq!(root.groups()[0].name, "Old");
+
+        let mail = root.entries()[0];
+        assert_eq!(mail.uuid, Uuid::from_bytes([3; 16]));
+        assert_eq!(mail.get_password(), Some("new & secret"));
+        assert!(matches!(
+            mail.fields.get("Password"),
+            Some(KpValue::Protected(..))
+        ));
+        assert_eq!(mail.tags, ["work", "mail"]);
+        assert_eq!(mail.custom_icon_uuid, Some(Uuid::from_bytes([1; 16])));
+        assert_eq!(mail.foreground_color, Some(Color { r: 255, g: 0, b: 0 }));
+        assert!(mail.times.expires);
+        assert_eq!(mail.times.usage_count, 3);
+        assert!(matches!(
+            mail.fields.get("notes.txt"),
+            Some(KpValue::Bytes(data)) if data == b"attached notes"
+        ));
+
+        let autotype = mail.autotype.as_ref().unwrap();
+        assert_eq!(
+            autotype.associations[0].sequence.as_deref(),
+            Some("{USERNAME}{ENTER}")
+        );
+
+        let history = mail.history.as_ref().unwrap().get_entries();
+        assert_eq!(
+            history
+                .iter()
+                .map(|e| e.get_password().unwrap())
+                .collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+    }
+
+    #[test]
+    fn test_save_as_kdbx4() {
+        let (database, _) = import(export().as_bytes()).unwrap();
+        let key = keepass::DatabaseKey::new().with_password("hunter2");
+
+        let mut data = Vec::new();
+        database.save(&mut data, key.clone()).unwrap();
+
+        let reopened = KpDatabase::open(&mut &data[..], key).unwrap();
+        assert_eq!(
+            reopened.root.entries()[0].get_password(),
+            Some("new & secret")
+        );
+        assert_eq!(reopened.root.entries()[0].uuid, Uuid::from_bytes([3; 16]));
+    }
+
+    #[test]
+    fn test_binary_attachment_is_skipped() {
+        let xml = export().replace(
+            &gzip_base64(b"attached notes"),
+            &gzip_base64(&[0xff, 0xfe, 0x00, 0x80]),
+        );
+
+        let mut state = crate::AppState::default();
+        let imported = state
+            .import_keepass_xml(
+                "Archive".to_string(),
+                xml.as_bytes(),
+                Some("hunter2".to_string()),
+                None,
+            )
+            .unwrap();
+        assert_eq!(imported.warnings.len(), 1);
+        assert!(imported.warnings[0].contains("notes.txt"));
+
+        // the converted database can be saved and opened again
+        let pending = state.prepare_save(0).unwrap();
+        let saved = state
+            .finish_save(crate::source::block_on(pending.run()))
+            .unwrap()
+            .unwrap();
+        let key = keepass::DatabaseKey::new().with_password("hunter2");
+        let database = KpDatabase::open(&mut &saved[..], key).unwrap();
+        assert_eq!(
+            database.root.entries()[0].get_password(),
+            Some("new & secret")
+        );
+    }
+
+    #[test]
+    fn test_reject_encrypted_values() {
+        let xml = export().replace(
+            r#"ProtectInMemory="True">new &amp; secret"#,
+            r#"Protected="True">bmV3"#,
+        );
+        assert!(import(xml.as_bytes()).is_err());
+
+        assert!(import(b"<Database></Database>").is_err());
+    }
+}
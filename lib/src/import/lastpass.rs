@@ -0,0 +1,252 @@
+//! Import of LastPass CSV exports
+//!
+//! LastPass exports the columns `url`, `username`, `password`, `totp`, `extra`, `name`, `grouping`
+//! and `fav`. Folders in `grouping` are separated by backslashes. Secure notes have the URL
+//! `http://sn`, and structured notes like cards or addresses carry their type and fields as
+//! `Key:Value` lines in `extra`, which become custom fields.
+
+use anyhow::{Result, bail};
+use keepass::db::{Entry as KpEntry, Group as KpGroup, Node as KpNode};
+
+use crate::exchange::ImportSummary;
+
+/// URL that marks secure notes
+const SECURE_NOTE_URL: &str = "http://sn";
+
+/// Grouping of items outside of any folder
+const NO_GROUPING: &str = "(none)";
+
+/// Prefix of the first line of structured secure notes
+const NOTE_TYPE_PREFIX: &str = "NoteType:";
+
+/// Keys of structured secure notes holding secrets
+const PROTECTED_KEYS: &[&str] = &[
+    "Password",
+    "Passphrase",
+    "Private Key",
+    "Number",
+    "Security Code",
+    "PIN",
+    "Account Number",
+    "Routing Number",
+];
+
+/// Column positions in the header of the export
+struct Columns {
+    url: usize,
+    username: usize,
+    password: usize,
+    totp: Option<usize>,
+    extra: usize,
+    name: usize,
+    grouping: usize,
+}
+
+impl Columns {
+    fn from_header(header: &[String]) -> Result<Self> {
+        let find = |name: &str| header.iter().position(|h| h.trim() == name);
+
+        let (Some(url), Some(username), Some(password), Some(extra), Some(name), Some(grouping)) = (
+            find("url"),
+            find("username"),
+            find("password"),
+            find("extra"),
+            find("name"),
+            find("grouping"),
+        ) else {
+            bail!("The file is not a LastPass CSV export");
+        };
+
+        Ok(Self {
+            url,
+            username,
+            password,
+            totp: find("totp"),
+            extra,
+            name,
+            grouping,
+        })
+    }
+}
+
+/// Import the items of a LastPass CSV export into a group
+pub fn import(group: &mut KpGroup, data: &[u8]) -> Result<ImportSummary> {
+    let format = super::csv::detect_format(data)?;
+    let rows = super::csv::read_rows(data, &format)?;
+
+    let Some((header, rows)) = rows.split_first() else {
+        bail!("The file is empty");
+    };
+    let columns = Columns::from_header(header)?;
+
+    let mut summary = ImportSummary::default();
+
+    for (idx, row) in rows.iter().enumerate() {
+        if row.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+
+        let cell = |column: usize| row.get(column).map(|v| v.as_str()).unwrap_or_default();
+
+        let entry = row_to_entry(&cell, &columns, idx + 1, &mut summary.warnings);
+
+        let path = match cell(columns.grouping).trim() {
+            NO_GROUPING => String::new(),
+            grouping => grouping.replace('\\', "/"),
+        };
+
+        super::group_at_path(group, &path, &mut summary)
+            .children
+            .push(KpNode::Entry(entry));
+        summary.entries += 1;
+    }
+
+    Ok(summary)
+}
+
+fn row_to_entry<'a>(
+    cell: &impl Fn(usize) -> &'a str,
+    columns: &Columns,
+    row_number: usize,
+    warnings: &mut Vec<String>,
+) -> KpEntry {
+    let url = cell(columns.url);
+    let extra = cell(columns.extra);
+
+    let title = match cell(columns.name) {
+        "" => url,
+        name => name,
+    };
+
+    if url != SECURE_NOTE_URL {
+        let mut entry = super::new_entry(
+            title,
+            cell(columns.username),
+            cell(columns.password),
+            url,
+            extra,
+        );
+
+        let totp = columns.totp.map(cell).unwrap_or_default();
+        if !totp.trim().is_empty()
+            && let Err(e) = super::set_otp(&mut entry, totp)
+        {
+            warnings.push(format!("Row {row_number}: invalid one-time password: {e}"));
+        }
+
+        return entry;
+    }
+
+    let Some(structured) = extra.strip_prefix(NOTE_TYPE_PREFIX) else {
+        return super::new_entry(title, "", "", "", extra);
+    };
+
+    let mut entry = super::new_entry(title, "", "", "", "");
+    let mut lines = structured.lines();
+
+    let note_type = lines.next().unwrap_or_default().trim();
+    if !note_type.is_empty() {
+        entry.tags.push(note_type.to_string());
+    }
+
+    let mut last_key: Option<String> = None;
+
+    while let Some(line) = lines.next() {
+        let Some((key, value)) = line.split_once(':') else {
+            // continuation of a multi-line value
+            if let Some(key) = &last_key {
+                let value = format!("{}\n{}", entry.get(key).unwrap_or_default(), line);
+                set_note_field(&mut entry, key, &value);
+            }
+            continue;
+        };
+
+        // the notes of a structured note take up the rest of it
+        if key == "Notes" {
+            let notes: Vec<&str> = std::iter::once(value).chain(lines.by_ref()).collect();
+            super::set_text(&mut entry, "Notes", notes.join("\n").trim());
+            break;
+        }
+
+        let name = match key {
+            "Username" | "User Name" => "UserName",
+            "Password" => "Password",
+            "URL" | "Website" => "URL",
+            key => key,
+        };
+
+        set_note_field(&mut entry, name, value);
+        last_key = Some(name.to_string());
+    }
+
+    entry
+        .fields
+        .retain(|name, value| name == "Title" || !value.is_empty());
+
+    entry
+}
+
+/// Set a field of a structured secure note, protecting secrets
+fn set_note_field(entry: &mut KpEntry, name: &str, value: &str) {
+    if PROTECTED_KEYS.contains(&name) {
+        super::set_protected(entry, name, value);
+    } else {
+        super::set_text(entry, name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = "url,username,password,totp,extra,name,grouping,fav\r\n\
+        https://mail.example.com,alice,secret,JBSWY3DPEHPK3PXP,work account,Mail,Internet\\Mail,1\r\n\
+        http://sn,,,,\"just a note\nwith two lines\",Note,(none),0\r\n\
+        http://sn,,,,\"NoteType:Credit Card\nLanguage:en-US\nName on Card:Alice\nType:Visa\nNumber:4111111111111111\nSecurity Code:123\nStart Date:,\nExpiration Date:December,2030\nNotes:first line\nsecond line\",Visa,Finance,0\r\n";
+
+    #[test]
+    fn test_import_lastpass() {
+        let mut root = KpGroup::new("Root");
+        let summary = import(&mut root, EXPORT.as_bytes()).unwrap();
+
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.groups, 3);
+        assert!(summary.warnings.is_empty());
+
+        let KpNode::Group(internet) = &root.children[0] else {
+            panic!("expected a group");
+        };
+        let mail = internet.groups()[0].entries()[0];
+        assert_eq!(internet.groups()[0].name, "Mail");
+        assert_eq!(mail.get_username(), Some("alice"));
+        assert_eq!(mail.get_password(), Some("secret"));
+        assert_eq!(mail.get("Notes"), Some("work account"));
+        assert!(mail.get_raw_otp_value().is_some());
+
+        let note = root.entries()[0];
+        assert_eq!(note.get_title(), Some("Note"));
+        assert_eq!(note.get("Notes"), Some("just a note\nwith two lines"));
+        assert_eq!(note.get_url(), None);
+
+        let KpNode::Group(finance) = &root.children[2] else {
+            panic!("expected a group");
+        };
+        let visa = finance.entries()[0];
+        assert_eq!(visa.tags, ["Credit Card"]);
+        assert_eq!(visa.get("Name on Card"), Some("Alice"));
+        assert_eq!(visa.get("Number"), Some("4111111111111111"));
+        assert!(matches!(
+            visa.fields.get("Number"),
+            Some(keepass::db::Value::Protected(..))
+        ));
+        assert_eq!(visa.get("Expiration Date"), Some("December,2030"));
+        assert_eq!(visa.get("Start Date"), Some(","));
+        assert_eq!(visa.get("Notes"), Some("first line\nsecond line"));
+    }
+
+    #[test]
+    fn test_reject_other_csv() {
+        let mut root = KpGroup::new("Root");
+        assert!(import(&mut root, b"title,username,password\nMail,alice,secret\n").is_err());
+    }
+}
@@ -0,0 +1,541 @@
+//! Import of 1Password `.1pux` exports
+//!
+//! A 1PUX file is a ZIP archive with the items of all exported accounts and vaults in an
+//! `export.data` JSON document, and the contents of attached files and documents under `files/`.
+//! Each vault becomes a group, with archived items in an `Archive` subgroup. The fields of all
+//! sections become custom fields, and files become attachments of their entries.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use anyhow::{Context, Result, anyhow};
+use keepass::db::{Entry as KpEntry, Group as KpGroup, Node as KpNode, Value as KpValue};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::exchange::ImportSummary;
+use crate::url_match::ADDITIONAL_URL_PREFIX;
+
+/// Name of the document holding the exported items
+const EXPORT_DATA: &str = "export.data";
+
+/// Directory holding attached files, named `<document ID>__<file name>`
+const FILES_DIR: &str = "files/";
+
+/// Name of the subgroup that archived items are put into
+const ARCHIVE_GROUP: &str = "Archive";
+
+#[derive(Deserialize)]
+struct Export {
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    attrs: VaultAttrs,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct VaultAttrs {
+    name: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Item {
+    state: String,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    details: Details,
+    overview: Overview,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Details {
+    login_fields: Vec<LoginField>,
+    notes_plain: Option<String>,
+    sections: Vec<Section>,
+    password: Option<String>,
+    document_attributes: Option<FileAttributes>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct LoginField {
+    value: String,
+    name: String,
+    field_type: String,
+    designation: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Section {
+    fields: Vec<SectionField>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SectionField {
+    title: String,
+    id: String,
+    value: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileAttributes {
+    file_name: String,
+    document_id: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Overview {
+    title: String,
+    url: String,
+    urls: Vec<OverviewUrl>,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct OverviewUrl {
+    url: String,
+}
+
+/// Import the items of a 1PUX archive into a group
+pub fn import(group: &mut KpGroup, data: &[u8]) -> Result<ImportSummary> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).context("Reading 1PUX archive")?;
+
+    let export: Export = {
+        let file = archive
+            .by_name(EXPORT_DATA)
+            .map_err(|_| anyhow!("The archive has no {}", EXPORT_DATA))?;
+        serde_json::from_reader(file).context("Reading 1Password export")?
+    };
+
+    let files = read_files(&mut archive)?;
+    let mut summary = ImportSummary::default();
+
+    for vault in export.accounts.iter().flat_map(|a| &a.vaults) {
+        for item in &vault.items {
+            let path = match item.state.as_str() {
+                "archived" => format!("{}/{}", vault.attrs.name, ARCHIVE_GROUP),
+                _ => vault.attrs.name.clone(),
+            };
+
+            let entry = item_to_entry(item, &files, &mut summary.warnings);
+
+            super::group_at_path(group, &path, &mut summary)
+                .children
+                .push(KpNode::Entry(entry));
+            summary.entries += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Read the attached files of an archive, keyed by their document ID
+fn read_files(archive: &mut zip::ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<u8>>> {
+    let mut files = HashMap::new();
+
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+
+        let Some(name) = file.name().strip_prefix(FILES_DIR) else {
+            continue;
+        };
+
+        let document_id = match name.split_once("__") {
+            Some((document_id, _)) => document_id,
+            None => name,
+        };
+
+        if document_id.is_empty() || file.is_dir() {
+            continue;
+        }
+
+        let document_id = document_id.to_string();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Reading attachment {}", document_id))?;
+
+        files.insert(document_id, data);
+    }
+
+    Ok(files)
+}
+
+fn item_to_entry(
+    item: &Item,
+    files: &HashMap<String, Vec<u8>>,
+    warnings: &mut Vec<String>,
+) -> KpEntry {
+    let details = &item.details;
+    let title = &item.overview.title;
+
+    let designated = |designation: &str| {
+        details
+            .login_fields
+            .iter()
+            .find(|f| f.designation == designation)
+            .map(|f| f.value.as_str())
+    };
+
+    let password = designated("password")
+        .or(details.password.as_deref())
+        .unwrap_or_default();
+
+    let mut entry = super::new_entry(
+        title,
+        designated("username").unwrap_or_default(),
+        password,
+        &item.overview.url,
+        details.notes_plain.as_deref().unwrap_or_default(),
+    );
+
+    let urls = item
+        .overview
+        .urls
+        .iter()
+        .map(|u| u.url.as_str())
+        .filter(|url| !url.is_empty() && *url != item.overview.url);
+
+    for (idx, url) in urls.enumerate() {
+        let name = format!("{ADDITIONAL_URL_PREFIX}_{}", idx + 1);
+        super::set_text(&mut entry, &name, url);
+    }
+
+    // other fields of login forms, such as a PIN or a second user name
+    for field in &details.login_fields {
+        if !field.designation.is_empty() || field.value.is_empty() || field.name.is_empty() {
+            continue;
+        }
+
+        let name = unique_name(&entry, &field.name);
+        match field.field_type.as_str() {
+            "P" => super::set_protected(&mut entry, &name, &field.value),
+            _ => super::set_text(&mut entry, &name, &field.value),
+        }
+    }
+
+    for section in &details.sections {
+        for field in &section.fields {
+            let name = match field.title.as_str() {
+                "" => field.id.as_str(),
+                title => title,
+            };
+
+            set_section_field(&mut entry, name, field, files, warnings, title);
+        }
+    }
+
+    if let Some(document) = &details.document_attributes {
+        attach(&mut entry, document, files, warnings, title);
+    }
+
+    entry.tags = item.overview.tags.clone();
+
+    let time = |timestamp: Option<i64>| {
+        chrono::DateTime::from_timestamp(timestamp?, 0).map(|t| t.naive_utc())
+    };
+
+    if let Some(time) = time(item.created_at) {
+        entry.times.set_creation(time);
+    }
+
+    if let Some(time) = time(item.updated_at) {
+        entry.times.set_last_modification(time);
+    }
+
+    entry
+}
+
+/// Store the value of a section field, which is tagged with its type
+fn set_section_field(
+    entry: &mut KpEntry,
+    name: &str,
+    field: &SectionField,
+    files: &HashMap<String, Vec<u8>>,
+    warnings: &mut Vec<String>,
+    title: &str,
+) {
+    let Some((kind, value)) = field.value.iter().next() else {
+        return;
+    };
+
+    let text = match (kind.as_str(), value) {
+        ("totp", Value::String(uri)) if !uri.is_empty() => {
+            if entry.get_raw_otp_value().is_none() {
+                if let Err(e) = super::set_otp(entry, uri) {
+                    warnings.push(format!("{}: invalid one-time password: {}", title, e));
+                }
+                return;
+            }
+            uri.clone()
+        }
+        ("file", value) => {
+            match serde_json::from_value::<FileAttributes>(value.clone()) {
+                Ok(file) => attach(entry, &file, files, warnings, title),
+                Err(e) => warnings.push(format!("{}: invalid attachment: {}", title, e)),
+            }
+            return;
+        }
+        ("email", Value::Object(email)) => email
+            .get("email_address")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        ("address", Value::Object(address)) => ["street", "city", "state", "zip", "country"]
+            .iter()
+            .filter_map(|key| address.get(*key)?.as_str())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        ("sshKey", Value::Object(key)) => {
+            let public_key = key
+                .get("metadata")
+                .and_then(|m| m.get("publicKey"))
+                .and_then(|v| v.as_str());
+
+            if let Some(public_key) = public_key.filter(|v| !v.is_empty()) {
+                let name = unique_name(entry, "Public key");
+                super::set_text(entry, &name, public_key);
+            }
+
+            let private_key = key.get("privateKey").and_then(|v| v.as_str());
+            if let Some(private_key) = private_key.filter(|v| !v.is_empty()) {
+                let name = unique_name(entry, name);
+                super::set_protected(entry, &name, private_key);
+            }
+            return;
+        }
+        ("date", Value::Number(timestamp)) => timestamp
+            .as_i64()
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        // stored as a number like 202512
+        ("monthYear", Value::Number(month_year)) => month_year
+            .as_u64()
+            .map(|v| format!("{:02}/{}", v % 100, v / 100))
+            .unwrap_or_default(),
+        (_, Value::String(value)) => value.clone(),
+        (_, Value::Number(value)) => value.to_string(),
+        (_, Value::Bool(value)) => value.to_string(),
+        _ => String::new(),
+    };
+
+    if text.is_empty() || name.is_empty() {
+        return;
+    }
+
+    let name = unique_name(entry, name);
+    match kind.as_str() {
+        "concealed" | "creditCardNumber" => super::set_protected(entry, &name, &text),
+        _ => super::set_text(entry, &name, &text),
+    }
+}
+
+/// Attach a file of the archive to an entry
+fn attach(
+    entry: &mut KpEntry,
+    file: &FileAttributes,
+    files: &HashMap<String, Vec<u8>>,
+    warnings: &mut Vec<String>,
+    title: &str,
+) {
+    let Some(data) = files.get(&file.document_id) else {
+        warnings.push(format!(
+            "{}: attachment {} is missing from the archive",
+            title, file.file_name
+        ));
+        return;
+    };
+
+    // binary attachments would keep the database from being saved
+    if let Err(e) = crate::export::kdbx::check_attachment(title, &file.file_name, data) {
+        warnings.push(format!("{}", e));
+        return;
+    }
+
+    let name = unique_name(entry, &file.file_name);
+    entry.fields.insert(name, KpValue::Bytes(data.clone()));
+}
+
+/// Make a field name unique within an entry by appending a number
+fn unique_name(entry: &KpEntry, name: &str) -> String {
+    let mut unique = name.to_string();
+    let mut counter = 1;
+
+    while entry.fields.contains_key(&unique) {
+        counter += 1;
+        unique = format!("{name} ({counter})");
+    }
+
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    const EXPORT: &str = r#"{
+        "accounts": [{
+            "attrs": {"accountName": "Alice"},
+            "vaults": [{
+                "attrs": {"uuid": "v1", "name": "Private"},
+                "items": [
+                    {
+                        "uuid": "i1",
+                        "state": "active",
+                        "createdAt": 1709296200,
+                        "updatedAt": 1709296200,
+                        "categoryUuid": "001",
+                        "details": {
+                            "loginFields": [
+                                {"value": "alice", "name": "email", "fieldType": "E", "designation": "username"},
+                                {"value": "secret", "name": "password", "fieldType": "P", "designation": "password"},
+                                {"value": "0000", "name": "pin", "fieldType": "P", "designation": ""}
+                            ],
+                            "notesPlain": "work account",
+                            "sections": [{
+                                "title": "Security",
+                                "fields": [
+                                    {"title": "one-time password", "id": "TOTP_1", "value": {"totp": "JBSWY3DPEHPK3PXP"}},
+                                    {"title": "Recovery code", "id": "rc", "value": {"concealed": "abcd-efgh"}},
+                                    {"title": "Backup", "id": "f", "value": {"file": {"fileName": "codes.txt", "documentId": "doc1", "decryptedSize": 5}}}
+                                ]
+                            }],
+                            "passwordHistory": []
+                        },
+                        "overview": {
+                            "title": "Mail",
+                            "url": "https://mail.example.com",
+                            "urls": [{"label": "", "url": "https://mail.example.com"}, {"label": "", "url": "https://webmail.example.com"}],
+                            "tags": ["work"]
+                        }
+                    },
+                    {
+                        "uuid": "i2",
+                        "state": "archived",
+                        "categoryUuid": "002",
+                        "details": {
+                            "sections": [{
+                                "title": "",
+                                "fields": [
+                                    {"title": "number", "id": "ccnum", "value": {"creditCardNumber": "4111111111111111"}},
+                                    {"title": "expiry date", "id": "expiry", "value": {"monthYear": 203012}}
+                                ]
+                            }]
+                        },
+                        "overview": {"title": "Visa"}
+                    }
+                ]
+            }]
+        }]
+    }"#;
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_import_1pux() {
+        let data = archive(&[
+            (EXPORT_DATA, EXPORT.as_bytes()),
+            ("files/doc1__codes.txt", b"12345"),
+        ]);
+
+        let mut root = KpGroup::new("Root");
+        let summary = import(&mut root, &data).unwrap();
+
+        assert_eq!(summary.entries, 2);
+        assert_eq!(summary.groups, 2);
+        assert!(summary.warnings.is_empty());
+
+        let KpNode::Group(private) = &root.children[0] else {
+            panic!("expected a group");
+        };
+        let mail = private.entries()[0];
+
+        assert_eq!(mail.get_title(), Some("Mail"));
+        assert_eq!(mail.get_username(), Some("alice"));
+        assert_eq!(mail.get_password(), Some("secret"));
+        assert_eq!(mail.get("KP2A_URL_1"), Some("https://webmail.example.com"));
+        assert_eq!(mail.get("pin"), Some("0000"));
+        assert_eq!(mail.get("Recovery code"), Some("abcd-efgh"));
+        assert!(matches!(
+            mail.fields.get("Recovery code"),
+            Some(KpValue::Protected(..))
+        ));
+        assert!(mail.get_raw_otp_value().unwrap().starts_with("otpauth://"));
+        assert_eq!(mail.tags, ["work"]);
+        assert!(matches!(
+            mail.fields.get("codes.txt"),
+            Some(KpValue::Bytes(data)) if data == b"12345"
+        ));
+
+        let visa = private.groups()[0].entries()[0];
+        assert_eq!(private.groups()[0].name, ARCHIVE_GROUP);
+        assert_eq!(visa.get("number"), Some("4111111111111111"));
+        assert_eq!(visa.get("expiry date"), Some("12/2030"));
+    }
+
+    #[test]
+    fn test_missing_attachment_and_export_data() {
+        let data = archive(&[(EXPORT_DATA, EXPORT.as_bytes())]);
+
+        let mut root = KpGroup::new("Root");
+        let summary = import(&mut root, &data).unwrap();
+        assert_eq!(summary.warnings.len(), 1);
+
+        let data = archive(&[("files/doc1__codes.txt", b"12345")]);
+        assert!(import(&mut root, &data).is_err());
+    }
+
+    #[test]
+    fn test_binary_attachment_is_skipped() {
+        let data = archive(&[
+            (EXPORT_DATA, EXPORT.as_bytes()),
+            ("files/doc1__codes.txt", &[0xff, 0xfe, 0x00, 0x80]),
+        ]);
+
+        let mut database = keepass::Database::new(Default::default());
+        let summary = import(&mut database.root, &data).unwrap();
+        assert_eq!(summary.entries, 2);
+        assert_eq!(summary.warnings.len(), 1);
+        assert!(summary.warnings[0].contains("codes.txt"));
+
+        // the database can still be saved
+        let key = keepass::DatabaseKey::new().with_password("test");
+        let mut saved = Vec::new();
+        database.save(&mut saved, key.clone()).unwrap();
+
+        let database = keepass::Database::open(&mut &saved[..], key).unwrap();
+        let KpNode::Group(private) = &database.root.children[0] else {
+            panic!("expected a group");
+        };
+        assert_eq!(private.entries()[0].get_password(), Some("secret"));
+    }
+}
@@ -0,0 +1,528 @@
+//! Sharing of groups between databases through KeeShare containers
+//!
+//! Like in KeePassXC, a shared group stores a reference to its container in the custom data item
+//! `KeeShare/Reference`, which holds Base64-encoded XML with the share type and the Base64-encoded
+//! path and password of the container. Containers are KDBX databases whose root group is a copy of
+//! the shared group. Groups are written to their containers when saving, and containers are merged
+//! into their groups when unlocking. Entries are matched by UUID, and the most recently modified
+//! version wins while the other one is kept in the history. Deletions are not synchronized.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use keepass::db::{
+    CustomDataItem, Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node as KpNode,
+    NodeRef, Value as KpValue,
+};
+use uuid::Uuid;
+
+use crate::attachment::Attachments;
+use crate::exchange::{GroupExportOptions, Share, ShareReference, ShareType};
+use crate::source::DatabaseSource;
+
+/// Key of the custom data item of a group that holds its reference
+const REFERENCE_KEY: &str = "KeeShare/Reference";
+
+/// Read the reference of a shared group
+pub(crate) fn get_reference(group: &KpGroup) -> Result<Option<ShareReference>> {
+    let Some(value) = group
+        .custom_data
+        .items
+        .get(REFERENCE_KEY)
+        .and_then(|item| item.value.as_ref())
+    else {
+        return Ok(None);
+    };
+
+    let KpValue::Unprotected(value) = value else {
+        bail!("Invalid KeeShare reference");
+    };
+
+    parse_reference(value).map(Some)
+}
+
+/// Share a group through a container, or stop sharing it
+pub(crate) fn set_reference(group: &mut KpGroup, reference: Option<&ShareReference>) {
+    let Some(reference) = reference else {
+        group.custom_data.items.remove(REFERENCE_KEY);
+        return;
+    };
+
+    let value = STANDARD.encode(serialize_reference(&group.uuid, reference));
+
+    group.custom_data.items.insert(
+        REFERENCE_KEY.to_string(),
+        CustomDataItem {
+            value: Some(KpValue::Unprotected(value)),
+            last_modification_time: Some(keepass::db::Times::now()),
+        },
+    );
+}
+
+/// Parse a reference, which KeePassXC stores Base64-encoded
+fn parse_reference(value: &str) -> Result<ShareReference> {
+    let xml = match STANDARD.decode(value.trim()) {
+        Ok(decoded) => String::from_utf8(decoded).context("Invalid KeeShare reference")?,
+        Err(..) => value.to_string(),
+    };
+
+    let document = roxmltree::Document::parse(&xml).context("Invalid KeeShare reference")?;
+    let root = document.root_element();
+
+    let child = |name: &str| root.children().find(|c| c.has_tag_name(name));
+    let decoded = |name: &str| -> Result<String> {
+        let text = child(name).and_then(|c| c.text()).unwrap_or_default();
+        let bytes = STANDARD
+            .decode(text.trim())
+            .with_context(|| format!("Invalid {} in KeeShare reference", name))?;
+        Ok(String::from_utf8(bytes)?)
+    };
+
+    let share_type = child("Type").ok_or(anyhow!("KeeShare reference without type"))?;
+    let import = share_type.children().any(|c| c.has_tag_name("Import"));
+    let export = share_type.children().any(|c| c.has_tag_name("Export"));
+
+    let share_type = match (import, export) {
+        (true, true) => ShareType::Synchronize,
+        (true, false) => ShareType::Import,
+        (false, true) => ShareType::Export,
+        (false, false) => bail!("KeeShare reference without type"),
+    };
+
+    Ok(ShareReference {
+        share_type,
+        path: decoded("Path")?,
+        password: decoded("Password")?,
+    })
+}
+
+fn serialize_reference(group_uuid: &Uuid, reference: &ShareReference) -> String {
+    let share_type = match reference.share_type {
+        ShareType::Import => "<Import/>",
+        ShareType::Export => "<Export/>",
+        ShareType::Synchronize => "<Import/><Export/>",
+    };
+
+    format!(
+        "<?xml version=\"1.0\"?>\n<KeeShare><Type>{}</Type><Group>{}</Group><Path>{}</Path><Password>{}</Password></KeeShare>\n",
+        share_type,
+        STANDARD.encode(group_uuid.as_bytes()),
+        STANDARD.encode(&reference.path),
+        STANDARD.encode(&reference.password),
+    )
+}
+
+/// List the shared groups of a database, skipping invalid references
+pub(crate) fn list_shares(database: &KpDatabase) -> Vec<Share> {
+    database
+        .root
+        .iter()
+        .filter_map(|node| match node {
+            NodeRef::Group(group) => Some(group),
+            NodeRef::Entry(..) => None,
+        })
+        .filter_map(|group| {
+            let reference = get_reference(group).ok().flatten()?;
+
+            Some(Share {
+                group_uuid: group.uuid,
+                group_name: group.name.clone(),
+                reference,
+            })
+        })
+        .collect()
+}
+
+/// Merge the containers of all imported and synchronized groups into their groups
+///
+/// Missing containers are skipped. Problems with individual containers do not stop the others
+/// from being imported and are reported together.
+pub(crate) async fn import_all(
+    database: &mut KpDatabase,
+    source: &dyn DatabaseSource,
+) -> Result<()> {
+    let containers = read_containers(list_shares(database), source).await;
+
+    merge_containers(database, containers)
+}
+
+/// Read the containers of the imported and synchronized groups among some shares, or `None` for
+/// missing ones
+pub(crate) async fn read_containers(
+    shares: Vec<Share>,
+    source: &dyn DatabaseSource,
+) -> Vec<(Share, Result<Option<Vec<u8>>>)> {
+    let mut containers = Vec::new();
+
+    for share in shares {
+        if share.reference.share_type == ShareType::Export {
+            continue;
+        }
+
+        let data = source.read_sibling(&share.reference.path).await;
+
+        containers.push((share, data));
+    }
+
+    containers
+}
+
+/// Merge containers read by [`read_containers`] into their groups, see [`import_all`]
+pub(crate) fn merge_containers(
+    database: &mut KpDatabase,
+    containers: Vec<(Share, Result<Option<Vec<u8>>>)>,
+) -> Result<()> {
+    let mut errors = Vec::new();
+
+    for (share, data) in containers {
+        let data = match data {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                errors.push(format!("{}: {}", share.reference.path, e));
+                continue;
+            }
+        };
+
+        let Some(group) = crate::database::find_group_mut(&mut database.root, &share.group_uuid)
+        else {
+            continue;
+        };
+
+        if let Err(e) = import_container(group, &data, &share.reference.password) {
+            errors.push(format!("{}: {}", share.reference.path, e));
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "Importing KeeShare containers failed: {}",
+            errors.join("; ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Export all exported and synchronized groups into containers, by the paths to write them to
+pub(crate) fn export_containers(
+    database: &KpDatabase,
+    attachments: &Attachments,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut containers = Vec::new();
+
+    for share in list_shares(database) {
+        if share.reference.share_type == ShareType::Import {
+            continue;
+        }
+
+        let Some(group) = database.root.iter().find_map(|node| match node {
+            NodeRef::Group(g) if g.uuid == share.group_uuid => Some(g),
+            _ => None,
+        }) else {
+            continue;
+        };
+
+        let data = export_container(database, attachments, group, &share.reference.password)
+            .with_context(|| format!("Exporting KeeShare container {}", share.reference.path))?;
+
+        containers.push((share.reference.path, data));
+    }
+
+    Ok(containers)
+}
+
+/// Write containers exported by [`export_containers`] next to the database
+pub(crate) async fn write_containers(
+    containers: &[(String, Vec<u8>)],
+    source: &mut dyn DatabaseSource,
+) -> Result<()> {
+    for (path, data) in containers {
+        source
+            .write_sibling(path, data)
+            .await
+            .with_context(|| format!("Writing KeeShare container {}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Write a group into a new container protected by a password
+fn export_container(
+    database: &KpDatabase,
+    attachments: &Attachments,
+    group: &KpGroup,
+    password: &str,
+) -> Result<Vec<u8>> {
+    let options = GroupExportOptions {
+        name: None,
+        include_history: false,
+    };
+
+    let mut container =
+        crate::export::kdbx::group_as_database(database, attachments, group, &options);
+
+    // the references of shared groups contain the passwords of their containers
+    remove_references(&mut container.database.root);
+
+    container.save(Some(password), None)
+}
+
+fn remove_references(group: &mut KpGroup) {
+    group.custom_data.items.remove(REFERENCE_KEY);
+
+    for node in group.children.iter_mut() {
+        if let KpNode::Group(child) = node {
+            remove_references(child);
+        }
+    }
+}
+
+/// Merge a container into a group
+fn import_container(group: &mut KpGroup, data: &[u8], password: &str) -> Result<()> {
+    let key = keepass::DatabaseKey::new().with_password(password);
+    let mut container = KpDatabase::open(&mut &data[..], key)?;
+
+    remove_references(&mut container.root);
+
+    let mut locations = HashMap::new();
+    entry_locations(&container.root, group.uuid, &mut locations);
+
+    let mut moved = HashMap::new();
+    take_moved_entries(group, &locations, &mut moved);
+
+    merge_group(group, &container.root, &mut moved);
+
+    Ok(())
+}
+
+/// Map the UUIDs of the entries of a container to the UUIDs of the groups that contain them, with
+/// the root group standing for the shared group
+fn entry_locations(group: &KpGroup, group_uuid: Uuid, locations: &mut HashMap<Uuid, Uuid>) {
+    for node in &group.children {
+        match node {
+            KpNode::Entry(entry) => {
+                locations.insert(entry.uuid, group_uuid);
+            }
+            KpNode::Group(child) => entry_locations(child, child.uuid, locations),
+        }
+    }
+}
+
+/// Detach the entries that the container has in another group, so that they can be moved
+fn take_moved_entries(
+    group: &mut KpGroup,
+    locations: &HashMap<Uuid, Uuid>,
+    moved: &mut HashMap<Uuid, KpEntry>,
+) {
+    let mut kept = Vec::with_capacity(group.children.len());
+
+    for node in std::mem::take(&mut group.children) {
+        match node {
+            KpNode::Entry(entry)
+                if locations
+                    .get(&entry.uuid)
+                    .is_some_and(|location| location != &group.uuid) =>
+            {
+                moved.insert(entry.uuid, entry);
+            }
+            KpNode::Group(mut child) => {
+                take_moved_entries(&mut child, locations, moved);
+                kept.push(KpNode::Group(child));
+            }
+            node => kept.push(node),
+        }
+    }
+
+    group.children = kept;
+}
+
+fn merge_group(target: &mut KpGroup, source: &KpGroup, moved: &mut HashMap<Uuid, KpEntry>) {
+    for node in &source.children {
+        match node {
+            KpNode::Entry(incoming) => {
+                let existing = target.children.iter_mut().find_map(|node| match node {
+                    KpNode::Entry(entry) if entry.uuid == incoming.uuid => Some(entry),
+                    _ => None,
+                });
+
+                if let Some(existing) = existing {
+                    merge_entry(existing, incoming);
+                } else if let Some(mut existing) = moved.remove(&incoming.uuid) {
+                    merge_entry(&mut existing, incoming);
+                    target.children.push(KpNode::Entry(existing));
+                } else {
+                    target.children.push(KpNode::Entry(incoming.clone()));
+                }
+            }
+            KpNode::Group(incoming) => {
+                let position = target
+                    .children
+                    .iter()
+                    .position(|node| matches!(node, KpNode::Group(g) if g.uuid == incoming.uuid));
+
+                let position = position.unwrap_or_else(|| {
+                    let mut group = incoming.clone();
+                    group.children.clear();
+                    target.children.push(KpNode::Group(group));
+                    target.children.len() - 1
+                });
+
+                if let KpNode::Group(child) = &mut target.children[position] {
+                    merge_group(child, incoming, moved);
+                }
+            }
+        }
+    }
+}
+
+/// Replace an entry by a newer version from a container, keeping the previous one in its history
+fn merge_entry(existing: &mut KpEntry, incoming: &KpEntry) {
+    if incoming.times.get_last_modification() <= existing.times.get_last_modification() {
+        return;
+    }
+
+    let mut history = existing.history.take().unwrap_or_default();
+
+    let mut previous = std::mem::replace(existing, incoming.clone());
+    previous.history = None;
+    history.add_entry(previous);
+
+    existing.history = Some(history);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::source::{BufferDatabaseSource, block_on};
+
+    fn reference(share_type: ShareType) -> ShareReference {
+        ShareReference {
+            share_type,
+            path: "shares/team.kdbx".to_string(),
+            password: "team password".to_string(),
+        }
+    }
+
+    fn time(s: &str) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_reference() {
+        let mut group = KpGroup::new("Team");
+        assert!(get_reference(&group).unwrap().is_none());
+
+        set_reference(&mut group, Some(&reference(ShareType::Synchronize)));
+        assert_eq!(
+            get_reference(&group).unwrap(),
+            Some(reference(ShareType::Synchronize))
+        );
+
+        set_reference(&mut group, None);
+        assert!(get_reference(&group).unwrap().is_none());
+
+        // as written by KeePassXC
+        let xml = "<?xml version=\"1.0\"?>\n<KeeShare><Type><Import/></Type><Group>AAAAAAAAAAAAAAAAAAAAAA==</Group><Path>dGVhbS5rZGJ4</Path><Password>c2VjcmV0</Password></KeeShare>\n";
+        let parsed = parse_reference(&STANDARD.encode(xml)).unwrap();
+        assert_eq!(parsed.share_type, ShareType::Import);
+        assert_eq!(parsed.path, "team.kdbx");
+        assert_eq!(parsed.password, "secret");
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        // the exporting database
+        let mut alice = KpDatabase::new(Default::default());
+        let mut team = KpGroup::new("Team");
+        set_reference(&mut team, Some(&reference(ShareType::Export)));
+
+        let mut wifi = crate::import::new_entry("Wi-Fi", "", "guest", "", "");
+        wifi.times
+            .set_last_modification(time("2024-01-01 00:00:00"));
+        team.children.push(KpNode::Entry(wifi.clone()));
+
+        let mut servers = KpGroup::new("Servers");
+        servers
+            .children
+            .push(KpNode::Entry(crate::import::new_entry(
+                "Router", "admin", "1234", "", "",
+            )));
+        team.children.push(KpNode::Group(servers));
+
+        alice.root.children.push(KpNode::Group(team));
+
+        let mut source = BufferDatabaseSource::empty("test.kdbx");
+        let containers = export_containers(&alice, &Attachments::default()).unwrap();
+        block_on(write_containers(&containers, &mut source)).unwrap();
+
+        let container = source.0.siblings.get("shares/team.kdbx").unwrap().clone();
+        let opened = KpDatabase::open(
+            &mut &container[..],
+            keepass::DatabaseKey::new().with_password("team password"),
+        )
+        .unwrap();
+        assert_eq!(opened.root.name, "Team");
+        assert!(get_reference(&opened.root).unwrap().is_none());
+
+        // the importing database has an older version of one of the entries
+        let mut bob = KpDatabase::new(Default::default());
+        let mut shared = KpGroup::new("From Alice");
+        set_reference(&mut shared, Some(&reference(ShareType::Import)));
+
+        let mut old_wifi = wifi.clone();
+        crate::import::set_protected(&mut old_wifi, "Password", "old");
+        old_wifi
+            .times
+            .set_last_modification(time("2023-01-01 00:00:00"));
+        shared.children.push(KpNode::Entry(old_wifi));
+        bob.root.children.push(KpNode::Group(shared));
+
+        let mut source = BufferDatabaseSource::empty("test.kdbx");
+        source
+            .0
+            .siblings
+            .insert("shares/team.kdbx".to_string(), container);
+        block_on(import_all(&mut bob, &source)).unwrap();
+
+        let shared = bob.root.groups()[0];
+        assert_eq!(shared.entries().len(), 1);
+        assert_eq!(shared.entries()[0].get_password(), Some("guest"));
+        assert_eq!(
+            shared.entries()[0].history.as_ref().unwrap().get_entries()[0].get_password(),
+            Some("old")
+        );
+        assert_eq!(shared.groups()[0].name, "Servers");
+        assert_eq!(
+            shared.groups()[0].entries()[0].get_username(),
+            Some("admin")
+        );
+
+        // importing again does not duplicate anything
+        block_on(import_all(&mut bob, &source)).unwrap();
+        let shared = bob.root.groups()[0];
+        assert_eq!(shared.entries().len(), 1);
+        assert_eq!(shared.groups()[0].entries().len(), 1);
+    }
+
+    #[test]
+    fn test_import_problems() {
+        let mut database = KpDatabase::new(Default::default());
+        let mut shared = KpGroup::new("Shared");
+        set_reference(&mut shared, Some(&reference(ShareType::Import)));
+        database.root.children.push(KpNode::Group(shared));
+
+        // a missing container is skipped
+        let mut source = BufferDatabaseSource::empty("test.kdbx");
+        block_on(import_all(&mut database, &source)).unwrap();
+
+        source
+            .0
+            .siblings
+            .insert("shares/team.kdbx".to_string(), b"not a database".to_vec());
+        assert!(block_on(import_all(&mut database, &source)).is_err());
+    }
+}
@@ -1,8 +1,24 @@
 mod icon;
 
+pub mod attachment;
+pub mod autotype;
+pub mod browser;
 mod database;
 mod exchange;
+pub mod export;
+pub mod favicon;
+pub mod generator;
+pub mod import;
+mod keeshare;
+mod operation;
+mod otp;
+mod placeholder;
+pub mod report;
+pub mod secret_service;
 mod source;
+#[cfg(feature = "tauri")]
+pub mod ssh_agent;
+pub mod url_match;
 
 use std::str::FromStr;
 
@@ -10,19 +26,26 @@ use std::str::FromStr;
 use std::path::Path;
 
 use anyhow::Result;
-use keepass::db::{Node, Value as KpValue};
+use keepass::db::{Node, NodeRef, Value as KpValue};
 
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
 
-use crate::database::Database;
+use crate::database::{CheckedOut, Database, DatabaseState};
 
 pub use crate::exchange::*;
+pub use crate::operation::{
+    PendingSave, PendingShareContainer, PendingShareImport, PendingUnlock, ReadShares,
+    SavedDatabase, ShareContainer, SharedState, UnlockedDatabase,
+};
 
 #[wasm_bindgen]
 #[derive(Default)]
 pub struct AppState {
     databases: Vec<Database>,
+
+    #[cfg(feature = "tauri")]
+    ssh_keys: std::sync::Arc<std::sync::Mutex<ssh_agent::KeyStore>>,
 }
 
 #[cfg_attr(not(feature = "tauri"), wasm_bindgen)]
@@ -41,19 +64,47 @@ impl AppState {
         self.databases.iter().map(|db| db.into()).collect()
     }
 
+    /// Get the identifier of a loaded database, which stays valid when other databases are closed
+    #[cfg(feature = "tauri")]
+    pub fn database_id(&self, database_idx: usize) -> Result<Uuid, String> {
+        self.databases
+            .get(database_idx)
+            .map(|db| db.id)
+            .ok_or("Cannot get database by that index".to_string())
+    }
+
+    /// Find the current index of a loaded database by its identifier
+    #[cfg(feature = "tauri")]
+    pub fn database_index(&self, database_id: &Uuid) -> Result<usize, String> {
+        self.databases
+            .iter()
+            .position(|db| &db.id == database_id)
+            .ok_or("The database has been closed".to_string())
+    }
+
     /// Load and unlock a demo database
-    #[cfg(not(feature = "tauri"))]
     pub fn load_demo(&mut self) -> Result<DatabaseOverview, String> {
         let data = include_bytes!("demo.kdbx");
 
-        let mut db = Database::load(crate::source::BufferDatabaseSource {
-            name: "demo.kdbx".to_string(),
-            buffer: data.to_vec(),
-        })
+        // the demo is built in, so it is decrypted right away instead of through its source
+        let key = keepass::DatabaseKey::new().with_password("demopass");
+        let database =
+            keepass::Database::open(&mut &data[..], key.clone()).map_err(|e| format!("{}", e))?;
+
+        let mut db = Database::load(crate::source::Blocking(
+            crate::source::BufferDatabaseSource {
+                name: "demo.kdbx".to_string(),
+                buffer: data.to_vec(),
+                siblings: Default::default(),
+            },
+        ))
         .map_err(|e| format!("{}", e))?;
 
-        db.unlock(Some("demopass".to_string()), None)
-            .map_err(|e| format!("{}", e))?;
+        db.state = crate::database::DatabaseState::Unlocked {
+            database,
+            key,
+            attachments: Default::default(),
+        };
 
         let res: DatabaseOverview = (&db).into();
         self.databases.push(db);
@@ -61,50 +112,146 @@ impl AppState {
         Ok(res)
     }
 
-    /// Load and unlock a demo database
-    #[cfg(feature = "tauri")]
-    pub fn load_demo(&mut self, app: tauri::AppHandle) -> Result<DatabaseOverview, String> {
-        let data = include_bytes!("demo.kdbx");
-
-        let mut db = Database::load(crate::source::BufferDatabaseSource {
-            name: "demo.kdbx".to_string(),
-            buffer: data.to_vec(),
-        })
+    /// Load a new database from a buffer
+    pub fn load_database_buffer(
+        &mut self,
+        name: String,
+        data: &[u8],
+    ) -> Result<DatabaseOverview, String> {
+        let db = Database::load(crate::source::Blocking(
+            crate::source::BufferDatabaseSource {
+                name,
+                buffer: data.to_vec(),
+                siblings: Default::default(),
+            },
+        ))
         .map_err(|e| format!("{}", e))?;
 
-        db.unlock(Some("demopass".to_string()), None, app)
-            .map_err(|e| format!("{}", e))?;
-
         let res: DatabaseOverview = (&db).into();
         self.databases.push(db);
 
         Ok(res)
     }
 
-    /// Load a new database from a buffer
-    pub fn load_database_buffer(
+    /// Create a new, empty database protected by a password and/or key file
+    ///
+    /// The database is kept in a buffer until it is saved to a destination.
+    pub fn create_database(
         &mut self,
         name: String,
-        data: &[u8],
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
     ) -> Result<DatabaseOverview, String> {
-        let db = Database::load(crate::source::BufferDatabaseSource {
-            name,
-            buffer: data.to_vec(),
+        let mut database = keepass::Database::new(Default::default());
+        database.root.name = name.clone();
+        database.meta.database_name = Some(name.clone());
+
+        self.add_new_database(name, database, password, keyfile)
+    }
+
+    /// Convert a KeePass 1.x database into a new database, keeping its password and key file
+    pub fn import_kdb(
+        &mut self,
+        name: String,
+        data: &[u8],
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<ImportedDatabase, String> {
+        let (database, warnings) =
+            crate::import::kdb::import(&name, data, password.as_deref(), keyfile.as_deref())
+                .map_err(|e| format!("{}", e))?;
+
+        Ok(ImportedDatabase {
+            database: self.add_new_database(name, database, password, keyfile)?,
+            warnings,
+        })
+    }
+
+    /// Convert a KeePass 2.x XML export into a new database protected by a password or key file
+    pub fn import_keepass_xml(
+        &mut self,
+        name: String,
+        data: &[u8],
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<ImportedDatabase, String> {
+        let (database, warnings) =
+            crate::import::keepass_xml::import(data).map_err(|e| format!("{}", e))?;
+
+        Ok(ImportedDatabase {
+            database: self.add_new_database(name, database, password, keyfile)?,
+            warnings,
         })
+    }
+
+    /// Add an unlocked database that is not saved yet and will be saved to a buffer
+    fn add_new_database(
+        &mut self,
+        name: String,
+        database: keepass::Database,
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<DatabaseOverview, String> {
+        let key = Self::new_key(password, keyfile)?;
+
+        let mut db = Database::load(crate::source::Blocking(
+            crate::source::BufferDatabaseSource {
+                name: format!("{name}.kdbx"),
+                buffer: Vec::new(),
+                siblings: Default::default(),
+            },
+        ))
         .map_err(|e| format!("{}", e))?;
 
+        db.state = crate::database::DatabaseState::Unlocked {
+            database,
+            key,
+            attachments: Default::default(),
+        };
+
         let res: DatabaseOverview = (&db).into();
         self.databases.push(db);
 
         Ok(res)
     }
 
+    /// Build the key of a new database, which needs a password, a key file or both
+    fn new_key(
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<keepass::DatabaseKey, String> {
+        let mut key = keepass::DatabaseKey::new();
+
+        if let Some(p) = password {
+            key = key.with_password(&p);
+        }
+
+        if let Some(kf) = keyfile {
+            key = key
+                .with_keyfile(&mut &kf[..])
+                .map_err(|e| format!("Reading keyfile: {}", e))?;
+        }
+
+        if key.is_empty() {
+            return Err("A password or key file is required".to_string());
+        }
+
+        Ok(key)
+    }
+
     /// Load a new database from a filesystem path
     #[cfg(feature = "tauri")]
-    pub fn load_database_path(&mut self, path: &Path) -> Result<DatabaseOverview, String> {
-        let db = Database::load(crate::source::FilesystemDatabaseSource {
-            path: tauri_plugin_fs::FilePath::Path(path.to_path_buf()),
-        })
+    pub fn load_database_path(
+        &mut self,
+        path: &Path,
+        app: tauri::AppHandle,
+    ) -> Result<DatabaseOverview, String> {
+        let db = Database::load(crate::source::Blocking(
+            crate::source::FilesystemDatabaseSource {
+                path: tauri_plugin_fs::FilePath::Path(path.to_path_buf()),
+                app,
+            },
+        ))
         .map_err(|e| format!("{}", e))?;
 
         let res: DatabaseOverview = (&db).into();
@@ -113,40 +260,255 @@ impl AppState {
         Ok(res)
     }
 
-    /// Unlock a loaded database
-    #[cfg(not(feature = "tauri"))]
-    pub fn unlock_database(
+    /// Load a new database from a WebDAV server, like Nextcloud
+    ///
+    /// If the server needs a login, the user name and password are taken from an entry of another
+    /// unlocked database.
+    pub fn load_database_webdav(
         &mut self,
-        database_idx: usize,
-        password: Option<String>,
-        keyfile: Option<Vec<u8>>,
+        url: &str,
+        credentials: Option<RemoteCredentials>,
     ) -> Result<DatabaseOverview, String> {
-        let Some(db) = self.databases.get_mut(database_idx) else {
-            return Err("No database by that index".to_string());
+        let credentials = credentials
+            .map(|c| self.remote_credentials(&c))
+            .transpose()?;
+
+        let client = crate::source::default_client().map_err(|e| format!("{}", e))?;
+        let source = crate::source::WebDavDatabaseSource::new(url, credentials, client)
+            .map_err(|e| format!("{}", e))?;
+
+        let db = Database::load(source).map_err(|e| format!("{}", e))?;
+
+        let res: DatabaseOverview = (&db).into();
+        self.databases.push(db);
+
+        Ok(res)
+    }
+
+    /// Load a new database from an object in an S3-compatible bucket
+    ///
+    /// The access key ID and secret access key are taken from the user name and password of an
+    /// entry of another unlocked database.
+    pub fn load_database_s3(&mut self, settings: S3Settings) -> Result<DatabaseOverview, String> {
+        let credentials = self.remote_credentials(&settings.credentials)?;
+
+        let location = crate::source::S3Location {
+            endpoint: settings.endpoint,
+            region: settings.region,
+            bucket: settings.bucket,
+            key: settings.key,
+            path_style: settings.path_style,
         };
 
-        db.unlock(password, keyfile).map_err(|e| format!("{}", e))?;
+        let client = crate::source::default_client().map_err(|e| format!("{}", e))?;
+        let source = crate::source::S3DatabaseSource::new(
+            location,
+            settings.version_id,
+            credentials,
+            client,
+        )
+        .map_err(|e| format!("{}", e))?;
 
-        Ok((&*db).into())
+        let db = Database::load(source).map_err(|e| format!("{}", e))?;
+
+        let res: DatabaseOverview = (&db).into();
+        self.databases.push(db);
+
+        Ok(res)
+    }
+
+    /// List the databases that the browser keeps between sessions
+    ///
+    /// Like the other methods that wait for the storage of the browser, this does not take the
+    /// state, so that it stays usable in the meantime.
+    #[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+    pub async fn list_stored_databases() -> Result<Vec<StoredDatabase>, String> {
+        crate::source::list_stored_databases()
+            .await
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Keep a database in the storage of the browser, to be loaded with `load_database_stored` so
+    /// that it is saved in place and available in later sessions
+    #[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+    pub async fn store_database(name: String, data: &[u8]) -> Result<StoredDatabase, String> {
+        crate::source::store_database(&name, data)
+            .await
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Remember a database file on disk that the user picked through the File System Access API,
+    /// to be loaded with `load_database_stored` so that it is saved back to the file
+    #[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+    pub async fn link_database_file(
+        handle: web_sys::FileSystemFileHandle,
+    ) -> Result<StoredDatabase, String> {
+        crate::source::link_database_file(handle)
+            .await
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Load a database that the browser keeps between sessions
+    #[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+    pub fn load_database_stored(
+        &mut self,
+        database: StoredDatabase,
+    ) -> Result<DatabaseOverview, String> {
+        let db = if database.linked {
+            Database::load(crate::source::FileHandleDatabaseSource::new(
+                &database.id,
+                &database.name,
+            ))
+        } else {
+            crate::source::StoredDatabaseSource::new(&database.id).and_then(Database::load)
+        }
+        .map_err(|e| format!("{}", e))?;
+
+        let res: DatabaseOverview = (&db).into();
+        self.databases.push(db);
+
+        Ok(res)
+    }
+
+    /// Delete a database from the storage of the browser, or forget a database file on disk
+    #[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+    pub async fn remove_stored_database(database: StoredDatabase) -> Result<(), String> {
+        crate::source::remove_stored_database(&database)
+            .await
+            .map_err(|e| format!("{}", e))
     }
 
-    /// Unlock a loaded database
+    /// Load a new database from a server over SSH
+    ///
+    /// The user name, and the password or SSH key to log in with are taken from an entry of
+    /// another unlocked database.
     #[cfg(feature = "tauri")]
-    pub fn unlock_database(
+    pub fn load_database_sftp(
+        &mut self,
+        settings: SftpSettings,
+    ) -> Result<DatabaseOverview, String> {
+        use crate::source::SftpLogin;
+
+        let entry = self.credentials_entry(&settings.credentials)?;
+
+        let login = match settings.auth {
+            SftpAuth::Password => {
+                SftpLogin::Password(entry.get_password().unwrap_or_default().to_string())
+            }
+            SftpAuth::Key => {
+                let attachments = self.databases[settings.credentials.database_idx]
+                    .attachments()
+                    .map_err(|e| format!("{}", e))?;
+
+                let key_settings = crate::ssh_agent::EntrySettings::from_entry(entry, attachments)
+                    .map_err(|e| format!("{}", e))?
+                    .ok_or("The entry has no SSH key".to_string())?;
+
+                let key = crate::ssh_agent::load_entry_key(entry, attachments, &key_settings)
+                    .map_err(|e| format!("{}", e))?;
+
+                SftpLogin::Key(Box::new(key))
+            }
+        };
+
+        let location = crate::source::SftpLocation {
+            host: settings.host,
+            port: settings.port,
+            username: entry.get_username().unwrap_or_default().to_string(),
+            path: settings.path,
+            host_key_fingerprint: settings.host_key_fingerprint,
+        };
+
+        let source = crate::source::SftpDatabaseSource::new(location, login)
+            .map_err(|e| format!("{}", e))?;
+
+        let db = Database::load(source).map_err(|e| format!("{}", e))?;
+
+        let res: DatabaseOverview = (&db).into();
+        self.databases.push(db);
+
+        Ok(res)
+    }
+
+    /// Get the user name and password to log in to a remote database with from an entry
+    fn remote_credentials(
+        &self,
+        credentials: &RemoteCredentials,
+    ) -> Result<crate::source::Credentials, String> {
+        let entry = self.credentials_entry(credentials)?;
+
+        Ok(crate::source::Credentials {
+            username: entry.get_username().unwrap_or_default().to_string(),
+            password: entry.get_password().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Get the entry holding the login to a remote database
+    fn credentials_entry(
+        &self,
+        credentials: &RemoteCredentials,
+    ) -> Result<&keepass::db::Entry, String> {
+        self.databases
+            .get(credentials.database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .entry(&credentials.entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Entry not found by UUID".to_string())
+    }
+
+    /// Start unlocking a loaded database, see [`PendingUnlock`]
+    pub fn prepare_unlock(
         &mut self,
         database_idx: usize,
         password: Option<String>,
         keyfile: Option<Vec<u8>>,
-        app: tauri::AppHandle,
+    ) -> Result<PendingUnlock, String> {
+        let source = self.check_out(database_idx)?;
+
+        Ok(PendingUnlock {
+            source,
+            password,
+            keyfile,
+        })
+    }
+
+    /// Take the source out of a database for an operation that waits for it
+    fn check_out(&mut self, database_idx: usize) -> Result<CheckedOut, String> {
+        self.databases
+            .get_mut(database_idx)
+            .ok_or("No database by that index".to_string())?
+            .check_out()
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Return a source taken out with [`AppState::check_out`] to its database, and get the
+    /// current index of the database unless it has been closed
+    fn check_in(&mut self, checked_out: CheckedOut) -> Option<usize> {
+        let database_idx = self
+            .databases
+            .iter()
+            .position(|db| db.id == checked_out.id)?;
+
+        self.databases[database_idx].check_in(checked_out);
+
+        Some(database_idx)
+    }
+
+    /// Unlock a database read by a [`PendingUnlock`]
+    pub fn finish_unlock(
+        &mut self,
+        unlocked: UnlockedDatabase,
     ) -> Result<DatabaseOverview, String> {
-        let Some(db) = self.databases.get_mut(database_idx) else {
-            return Err("No database by that index".to_string());
-        };
+        let database_idx = self
+            .check_in(unlocked.source)
+            .ok_or("The database has been closed".to_string())?;
 
-        db.unlock(password, keyfile, app)
-            .map_err(|e| format!("{}", e))?;
+        self.databases[database_idx].state = unlocked.state.map_err(|e| format!("{}", e))?;
 
-        Ok((&*db).into())
+        #[cfg(feature = "tauri")]
+        self.add_ssh_keys_at_open(database_idx);
+
+        Ok((&self.databases[database_idx]).into())
     }
 
     /// Lock a loaded database
@@ -155,145 +517,1198 @@ impl AppState {
             return Err("No database by that index".to_string());
         };
 
+        #[cfg(feature = "tauri")]
+        self.ssh_keys.lock().unwrap().remove_database(&db.id);
+
         db.lock();
         Ok((&*db).into())
     }
 
-    /// Save a database to the same path it was loaded from
-    #[cfg(not(feature = "tauri"))]
-    pub fn save_database(&mut self, database_idx: usize) -> Result<Option<Vec<u8>>, String> {
-        let Some(db) = self.databases.get_mut(database_idx) else {
-            return Err("No database by that index".to_string());
+    /// Start saving a database to the same path it was loaded from, see [`PendingSave`]
+    pub fn prepare_save(&mut self, database_idx: usize) -> Result<PendingSave, String> {
+        let db = self
+            .databases
+            .get(database_idx)
+            .ok_or("No database by that index".to_string())?;
+
+        let DatabaseState::Unlocked {
+            database,
+            key,
+            attachments,
+        } = &db.state
+        else {
+            return Err("Database must be open to save".to_string());
         };
 
-        db.save().map_err(|e| format!("{}", e))
-    }
+        let mut data = Vec::new();
+        database
+            .save(&mut data, key.clone())
+            .map_err(|e| format!("{}", e))?;
 
-    /// Save a database to the same path it was loaded from
-    #[cfg(feature = "tauri")]
-    pub fn save_database(
-        &mut self,
-        database_idx: usize,
-        app: tauri::AppHandle,
-    ) -> Result<Option<Vec<u8>>, String> {
-        let Some(db) = self.databases.get_mut(database_idx) else {
-            return Err("No database by that index".to_string());
-        };
+        let containers = crate::keeshare::export_containers(database, attachments)
+            .map_err(|e| format!("{}", e))?;
 
-        db.save(app).map_err(|e| format!("{}", e))
+        Ok(PendingSave {
+            source: self.check_out(database_idx)?,
+            data,
+            containers,
+        })
     }
 
-    /// Save a database to a specified destination
+    /// Start saving a database to a specified destination, which it is saved to from then on
     #[cfg(feature = "tauri")]
-    pub fn save_database_as(
+    pub(crate) fn prepare_save_as(
         &mut self,
         database_idx: usize,
         path: tauri_plugin_fs::FilePath,
         app: tauri::AppHandle,
-    ) -> Result<Option<Vec<u8>>, String> {
-        let Some(db) = self.databases.get_mut(database_idx) else {
-            return Err("No database by that index".to_string());
-        };
+    ) -> Result<PendingSave, String> {
+        let mut pending = self.prepare_save(database_idx)?;
 
-        db.source = Box::new(crate::source::FilesystemDatabaseSource { path });
+        pending.source.source = Box::new(crate::source::Blocking(
+            crate::source::FilesystemDatabaseSource { path, app },
+        ));
 
-        db.save(app).map_err(|e| format!("{}", e))
+        Ok(pending)
     }
 
-    /// Close a database
-    pub fn close_database(&mut self, database_idx: usize) -> Result<(), String> {
-        if database_idx >= self.databases.len() {
-            return Err("No database by that index".to_string());
-        }
-
-        self.databases.remove(database_idx);
+    /// Finish saving a database written by a [`PendingSave`], returning the saved data for
+    /// sources that provide it
+    pub fn finish_save(&mut self, saved: SavedDatabase) -> Result<Option<Vec<u8>>, String> {
+        // the database was saved even if it has been closed in the meantime
+        self.check_in(saved.source);
 
-        Ok(())
+        saved.result.map_err(|e| format!("{}", e))
     }
 
-    /// List the entries directly contained within a group of a database
-    pub fn list_entries(
+    /// Export a database, or the group with the given UUID and its subgroups, to an unencrypted
+    /// format. Protected values are left empty unless `include_protected` is set.
+    pub fn export_database(
         &self,
         database_idx: usize,
-        group_uuid: String,
-    ) -> Result<Vec<Entry>, String> {
-        let group_uuid = Uuid::from_str(&group_uuid).map_err(|e| format!("{}", e))?;
-
-        let database = self
+        group_uuid: Option<String>,
+        format: ExportFormat,
+        include_protected: bool,
+    ) -> Result<Vec<u8>, String> {
+        let db = self
             .databases
             .get(database_idx)
             .ok_or("Cannot get database by that index".to_string())?;
 
-        let group = database
-            .group(&group_uuid)
-            .map_err(|e| format!("{}", e))?
-            .ok_or("Group not found by UUID".to_string())?;
+        let database = db.get_database().map_err(|e| format!("{}", e))?;
 
-        let out = group
-            .children
-            .iter()
-            .filter_map(|node| match node {
-                Node::Entry(entry) => {
-                    let name = entry.get_title().map(String::from);
-                    let uuid = entry.uuid.clone();
-                    let user_name = entry.get_username().map(String::from);
-                    let url = entry.get_url().map(String::from);
+        let group = match group_uuid {
+            Some(uuid) => {
+                let uuid = Uuid::from_str(&uuid).map_err(|e| format!("{}", e))?;
 
-                    let fields = entry
-                        .fields
-                        .iter()
-                        .map(|(k, v)| (k.to_string(), v.into()))
-                        .collect();
+                db.group(&uuid)
+                    .map_err(|e| format!("{}", e))?
+                    .ok_or("Group not found by UUID".to_string())?
+            }
+            None => &database.root,
+        };
 
-                    let icon = database
-                        .get_icon(entry.custom_icon_uuid.as_ref(), entry.icon_id)
-                        .unwrap_or_default();
+        crate::export::export(database, group, format, include_protected)
+            .map_err(|e| format!("{}", e))
+    }
 
-                    Some(Entry {
-                        name,
-                        uuid,
-                        user_name,
-                        url,
-                        fields,
-                        icon,
-                    })
-                }
-                Node::Group(..) => None,
-            })
-            .collect();
+    /// Export a database or group like `export_database` and write it to a specified destination
+    #[cfg(feature = "tauri")]
+    pub fn export_database_as(
+        &self,
+        database_idx: usize,
+        group_uuid: Option<String>,
+        format: ExportFormat,
+        include_protected: bool,
+        path: tauri_plugin_fs::FilePath,
+        app: tauri::AppHandle,
+    ) -> Result<(), String> {
+        let data = self.export_database(database_idx, group_uuid, format, include_protected)?;
 
-        Ok(out)
+        Self::write_export(&data, path, app)
     }
 
-    /// Reveal a protected value within an entry, e.g. a password
-    pub fn reveal_protected(
+    /// Export a group with its subgroups and entries into a new database with its own password or
+    /// key file, returning the KDBX file
+    pub fn export_group_as_database(
         &self,
         database_idx: usize,
-        entry_uuid: &str,
-        field_name: &str,
-    ) -> Result<String, String> {
-        let entry_uuid = Uuid::from_str(&entry_uuid).map_err(|e| format!("{}", e))?;
+        group_uuid: &str,
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+        options: GroupExportOptions,
+    ) -> Result<Vec<u8>, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
 
-        let database = self
+        let db = self
             .databases
             .get(database_idx)
             .ok_or("Cannot get database by that index".to_string())?;
 
-        let entry = database
-            .entry(&entry_uuid)
+        let database = db.get_database().map_err(|e| format!("{}", e))?;
+        let group = db
+            .group(&group_uuid)
             .map_err(|e| format!("{}", e))?
-            .ok_or("No entry by that UUID".to_string())?;
+            .ok_or("Group not found by UUID".to_string())?;
 
-        let value = entry
-            .fields
+        let attachments = db.attachments().map_err(|e| format!("{}", e))?;
+
+        crate::export::kdbx::group_as_database(database, attachments, group, &options)
+            .save(password.as_deref(), keyfile.as_deref())
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Write an exported file to a specified destination, replacing any previous contents
+    #[cfg(feature = "tauri")]
+    pub fn write_export(
+        data: &[u8],
+        path: tauri_plugin_fs::FilePath,
+        app: tauri::AppHandle,
+    ) -> Result<(), String> {
+        use std::io::Write;
+        use tauri_plugin_fs::{FsExt, OpenOptions};
+
+        let options = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .clone();
+
+        app.fs()
+            .open(path, options)
+            .and_then(|mut file| file.write_all(data))
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// List the groups of a database that are shared through KeeShare containers
+    pub fn list_shares(&self, database_idx: usize) -> Result<Vec<Share>, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .get_database()
+            .map_err(|e| format!("{}", e))?;
+
+        Ok(crate::keeshare::list_shares(database))
+    }
+
+    /// Share a group through a KeeShare container, or stop sharing it
+    pub fn set_share(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        reference: Option<ShareReference>,
+    ) -> Result<(), String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let group = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        crate::keeshare::set_reference(group, reference.as_ref());
+
+        Ok(())
+    }
+
+    /// Start merging the KeeShare containers of imported and synchronized groups into a
+    /// database, see [`PendingShareImport`]
+    pub fn prepare_import_shares(
+        &mut self,
+        database_idx: usize,
+    ) -> Result<PendingShareImport, String> {
+        let shares = crate::keeshare::list_shares(
+            self.databases
+                .get(database_idx)
+                .ok_or("Cannot get database by that index".to_string())?
+                .get_database()
+                .map_err(|e| format!("{}", e))?,
+        );
+
+        Ok(PendingShareImport {
+            source: self.check_out(database_idx)?,
+            shares,
+        })
+    }
+
+    /// Merge the KeeShare containers read by a [`PendingShareImport`] into their database
+    pub fn finish_import_shares(&mut self, read: ReadShares) -> Result<(), String> {
+        let database_idx = self
+            .check_in(read.source)
+            .ok_or("The database has been closed".to_string())?;
+
+        crate::keeshare::merge_containers(
+            self.databases[database_idx]
+                .get_database_mut()
+                .map_err(|e| format!("{}", e))?,
+            read.containers,
+        )
+        .map_err(|e| format!("{}", e))
+    }
+
+    /// Start providing a KeeShare container for a database loaded from a buffer, before unlocking
+    /// it or importing its shares, see [`PendingShareContainer`]
+    pub fn prepare_add_share_container(
+        &mut self,
+        database_idx: usize,
+        path: String,
+        data: Vec<u8>,
+    ) -> Result<PendingShareContainer, String> {
+        Ok(PendingShareContainer {
+            source: self.check_out(database_idx)?,
+            path,
+            data: Some(data),
+        })
+    }
+
+    /// Start getting a KeeShare container of a database loaded from a buffer, which is updated
+    /// when saving, see [`PendingShareContainer`]
+    pub fn prepare_get_share_container(
+        &mut self,
+        database_idx: usize,
+        path: String,
+    ) -> Result<PendingShareContainer, String> {
+        Ok(PendingShareContainer {
+            source: self.check_out(database_idx)?,
+            path,
+            data: None,
+        })
+    }
+
+    /// Finish reading or writing a KeeShare container, returning the container if it was read
+    pub fn finish_share_container(
+        &mut self,
+        container: ShareContainer,
+    ) -> Result<Option<Vec<u8>>, String> {
+        self.check_in(container.source);
+
+        container.result.map_err(|e| format!("{}", e))
+    }
+
+    /// Print the entries of selected groups of a database as an HTML document
+    pub fn entry_report(
+        &self,
+        database_idx: usize,
+        options: ReportOptions,
+    ) -> Result<String, String> {
+        let db = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = db.get_database().map_err(|e| format!("{}", e))?;
+
+        crate::report::entry_report(database, db.get_name(), &options).map_err(|e| format!("{}", e))
+    }
+
+    /// Create an emergency sheet for a database as an HTML document
+    pub fn emergency_sheet(
+        &self,
+        database_idx: usize,
+        keyfile_hint: Option<String>,
+    ) -> Result<String, String> {
+        let db = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        Ok(crate::report::emergency_sheet(
+            db.get_name(),
+            db.source.get_name(),
+            keyfile_hint.as_deref(),
+        ))
+    }
+
+    /// Write an entry report like `entry_report` to a specified destination
+    #[cfg(feature = "tauri")]
+    pub fn save_entry_report(
+        &self,
+        database_idx: usize,
+        options: ReportOptions,
+        path: tauri_plugin_fs::FilePath,
+        app: tauri::AppHandle,
+    ) -> Result<(), String> {
+        let report = self.entry_report(database_idx, options)?;
+
+        Self::write_export(report.as_bytes(), path, app)
+    }
+
+    /// Write an emergency sheet like `emergency_sheet` to a specified destination
+    #[cfg(feature = "tauri")]
+    pub fn save_emergency_sheet(
+        &self,
+        database_idx: usize,
+        keyfile_hint: Option<String>,
+        path: tauri_plugin_fs::FilePath,
+        app: tauri::AppHandle,
+    ) -> Result<(), String> {
+        let sheet = self.emergency_sheet(database_idx, keyfile_hint)?;
+
+        Self::write_export(sheet.as_bytes(), path, app)
+    }
+
+    /// Close a database
+    pub fn close_database(&mut self, database_idx: usize) -> Result<(), String> {
+        if database_idx >= self.databases.len() {
+            return Err("No database by that index".to_string());
+        }
+
+        #[cfg(feature = "tauri")]
+        {
+            let id = self.databases[database_idx].id;
+            self.ssh_keys.lock().unwrap().remove_database(&id);
+        }
+
+        self.databases.remove(database_idx);
+
+        Ok(())
+    }
+
+    /// Merge the changes of another unlocked database into a database, returning the number of
+    /// entries and groups that were created, updated, moved or deleted
+    ///
+    /// This relies on the unstable merge feature of the keepass crate, which is why its version is
+    /// pinned. Check the merge results again when upgrading it.
+    pub fn merge_database(
+        &mut self,
+        database_idx: usize,
+        other_database_idx: usize,
+    ) -> Result<usize, String> {
+        let other = self
+            .databases
+            .get(other_database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .get_database()
+            .map_err(|e| format!("{}", e))?
+            .clone();
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .get_database_mut()
+            .map_err(|e| format!("{}", e))?;
+
+        let log = database.merge(&other).map_err(|e| format!("{}", e))?;
+
+        Ok(log.events.len())
+    }
+
+    /// Detect the format of a CSV file and suggest how to map its columns, or read it with the
+    /// given format
+    pub fn preview_csv_import(
+        &self,
+        data: &[u8],
+        format: Option<CsvFormat>,
+    ) -> Result<CsvPreview, String> {
+        crate::import::csv::preview(data, format).map_err(|e| format!("{}", e))
+    }
+
+    /// Import the rows of a CSV file as entries into a group
+    pub fn import_csv(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        data: &[u8],
+        format: CsvFormat,
+        mapping: CsvMapping,
+    ) -> Result<ImportSummary, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let root_name = database
+            .get_database()
+            .map_err(|e| format!("{}", e))?
+            .root
+            .name
+            .clone();
+
+        let group = database
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        crate::import::csv::import(group, &root_name, data, &format, &mapping)
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Import the items of a Bitwarden JSON export into a group, decrypting it with a password if
+    /// the export is password-protected
+    pub fn import_bitwarden(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        data: &[u8],
+        password: Option<String>,
+    ) -> Result<ImportSummary, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let group = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        crate::import::bitwarden::import(group, data, password.as_deref())
+            .map_err(|e| format!("{}", e))
+    }
+
+    /// Import the items of a 1Password `.1pux` archive, including attached files, into a group
+    pub fn import_onepassword(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        data: &[u8],
+    ) -> Result<ImportSummary, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let group = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        crate::import::onepassword::import(group, data).map_err(|e| format!("{}", e))
+    }
+
+    /// Import the items of a LastPass CSV export into a group
+    pub fn import_lastpass(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        data: &[u8],
+    ) -> Result<ImportSummary, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let group = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        crate::import::lastpass::import(group, data).map_err(|e| format!("{}", e))
+    }
+
+    /// List the entries directly contained within a group of a database
+    pub fn list_entries(
+        &self,
+        database_idx: usize,
+        group_uuid: String,
+    ) -> Result<Vec<Entry>, String> {
+        let group_uuid = Uuid::from_str(&group_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let group = database
+            .group(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        let resolver = crate::placeholder::Resolver::new(
+            database.get_database().map_err(|e| format!("{}", e))?,
+        );
+        let attachments = database.attachments().map_err(|e| format!("{}", e))?;
+
+        let out = group
+            .children
+            .iter()
+            .filter_map(|node| match node {
+                Node::Entry(entry) => {
+                    let name = resolver.field(entry, "Title");
+                    let uuid = entry.uuid.clone();
+                    let user_name = resolver.field(entry, "UserName");
+                    let url = resolver.field(entry, "URL");
+
+                    let fields: std::collections::HashMap<String, Value> = entry
+                        .fields
+                        .iter()
+                        .filter(|(_, v)| !matches!(v, KpValue::Bytes(..)))
+                        .map(|(k, v)| (k.to_string(), v.into()))
+                        .collect();
+
+                    let attachments = attachments
+                        .list(entry)
+                        .map(|(name, data)| Attachment {
+                            name: name.to_string(),
+                            size: data.len(),
+                        })
+                        .collect();
+
+                    let icon = database
+                        .get_icon(entry.custom_icon_uuid.as_ref(), entry.icon_id)
+                        .unwrap_or_default();
+
+                    Some(Entry {
+                        name,
+                        uuid,
+                        user_name,
+                        url,
+                        fields,
+                        attachments,
+                        icon,
+                    })
+                }
+                Node::Group(..) => None,
+            })
+            .collect();
+
+        Ok(out)
+    }
+
+    /// Reveal a protected value within an entry, e.g. a password
+    pub fn reveal_protected(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+        field_name: &str,
+    ) -> Result<String, String> {
+        let entry_uuid = Uuid::from_str(&entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let value = entry
+            .fields
             .get(field_name)
             .ok_or("Cannot find a field with that name".to_string())?;
 
-        if let KpValue::Protected(v) = value {
-            String::from_utf8(v.unsecure().to_vec()).map_err(|e| format!("{}", e))
-        } else {
-            Err("The field is not protected".to_string())
+        if let KpValue::Protected(v) = value {
+            String::from_utf8(v.unsecure().to_vec()).map_err(|e| format!("{}", e))
+        } else {
+            Err("The field is not protected".to_string())
+        }
+    }
+
+    /// Get the data of an attachment of an entry
+    pub fn get_attachment(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+        name: &str,
+    ) -> Result<Vec<u8>, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let attachments = database.attachments().map_err(|e| format!("{}", e))?;
+
+        attachments
+            .get(entry, name)
+            .map(<[u8]>::to_vec)
+            .ok_or("Cannot find an attachment with that name".to_string())
+    }
+
+    /// Get the value of a field both as stored and with placeholders and field references resolved
+    pub fn resolve_field(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+        field_name: &str,
+    ) -> Result<ResolvedValue, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let raw = match entry.fields.get(field_name) {
+            Some(KpValue::Unprotected(v)) => v.to_string(),
+            Some(KpValue::Protected(v)) => {
+                String::from_utf8(v.unsecure().to_vec()).map_err(|e| format!("{}", e))?
+            }
+            Some(KpValue::Bytes(..)) => return Err("The field contains binary data".to_string()),
+            None => return Err("Cannot find a field with that name".to_string()),
+        };
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+        let resolved = crate::placeholder::Resolver::new(database)
+            .field(entry, field_name)
+            .unwrap_or_else(|| raw.clone());
+
+        Ok(ResolvedValue { raw, resolved })
+    }
+
+    /// Resolve placeholders and field references in a text in the context of an entry
+    pub fn resolve_placeholders(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+        text: &str,
+    ) -> Result<String, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::placeholder::Resolver::new(database).resolve(entry, text))
+    }
+
+    /// Find entries in all unlocked databases whose URLs match a page, best matches first
+    pub fn find_entries_for_url(&self, url: &str) -> Result<Vec<UrlMatch>, String> {
+        let databases = self
+            .databases
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, db)| db.get_database().ok().map(|db| (idx, db)));
+
+        crate::url_match::find_matches(databases, url).map_err(|e| format!("{}", e))
+    }
+
+    /// Check whether a database is saved automatically after changes from the browser extension
+    pub fn get_browser_auto_save(&self, database_idx: usize) -> Result<bool, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::browser::is_auto_save_enabled(database))
+    }
+
+    /// Opt a database in or out of saving automatically after changes from the browser extension
+    pub fn set_browser_auto_save(
+        &mut self,
+        database_idx: usize,
+        enabled: bool,
+    ) -> Result<(), String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        crate::browser::set_auto_save(database, enabled);
+
+        Ok(())
+    }
+
+    /// Plan the keystrokes to auto-type an entry, optionally into a window with a given title
+    ///
+    /// The sequence of a matching window association is used if a window title is given,
+    /// otherwise the default sequence of the entry.
+    pub fn get_auto_type_plan(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+        window_title: Option<String>,
+    ) -> Result<Vec<AutoTypeAction>, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        if !crate::autotype::is_enabled(database, entry) {
+            return Err("Auto-Type is disabled for this entry".to_string());
+        }
+
+        let sequence = window_title
+            .and_then(|title| crate::autotype::sequence_for_window(database, entry, &title))
+            .unwrap_or_else(|| crate::autotype::default_sequence(database, entry));
+
+        let resolver = crate::placeholder::Resolver::new(database);
+
+        crate::autotype::plan(&resolver, entry, &sequence).map_err(|e| format!("{}", e))
+    }
+
+    /// Find the entries of a database that can be auto-typed into a window with a given title
+    pub fn find_auto_type_matches(
+        &self,
+        database_idx: usize,
+        window_title: &str,
+    ) -> Result<Vec<AutoTypeMatch>, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::autotype::find_matches(database, window_title))
+    }
+
+    /// Get a handle to the keys served by the SSH agent
+    #[cfg(feature = "tauri")]
+    pub fn ssh_key_store(&self) -> std::sync::Arc<std::sync::Mutex<ssh_agent::KeyStore>> {
+        self.ssh_keys.clone()
+    }
+
+    /// Add the SSH keys of a freshly unlocked database that are configured to be added at open
+    #[cfg(feature = "tauri")]
+    fn add_ssh_keys_at_open(&self, database_idx: usize) {
+        let db = &self.databases[database_idx];
+
+        let (Ok(database), Ok(attachments)) = (db.get_database(), db.attachments()) else {
+            return;
+        };
+
+        let mut store = self.ssh_keys.lock().unwrap();
+        for key in ssh_agent::keys_at_open(db.id, database, attachments) {
+            store.add(key);
+        }
+    }
+
+    /// List the keys served by the SSH agent
+    #[cfg(feature = "tauri")]
+    pub fn list_ssh_keys(&self) -> Vec<SshKey> {
+        let mut store = self.ssh_keys.lock().unwrap();
+
+        store
+            .keys()
+            .iter()
+            .map(|key| {
+                let database_idx = self
+                    .databases
+                    .iter()
+                    .position(|db| db.id == key.database_id);
+                key.describe(database_idx)
+            })
+            .collect()
+    }
+
+    /// Add the SSH key of an entry to the agent
+    #[cfg(feature = "tauri")]
+    pub fn add_ssh_key(&mut self, database_idx: usize, entry_uuid: &str) -> Result<SshKey, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let db = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = db
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let attachments = db.attachments().map_err(|e| format!("{}", e))?;
+        let key = ssh_agent::entry_key(db.id, entry, attachments).map_err(|e| format!("{}", e))?;
+        let summary = key.describe(Some(database_idx));
+
+        self.ssh_keys.lock().unwrap().add(key);
+
+        Ok(summary)
+    }
+
+    /// Remove the SSH key of an entry from the agent
+    #[cfg(feature = "tauri")]
+    pub fn remove_ssh_key(&mut self, entry_uuid: &str) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        self.ssh_keys.lock().unwrap().remove_entry(&entry_uuid);
+
+        Ok(())
+    }
+
+    /// Check whether a database is exposed through the Secret Service
+    pub fn get_secret_service_enabled(&self, database_idx: usize) -> Result<bool, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::secret_service::is_enabled(database))
+    }
+
+    /// Opt a database in or out of being exposed through the Secret Service
+    pub fn set_secret_service_enabled(
+        &mut self,
+        database_idx: usize,
+        enabled: bool,
+    ) -> Result<(), String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        crate::secret_service::set_enabled(database, enabled);
+
+        Ok(())
+    }
+
+    /// Get the UUID of the group that is exposed through the Secret Service, if not the whole
+    /// database
+    pub fn get_secret_service_group(&self, database_idx: usize) -> Result<Option<String>, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::secret_service::exposed_group_uuid(database).map(|uuid| uuid.to_string()))
+    }
+
+    /// Expose a single group of a database through the Secret Service, or the whole database
+    pub fn set_secret_service_group(
+        &mut self,
+        database_idx: usize,
+        group_uuid: Option<String>,
+    ) -> Result<(), String> {
+        let group_uuid = group_uuid
+            .map(|uuid| Uuid::from_str(&uuid))
+            .transpose()
+            .map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        if let Some(uuid) = &group_uuid {
+            let root_uuid = database
+                .get_database()
+                .map_err(|e| format!("{}", e))?
+                .root
+                .uuid;
+
+            if uuid != &root_uuid
+                && database
+                    .group(uuid)
+                    .map_err(|e| format!("{}", e))?
+                    .is_none()
+            {
+                return Err("No group by that UUID".to_string());
+            }
+        }
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        crate::secret_service::set_exposed_group(database, group_uuid);
+
+        Ok(())
+    }
+
+    /// Get the image data of a custom icon
+    pub fn get_custom_icon(&self, database_idx: usize, icon_uuid: &str) -> Result<Vec<u8>, String> {
+        let icon_uuid = Uuid::from_str(icon_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let data = database
+            .get_custom_icon(&icon_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No custom icon by that UUID".to_string())?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Answer a request to the `omnikee-icon://` URI scheme protocol
+    #[cfg(feature = "tauri")]
+    pub fn custom_icon_response(
+        &self,
+        request: &tauri::http::Request<Vec<u8>>,
+    ) -> tauri::http::Response<Vec<u8>> {
+        crate::icon::protocol_response(self, request)
+    }
+
+    /// List the custom icons stored in a database
+    pub fn list_custom_icons(&self, database_idx: usize) -> Result<Vec<CustomIcon>, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::icon::list_custom_icons(database))
+    }
+
+    /// Import an image (PNG, ICO, SVG, ...) as a custom icon, returning the UUID of the icon
+    pub fn add_custom_icon(&mut self, database_idx: usize, data: &[u8]) -> Result<String, String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        let uuid = crate::icon::add_custom_icon(database, data).map_err(|e| format!("{}", e))?;
+
+        Ok(uuid.to_string())
+    }
+
+    /// Replace the image of an existing custom icon
+    pub fn replace_custom_icon(
+        &mut self,
+        database_idx: usize,
+        icon_uuid: &str,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let icon_uuid = Uuid::from_str(icon_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        crate::icon::replace_custom_icon(database, &icon_uuid, data).map_err(|e| format!("{}", e))
+    }
+
+    /// Merge custom icons with identical images, returning the number of icons removed
+    pub fn deduplicate_custom_icons(&mut self, database_idx: usize) -> Result<usize, String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::icon::deduplicate_custom_icons(database))
+    }
+
+    /// Remove custom icons not used by any group or entry, returning the number of icons removed
+    pub fn purge_unused_custom_icons(&mut self, database_idx: usize) -> Result<usize, String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::icon::purge_unused_custom_icons(database))
+    }
+
+    /// Set the icon of a group
+    pub fn set_group_icon(
+        &mut self,
+        database_idx: usize,
+        group_uuid: &str,
+        icon: IconSet,
+    ) -> Result<(), String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let (icon_id, custom_icon_uuid) = database
+            .get_database()
+            .and_then(|db| crate::icon::resolve_icon_set(db, icon))
+            .map_err(|e| format!("{}", e))?;
+
+        let group = database
+            .group_mut(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No group by that UUID".to_string())?;
+
+        if let Some(id) = icon_id {
+            group.icon_id = Some(id);
+        }
+        group.custom_icon_uuid = custom_icon_uuid;
+
+        Ok(())
+    }
+
+    /// Set the icon of an entry
+    pub fn set_entry_icon(
+        &mut self,
+        database_idx: usize,
+        entry_uuid: &str,
+        icon: IconSet,
+    ) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let (icon_id, custom_icon_uuid) = database
+            .get_database()
+            .and_then(|db| crate::icon::resolve_icon_set(db, icon))
+            .map_err(|e| format!("{}", e))?;
+
+        let entry = database
+            .entry_mut(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        if let Some(id) = icon_id {
+            entry.icon_id = Some(id);
+        }
+        entry.custom_icon_uuid = custom_icon_uuid;
+
+        Ok(())
+    }
+
+    /// Check whether favicon downloads are enabled for a database
+    pub fn get_favicon_download(&self, database_idx: usize) -> Result<bool, String> {
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        Ok(crate::favicon::is_enabled(database))
+    }
+
+    /// Opt a database in or out of favicon downloads
+    pub fn set_favicon_download(
+        &mut self,
+        database_idx: usize,
+        enabled: bool,
+    ) -> Result<(), String> {
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        crate::favicon::set_enabled(database, enabled);
+
+        Ok(())
+    }
+
+    /// List the URLs that favicons should be downloaded for
+    ///
+    /// If `entry_uuids` is empty, all entries with a URL are considered.
+    pub fn list_favicon_requests(
+        &self,
+        database_idx: usize,
+        entry_uuids: Vec<String>,
+    ) -> Result<Vec<FaviconRequest>, String> {
+        let entry_uuids = entry_uuids
+            .iter()
+            .map(|uuid| Uuid::from_str(uuid))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let database = database.get_database().map_err(|e| format!("{}", e))?;
+
+        if !crate::favicon::is_enabled(database) {
+            return Err("Favicon downloads are not enabled for this database".to_string());
+        }
+
+        let out = database
+            .root
+            .iter()
+            .filter_map(|node| match node {
+                NodeRef::Entry(entry) => Some(entry),
+                NodeRef::Group(..) => None,
+            })
+            .filter(|entry| entry_uuids.is_empty() || entry_uuids.contains(&entry.uuid))
+            .filter_map(|entry| {
+                let url = entry.get_url()?;
+
+                crate::favicon::parse_entry_url(url).ok()?;
+
+                Some(FaviconRequest {
+                    entry_uuid: entry.uuid,
+                    url: url.to_string(),
+                })
+            })
+            .collect();
+
+        Ok(out)
+    }
+
+    /// Store a favicon downloaded by [`favicon::fetch_favicon`] as the custom icon of an entry
+    ///
+    /// The database may have been opted out while the icon was downloading, in which case the
+    /// icon is not stored.
+    pub fn set_entry_favicon(
+        &mut self,
+        database_idx: usize,
+        entry_uuid: &str,
+        data: Vec<u8>,
+    ) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let db = database.get_database_mut().map_err(|e| format!("{}", e))?;
+
+        if !crate::favicon::is_enabled(db) {
+            return Err("Favicon downloads are not enabled for this database".to_string());
         }
+
+        let icon_uuid = crate::icon::add_converted_icon(db, data);
+
+        let entry = database
+            .entry_mut(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        entry.custom_icon_uuid = Some(icon_uuid);
+
+        Ok(())
     }
 
     /// Set the name of a group
@@ -345,15 +1760,135 @@ impl AppState {
         Ok(())
     }
 
-    /// Get the current time-based one-time password (TOTP) for an entry
+    /// Add a new entry to a group, returning the UUID of the entry
+    pub fn add_entry(&mut self, database_idx: usize, group_uuid: &str) -> Result<String, String> {
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry_uuid = database
+            .add_entry(&group_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        Ok(entry_uuid.to_string())
+    }
+
+    /// Add a new group to a group, returning the UUID of the new group
+    pub fn add_group(
+        &mut self,
+        database_idx: usize,
+        parent_uuid: &str,
+        name: String,
+    ) -> Result<String, String> {
+        let parent_uuid = Uuid::from_str(parent_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let group_uuid = database
+            .add_group(&parent_uuid, &name)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("Group not found by UUID".to_string())?;
+
+        Ok(group_uuid.to_string())
+    }
+
+    /// Move an entry into another group
+    pub fn move_entry(
+        &mut self,
+        database_idx: usize,
+        entry_uuid: &str,
+        group_uuid: &str,
+    ) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+        let group_uuid = Uuid::from_str(group_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        if !database
+            .move_entry(&entry_uuid, &group_uuid)
+            .map_err(|e| format!("{}", e))?
+        {
+            return Err("No entry or group by that UUID".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Remove an entry, moving it to the recycle bin unless it is already there or the recycle bin
+    /// is disabled
+    pub fn remove_entry(&mut self, database_idx: usize, entry_uuid: &str) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        if !database
+            .remove_entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+        {
+            return Err("No entry by that UUID".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Generate a one-time password for an entry
+    ///
+    /// For time-based codes, `time` is the UNIX timestamp to generate the code for. For
+    /// counter-based codes, the counter stored in the entry is advanced.
     pub fn get_otp(
-        &self,
+        &mut self,
         database_idx: usize,
         entry_uuid: &str,
         time: u64,
     ) -> Result<OTPResponse, String> {
         let entry_uuid = Uuid::from_str(&entry_uuid).map_err(|e| format!("{}", e))?;
 
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry_mut(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let config = crate::otp::OtpConfig::from_entry(entry)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("The entry has no one-time password".to_string())?;
+
+        match config {
+            crate::otp::OtpConfig::Totp(totp) => Ok(totp.value_at(time)),
+            crate::otp::OtpConfig::Hotp(mut hotp) => {
+                let res = hotp.generate().map_err(|e| format!("{}", e))?;
+                hotp.write_counter(entry);
+
+                Ok(res)
+            }
+        }
+    }
+
+    /// Get the one-time password settings of an entry, if any
+    pub fn get_otp_settings(
+        &self,
+        database_idx: usize,
+        entry_uuid: &str,
+    ) -> Result<Option<OTPSettings>, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
         let database = self
             .databases
             .get(database_idx)
@@ -364,12 +1899,98 @@ impl AppState {
             .map_err(|e| format!("{}", e))?
             .ok_or("No entry by that UUID".to_string())?;
 
-        let value = entry.get_otp().map_err(|e| e.to_string())?.value_at(time);
+        let config = crate::otp::OtpConfig::from_entry(entry).map_err(|e| format!("{}", e))?;
 
-        Ok(OTPResponse {
-            code: value.code,
-            valid_for: value.valid_for,
-            period: value.period,
-        })
+        Ok(config.as_ref().map(|c| c.into()))
+    }
+
+    /// Set up one-time passwords for an entry
+    ///
+    /// Time-based settings are stored in both the KeePassXC and KeePass 2.x formats.
+    pub fn set_otp(
+        &mut self,
+        database_idx: usize,
+        entry_uuid: &str,
+        setup: OTPSetup,
+    ) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let mut config: crate::otp::OtpConfig = setup.try_into().map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry_mut(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        config.fill_label(entry);
+        config.write_to_entry(entry);
+
+        Ok(())
+    }
+
+    /// Remove one-time password settings from an entry
+    pub fn remove_otp(&mut self, database_idx: usize, entry_uuid: &str) -> Result<(), String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get_mut(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry_mut(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        crate::otp::remove_from_entry(entry);
+
+        Ok(())
+    }
+
+    /// Get the `otpauth://` URI of an entry, e.g. for migrating to another app
+    pub fn get_otp_uri(&self, database_idx: usize, entry_uuid: &str) -> Result<String, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let config = crate::otp::OtpConfig::from_entry(entry)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("The entry has no one-time password".to_string())?;
+
+        Ok(config.to_uri())
+    }
+
+    /// Render the `otpauth://` URI of an entry as a QR code in SVG format
+    pub fn get_otp_qr_code(&self, database_idx: usize, entry_uuid: &str) -> Result<String, String> {
+        let entry_uuid = Uuid::from_str(entry_uuid).map_err(|e| format!("{}", e))?;
+
+        let database = self
+            .databases
+            .get(database_idx)
+            .ok_or("Cannot get database by that index".to_string())?;
+
+        let entry = database
+            .entry(&entry_uuid)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("No entry by that UUID".to_string())?;
+
+        let config = crate::otp::OtpConfig::from_entry(entry)
+            .map_err(|e| format!("{}", e))?
+            .ok_or("The entry has no one-time password".to_string())?;
+
+        config.to_qr_svg().map_err(|e| format!("{}", e))
     }
 }
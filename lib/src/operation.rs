@@ -0,0 +1,384 @@
+//! Operations that wait for the source of a database
+//!
+//! Waiting for a source, like a server, must not keep the [`AppState`](crate::AppState) borrowed,
+//! so that other requests can be answered in the meantime. In the browser, the state cannot even
+//! be borrowed across an `await`. Such operations are therefore split into three steps: the state
+//! prepares the operation and hands out the source of the database, the operation runs on its
+//! own, and the state applies its result and takes the source back.
+//!
+//! Frontends that share the state, like the desktop app and the CLI, run the three steps through
+//! the async entry points on [`AppState`] that take a [`SharedState`]. The web frontend runs them
+//! itself: wasm-bindgen keeps the state borrowed for as long as any of its async methods runs.
+
+use std::cell::RefCell;
+use std::ops::DerefMut;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use wasm_bindgen::prelude::*;
+
+use crate::AppState;
+use crate::database::{CheckedOut, DatabaseState};
+use crate::exchange::{DatabaseOverview, Share};
+
+/// Application state that operations only borrow between their steps
+pub trait SharedState {
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R;
+}
+
+impl SharedState for Mutex<AppState> {
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+        f(&mut self.lock().unwrap())
+    }
+}
+
+impl<S: DerefMut<Target = AppState>> SharedState for RefCell<S> {
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+}
+
+impl AppState {
+    /// Unlock a loaded database
+    pub async fn unlock_database(
+        state: &impl SharedState,
+        database_idx: usize,
+        password: Option<String>,
+        keyfile: Option<Vec<u8>>,
+    ) -> Result<DatabaseOverview, String> {
+        let pending =
+            state.with_state(|state| state.prepare_unlock(database_idx, password, keyfile))?;
+
+        let unlocked = pending.run().await;
+
+        state.with_state(|state| state.finish_unlock(unlocked))
+    }
+
+    /// Save a database to the same path it was loaded from, returning the saved data for sources
+    /// that provide it
+    pub async fn save_database(
+        state: &impl SharedState,
+        database_idx: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let pending = state.with_state(|state| state.prepare_save(database_idx))?;
+
+        let saved = pending.run().await;
+
+        state.with_state(|state| state.finish_save(saved))
+    }
+
+    /// Save a database to a specified destination, which it is saved to from then on
+    #[cfg(feature = "tauri")]
+    pub async fn save_database_as(
+        state: &impl SharedState,
+        database_idx: usize,
+        path: tauri_plugin_fs::FilePath,
+        app: tauri::AppHandle,
+    ) -> Result<(), String> {
+        let pending = state.with_state(|state| state.prepare_save_as(database_idx, path, app))?;
+        let saved = pending.run().await;
+
+        state.with_state(|state| state.finish_save(saved))?;
+
+        Ok(())
+    }
+
+    /// Merge the KeeShare containers of imported and synchronized groups into a database
+    ///
+    /// This also happens when unlocking, but only reports problems with containers when done
+    /// explicitly.
+    pub async fn import_shares(
+        state: &impl SharedState,
+        database_idx: usize,
+    ) -> Result<(), String> {
+        let pending = state.with_state(|state| state.prepare_import_shares(database_idx))?;
+
+        let read = pending.run().await;
+
+        state.with_state(|state| state.finish_import_shares(read))
+    }
+}
+
+/// Unlocking a database, prepared by [`AppState::prepare_unlock`](crate::AppState::prepare_unlock)
+#[wasm_bindgen]
+pub struct PendingUnlock {
+    pub(crate) source: CheckedOut,
+    pub(crate) password: Option<String>,
+    pub(crate) keyfile: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl PendingUnlock {
+    /// Read and decrypt the database
+    pub async fn run(self) -> UnlockedDatabase {
+        let state = DatabaseState::open(&*self.source.source, self.password, self.keyfile).await;
+
+        UnlockedDatabase {
+            source: self.source,
+            state,
+        }
+    }
+}
+
+/// A database read by a [`PendingUnlock`], to be passed to
+/// [`AppState::finish_unlock`](crate::AppState::finish_unlock)
+#[wasm_bindgen]
+pub struct UnlockedDatabase {
+    pub(crate) source: CheckedOut,
+    pub(crate) state: Result<DatabaseState>,
+}
+
+/// Saving a database, prepared by [`AppState::prepare_save`](crate::AppState::prepare_save)
+#[wasm_bindgen]
+pub struct PendingSave {
+    pub(crate) source: CheckedOut,
+    pub(crate) data: Vec<u8>,
+
+    /// the KeeShare containers to write next to the database, by their paths
+    pub(crate) containers: Vec<(String, Vec<u8>)>,
+}
+
+#[wasm_bindgen]
+impl PendingSave {
+    /// Write the database and its KeeShare containers
+    pub async fn run(mut self) -> SavedDatabase {
+        let result = self.write().await;
+
+        SavedDatabase {
+            source: self.source,
+            result,
+        }
+    }
+}
+
+impl PendingSave {
+    async fn write(&mut self) -> Result<Option<Vec<u8>>> {
+        let source = &mut *self.source.source;
+
+        source.save(&self.data).await.context("Saving database")?;
+
+        crate::keeshare::write_containers(&self.containers, source).await?;
+
+        Ok(source.send_saved())
+    }
+}
+
+/// A database written by a [`PendingSave`], to be passed to
+/// [`AppState::finish_save`](crate::AppState::finish_save)
+#[wasm_bindgen]
+pub struct SavedDatabase {
+    pub(crate) source: CheckedOut,
+    pub(crate) result: Result<Option<Vec<u8>>>,
+}
+
+/// Importing the KeeShare containers of a database, prepared by
+/// [`AppState::prepare_import_shares`](crate::AppState::prepare_import_shares)
+#[wasm_bindgen]
+pub struct PendingShareImport {
+    pub(crate) source: CheckedOut,
+    pub(crate) shares: Vec<Share>,
+}
+
+#[wasm_bindgen]
+impl PendingShareImport {
+    /// Read the containers of the shared groups
+    pub async fn run(self) -> ReadShares {
+        let containers = crate::keeshare::read_containers(self.shares, &*self.source.source).await;
+
+        ReadShares {
+            source: self.source,
+            containers,
+        }
+    }
+}
+
+/// KeeShare containers read by a [`PendingShareImport`], to be passed to
+/// [`AppState::finish_import_shares`](crate::AppState::finish_import_shares)
+#[wasm_bindgen]
+pub struct ReadShares {
+    pub(crate) source: CheckedOut,
+    pub(crate) containers: Vec<(Share, Result<Option<Vec<u8>>>)>,
+}
+
+/// Reading or writing a KeeShare container next to a database loaded from a buffer, prepared by
+/// [`AppState::prepare_add_share_container`] or [`AppState::prepare_get_share_container`]
+#[wasm_bindgen]
+pub struct PendingShareContainer {
+    pub(crate) source: CheckedOut,
+    pub(crate) path: String,
+
+    /// the container to write, or `None` to read it
+    pub(crate) data: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl PendingShareContainer {
+    /// Read or write the container
+    pub async fn run(mut self) -> ShareContainer {
+        let source = &mut *self.source.source;
+
+        let result = match &self.data {
+            Some(data) => source.write_sibling(&self.path, data).await.map(|_| None),
+            None => source.read_sibling(&self.path).await,
+        };
+
+        ShareContainer {
+            source: self.source,
+            result,
+        }
+    }
+}
+
+/// A KeeShare container read or written by a [`PendingShareContainer`], to be passed to
+/// [`AppState::finish_share_container`]
+#[wasm_bindgen]
+pub struct ShareContainer {
+    pub(crate) source: CheckedOut,
+    pub(crate) result: Result<Option<Vec<u8>>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::source::block_on;
+    use crate::{AppState, DatabaseOverview};
+
+    fn save(state: &mut AppState, database_idx: usize) -> Result<Option<Vec<u8>>, String> {
+        block_on(AppState::save_database(&RefCell::new(state), database_idx))
+    }
+
+    fn unlock(
+        state: &mut AppState,
+        database_idx: usize,
+        password: &str,
+    ) -> Result<DatabaseOverview, String> {
+        block_on(AppState::unlock_database(
+            &RefCell::new(state),
+            database_idx,
+            Some(password.to_string()),
+            None,
+        ))
+    }
+
+    fn file_name(state: &AppState, database_idx: usize) -> String {
+        match &state.list_databases()[database_idx] {
+            DatabaseOverview::Unlocked { file_name, .. }
+            | DatabaseOverview::Locked { file_name, .. } => file_name.clone(),
+        }
+    }
+
+    #[test]
+    fn test_save_and_unlock() {
+        let mut state = AppState::default();
+        state
+            .create_database("Test".to_string(), Some("hunter2".to_string()), None)
+            .unwrap();
+
+        save(&mut state, 0).unwrap().unwrap();
+        state.lock_database(0).unwrap();
+
+        assert!(unlock(&mut state, 0, "wrong").is_err());
+
+        // the source was returned after the failed attempt, so unlocking can be retried
+        let overview = unlock(&mut state, 0, "hunter2").unwrap();
+        assert!(matches!(overview, DatabaseOverview::Unlocked { .. }));
+
+        // saving again writes a database that unlocks with the same password
+        let saved = save(&mut state, 0).unwrap().unwrap();
+        state
+            .load_database_buffer("Saved.kdbx".to_string(), &saved)
+            .unwrap();
+        assert!(unlock(&mut state, 1, "hunter2").is_ok());
+    }
+
+    #[test]
+    fn test_busy_while_waiting() {
+        let mut state = AppState::default();
+        state
+            .create_database("Test".to_string(), Some("hunter2".to_string()), None)
+            .unwrap();
+
+        let pending = state.prepare_save(0).unwrap();
+
+        // the database stays usable, but its source cannot be used twice
+        assert_eq!(file_name(&state, 0), "Test.kdbx");
+        assert!(state.prepare_save(0).is_err());
+        assert!(state.prepare_unlock(0, None, None).is_err());
+
+        assert!(state.finish_save(block_on(pending.run())).is_ok());
+        assert!(save(&mut state, 0).is_ok());
+    }
+
+    #[test]
+    fn test_closed_while_waiting() {
+        let mut state = AppState::default();
+        for (database_idx, name) in ["First", "Second"].into_iter().enumerate() {
+            state
+                .create_database(name.to_string(), Some("hunter2".to_string()), None)
+                .unwrap();
+            save(&mut state, database_idx).unwrap();
+        }
+        state.lock_database(1).unwrap();
+
+        // the result is applied to the database even though its index changed
+        let pending = state
+            .prepare_unlock(1, Some("hunter2".to_string()), None)
+            .unwrap();
+        state.close_database(0).unwrap();
+        state.finish_unlock(block_on(pending.run())).unwrap();
+        assert_eq!(file_name(&state, 0), "Second.kdbx");
+        assert!(state.databases[0].get_database().is_ok());
+
+        // saving succeeded even if the database was closed in the meantime
+        let pending = state.prepare_save(0).unwrap();
+        state.close_database(0).unwrap();
+        assert!(
+            state
+                .finish_save(block_on(pending.run()))
+                .unwrap()
+                .is_some()
+        );
+
+        state
+            .create_database("Third".to_string(), Some("hunter2".to_string()), None)
+            .unwrap();
+        let pending = state.prepare_unlock(0, None, None).unwrap();
+        state.close_database(0).unwrap();
+        assert_eq!(
+            state.finish_unlock(block_on(pending.run())).err(),
+            Some("The database has been closed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_share_container() {
+        let mut state = AppState::default();
+        state
+            .create_database("Test".to_string(), Some("hunter2".to_string()), None)
+            .unwrap();
+
+        let pending = state
+            .prepare_add_share_container(0, "share.kdbx".to_string(), b"container".to_vec())
+            .unwrap();
+
+        // the database is busy until the container is written
+        assert!(
+            state
+                .prepare_get_share_container(0, "share.kdbx".to_string())
+                .is_err()
+        );
+        assert_eq!(
+            state.finish_share_container(block_on(pending.run())),
+            Ok(None)
+        );
+
+        let pending = state
+            .prepare_get_share_container(0, "share.kdbx".to_string())
+            .unwrap();
+        assert_eq!(
+            state.finish_share_container(block_on(pending.run())),
+            Ok(Some(b"container".to_vec()))
+        );
+    }
+}
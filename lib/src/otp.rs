@@ -0,0 +1,1366 @@
+//! One-time password configuration and code generation
+//!
+//! TOTP settings are read from and written to both the KeePassXC `otp` field (an `otpauth://`
+//! URI) and the KeePass 2.x `TimeOtp-*` fields so that entries work in either client. Counter-based
+//! HOTP uses the KeePass 2.x `HmacOtp-*` fields, and Steam Guard codes use the KeePassXC
+//! `encoder=steam` URI parameter.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use data_encoding::{BASE32_NOPAD, HEXLOWER_PERMISSIVE};
+use hmac::{Hmac, Mac};
+use keepass::db::{Entry as KpEntry, Value as KpValue};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use url::Url;
+
+use crate::exchange::{OTPAlgorithm, OTPEncoder, OTPResponse, OTPSettings, OTPSetup};
+
+/// Field used by KeePassXC to store an `otpauth://` URI
+pub const OTP_FIELD: &str = "otp";
+
+pub const TIME_OTP_SECRET: &str = "TimeOtp-Secret";
+pub const TIME_OTP_SECRET_HEX: &str = "TimeOtp-Secret-Hex";
+pub const TIME_OTP_SECRET_BASE32: &str = "TimeOtp-Secret-Base32";
+pub const TIME_OTP_SECRET_BASE64: &str = "TimeOtp-Secret-Base64";
+pub const TIME_OTP_LENGTH: &str = "TimeOtp-Length";
+pub const TIME_OTP_PERIOD: &str = "TimeOtp-Period";
+pub const TIME_OTP_ALGORITHM: &str = "TimeOtp-Algorithm";
+
+pub const HMAC_OTP_SECRET: &str = "HmacOtp-Secret";
+pub const HMAC_OTP_SECRET_HEX: &str = "HmacOtp-Secret-Hex";
+pub const HMAC_OTP_SECRET_BASE32: &str = "HmacOtp-Secret-Base32";
+pub const HMAC_OTP_SECRET_BASE64: &str = "HmacOtp-Secret-Base64";
+pub const HMAC_OTP_COUNTER: &str = "HmacOtp-Counter";
+
+/// Fields used by older KeePassXC versions, with settings formatted as `<period>;<digits>`
+pub const LEGACY_TOTP_SEED: &str = "TOTP Seed";
+pub const LEGACY_TOTP_SETTINGS: &str = "TOTP Settings";
+
+/// Alphabet of Steam Guard codes
+const STEAM_ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+/// Number of characters in a Steam Guard code
+const STEAM_DIGITS: u32 = 5;
+
+/// Number of digits of KeePass 2.x HOTP codes
+const HOTP_DIGITS: u32 = 6;
+
+/// Characters that need escaping in the label and parameters of an `otpauth://` URI
+const URI_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_');
+
+/// One-time password settings of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OtpConfig {
+    Totp(TotpConfig),
+    Hotp(HotpConfig),
+}
+
+/// Settings for generating time-based one-time passwords
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpConfig {
+    pub secret: Vec<u8>,
+    pub algorithm: OTPAlgorithm,
+    pub digits: u32,
+    pub period: u64,
+    pub encoder: OTPEncoder,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+/// Settings for generating counter-based one-time passwords
+///
+/// KeePass 2.x only supports 6-digit HMAC-SHA-1 codes for HOTP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotpConfig {
+    pub secret: Vec<u8>,
+    pub counter: u64,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+impl OtpConfig {
+    /// Parse an `otpauth://totp/` or `otpauth://hotp/` URI
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let parsed = ParsedUri::parse(uri)?;
+
+        let config = match &parsed.kind[..] {
+            "totp" => OtpConfig::Totp(TotpConfig::from_parsed(parsed)?),
+            "hotp" => OtpConfig::Hotp(HotpConfig::from_parsed(parsed)?),
+            other => bail!("Unsupported one-time password type: {}", other),
+        };
+
+        Ok(config)
+    }
+
+    /// Read one-time password settings from an entry
+    ///
+    /// The KeePassXC `otp` field is preferred, followed by the KeePass 2.x `TimeOtp-*` and
+    /// `HmacOtp-*` fields and the legacy KeePassXC `TOTP Seed` field.
+    pub fn from_entry(entry: &KpEntry) -> Result<Option<Self>> {
+        let mut config = if let Some(uri) = entry.get(OTP_FIELD).filter(|v| !v.trim().is_empty()) {
+            // KeePassXC also accepts a bare Base32 secret in the otp field
+            if uri.trim_start().starts_with("otpauth://") {
+                Self::from_uri(uri)?
+            } else {
+                OtpConfig::Totp(TotpConfig::from_base32(uri)?)
+            }
+        } else if let Some(totp) = TotpConfig::from_keepass_fields(entry)? {
+            OtpConfig::Totp(totp)
+        } else if let Some(hotp) = HotpConfig::from_keepass_fields(entry)? {
+            OtpConfig::Hotp(hotp)
+        } else if let Some(totp) = TotpConfig::from_legacy_fields(entry)? {
+            OtpConfig::Totp(totp)
+        } else {
+            return Ok(None);
+        };
+
+        config.fill_label(entry);
+
+        Ok(Some(config))
+    }
+
+    /// Use the title and user name of an entry where the issuer and account are not known
+    pub fn fill_label(&mut self, entry: &KpEntry) {
+        let (issuer, account) = match self {
+            OtpConfig::Totp(c) => (&mut c.issuer, &mut c.account),
+            OtpConfig::Hotp(c) => (&mut c.issuer, &mut c.account),
+        };
+
+        if issuer.is_none() {
+            *issuer = entry.get_title().map(String::from);
+        }
+
+        if account.is_none() {
+            *account = entry.get_username().map(String::from);
+        }
+    }
+
+    /// Store the settings in an entry, replacing any previous one-time password settings
+    pub fn write_to_entry(&self, entry: &mut KpEntry) {
+        remove_from_entry(entry);
+
+        match self {
+            OtpConfig::Totp(c) => c.write_to_entry(entry),
+            OtpConfig::Hotp(c) => c.write_to_entry(entry),
+        }
+    }
+
+    /// Generate an `otpauth://` URI, e.g. for importing into a phone app
+    pub fn to_uri(&self) -> String {
+        match self {
+            OtpConfig::Totp(c) => c.to_uri(),
+            OtpConfig::Hotp(c) => c.to_uri(),
+        }
+    }
+
+    /// Render the `otpauth://` URI as a QR code in SVG format
+    pub fn to_qr_svg(&self) -> Result<String> {
+        let code = qrcode::QrCode::new(self.to_uri().as_bytes()).context("Encoding QR code")?;
+
+        let svg = code
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(256, 256)
+            .quiet_zone(true)
+            .build();
+
+        Ok(svg)
+    }
+}
+
+impl TotpConfig {
+    /// Create settings with the defaults used by most services (SHA-1, 6 digits, 30 seconds)
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self {
+            secret,
+            algorithm: OTPAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+            encoder: OTPEncoder::Digits,
+            issuer: None,
+            account: None,
+        }
+    }
+
+    /// Create settings for Steam Guard codes
+    pub fn steam(secret: Vec<u8>) -> Self {
+        Self {
+            digits: STEAM_DIGITS,
+            encoder: OTPEncoder::Steam,
+            issuer: Some("Steam".to_string()),
+            ..Self::new(secret)
+        }
+    }
+
+    /// Create settings from a Base32-encoded secret and default parameters
+    pub fn from_base32(secret: &str) -> Result<Self> {
+        let config = Self::new(decode_base32(secret)?);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_parsed(parsed: ParsedUri) -> Result<Self> {
+        let mut config = Self::new(parsed.secret);
+        config.issuer = parsed.issuer;
+        config.account = parsed.account;
+
+        if let Some(algorithm) = parsed.algorithm {
+            config.algorithm = algorithm;
+        }
+
+        if let Some(period) = parsed.period {
+            config.period = period;
+        }
+
+        if parsed.encoder.as_deref() == Some("steam") {
+            config.encoder = OTPEncoder::Steam;
+            config.digits = STEAM_DIGITS;
+        } else if let Some(digits) = parsed.digits {
+            config.digits = digits;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Read settings from the KeePass 2.x `TimeOtp-*` fields
+    fn from_keepass_fields(entry: &KpEntry) -> Result<Option<Self>> {
+        let Some(secret) = read_secret(
+            entry,
+            TIME_OTP_SECRET,
+            TIME_OTP_SECRET_HEX,
+            TIME_OTP_SECRET_BASE32,
+            TIME_OTP_SECRET_BASE64,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let mut config = Self::new(secret);
+
+        if let Some(digits) = entry.get(TIME_OTP_LENGTH) {
+            config.digits = digits.trim().parse().context("Parsing TOTP length")?;
+        }
+
+        if let Some(period) = entry.get(TIME_OTP_PERIOD) {
+            config.period = period.trim().parse().context("Parsing TOTP period")?;
+        }
+
+        if let Some(algorithm) = entry.get(TIME_OTP_ALGORITHM) {
+            config.algorithm = algorithm.parse()?;
+        }
+
+        config.validate()?;
+
+        Ok(Some(config))
+    }
+
+    /// Read settings from the fields used by older KeePassXC versions
+    fn from_legacy_fields(entry: &KpEntry) -> Result<Option<Self>> {
+        let Some(seed) = entry.get(LEGACY_TOTP_SEED) else {
+            return Ok(None);
+        };
+
+        let mut config = Self::new(decode_base32(seed)?);
+
+        if let Some((period, digits)) = entry
+            .get(LEGACY_TOTP_SETTINGS)
+            .and_then(|s| s.split_once(';'))
+        {
+            config.period = period.trim().parse().context("Parsing TOTP period")?;
+
+            if digits.trim() == "S" {
+                config.encoder = OTPEncoder::Steam;
+                config.digits = STEAM_DIGITS;
+            } else {
+                config.digits = digits.trim().parse().context("Parsing TOTP length")?;
+            }
+        }
+
+        config.validate()?;
+
+        Ok(Some(config))
+    }
+
+    /// Check that the settings can be used to generate codes
+    pub fn validate(&self) -> Result<()> {
+        if self.secret.is_empty() {
+            bail!("The secret must not be empty")
+        }
+
+        match self.encoder {
+            OTPEncoder::Digits if !(6..=10).contains(&self.digits) => {
+                bail!("The number of digits must be between 6 and 10")
+            }
+            OTPEncoder::Steam if self.digits != STEAM_DIGITS => {
+                bail!("Steam Guard codes must have {} characters", STEAM_DIGITS)
+            }
+            _ => {}
+        }
+
+        if !(1..=3600).contains(&self.period) {
+            bail!("The period must be between 1 and 3600 seconds")
+        }
+
+        Ok(())
+    }
+
+    /// Store the settings in both the KeePassXC and KeePass 2.x formats
+    ///
+    /// KeePass 2.x cannot generate Steam Guard codes, so these are only stored for KeePassXC.
+    fn write_to_entry(&self, entry: &mut KpEntry) {
+        entry.fields.insert(
+            OTP_FIELD.to_string(),
+            KpValue::Protected(self.to_uri().into()),
+        );
+
+        if self.encoder == OTPEncoder::Steam {
+            return;
+        }
+
+        entry.fields.insert(
+            TIME_OTP_SECRET_BASE32.to_string(),
+            KpValue::Protected(BASE32_NOPAD.encode(&self.secret).into()),
+        );
+
+        entry.fields.insert(
+            TIME_OTP_LENGTH.to_string(),
+            KpValue::Unprotected(self.digits.to_string()),
+        );
+
+        entry.fields.insert(
+            TIME_OTP_PERIOD.to_string(),
+            KpValue::Unprotected(self.period.to_string()),
+        );
+
+        entry.fields.insert(
+            TIME_OTP_ALGORITHM.to_string(),
+            KpValue::Unprotected(self.algorithm.keepass_name().to_string()),
+        );
+    }
+
+    fn to_uri(&self) -> String {
+        let mut uri = format!(
+            "otpauth://totp/{}?secret={}&period={}&digits={}&algorithm={}",
+            uri_label(self.issuer.as_deref(), self.account.as_deref()),
+            BASE32_NOPAD.encode(&self.secret),
+            self.period,
+            self.digits,
+            self.algorithm.uri_name(),
+        );
+
+        if let Some(issuer) = &self.issuer {
+            uri.push_str("&issuer=");
+            uri.push_str(&utf8_percent_encode(issuer, URI_ESCAPE).to_string());
+        }
+
+        if self.encoder == OTPEncoder::Steam {
+            uri.push_str("&encoder=steam");
+        }
+
+        uri
+    }
+
+    /// Generate the code that is valid at a UNIX timestamp
+    pub fn value_at(&self, time: u64) -> OTPResponse {
+        let counter = time / self.period;
+        let binary = truncate(&self.secret, counter, self.algorithm);
+
+        let code = match self.encoder {
+            OTPEncoder::Digits => format_digits(binary, self.digits),
+            OTPEncoder::Steam => format_steam(binary),
+        };
+
+        OTPResponse::Totp {
+            code,
+            valid_for: Duration::from_secs(self.period - time % self.period),
+            period: Duration::from_secs(self.period),
+        }
+    }
+}
+
+impl HotpConfig {
+    pub fn new(secret: Vec<u8>, counter: u64) -> Self {
+        Self {
+            secret,
+            counter,
+            issuer: None,
+            account: None,
+        }
+    }
+
+    fn from_parsed(parsed: ParsedUri) -> Result<Self> {
+        if parsed.algorithm.is_some_and(|a| a != OTPAlgorithm::Sha1) {
+            bail!("Only HMAC-SHA-1 is supported for counter-based one-time passwords")
+        }
+
+        if parsed.digits.is_some_and(|d| d != HOTP_DIGITS) {
+            bail!(
+                "Only {} digits are supported for counter-based one-time passwords",
+                HOTP_DIGITS
+            )
+        }
+
+        let mut config = Self::new(parsed.secret, parsed.counter.unwrap_or_default());
+        config.issuer = parsed.issuer;
+        config.account = parsed.account;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Read settings from the KeePass 2.x `HmacOtp-*` fields
+    fn from_keepass_fields(entry: &KpEntry) -> Result<Option<Self>> {
+        let Some(secret) = read_secret(
+            entry,
+            HMAC_OTP_SECRET,
+            HMAC_OTP_SECRET_HEX,
+            HMAC_OTP_SECRET_BASE32,
+            HMAC_OTP_SECRET_BASE64,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let counter = match entry.get(HMAC_OTP_COUNTER) {
+            Some(c) if !c.trim().is_empty() => c.trim().parse().context("Parsing HOTP counter")?,
+            _ => 0,
+        };
+
+        let config = Self::new(secret, counter);
+        config.validate()?;
+
+        Ok(Some(config))
+    }
+
+    /// Check that the settings can be used to generate codes
+    pub fn validate(&self) -> Result<()> {
+        if self.secret.is_empty() {
+            bail!("The secret must not be empty")
+        }
+
+        Ok(())
+    }
+
+    fn write_to_entry(&self, entry: &mut KpEntry) {
+        entry.fields.insert(
+            HMAC_OTP_SECRET_BASE32.to_string(),
+            KpValue::Protected(BASE32_NOPAD.encode(&self.secret).into()),
+        );
+
+        self.write_counter(entry);
+    }
+
+    /// Persist the current counter value in an entry
+    ///
+    /// An `otpauth://hotp/` URI in the `otp` field is updated as well, as it is read in preference
+    /// to the `HmacOtp-Counter` field.
+    pub fn write_counter(&self, entry: &mut KpEntry) {
+        entry.fields.insert(
+            HMAC_OTP_COUNTER.to_string(),
+            KpValue::Unprotected(self.counter.to_string()),
+        );
+
+        let uri = entry
+            .get(OTP_FIELD)
+            .filter(|uri| uri.trim_start().starts_with("otpauth://hotp/"))
+            .map(|uri| uri_with_counter(uri, self.counter));
+
+        if let Some(uri) = uri {
+            entry
+                .fields
+                .insert(OTP_FIELD.to_string(), KpValue::Protected(uri.into()));
+        }
+    }
+
+    fn to_uri(&self) -> String {
+        let mut uri = format!(
+            "otpauth://hotp/{}?secret={}&counter={}&digits={}&algorithm={}",
+            uri_label(self.issuer.as_deref(), self.account.as_deref()),
+            BASE32_NOPAD.encode(&self.secret),
+            self.counter,
+            HOTP_DIGITS,
+            OTPAlgorithm::Sha1.uri_name(),
+        );
+
+        if let Some(issuer) = &self.issuer {
+            uri.push_str("&issuer=");
+            uri.push_str(&utf8_percent_encode(issuer, URI_ESCAPE).to_string());
+        }
+
+        uri
+    }
+
+    /// Generate the code for the current counter and advance the counter
+    pub fn generate(&mut self) -> Result<OTPResponse> {
+        let next = self
+            .counter
+            .checked_add(1)
+            .context("The HOTP counter cannot be advanced any further")?;
+
+        let binary = truncate(&self.secret, self.counter, OTPAlgorithm::Sha1);
+
+        let response = OTPResponse::Hotp {
+            code: format_digits(binary, HOTP_DIGITS),
+            counter: self.counter,
+        };
+
+        self.counter = next;
+
+        Ok(response)
+    }
+}
+
+impl TryFrom<OTPSetup> for OtpConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(setup: OTPSetup) -> Result<Self> {
+        match setup {
+            OTPSetup::Uri { uri } => Self::from_uri(&uri),
+            OTPSetup::Secret {
+                secret,
+                algorithm,
+                digits,
+                period,
+            } => {
+                let mut config = TotpConfig::new(decode_base32(&secret)?);
+                config.algorithm = algorithm;
+                config.digits = digits;
+                config.period = period;
+                config.validate()?;
+
+                Ok(OtpConfig::Totp(config))
+            }
+            OTPSetup::Steam { secret } => {
+                let config = TotpConfig::steam(decode_base32(&secret)?);
+                config.validate()?;
+
+                Ok(OtpConfig::Totp(config))
+            }
+            OTPSetup::Hotp { secret, counter } => {
+                let config = HotpConfig::new(decode_base32(&secret)?, counter);
+                config.validate()?;
+
+                Ok(OtpConfig::Hotp(config))
+            }
+        }
+    }
+}
+
+impl From<&OtpConfig> for OTPSettings {
+    fn from(config: &OtpConfig) -> Self {
+        match config {
+            OtpConfig::Totp(c) => OTPSettings::Totp {
+                algorithm: c.algorithm,
+                digits: c.digits,
+                period: c.period,
+                encoder: c.encoder,
+                issuer: c.issuer.clone(),
+                account: c.account.clone(),
+            },
+            OtpConfig::Hotp(c) => OTPSettings::Hotp {
+                counter: c.counter,
+                issuer: c.issuer.clone(),
+                account: c.account.clone(),
+            },
+        }
+    }
+}
+
+/// The components of an `otpauth://` URI
+struct ParsedUri {
+    kind: String,
+    secret: Vec<u8>,
+    issuer: Option<String>,
+    account: Option<String>,
+    algorithm: Option<OTPAlgorithm>,
+    digits: Option<u32>,
+    period: Option<u64>,
+    counter: Option<u64>,
+    encoder: Option<String>,
+}
+
+impl ParsedUri {
+    fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri.trim()).context("Parsing otpauth URI")?;
+
+        if url.scheme() != "otpauth" {
+            bail!("Not an otpauth:// URI")
+        }
+
+        let kind = url
+            .host_str()
+            .context("The otpauth URI has no type")?
+            .to_ascii_lowercase();
+
+        let label = percent_encoding::percent_decode_str(url.path().trim_start_matches('/'))
+            .decode_utf8()
+            .context("Decoding label")?;
+
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim()), account.trim()),
+            None => (None, label.trim()),
+        };
+
+        let mut parsed = ParsedUri {
+            kind,
+            secret: Vec::new(),
+            issuer: label_issuer.map(String::from),
+            account: Some(account.to_string()).filter(|a| !a.is_empty()),
+            algorithm: None,
+            digits: None,
+            period: None,
+            counter: None,
+            encoder: None,
+        };
+
+        let mut has_secret = false;
+
+        for (key, value) in url.query_pairs() {
+            match &key.to_ascii_lowercase()[..] {
+                "secret" => {
+                    parsed.secret = decode_base32(&value)?;
+                    has_secret = true;
+                }
+                "issuer" => parsed.issuer = Some(value.to_string()),
+                "algorithm" => parsed.algorithm = Some(value.parse()?),
+                "digits" => parsed.digits = Some(value.parse().context("Parsing digits")?),
+                "period" => parsed.period = Some(value.parse().context("Parsing period")?),
+                "counter" => parsed.counter = Some(value.parse().context("Parsing counter")?),
+                "encoder" => parsed.encoder = Some(value.to_ascii_lowercase()),
+                _ => {}
+            }
+        }
+
+        if !has_secret {
+            bail!("The otpauth URI does not contain a secret")
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Remove all one-time password settings from an entry
+pub fn remove_from_entry(entry: &mut KpEntry) {
+    for field in [
+        OTP_FIELD,
+        TIME_OTP_SECRET,
+        TIME_OTP_SECRET_HEX,
+        TIME_OTP_SECRET_BASE32,
+        TIME_OTP_SECRET_BASE64,
+        TIME_OTP_LENGTH,
+        TIME_OTP_PERIOD,
+        TIME_OTP_ALGORITHM,
+        HMAC_OTP_SECRET,
+        HMAC_OTP_SECRET_HEX,
+        HMAC_OTP_SECRET_BASE32,
+        HMAC_OTP_SECRET_BASE64,
+        HMAC_OTP_COUNTER,
+        LEGACY_TOTP_SEED,
+        LEGACY_TOTP_SETTINGS,
+    ] {
+        entry.fields.remove(field);
+    }
+}
+
+/// Read a secret stored in one of the KeePass 2.x encodings
+fn read_secret(
+    entry: &KpEntry,
+    utf8: &str,
+    hex: &str,
+    base32: &str,
+    base64: &str,
+) -> Result<Option<Vec<u8>>> {
+    let secret = if let Some(s) = entry.get(base32) {
+        decode_base32(s)?
+    } else if let Some(s) = entry.get(hex) {
+        let cleaned: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        HEXLOWER_PERMISSIVE
+            .decode(cleaned.as_bytes())
+            .context("Decoding hex secret")?
+    } else if let Some(s) = entry.get(base64) {
+        base64::engine::general_purpose::STANDARD
+            .decode(s.trim())
+            .context("Decoding Base64 secret")?
+    } else if let Some(s) = entry.get(utf8) {
+        s.as_bytes().to_vec()
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(secret))
+}
+
+/// Format the label of an `otpauth://` URI
+fn uri_label(issuer: Option<&str>, account: Option<&str>) -> String {
+    let escape = |s: &str| utf8_percent_encode(s, URI_ESCAPE).to_string();
+    let account = account.unwrap_or_default();
+
+    match issuer {
+        Some(issuer) => format!("{}:{}", escape(issuer), escape(account)),
+        None => escape(account),
+    }
+}
+
+/// Replace the `counter` parameter of an `otpauth://` URI, keeping the other parameters as they are
+fn uri_with_counter(uri: &str, counter: u64) -> String {
+    let uri = uri.trim();
+    let (base, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+    let mut found = false;
+    let mut params: Vec<String> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let key = param.split_once('=').map(|(key, _)| key).unwrap_or(param);
+
+            if key.eq_ignore_ascii_case("counter") {
+                found = true;
+                format!("{key}={counter}")
+            } else {
+                param.to_string()
+            }
+        })
+        .collect();
+
+    if !found {
+        params.push(format!("counter={counter}"));
+    }
+
+    format!("{}?{}", base, params.join("&"))
+}
+
+/// Decode a Base32 secret, ignoring case, whitespace and padding
+pub fn decode_base32(secret: &str) -> Result<Vec<u8>> {
+    let cleaned: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    BASE32_NOPAD
+        .decode(cleaned.as_bytes())
+        .context("Decoding Base32 secret")
+}
+
+/// Compute the dynamically truncated HMAC of a counter value (RFC 4226)
+fn truncate(secret: &[u8], counter: u64, algorithm: OTPAlgorithm) -> u32 {
+    let digest = hmac_digest(secret, &counter.to_be_bytes(), algorithm);
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ])
+}
+
+/// Format a truncated HMAC as a decimal code
+fn format_digits(binary: u32, digits: u32) -> String {
+    let code = binary as u64 % 10u64.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Format a truncated HMAC using the Steam Guard alphabet
+fn format_steam(mut binary: u32) -> String {
+    let mut code = String::with_capacity(STEAM_DIGITS as usize);
+
+    for _ in 0..STEAM_DIGITS {
+        let idx = binary as usize % STEAM_ALPHABET.len();
+        code.push(STEAM_ALPHABET[idx] as char);
+        binary /= STEAM_ALPHABET.len() as u32;
+    }
+
+    code
+}
+
+fn hmac_digest(secret: &[u8], message: &[u8], algorithm: OTPAlgorithm) -> Vec<u8> {
+    macro_rules! digest {
+        ($hash:ty) => {{
+            let mut mac =
+                Hmac::<$hash>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }};
+    }
+
+    match algorithm {
+        OTPAlgorithm::Sha1 => digest!(sha1::Sha1),
+        OTPAlgorithm::Sha256 => digest!(sha2::Sha256),
+        OTPAlgorithm::Sha512 => digest!(sha2::Sha512),
+    }
+}
+
+impl OTPAlgorithm {
+    /// Name of the algorithm in `otpauth://` URIs
+    pub fn uri_name(&self) -> &'static str {
+        match self {
+            OTPAlgorithm::Sha1 => "SHA1",
+            OTPAlgorithm::Sha256 => "SHA256",
+            OTPAlgorithm::Sha512 => "SHA512",
+        }
+    }
+
+    /// Name of the algorithm in the KeePass 2.x `TimeOtp-Algorithm` field
+    pub fn keepass_name(&self) -> &'static str {
+        match self {
+            OTPAlgorithm::Sha1 => "HMAC-SHA-1",
+            OTPAlgorithm::Sha256 => "HMAC-SHA-256",
+            OTPAlgorithm::Sha512 => "HMAC-SHA-512",
+        }
+    }
+}
+
+impl std::str::FromStr for OTPAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let normalized: String = s
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_uppercase();
+
+        match normalized.trim_start_matches("HMAC") {
+            "SHA1" => Ok(OTPAlgorithm::Sha1),
+            "SHA256" => Ok(OTPAlgorithm::Sha256),
+            "SHA512" => Ok(OTPAlgorithm::Sha512),
+            _ => bail!("Unsupported OTP algorithm: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secrets of the RFC 4226 and RFC 6238 test vectors
+    const SHA1_SECRET: &[u8] = b"12345678901234567890";
+    const SHA256_SECRET: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_SECRET: &[u8] =
+        b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    fn code(response: OTPResponse) -> String {
+        match response {
+            OTPResponse::Totp { code, .. } | OTPResponse::Hotp { code, .. } => code,
+        }
+    }
+
+    fn totp(secret: &[u8], algorithm: OTPAlgorithm, digits: u32) -> TotpConfig {
+        TotpConfig {
+            algorithm,
+            digits,
+            ..TotpConfig::new(secret.to_vec())
+        }
+    }
+
+    fn entry(fields: &[(&str, &str)]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        for (name, value) in fields {
+            entry
+                .fields
+                .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+        }
+        entry
+    }
+
+    /// Application state with a single unlocked database that contains an entry
+    fn state_with_entry(entry: KpEntry) -> crate::AppState {
+        use keepass::db::{Database as KpDatabase, Node};
+
+        use crate::database::Database;
+
+        let mut database = KpDatabase::new(Default::default());
+        database.root.children.push(Node::Entry(entry));
+
+        let mut state = crate::AppState::default();
+        state.databases.push(Database::unlocked(database));
+
+        state
+    }
+
+    #[test]
+    fn rfc6238_vectors() {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+
+        let sha1 = totp(SHA1_SECRET, OTPAlgorithm::Sha1, 8);
+        let sha256 = totp(SHA256_SECRET, OTPAlgorithm::Sha256, 8);
+        let sha512 = totp(SHA512_SECRET, OTPAlgorithm::Sha512, 8);
+
+        for (time, expected_sha1, expected_sha256, expected_sha512) in vectors {
+            assert_eq!(code(sha1.value_at(time)), expected_sha1, "SHA1 at {time}");
+            assert_eq!(
+                code(sha256.value_at(time)),
+                expected_sha256,
+                "SHA256 at {time}"
+            );
+            assert_eq!(
+                code(sha512.value_at(time)),
+                expected_sha512,
+                "SHA512 at {time}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_remaining_validity() {
+        let OTPResponse::Totp {
+            valid_for, period, ..
+        } = TotpConfig::new(SHA1_SECRET.to_vec()).value_at(59)
+        else {
+            panic!("expected a time-based code")
+        };
+
+        assert_eq!(valid_for, Duration::from_secs(1));
+        assert_eq!(period, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn parses_otpauth_uri() {
+        let config = OtpConfig::from_uri(
+            "otpauth://totp/ACME%20Co:john.doe@email.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            OtpConfig::Totp(TotpConfig {
+                secret: SHA1_SECRET.to_vec(),
+                algorithm: OTPAlgorithm::Sha256,
+                digits: 8,
+                period: 60,
+                encoder: OTPEncoder::Digits,
+                issuer: Some("ACME Co".to_string()),
+                account: Some("john.doe@email.com".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn uses_defaults_for_missing_parameters() {
+        let config = OtpConfig::from_uri("otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ").unwrap();
+
+        assert_eq!(
+            config,
+            OtpConfig::Totp(TotpConfig {
+                account: Some("alice".to_string()),
+                ..TotpConfig::new(b"1234567890".to_vec())
+            })
+        );
+    }
+
+    #[test]
+    fn uri_round_trip() {
+        let config = OtpConfig::Totp(TotpConfig {
+            secret: SHA512_SECRET.to_vec(),
+            algorithm: OTPAlgorithm::Sha512,
+            digits: 7,
+            period: 45,
+            encoder: OTPEncoder::Digits,
+            issuer: Some("Example & Co".to_string()),
+            account: Some("alice+otp@example.com".to_string()),
+        });
+
+        let uri = config.to_uri();
+        assert!(uri.starts_with("otpauth://totp/Example%20%26%20Co:alice%2Botp%40example.com?"));
+        assert_eq!(OtpConfig::from_uri(&uri).unwrap(), config);
+    }
+
+    #[test]
+    fn decodes_base32_leniently() {
+        assert_eq!(decode_base32("GEZDGNBVGY3TQOJQ").unwrap(), b"1234567890");
+        assert_eq!(decode_base32("gezdgnbvgy3tqojq").unwrap(), b"1234567890");
+        assert_eq!(
+            decode_base32("gezd gnbv-gy3t qojq\n").unwrap(),
+            b"1234567890"
+        );
+        assert_eq!(decode_base32("NBSWY3DPEE======").unwrap(), b"hello!");
+        assert_eq!(decode_base32("nbswy3dpee").unwrap(), b"hello!");
+
+        assert!(decode_base32("GEZDGNB1").is_err());
+        assert!(decode_base32("not base32!").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let secret = "secret=GEZDGNBVGY3TQOJQ";
+
+        for uri in [
+            format!("otpauth://totp/a?{secret}&digits=5"),
+            format!("otpauth://totp/a?{secret}&digits=11"),
+            format!("otpauth://totp/a?{secret}&digits=six"),
+            format!("otpauth://totp/a?{secret}&period=0"),
+            format!("otpauth://totp/a?{secret}&period=-30"),
+            format!("otpauth://totp/a?{secret}&algorithm=MD5"),
+            format!("otpauth://xotp/a?{secret}"),
+            format!("https://example.com/a?{secret}"),
+            "otpauth://totp/a?secret=".to_string(),
+            "otpauth://totp/a?digits=6".to_string(),
+        ] {
+            assert!(OtpConfig::from_uri(&uri).is_err(), "{uri} was accepted");
+        }
+
+        let setup = |digits, period| OTPSetup::Secret {
+            secret: "GEZDGNBVGY3TQOJQ".to_string(),
+            algorithm: OTPAlgorithm::Sha1,
+            digits,
+            period,
+        };
+
+        assert!(OtpConfig::try_from(setup(6, 30)).is_ok());
+        assert!(OtpConfig::try_from(setup(4, 30)).is_err());
+        assert!(OtpConfig::try_from(setup(6, 7200)).is_err());
+    }
+
+    #[test]
+    fn parses_algorithm_names() {
+        for name in ["SHA1", "sha1", "HMAC-SHA-1", "HmacSha1"] {
+            assert_eq!(name.parse::<OTPAlgorithm>().unwrap(), OTPAlgorithm::Sha1);
+        }
+
+        assert_eq!(
+            "HMAC-SHA-256".parse::<OTPAlgorithm>().unwrap(),
+            OTPAlgorithm::Sha256
+        );
+        assert_eq!(
+            "sha512".parse::<OTPAlgorithm>().unwrap(),
+            OTPAlgorithm::Sha512
+        );
+        assert!("SHA384".parse::<OTPAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn keepassxc_and_keepass_fields_agree() {
+        let config = OtpConfig::Totp(TotpConfig {
+            algorithm: OTPAlgorithm::Sha256,
+            digits: 8,
+            issuer: Some("Example".to_string()),
+            account: Some("alice".to_string()),
+            ..TotpConfig::new(SHA256_SECRET.to_vec())
+        });
+
+        let mut entry = entry(&[("Title", "Example"), ("UserName", "alice")]);
+        config.write_to_entry(&mut entry);
+
+        assert_eq!(entry.get(TIME_OTP_LENGTH), Some("8"));
+        assert_eq!(entry.get(TIME_OTP_PERIOD), Some("30"));
+        assert_eq!(entry.get(TIME_OTP_ALGORITHM), Some("HMAC-SHA-256"));
+
+        let from_uri = OtpConfig::from_entry(&entry).unwrap().unwrap();
+
+        entry.fields.remove(OTP_FIELD);
+        let from_keepass_fields = OtpConfig::from_entry(&entry).unwrap().unwrap();
+
+        assert_eq!(from_uri, config);
+        assert_eq!(from_keepass_fields, config);
+
+        let (OtpConfig::Totp(a), OtpConfig::Totp(b)) = (from_uri, from_keepass_fields) else {
+            panic!("expected time-based settings")
+        };
+        assert_eq!(code(a.value_at(1234567890)), "91819424");
+        assert_eq!(code(b.value_at(1234567890)), "91819424");
+    }
+
+    #[test]
+    fn reads_keepass_secret_encodings() {
+        for (field, value) in [
+            (TIME_OTP_SECRET, "12345678901234567890"),
+            (
+                TIME_OTP_SECRET_HEX,
+                "3132333435363738393031323334353637383930",
+            ),
+            (TIME_OTP_SECRET_BASE32, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            (TIME_OTP_SECRET_BASE64, "MTIzNDU2Nzg5MDEyMzQ1Njc4OTA="),
+        ] {
+            let entry = entry(&[(field, value), (TIME_OTP_LENGTH, "8")]);
+
+            let Some(OtpConfig::Totp(config)) = OtpConfig::from_entry(&entry).unwrap() else {
+                panic!("{field} was not read")
+            };
+
+            assert_eq!(config.secret, SHA1_SECRET, "{field}");
+            assert_eq!(code(config.value_at(59)), "94287082", "{field}");
+        }
+    }
+
+    #[test]
+    fn prefers_otp_field() {
+        let entry = entry(&[
+            (
+                OTP_FIELD,
+                "otpauth://totp/a?secret=GEZDGNBVGY3TQOJQ&digits=7",
+            ),
+            (TIME_OTP_SECRET_BASE32, "NBSWY3DPEE"),
+        ]);
+
+        let Some(OtpConfig::Totp(config)) = OtpConfig::from_entry(&entry).unwrap() else {
+            panic!("expected time-based settings")
+        };
+
+        assert_eq!(config.secret, b"1234567890");
+        assert_eq!(config.digits, 7);
+    }
+
+    #[test]
+    fn reads_bare_secret_and_legacy_fields() {
+        let entry_bare = entry(&[(OTP_FIELD, "gezd gnbv gy3t qojq")]);
+        let Some(OtpConfig::Totp(config)) = OtpConfig::from_entry(&entry_bare).unwrap() else {
+            panic!("expected time-based settings")
+        };
+        assert_eq!(config.secret, b"1234567890");
+
+        let entry_legacy = entry(&[
+            (LEGACY_TOTP_SEED, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            (LEGACY_TOTP_SETTINGS, "30;8"),
+        ]);
+        let Some(OtpConfig::Totp(config)) = OtpConfig::from_entry(&entry_legacy).unwrap() else {
+            panic!("expected time-based settings")
+        };
+        assert_eq!(code(config.value_at(1111111109)), "07081804");
+    }
+
+    #[test]
+    fn fills_label_from_entry() {
+        let entry = entry(&[
+            ("Title", "Example"),
+            ("UserName", "alice"),
+            (OTP_FIELD, "otpauth://totp/?secret=GEZDGNBVGY3TQOJQ"),
+        ]);
+
+        let Some(OtpConfig::Totp(config)) = OtpConfig::from_entry(&entry).unwrap() else {
+            panic!("expected time-based settings")
+        };
+
+        assert_eq!(config.issuer.as_deref(), Some("Example"));
+        assert_eq!(config.account.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn removes_all_fields() {
+        let mut entry = entry(&[("Title", "Example")]);
+
+        OtpConfig::Totp(TotpConfig::new(SHA1_SECRET.to_vec())).write_to_entry(&mut entry);
+        remove_from_entry(&mut entry);
+
+        assert_eq!(OtpConfig::from_entry(&entry).unwrap(), None);
+        assert_eq!(entry.fields.len(), 1);
+    }
+
+    #[test]
+    fn rfc4226_vectors() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        let mut config = HotpConfig::new(SHA1_SECRET.to_vec(), 0);
+
+        for (counter, expected) in expected.into_iter().enumerate() {
+            let OTPResponse::Hotp {
+                code,
+                counter: used,
+            } = config.generate().unwrap()
+            else {
+                panic!("expected a counter-based code")
+            };
+
+            assert_eq!(code, expected, "counter {counter}");
+            assert_eq!(used, counter as u64);
+        }
+
+        assert_eq!(config.counter, 10);
+    }
+
+    #[test]
+    fn hotp_counter_overflow() {
+        let mut config = HotpConfig::new(SHA1_SECRET.to_vec(), u64::MAX);
+
+        assert!(config.generate().is_err());
+        assert_eq!(config.counter, u64::MAX);
+    }
+
+    #[test]
+    fn rfc4226_truncation() {
+        assert_eq!(truncate(SHA1_SECRET, 0, OTPAlgorithm::Sha1), 0x4c93cf18);
+        assert_eq!(truncate(SHA1_SECRET, 1, OTPAlgorithm::Sha1), 0x41397eea);
+        assert_eq!(truncate(SHA1_SECRET, 9, OTPAlgorithm::Sha1), 0x2679dc69);
+    }
+
+    #[test]
+    fn steam_codes() {
+        assert_eq!(format_steam(0), "22222");
+        assert_eq!(format_steam(0x4c93cf18), "GG5F5");
+        assert_eq!(format_steam(0x41397eea), "PV9M4");
+
+        let config = TotpConfig::steam(SHA1_SECRET.to_vec());
+        let steam = code(config.value_at(1234567890));
+
+        assert_eq!(steam, "VHHQY");
+        assert!(steam.bytes().all(|c| STEAM_ALPHABET.contains(&c)));
+    }
+
+    #[test]
+    fn steam_settings() {
+        let config = OtpConfig::from_uri(
+            "otpauth://totp/Steam:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&encoder=steam",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            OtpConfig::Totp(TotpConfig {
+                account: Some("alice".to_string()),
+                ..TotpConfig::steam(SHA1_SECRET.to_vec())
+            })
+        );
+        assert_eq!(OtpConfig::from_uri(&config.to_uri()).unwrap(), config);
+
+        // KeePass 2.x cannot generate Steam codes, so only the KeePassXC field is written
+        let mut written = entry(&[]);
+        config.write_to_entry(&mut written);
+        assert!(written.get(OTP_FIELD).is_some());
+        assert!(written.get(TIME_OTP_SECRET_BASE32).is_none());
+
+        let legacy = entry(&[
+            (LEGACY_TOTP_SEED, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            (LEGACY_TOTP_SETTINGS, "30;S"),
+        ]);
+        let Some(OtpConfig::Totp(legacy)) = OtpConfig::from_entry(&legacy).unwrap() else {
+            panic!("expected time-based settings")
+        };
+        assert_eq!(legacy.encoder, OTPEncoder::Steam);
+        assert_eq!(code(legacy.value_at(1234567890)), "VHHQY");
+    }
+
+    #[test]
+    fn hotp_settings() {
+        let config =
+            OtpConfig::from_uri("otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQ&counter=42")
+                .unwrap();
+
+        assert_eq!(
+            config,
+            OtpConfig::Hotp(HotpConfig {
+                issuer: Some("Example".to_string()),
+                account: Some("alice".to_string()),
+                ..HotpConfig::new(b"1234567890".to_vec(), 42)
+            })
+        );
+        assert_eq!(OtpConfig::from_uri(&config.to_uri()).unwrap(), config);
+
+        // KeePass 2.x fields have no label, so it comes from the entry
+        let mut entry = entry(&[("Title", "Example"), ("UserName", "alice")]);
+        config.write_to_entry(&mut entry);
+        assert_eq!(entry.get(HMAC_OTP_COUNTER), Some("42"));
+        assert_eq!(OtpConfig::from_entry(&entry).unwrap(), Some(config));
+
+        for uri in [
+            "otpauth://hotp/a?secret=GEZDGNBVGY3TQOJQ&algorithm=SHA256",
+            "otpauth://hotp/a?secret=GEZDGNBVGY3TQOJQ&digits=8",
+            "otpauth://hotp/a?secret=GEZDGNBVGY3TQOJQ&counter=-1",
+        ] {
+            assert!(OtpConfig::from_uri(uri).is_err(), "{uri} was accepted");
+        }
+    }
+
+    #[test]
+    fn get_otp_advances_stored_counter() {
+        use crate::AppState;
+
+        let entry = entry(&[
+            ("Title", "Example"),
+            (HMAC_OTP_SECRET, "12345678901234567890"),
+            (HMAC_OTP_COUNTER, "1"),
+        ]);
+        let entry_uuid = entry.uuid.to_string();
+
+        let mut state = state_with_entry(entry);
+
+        let counter = |state: &AppState| {
+            let entry_uuid = uuid::Uuid::parse_str(&entry_uuid).unwrap();
+            state.databases[0]
+                .entry(&entry_uuid)
+                .unwrap()
+                .unwrap()
+                .get(HMAC_OTP_COUNTER)
+                .map(String::from)
+        };
+
+        for (expected_code, expected_counter) in [("287082", 1), ("359152", 2), ("969429", 3)] {
+            let OTPResponse::Hotp {
+                code,
+                counter: used,
+            } = state.get_otp(0, &entry_uuid, 0).unwrap()
+            else {
+                panic!("expected a counter-based code")
+            };
+
+            assert_eq!(code, expected_code);
+            assert_eq!(used, expected_counter);
+            assert_eq!(counter(&state), Some((expected_counter + 1).to_string()));
+        }
+
+        let Some(OTPSettings::Hotp { counter, .. }) =
+            state.get_otp_settings(0, &entry_uuid).unwrap()
+        else {
+            panic!("expected counter-based settings")
+        };
+        assert_eq!(counter, 4);
+
+        // the secret stays in the field it was read from
+        let entry_uuid = uuid::Uuid::parse_str(&entry_uuid).unwrap();
+        let entry = state.databases[0].entry(&entry_uuid).unwrap().unwrap();
+        assert_eq!(entry.get(HMAC_OTP_SECRET), Some("12345678901234567890"));
+    }
+
+    #[test]
+    fn get_otp_advances_counter_in_uri() {
+        let entry = entry(&[(
+            OTP_FIELD,
+            "otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1&issuer=Example",
+        )]);
+        let entry_uuid = entry.uuid.to_string();
+
+        let mut state = state_with_entry(entry);
+
+        let first = code(state.get_otp(0, &entry_uuid, 0).unwrap());
+        let second = code(state.get_otp(0, &entry_uuid, 0).unwrap());
+
+        assert_eq!(first, "287082");
+        assert_eq!(second, "359152");
+
+        let entry_uuid = uuid::Uuid::parse_str(&entry_uuid).unwrap();
+        let entry = state.databases[0].entry(&entry_uuid).unwrap().unwrap();
+        assert_eq!(
+            entry.get(OTP_FIELD),
+            Some(
+                "otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=3&issuer=Example"
+            )
+        );
+    }
+
+    #[test]
+    fn adds_missing_counter_to_uri() {
+        assert_eq!(
+            uri_with_counter("otpauth://hotp/a?secret=GEZDGNBVGY3TQOJQ", 7),
+            "otpauth://hotp/a?secret=GEZDGNBVGY3TQOJQ&counter=7"
+        );
+        assert_eq!(
+            uri_with_counter("otpauth://hotp/a?Counter=2&secret=GEZDGNBVGY3TQOJQ", 3),
+            "otpauth://hotp/a?Counter=3&secret=GEZDGNBVGY3TQOJQ"
+        );
+    }
+}
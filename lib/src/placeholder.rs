@@ -0,0 +1,800 @@
+//! Resolution of KeePass placeholders and field references
+//!
+//! Supports entry field placeholders (`{TITLE}`, `{S:Name}`, `{URL:HOST}`, ...), field references
+//! to other entries (`{REF:P@I:<uuid>}`), date/time placeholders (`{DT_SIMPLE}`, ...) and the
+//! string transformations `{T-CONV:...}` and `{T-REPLACE-RX:...}`. Placeholders that depend on
+//! the environment (clipboard, environment variables, commands) are left untouched.
+
+use std::collections::{HashMap, HashSet};
+
+use base64::Engine;
+use chrono::{DateTime, Local, Utc};
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node, NodeRef};
+use url::Url;
+use uuid::Uuid;
+
+/// Maximum nesting depth when resolving placeholders within the values of other placeholders
+pub const MAX_DEPTH: usize = 12;
+
+/// Names of the standard fields of an entry
+const STANDARD_FIELDS: [&str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+
+/// Components that can be extracted with `{URL:<component>}`
+const URL_COMPONENTS: [&str; 9] = [
+    "RMVSCM", "SCM", "HOST", "PORT", "PATH", "QUERY", "USERINFO", "USERNAME", "PASSWORD",
+];
+
+/// The fields of entries that are being resolved and those that are done
+///
+/// A field that refers to itself, directly or through other fields, would otherwise be expanded
+/// again and again. Such references are left unresolved, and fields that are referenced several
+/// times are only resolved once.
+#[derive(Default)]
+struct Expansion {
+    active: HashSet<(Uuid, String)>,
+    done: HashMap<(Uuid, String), String>,
+}
+
+/// Resolves placeholders in the context of a database
+pub struct Resolver<'a> {
+    database: &'a KpDatabase,
+    now: DateTime<Local>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Create a resolver that uses the current time for date/time placeholders
+    pub fn new(database: &'a KpDatabase) -> Self {
+        Self::with_time(database, Local::now())
+    }
+
+    /// Create a resolver that uses a fixed time for date/time placeholders
+    pub fn with_time(database: &'a KpDatabase, now: DateTime<Local>) -> Self {
+        Self { database, now }
+    }
+
+    /// Resolve all placeholders in a text, in the context of an entry
+    pub fn resolve(&self, entry: &KpEntry, text: &str) -> String {
+        self.resolve_depth(entry, text, 0, &mut Expansion::default())
+    }
+
+    /// Get a field of an entry with all placeholders resolved
+    pub fn field(&self, entry: &KpEntry, name: &str) -> Option<String> {
+        self.resolve_field(entry, name, 0, &mut Expansion::default())
+    }
+
+    fn resolve_depth(
+        &self,
+        entry: &KpEntry,
+        text: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> String {
+        if depth > MAX_DEPTH || !text.contains('{') {
+            return text.to_string();
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+
+            let candidate = &rest[start..];
+            let Some(end) = matching_brace(candidate) else {
+                // unbalanced braces are kept as they are
+                out.push_str(candidate);
+                rest = "";
+                break;
+            };
+
+            match self.placeholder(entry, &candidate[1..end], depth, expansion) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&candidate[..=end]),
+            }
+
+            rest = &candidate[end + 1..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    /// Evaluate a single placeholder (without the surrounding braces)
+    ///
+    /// Returns `None` for unknown placeholders and references to fields that are being resolved,
+    /// which are then kept in the text.
+    fn placeholder(
+        &self,
+        entry: &KpEntry,
+        inner: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> Option<String> {
+        let upper = inner.to_ascii_uppercase();
+
+        if upper.starts_with("T-CONV:") {
+            return self.transform_convert(entry, &inner["T-CONV:".len()..], depth, expansion);
+        }
+
+        if upper.starts_with("T-REPLACE-RX:") {
+            return self.transform_replace(
+                entry,
+                &inner["T-REPLACE-RX:".len()..],
+                depth,
+                expansion,
+            );
+        }
+
+        if upper.starts_with("REF:") {
+            return self.reference(&inner["REF:".len()..], depth, expansion);
+        }
+
+        if upper.starts_with("S:") {
+            return self.resolve_field(entry, &inner["S:".len()..], depth + 1, expansion);
+        }
+
+        if let Some(component) = upper.strip_prefix("URL:") {
+            if !URL_COMPONENTS.contains(&component) {
+                return None;
+            }
+
+            let url = self.standard_field(entry, "URL", depth, expansion)?;
+            return Some(url_component(&url, component).unwrap_or_default());
+        }
+
+        let value = match &upper[..] {
+            "TITLE" => self.standard_field(entry, "Title", depth, expansion)?,
+            "USERNAME" => self.standard_field(entry, "UserName", depth, expansion)?,
+            "PASSWORD" => self.standard_field(entry, "Password", depth, expansion)?,
+            "URL" => self.standard_field(entry, "URL", depth, expansion)?,
+            "NOTES" => self.standard_field(entry, "Notes", depth, expansion)?,
+            "UUID" => format_uuid(&entry.uuid),
+
+            "GROUP" => self.parent_groups(entry)?.last()?.name.clone(),
+            "GROUP_PATH" => self
+                .parent_groups(entry)?
+                .iter()
+                .skip(1)
+                .map(|g| &g.name[..])
+                .collect::<Vec<_>>()
+                .join("."),
+            "GROUP_NOTES" => self
+                .parent_groups(entry)?
+                .last()?
+                .notes
+                .clone()
+                .unwrap_or_default(),
+
+            "DB_NAME" => self.database.meta.database_name.clone().unwrap_or_default(),
+
+            "DT_SIMPLE" => self.now.format("%Y%m%d%H%M%S").to_string(),
+            "DT_YEAR" => self.now.format("%Y").to_string(),
+            "DT_MONTH" => self.now.format("%m").to_string(),
+            "DT_DAY" => self.now.format("%d").to_string(),
+            "DT_HOUR" => self.now.format("%H").to_string(),
+            "DT_MINUTE" => self.now.format("%M").to_string(),
+            "DT_SECOND" => self.now.format("%S").to_string(),
+
+            "DT_UTC_SIMPLE" => self.utc().format("%Y%m%d%H%M%S").to_string(),
+            "DT_UTC_YEAR" => self.utc().format("%Y").to_string(),
+            "DT_UTC_MONTH" => self.utc().format("%m").to_string(),
+            "DT_UTC_DAY" => self.utc().format("%d").to_string(),
+            "DT_UTC_HOUR" => self.utc().format("%H").to_string(),
+            "DT_UTC_MINUTE" => self.utc().format("%M").to_string(),
+            "DT_UTC_SECOND" => self.utc().format("%S").to_string(),
+
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        self.now.with_timezone(&Utc)
+    }
+
+    /// Get a standard field, resolving placeholders in its value. Missing fields are empty.
+    ///
+    /// Returns `None` if the field is already being resolved.
+    fn standard_field(
+        &self,
+        entry: &KpEntry,
+        name: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> Option<String> {
+        if entry.get(name).is_none() {
+            return Some(String::new());
+        }
+
+        self.resolve_field(entry, name, depth + 1, expansion)
+    }
+
+    /// Get a field, resolving placeholders in its value
+    ///
+    /// Returns `None` if the field is missing or already being resolved.
+    fn resolve_field(
+        &self,
+        entry: &KpEntry,
+        name: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> Option<String> {
+        let value = entry.get(name)?;
+        let key = (entry.uuid, name.to_string());
+
+        if let Some(resolved) = expansion.done.get(&key) {
+            return Some(resolved.clone());
+        }
+
+        if !expansion.active.insert(key.clone()) {
+            return None;
+        }
+
+        let resolved = self.resolve_depth(entry, value, depth, expansion);
+
+        expansion.active.remove(&key);
+        expansion.done.insert(key, resolved.clone());
+
+        Some(resolved)
+    }
+
+    /// Resolve a field reference like `P@I:<uuid>`
+    fn reference(&self, spec: &str, depth: usize, expansion: &mut Expansion) -> Option<String> {
+        let (fields, text) = spec.split_once(':')?;
+        let (wanted, search) = fields.split_once('@')?;
+
+        let wanted = single_char(wanted)?;
+        let search = single_char(search)?;
+
+        let target = self.find_entry(search, text)?;
+
+        match wanted {
+            'I' => Some(format_uuid(&target.uuid)),
+            _ => {
+                let name = field_by_code(wanted)?;
+                self.standard_field(target, name, depth, expansion)
+            }
+        }
+    }
+
+    /// Find the first entry where the field selected by `search` matches a text
+    ///
+    /// UUIDs have to match exactly, other fields are searched case-insensitively for the text.
+    fn find_entry(&self, search: char, text: &str) -> Option<&'a KpEntry> {
+        let needle = text.to_lowercase();
+
+        let uuid = if search == 'I' {
+            Some(Uuid::parse_str(text.trim()).ok()?)
+        } else {
+            None
+        };
+
+        self.database.root.iter().find_map(|node| {
+            let NodeRef::Entry(entry) = node else {
+                return None;
+            };
+
+            let matches = match search {
+                'I' => Some(&entry.uuid) == uuid.as_ref(),
+                'O' => entry
+                    .fields
+                    .keys()
+                    .filter(|k| !STANDARD_FIELDS.contains(&&k[..]))
+                    .filter_map(|k| entry.get(k))
+                    .any(|v| v.to_lowercase().contains(&needle)),
+                code => entry
+                    .get(field_by_code(code)?)
+                    .map(|v| v.to_lowercase().contains(&needle))
+                    .unwrap_or(false),
+            };
+
+            matches.then_some(entry)
+        })
+    }
+
+    /// Evaluate `{T-CONV:/<text>/<type>/}`
+    fn transform_convert(
+        &self,
+        entry: &KpEntry,
+        spec: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> Option<String> {
+        let [text, kind] = split_transform::<2>(spec)?;
+
+        let kind = kind.to_ascii_lowercase();
+        if kind == "raw" {
+            return Some(text.to_string());
+        }
+
+        let text = self.resolve_depth(entry, text, depth + 1, expansion);
+
+        let out = match &kind[..] {
+            "u" | "upper" => text.to_uppercase(),
+            "l" | "lower" => text.to_lowercase(),
+            "base64" => base64::engine::general_purpose::STANDARD.encode(text.as_bytes()),
+            "hex" => text.bytes().map(|b| format!("{:02x}", b)).collect(),
+            "uri" => {
+                percent_encoding::utf8_percent_encode(&text, percent_encoding::NON_ALPHANUMERIC)
+                    .to_string()
+            }
+            "uri-dec" => percent_encoding::percent_decode_str(&text)
+                .decode_utf8_lossy()
+                .to_string(),
+            _ => return None,
+        };
+
+        Some(out)
+    }
+
+    /// Evaluate `{T-REPLACE-RX:/<text>/<regex>/<replacement>/}`
+    fn transform_replace(
+        &self,
+        entry: &KpEntry,
+        spec: &str,
+        depth: usize,
+        expansion: &mut Expansion,
+    ) -> Option<String> {
+        let [text, pattern, replacement] = split_transform::<3>(spec)?;
+
+        let text = self.resolve_depth(entry, text, depth + 1, expansion);
+        let pattern = self.resolve_depth(entry, pattern, depth + 1, expansion);
+        let replacement = self.resolve_depth(entry, replacement, depth + 1, expansion);
+
+        let regex = regex::Regex::new(&pattern).ok()?;
+
+        Some(regex.replace_all(&text, &replacement[..]).into_owned())
+    }
+
+    /// Find the groups from the root group to the parent group of an entry
+    pub(crate) fn parent_groups(&self, entry: &KpEntry) -> Option<Vec<&'a KpGroup>> {
+        fn inner<'b>(group: &'b KpGroup, uuid: &Uuid, path: &mut Vec<&'b KpGroup>) -> bool {
+            path.push(group);
+
+            for node in group.children.iter() {
+                match node {
+                    Node::Entry(entry) if &entry.uuid == uuid => return true,
+                    Node::Group(child) if inner(child, uuid, path) => return true,
+                    _ => {}
+                }
+            }
+
+            path.pop();
+            false
+        }
+
+        let mut path = Vec::new();
+        inner(&self.database.root, &entry.uuid, &mut path).then_some(path)
+    }
+}
+
+/// Find the index of the brace that closes the brace at the start of `text`
+pub(crate) fn matching_brace(text: &str) -> Option<usize> {
+    let mut level = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => level += 1,
+            '}' => {
+                level -= 1;
+                if level == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Split the arguments of a transformation like `/a/b/` where the first character is the
+/// separator
+fn split_transform<const N: usize>(spec: &str) -> Option<[&str; N]> {
+    let separator = spec.chars().next()?;
+    let mut parts = spec[separator.len_utf8()..].split(separator);
+
+    let mut out = [""; N];
+    for part in out.iter_mut() {
+        *part = parts.next()?;
+    }
+
+    Some(out)
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let c = chars.next()?.to_ascii_uppercase();
+
+    chars.next().is_none().then_some(c)
+}
+
+/// Map the field codes used in field references to field names
+fn field_by_code(code: char) -> Option<&'static str> {
+    match code {
+        'T' => Some("Title"),
+        'U' => Some("UserName"),
+        'P' => Some("Password"),
+        'A' => Some("URL"),
+        'N' => Some("Notes"),
+        _ => None,
+    }
+}
+
+/// Format a UUID like KeePass does in placeholders and references
+fn format_uuid(uuid: &Uuid) -> String {
+    uuid.simple().to_string().to_uppercase()
+}
+
+/// Extract a component of a URL for `{URL:<component>}` placeholders
+fn url_component(url: &str, component: &str) -> Option<String> {
+    if component == "RMVSCM" {
+        return Some(match url.split_once("://") {
+            Some((_, rest)) => rest.to_string(),
+            None => url
+                .split_once(':')
+                .map(|(_, rest)| rest.to_string())
+                .unwrap_or_else(|| url.to_string()),
+        });
+    }
+
+    let parsed = Url::parse(url).ok()?;
+
+    let value = match component {
+        "SCM" => parsed.scheme().to_string(),
+        "HOST" => parsed.host_str()?.to_string(),
+        "PORT" => parsed.port_or_known_default()?.to_string(),
+        "PATH" => parsed.path().to_string(),
+        "QUERY" => parsed.query().map(|q| format!("?{q}")).unwrap_or_default(),
+        "USERINFO" => match parsed.password() {
+            Some(password) => format!("{}:{}", parsed.username(), password),
+            None => parsed.username().to_string(),
+        },
+        "USERNAME" => parsed.username().to_string(),
+        "PASSWORD" => parsed.password().unwrap_or_default().to_string(),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::Value as KpValue;
+
+    fn entry(fields: &[(&str, &str)]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        for (name, value) in fields {
+            entry
+                .fields
+                .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+        }
+        entry
+    }
+
+    fn database(entries: Vec<KpEntry>) -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database
+            .root
+            .children
+            .extend(entries.into_iter().map(Node::Entry));
+        database
+    }
+
+    fn example() -> KpEntry {
+        entry(&[
+            ("Title", "Example"),
+            ("UserName", "alice"),
+            ("Password", "secret"),
+            (
+                "URL",
+                "https://user:pw@www.example.com:8443/path/to?x=1#frag",
+            ),
+            ("Notes", "note"),
+            ("Custom", "custom value of {USERNAME}"),
+        ])
+    }
+
+    #[test]
+    fn entry_fields() {
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        assert_eq!(
+            resolver.resolve(&e, "{TITLE}/{username}/{PASSWORD}/{NOTES}/{S:Custom}"),
+            "Example/alice/secret/note/custom value of alice"
+        );
+        assert_eq!(resolver.resolve(&e, "{UUID}"), format_uuid(&e.uuid));
+        assert_eq!(
+            resolver.field(&e, "Custom").as_deref(),
+            Some("custom value of alice")
+        );
+        assert_eq!(resolver.field(&e, "Missing"), None);
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders() {
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        assert_eq!(resolver.resolve(&e, "{UNKNOWN}"), "{UNKNOWN}");
+        assert_eq!(resolver.resolve(&e, "{S:Missing}"), "{S:Missing}");
+        assert_eq!(resolver.resolve(&e, "{CLIPBOARD}"), "{CLIPBOARD}");
+        assert_eq!(resolver.resolve(&e, "{TITLE"), "{TITLE");
+        assert_eq!(resolver.resolve(&e, "a}b{TITLE}"), "a}bExample");
+    }
+
+    #[test]
+    fn references_by_uuid() {
+        let e = example();
+        let uuid = e.uuid;
+        let other = entry(&[
+            ("Title", "Other"),
+            ("UserName", &format!("{{REF:U@I:{}}}", format_uuid(&uuid))),
+        ]);
+        let db = database(vec![e.clone(), other.clone()]);
+        let resolver = Resolver::new(&db);
+
+        assert_eq!(resolver.field(&other, "UserName").as_deref(), Some("alice"));
+        assert_eq!(
+            resolver.resolve(&other, &format!("{{REF:P@I:{}}}", uuid.hyphenated())),
+            "secret"
+        );
+        assert_eq!(
+            resolver.resolve(&other, &format!("{{ref:a@i:{}}}", uuid.simple())),
+            "https://user:pw@www.example.com:8443/path/to?x=1#frag"
+        );
+        assert_eq!(
+            resolver.resolve(&other, &format!("{{REF:I@I:{}}}", uuid)),
+            format_uuid(&uuid)
+        );
+
+        let missing = format!("{{REF:P@I:{}}}", Uuid::new_v4().simple());
+        assert_eq!(resolver.resolve(&other, &missing), missing);
+        assert_eq!(resolver.resolve(&other, "{REF:P@I:nope}"), "{REF:P@I:nope}");
+    }
+
+    #[test]
+    fn references_by_search() {
+        let e = example();
+        let other = entry(&[
+            ("Title", "Other"),
+            ("UserName", "bob"),
+            ("Password", "hunter2"),
+            ("Key", "Custom Data"),
+        ]);
+        let db = database(vec![e.clone(), other.clone()]);
+        let resolver = Resolver::new(&db);
+
+        // searching in titles, user names and other fields is case-insensitive
+        assert_eq!(resolver.resolve(&e, "{REF:P@T:other}"), "hunter2");
+        assert_eq!(resolver.resolve(&e, "{REF:T@U:BO}"), "Other");
+        assert_eq!(resolver.resolve(&e, "{REF:U@P:hunter}"), "bob");
+        assert_eq!(resolver.resolve(&e, "{REF:U@A:example.com}"), "alice");
+        assert_eq!(resolver.resolve(&e, "{REF:U@N:NOTE}"), "alice");
+        assert_eq!(resolver.resolve(&e, "{REF:T@O:custom data}"), "Other");
+        assert_eq!(
+            resolver.resolve(&e, "{REF:I@U:bob}"),
+            format_uuid(&other.uuid)
+        );
+
+        // the first matching entry wins
+        assert_eq!(resolver.resolve(&other, "{REF:T@T:e}"), "Example");
+
+        for unresolved in [
+            "{REF:P@T:nobody}",
+            "{REF:X@T:Other}",
+            "{REF:P@X:Other}",
+            "{REF:P}",
+        ] {
+            assert_eq!(resolver.resolve(&e, unresolved), unresolved);
+        }
+    }
+
+    #[test]
+    fn url_components() {
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        for (placeholder, expected) in [
+            ("{URL:SCM}", "https"),
+            ("{URL:HOST}", "www.example.com"),
+            ("{URL:PORT}", "8443"),
+            ("{URL:PATH}", "/path/to"),
+            ("{URL:QUERY}", "?x=1"),
+            ("{URL:USERINFO}", "user:pw"),
+            ("{URL:USERNAME}", "user"),
+            ("{URL:PASSWORD}", "pw"),
+            (
+                "{URL:RMVSCM}",
+                "user:pw@www.example.com:8443/path/to?x=1#frag",
+            ),
+            ("{url:host}", "www.example.com"),
+            ("{URL:OTHER}", "{URL:OTHER}"),
+        ] {
+            assert_eq!(resolver.resolve(&e, placeholder), expected, "{placeholder}");
+        }
+
+        let simple = entry(&[("URL", "http://example.com")]);
+        assert_eq!(resolver.resolve(&simple, "{URL:PORT}"), "80");
+        assert_eq!(resolver.resolve(&simple, "{URL:QUERY}"), "");
+
+        let relative = entry(&[("URL", "example.com/login")]);
+        assert_eq!(resolver.resolve(&relative, "{URL:HOST}"), "");
+        assert_eq!(
+            resolver.resolve(&relative, "{URL:RMVSCM}"),
+            "example.com/login"
+        );
+
+        let empty = entry(&[]);
+        assert_eq!(resolver.resolve(&empty, "{URL:HOST}"), "");
+        assert_eq!(resolver.resolve(&empty, "[{URL}]"), "[]");
+    }
+
+    #[test]
+    fn transform_convert() {
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        for (placeholder, expected) in [
+            ("{T-CONV:/{USERNAME}/U/}", "ALICE"),
+            ("{T-CONV:/{TITLE}/upper/}", "EXAMPLE"),
+            ("{T-CONV:/{TITLE}/L/}", "example"),
+            ("{T-CONV:/abc/Base64/}", "YWJj"),
+            ("{T-CONV:/ab/hex/}", "6162"),
+            ("{T-CONV:/a b&c/uri/}", "a%20b%26c"),
+            ("{T-CONV:/a%20b%26c/uri-dec/}", "a b&c"),
+            ("{T-CONV:/{USERNAME}/raw/}", "{USERNAME}"),
+            ("{T-CONV:|a/b|u|}", "A/B"),
+            ("{T-CONV:/abc/unknown/}", "{T-CONV:/abc/unknown/}"),
+            ("{T-CONV:/abc/}", "{T-CONV:/abc/}"),
+        ] {
+            assert_eq!(resolver.resolve(&e, placeholder), expected, "{placeholder}");
+        }
+    }
+
+    #[test]
+    fn transform_replace() {
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        for (placeholder, expected) in [
+            ("{T-REPLACE-RX:/{USERNAME}/a(l)/$1$1/}", "llice"),
+            (
+                "{T-REPLACE-RX:!{URL}!^[a-z]+://([^/@]+@)?([^/:]+).*$!$2!}",
+                "www.example.com",
+            ),
+            ("{T-REPLACE-RX:!a-b-c!-!+!}", "a+b+c"),
+            ("{T-REPLACE-RX:/{TITLE}/{TITLE}/{USERNAME}/}", "alice"),
+            ("{T-REPLACE-RX:/abc/(/x/}", "{T-REPLACE-RX:/abc/(/x/}"),
+            ("{T-REPLACE-RX:/abc/b/}", "ac"),
+            ("{T-REPLACE-RX:/abc/b}", "{T-REPLACE-RX:/abc/b}"),
+        ] {
+            assert_eq!(resolver.resolve(&e, placeholder), expected, "{placeholder}");
+        }
+    }
+
+    #[test]
+    fn date_and_time() {
+        use chrono::TimeZone;
+
+        let now = Local.with_ymd_and_hms(2024, 2, 3, 4, 5, 6).unwrap();
+        let e = example();
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::with_time(&db, now);
+
+        assert_eq!(resolver.resolve(&e, "{DT_SIMPLE}"), "20240203040506");
+        assert_eq!(
+            resolver.resolve(
+                &e,
+                "{DT_YEAR}-{DT_MONTH}-{DT_DAY} {DT_HOUR}:{DT_MINUTE}:{DT_SECOND}"
+            ),
+            "2024-02-03 04:05:06"
+        );
+        assert_eq!(
+            resolver.resolve(&e, "{DT_UTC_SIMPLE}"),
+            now.with_timezone(&Utc).format("%Y%m%d%H%M%S").to_string()
+        );
+    }
+
+    #[test]
+    fn self_references_are_left_unresolved() {
+        let mut e = entry(&[
+            ("Title", "{TITLE}{TITLE}"),
+            ("UserName", "{S:UserName}{S:UserName}"),
+        ]);
+        let reference = format!("{{REF:P@I:{}}}", e.uuid.simple());
+        e.fields.insert(
+            "Password".to_string(),
+            KpValue::Unprotected(format!("x{reference}{reference}")),
+        );
+        e.fields.insert(
+            "Notes".to_string(),
+            KpValue::Unprotected("{PASSWORD}".to_string()),
+        );
+
+        let db = database(vec![e.clone()]);
+        let resolver = Resolver::new(&db);
+
+        assert_eq!(
+            resolver.field(&e, "Title").as_deref(),
+            Some("{TITLE}{TITLE}")
+        );
+        assert_eq!(
+            resolver.field(&e, "UserName").as_deref(),
+            Some("{S:UserName}{S:UserName}")
+        );
+        assert_eq!(
+            resolver.field(&e, "Password"),
+            Some(format!("x{reference}{reference}"))
+        );
+        assert_eq!(
+            resolver.field(&e, "Notes"),
+            Some(format!("x{reference}{reference}"))
+        );
+    }
+
+    #[test]
+    fn cycles_between_entries_are_left_unresolved() {
+        let mut a = entry(&[("Title", "A")]);
+        let mut b = entry(&[("Title", "B")]);
+
+        let to_a = format!("{{REF:P@I:{}}}", a.uuid.simple());
+        let to_b = format!("{{REF:P@I:{}}}", b.uuid.simple());
+
+        a.fields.insert(
+            "Password".to_string(),
+            KpValue::Unprotected(format!("a{to_b}{to_b}")),
+        );
+        b.fields.insert(
+            "Password".to_string(),
+            KpValue::Unprotected(format!("b{to_a}{to_a}")),
+        );
+
+        let db = database(vec![a.clone(), b.clone()]);
+        let resolver = Resolver::new(&db);
+
+        let b_in_a = format!("b{to_a}{to_a}");
+        assert_eq!(
+            resolver.field(&a, "Password"),
+            Some(format!("a{b_in_a}{b_in_a}"))
+        );
+    }
+
+    #[test]
+    fn fan_out_is_resolved_once() {
+        // each entry refers twice to the next one, which would be expanded 2^n times
+        let mut entries: Vec<KpEntry> = (0..MAX_DEPTH / 2).map(|_| entry(&[])).collect();
+
+        for i in 0..entries.len() {
+            let value = match entries.get(i + 1) {
+                Some(next) => {
+                    let reference = format!("{{REF:P@I:{}}}", next.uuid.simple());
+                    format!("{reference}{reference}")
+                }
+                None => "x".to_string(),
+            };
+
+            entries[i]
+                .fields
+                .insert("Password".to_string(), KpValue::Unprotected(value));
+        }
+
+        let first = entries[0].clone();
+        let db = database(entries);
+        let resolver = Resolver::new(&db);
+
+        assert_eq!(
+            resolver.field(&first, "Password"),
+            Some("x".repeat(1 << (MAX_DEPTH / 2 - 1)))
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Printable reports of a database
+//!
+//! Reports are self-contained HTML documents without external resources that can be printed or
+//! saved as PDF by a browser. The entry report lists the entries of selected groups with a chosen
+//! set of columns, and the emergency sheet records where to find a database and leaves room to
+//! write down its master password.
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDateTime;
+use keepass::db::{
+    Database as KpDatabase, Entry as KpEntry, Group as KpGroup, NodeRef, Value as KpValue,
+};
+
+use crate::exchange::{ReportColumn, ReportOptions};
+use crate::otp::OtpConfig;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; font-size: 10pt; color: #000; margin: 2em; }
+h1 { font-size: 18pt; margin-bottom: 0.2em; }
+h2 { font-size: 12pt; margin-top: 1.5em; }
+.generated { color: #555; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #888; padding: 0.3em 0.5em; text-align: left; vertical-align: top; }
+th { background: #eee; }
+td { white-space: pre-wrap; word-break: break-word; }
+.secret { font-family: monospace; }
+.details th { width: 12em; }
+.password { border: 2px solid #000; height: 5em; margin: 0.5em 0 2em; }
+@media print {
+    body { margin: 0; }
+    h2 { break-after: avoid; }
+    tr { break-inside: avoid; }
+}
+"#;
+
+/// Escape text for HTML element content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn format_time(time: &NaiveDateTime) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Wrap the body of a report into a complete HTML document
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p class=\"generated\">Generated by OmniKee on {generated}</p>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        generated = chrono::Local::now().format("%Y-%m-%d %H:%M"),
+    )
+}
+
+impl ReportColumn {
+    fn header(&self) -> &str {
+        match self {
+            ReportColumn::Title => "Title",
+            ReportColumn::UserName => "User name",
+            ReportColumn::Password => "Password",
+            ReportColumn::Url => "URL",
+            ReportColumn::Notes => "Notes",
+            ReportColumn::Otp => "One-time password",
+            ReportColumn::Tags => "Tags",
+            ReportColumn::Modified => "Modified",
+            ReportColumn::Expires => "Expires",
+            ReportColumn::Field { name } => name,
+        }
+    }
+
+    fn value(&self, entry: &KpEntry) -> String {
+        let field = |name: &str| match entry.fields.get(name) {
+            Some(KpValue::Bytes(..)) | None => String::new(),
+            Some(..) => entry.get(name).unwrap_or_default().to_string(),
+        };
+
+        match self {
+            ReportColumn::Title => field("Title"),
+            ReportColumn::UserName => field("UserName"),
+            ReportColumn::Password => field("Password"),
+            ReportColumn::Url => field("URL"),
+            ReportColumn::Notes => field("Notes"),
+            ReportColumn::Otp => OtpConfig::from_entry(entry)
+                .ok()
+                .flatten()
+                .map(|otp| otp.to_uri())
+                .unwrap_or_default(),
+            ReportColumn::Tags => entry.tags.join(", "),
+            ReportColumn::Modified => entry
+                .times
+                .get_last_modification()
+                .map(format_time)
+                .unwrap_or_default(),
+            ReportColumn::Expires => entry
+                .times
+                .get_expiry()
+                .filter(|_| entry.times.expires)
+                .map(format_time)
+                .unwrap_or_default(),
+            ReportColumn::Field { name } => field(name),
+        }
+    }
+
+    /// Whether the column shows secrets, which are printed in a monospace font
+    fn is_secret(&self) -> bool {
+        matches!(self, ReportColumn::Password | ReportColumn::Otp)
+    }
+}
+
+/// List the entries of the selected groups of a database as an HTML document
+pub fn entry_report(database: &KpDatabase, title: &str, options: &ReportOptions) -> Result<String> {
+    for uuid in &options.groups {
+        database
+            .root
+            .iter()
+            .find(|node| matches!(node, NodeRef::Group(g) if &g.uuid == uuid))
+            .ok_or(anyhow!("Group not found by UUID"))?;
+    }
+
+    let mut sections = Vec::new();
+    collect_sections(
+        database,
+        &database.root,
+        database.root.name.clone(),
+        false,
+        options,
+        &mut sections,
+    );
+
+    let mut body = String::new();
+
+    for (path, group) in sections {
+        body.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", escape(&path)));
+        for column in &options.columns {
+            body.push_str(&format!("<th>{}</th>", escape(column.header())));
+        }
+        body.push_str("</tr>\n");
+
+        for entry in group.entries() {
+            body.push_str("<tr>");
+            for column in &options.columns {
+                let class = if column.is_secret() {
+                    " class=\"secret\""
+                } else {
+                    ""
+                };
+                body.push_str(&format!(
+                    "<td{}>{}</td>",
+                    class,
+                    escape(&column.value(entry))
+                ));
+            }
+            body.push_str("</tr>\n");
+        }
+
+        body.push_str("</table>\n");
+    }
+
+    Ok(page(title, &body))
+}
+
+/// Collect the groups with entries to list along with their paths, leaving out the recycle bin
+fn collect_sections<'a>(
+    database: &KpDatabase,
+    group: &'a KpGroup,
+    path: String,
+    parent_selected: bool,
+    options: &ReportOptions,
+    sections: &mut Vec<(String, &'a KpGroup)>,
+) {
+    let selected = options.groups.is_empty()
+        || options.groups.contains(&group.uuid)
+        || (parent_selected && options.include_subgroups);
+
+    if selected && !group.entries().is_empty() {
+        sections.push((path.clone(), group));
+    }
+
+    for child in group.groups() {
+        if database.meta.recyclebin_uuid == Some(child.uuid) {
+            continue;
+        }
+
+        let child_path = format!("{} / {}", path, child.name);
+        collect_sections(database, child, child_path, selected, options, sections);
+    }
+}
+
+/// Create an emergency sheet to print and keep in a safe place
+///
+/// The sheet names the database and its file, hints at the key file if there is one, and has an
+/// empty box to write down the master password by hand.
+pub fn emergency_sheet(database_name: &str, file_name: &str, keyfile_hint: Option<&str>) -> String {
+    let keyfile = match keyfile_hint.filter(|h| !h.trim().is_empty()) {
+        Some(hint) => escape(hint),
+        None => "No key file is needed".to_string(),
+    };
+
+    let body = format!(
+        r#"<p>Print this sheet, write down the master password and keep it in a safe place. Anyone who finds it together with the database file can read all of your passwords.</p>
+<table class="details">
+<tr><th>Database</th><td>{database_name}</td></tr>
+<tr><th>File name</th><td>{file_name}</td></tr>
+<tr><th>Key file</th><td>{keyfile}</td></tr>
+</table>
+<h2>Master password</h2>
+<div class="password"></div>
+<h2>Opening the database</h2>
+<p>The database can be opened with OmniKee or any other application that supports KeePass databases, such as KeePassXC, KeePass or KeePassDX. Open the file, enter the master password and select the key file if one is needed.</p>
+"#,
+        database_name = escape(database_name),
+        file_name = escape(file_name),
+    );
+
+    page("Emergency Sheet", &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::Node as KpNode;
+
+    fn database() -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database.root.name = "Root".to_string();
+
+        let mut internet = KpGroup::new("Internet");
+        internet
+            .children
+            .push(KpNode::Entry(crate::import::new_entry(
+                "Mail <work>",
+                "alice",
+                "secret",
+                "",
+                "",
+            )));
+
+        let mut bin = KpGroup::new("Recycle Bin");
+        bin.children.push(KpNode::Entry(crate::import::new_entry(
+            "Deleted", "", "", "", "",
+        )));
+        database.meta.recyclebin_uuid = Some(bin.uuid);
+
+        database.root.children.push(KpNode::Group(internet));
+        database.root.children.push(KpNode::Group(bin));
+
+        database
+    }
+
+    #[test]
+    fn test_entry_report() {
+        let database = database();
+        let options = ReportOptions {
+            groups: vec![],
+            include_subgroups: true,
+            columns: vec![ReportColumn::Title, ReportColumn::Password],
+        };
+
+        let html = entry_report(&database, "Passwords", &options).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h2>Root / Internet</h2>"));
+        assert!(html.contains("<td>Mail &lt;work&gt;</td><td class=\"secret\">secret</td>"));
+        assert!(!html.contains("Deleted"));
+        assert!(!html.contains("alice"));
+    }
+
+    #[test]
+    fn test_select_groups() {
+        let database = database();
+        let mut options = ReportOptions {
+            groups: vec![database.root.uuid],
+            include_subgroups: false,
+            columns: vec![ReportColumn::Title],
+        };
+
+        let html = entry_report(&database, "Passwords", &options).unwrap();
+        assert!(!html.contains("Mail"));
+
+        options.include_subgroups = true;
+        let html = entry_report(&database, "Passwords", &options).unwrap();
+        assert!(html.contains("Mail"));
+
+        options.groups = vec![uuid::Uuid::new_v4()];
+        assert!(entry_report(&database, "Passwords", &options).is_err());
+    }
+
+    #[test]
+    fn test_emergency_sheet() {
+        let html = emergency_sheet(
+            "Family",
+            "family.kdbx",
+            Some("USB stick in the \"red\" box"),
+        );
+        assert!(html.contains("<td>family.kdbx</td>"));
+        assert!(html.contains("USB stick in the &quot;red&quot; box"));
+        assert!(html.contains("class=\"password\""));
+
+        let html = emergency_sheet("Family", "family.kdbx", None);
+        assert!(html.contains("No key file is needed"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Provider of the freedesktop Secret Service API, so that applications using libsecret can read
+//! secrets from databases
+//!
+//! Nothing is exposed until a database is opted in, after which it is a collection. Its items are
+//! the entries of the whole database or, if one is configured, of an exposed group, without those
+//! in the recycle bin. The label of an item is the title of its entry, the secret is the password
+//! and the lookup attributes are the custom text fields of the entry.
+//!
+//! The opt-in is stored in the database, so a database that has never been unlocked is not
+//! exposed. One that was opted in stays a collection while it is locked, so that clients can ask
+//! for it to be unlocked.
+//!
+//! The desktop app provides the service on the D-Bus session bus, which is only available on Linux.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use keepass::db::{
+    CustomDataItem, Database as KpDatabase, Entry as KpEntry, Group as KpGroup, Node, NodeRef,
+    Value as KpValue,
+};
+use uuid::Uuid;
+
+use crate::database::{find_group_mut, recycle_entry};
+use crate::url_match::is_recycled;
+
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+mod server;
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+mod session;
+
+#[cfg(all(feature = "tauri", target_os = "linux"))]
+pub use server::{SecretServiceContext, listen, serve};
+
+/// Key in the database's custom data that exposes the database through the Secret Service
+pub const SECRET_SERVICE_KEY: &str = "OmniKee/SecretService";
+
+/// Key in the database's custom data that holds the UUID of the exposed group
+pub const EXPOSED_GROUP_KEY: &str = "OmniKee/SecretServiceGroup";
+
+/// Fields of an entry that are not lookup attributes
+const STANDARD_FIELDS: &[&str] = &["Title", "UserName", "Password", "URL", "Notes"];
+
+/// Check whether a database is exposed through the Secret Service
+pub fn is_enabled(database: &KpDatabase) -> bool {
+    crate::database::is_option_enabled(database, SECRET_SERVICE_KEY)
+}
+
+/// Opt a database in or out of being exposed through the Secret Service
+pub fn set_enabled(database: &mut KpDatabase, enabled: bool) {
+    crate::database::set_option_enabled(database, SECRET_SERVICE_KEY, enabled)
+}
+
+/// Get the group that is configured to be exposed, if any
+pub fn exposed_group_uuid(database: &KpDatabase) -> Option<Uuid> {
+    database
+        .meta
+        .custom_data
+        .items
+        .get(EXPOSED_GROUP_KEY)
+        .and_then(|item| match &item.value {
+            Some(KpValue::Unprotected(v)) => Uuid::from_str(v).ok(),
+            _ => None,
+        })
+}
+
+/// Expose a single group of a database, or the whole database if `None`
+pub fn set_exposed_group(database: &mut KpDatabase, group: Option<Uuid>) {
+    let items = &mut database.meta.custom_data.items;
+
+    match group {
+        Some(uuid) => {
+            items.insert(
+                EXPOSED_GROUP_KEY.to_string(),
+                CustomDataItem {
+                    value: Some(KpValue::Unprotected(uuid.to_string())),
+                    last_modification_time: Some(chrono::Utc::now().naive_utc()),
+                },
+            );
+        }
+        None => {
+            items.remove(EXPOSED_GROUP_KEY);
+        }
+    }
+}
+
+/// Get the group whose entries are exposed, falling back to the root if the configured group is
+/// missing
+pub fn exposed_group(database: &KpDatabase) -> &KpGroup {
+    let Some(uuid) = exposed_group_uuid(database) else {
+        return &database.root;
+    };
+
+    database
+        .root
+        .iter()
+        .find_map(|node| match node {
+            NodeRef::Group(group) if group.uuid == uuid => Some(group),
+            _ => None,
+        })
+        .unwrap_or(&database.root)
+}
+
+/// Get a mutable reference to the group whose entries are exposed
+pub fn exposed_group_mut(database: &mut KpDatabase) -> &mut KpGroup {
+    let uuid = exposed_group(database).uuid;
+    find_group_mut(&mut database.root, &uuid).expect("the exposed group is part of the database")
+}
+
+/// List the entries that are exposed as items
+pub fn items(database: &KpDatabase) -> Vec<&KpEntry> {
+    exposed_group(database)
+        .iter()
+        .filter_map(|node| match node {
+            NodeRef::Entry(entry) => Some(entry),
+            NodeRef::Group(..) => None,
+        })
+        .filter(|entry| !is_recycled(database, entry))
+        .collect()
+}
+
+/// Find an exposed entry by its UUID
+pub fn item<'a>(database: &'a KpDatabase, uuid: &Uuid) -> Option<&'a KpEntry> {
+    items(database)
+        .into_iter()
+        .find(|entry| &entry.uuid == uuid)
+}
+
+/// Get a mutable reference to an exposed entry by its UUID
+pub fn item_mut<'a>(database: &'a mut KpDatabase, uuid: &Uuid) -> Option<&'a mut KpEntry> {
+    fn inner<'a>(group: &'a mut KpGroup, uuid: &Uuid) -> Option<&'a mut KpEntry> {
+        group.children.iter_mut().find_map(|node| match node {
+            Node::Group(group) => inner(group, uuid),
+            Node::Entry(entry) if &entry.uuid == uuid => Some(entry),
+            Node::Entry(..) => None,
+        })
+    }
+
+    item(database, uuid)?;
+    inner(exposed_group_mut(database), uuid)
+}
+
+/// Get the lookup attributes of an entry
+pub fn item_attributes(entry: &KpEntry) -> HashMap<String, String> {
+    entry
+        .fields
+        .iter()
+        .filter(|(name, _)| !STANDARD_FIELDS.contains(&name.as_str()))
+        .filter_map(|(name, value)| match value {
+            KpValue::Unprotected(v) => Some((name.to_string(), v.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replace the lookup attributes of an entry, keeping its standard and protected fields
+pub fn set_item_attributes(entry: &mut KpEntry, attributes: &HashMap<String, String>) {
+    entry.fields.retain(|name, value| {
+        STANDARD_FIELDS.contains(&name.as_str()) || !matches!(value, KpValue::Unprotected(..))
+    });
+
+    for (name, value) in attributes {
+        if STANDARD_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+
+        entry
+            .fields
+            .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+    }
+}
+
+/// Check whether an entry has all of the given attributes
+pub fn matches_attributes(entry: &KpEntry, attributes: &HashMap<String, String>) -> bool {
+    let own = item_attributes(entry);
+
+    attributes
+        .iter()
+        .all(|(name, value)| own.get(name) == Some(value))
+}
+
+/// Remove an exposed entry, moving it to the recycle bin if the database has one enabled
+///
+/// Returns whether the entry was found.
+pub fn remove_item(database: &mut KpDatabase, uuid: &Uuid) -> bool {
+    if item(database, uuid).is_none() {
+        return false;
+    }
+
+    recycle_entry(database, uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, attributes: &[(&str, &str)]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        entry
+            .fields
+            .insert("Title".to_string(), KpValue::Unprotected(title.to_string()));
+        entry.fields.insert(
+            "Password".to_string(),
+            KpValue::Protected("secret".as_bytes().into()),
+        );
+
+        for (name, value) in attributes {
+            entry
+                .fields
+                .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+        }
+
+        entry
+    }
+
+    #[test]
+    fn test_attributes_are_custom_text_fields() {
+        let mut e = entry("Mail", &[("service", "imap"), ("username", "alice")]);
+        e.fields.insert(
+            "token".to_string(),
+            KpValue::Protected("hidden".as_bytes().into()),
+        );
+
+        let attributes = item_attributes(&e);
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["service"], "imap");
+
+        assert!(matches_attributes(
+            &e,
+            &HashMap::from([("service".to_string(), "imap".to_string())])
+        ));
+        assert!(!matches_attributes(
+            &e,
+            &HashMap::from([("service".to_string(), "smtp".to_string())])
+        ));
+
+        set_item_attributes(
+            &mut e,
+            &HashMap::from([("xdg:schema".to_string(), "org.example".to_string())]),
+        );
+
+        assert_eq!(item_attributes(&e).len(), 1);
+        assert!(e.fields.contains_key("token"));
+        assert_eq!(e.get_title(), Some("Mail"));
+    }
+
+    #[test]
+    fn test_exposed_group() {
+        let mut database = KpDatabase::new(Default::default());
+        database.root.children.push(Node::Entry(entry("Top", &[])));
+
+        let mut group = KpGroup::new("Secrets");
+        group.children.push(Node::Entry(entry("Nested", &[])));
+        let group_uuid = group.uuid;
+        database.root.children.push(Node::Group(group));
+
+        assert_eq!(items(&database).len(), 2);
+
+        set_exposed_group(&mut database, Some(group_uuid));
+        assert_eq!(exposed_group_uuid(&database), Some(group_uuid));
+
+        let exposed = items(&database);
+        assert_eq!(exposed.len(), 1);
+        assert_eq!(exposed[0].get_title(), Some("Nested"));
+
+        exposed_group_mut(&mut database)
+            .children
+            .push(Node::Entry(entry("Added", &[])));
+        assert_eq!(items(&database).len(), 2);
+
+        set_exposed_group(&mut database, None);
+        assert_eq!(items(&database).len(), 3);
+    }
+
+    #[test]
+    fn test_remove_item_uses_recycle_bin() {
+        let mut database = KpDatabase::new(Default::default());
+        let e = entry("Old", &[]);
+        let uuid = e.uuid;
+        database.root.children.push(Node::Entry(e));
+
+        let bin = KpGroup::new("Recycle Bin");
+        database.meta.recyclebin_uuid = Some(bin.uuid);
+        database.root.children.push(Node::Group(bin));
+
+        assert!(remove_item(&mut database, &uuid));
+        assert!(item(&database, &uuid).is_none());
+        assert_eq!(database.root.groups()[0].entries().len(), 1);
+
+        // recycled entries are not items anymore
+        assert!(!remove_item(&mut database, &uuid));
+    }
+}
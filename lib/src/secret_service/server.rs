@@ -0,0 +1,1319 @@
+//! The `org.freedesktop.secrets` service on a D-Bus connection
+//!
+//! Method calls are dispatched by hand instead of through the zbus object server, since the
+//! objects come and go with the databases and their entries.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use chrono::NaiveDateTime;
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, Node, Value as KpValue};
+use uuid::Uuid;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::fdo::{RequestNameFlags, RequestNameReply};
+use zbus::message::{Message, Type as MessageType};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+use super::session::{self, Encryption};
+use crate::AppState;
+use crate::database::Database;
+
+/// The well-known bus name of the service
+pub const SERVICE_NAME: &str = "org.freedesktop.secrets";
+
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const COLLECTION_PREFIX: &str = "/org/freedesktop/secrets/collection/";
+const ALIAS_PREFIX: &str = "/org/freedesktop/secrets/aliases/";
+const SESSION_PREFIX: &str = "/org/freedesktop/secrets/session/";
+const PROMPT_PREFIX: &str = "/org/freedesktop/secrets/prompt/";
+
+/// The only alias, which refers to the first loaded database
+const DEFAULT_ALIAS: &str = "default";
+
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_INTERFACE: &str = "org.freedesktop.Secret.Session";
+const PROMPT_INTERFACE: &str = "org.freedesktop.Secret.Prompt";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+
+const CONTENT_TYPE: &str = "text/plain; charset=utf8";
+
+// error names as defined by the specification and D-Bus itself
+const ERROR_IS_LOCKED: &str = "org.freedesktop.Secret.Error.IsLocked";
+const ERROR_NO_SESSION: &str = "org.freedesktop.Secret.Error.NoSession";
+const ERROR_NO_SUCH_OBJECT: &str = "org.freedesktop.Secret.Error.NoSuchObject";
+const ERROR_FAILED: &str = "org.freedesktop.DBus.Error.Failed";
+const ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const ERROR_NOT_SUPPORTED: &str = "org.freedesktop.DBus.Error.NotSupported";
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+/// A secret as transferred over D-Bus: session, parameters, value and content type
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+/// Access to the application from the Secret Service
+pub trait SecretServiceContext {
+    /// Run a function with exclusive access to the application state
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R;
+
+    /// Ask the user to unlock a database, returning once it was unlocked or the user declined
+    ///
+    /// This is called without holding the application state.
+    fn request_unlock(&self, database_name: &str);
+
+    /// Notification that a client changed a database, e.g. to save it
+    fn database_changed(&self, database_idx: usize);
+}
+
+/// An error that is reported to the client
+struct ServiceError {
+    name: &'static str,
+    message: String,
+}
+
+impl ServiceError {
+    fn new(name: &'static str, message: &str) -> Self {
+        Self {
+            name,
+            message: message.to_string(),
+        }
+    }
+
+    fn no_such_object() -> Self {
+        Self::new(ERROR_NO_SUCH_OBJECT, "No such object")
+    }
+
+    fn is_locked() -> Self {
+        Self::new(ERROR_IS_LOCKED, "The database is locked")
+    }
+}
+
+impl From<zbus::Error> for ServiceError {
+    fn from(e: zbus::Error) -> Self {
+        Self::new(ERROR_FAILED, &format!("{}", e))
+    }
+}
+
+type ServiceResult<T> = std::result::Result<T, ServiceError>;
+
+/// An object that method calls can be addressed to
+enum Object {
+    Service,
+    Collection(Uuid),
+    Item(Uuid, Uuid),
+    Session(String),
+    Prompt(String),
+}
+
+impl Object {
+    /// The interface that method calls without an interface are addressed to
+    fn interface(&self) -> &'static str {
+        match self {
+            Object::Service => SERVICE_INTERFACE,
+            Object::Collection(..) => COLLECTION_INTERFACE,
+            Object::Item(..) => ITEM_INTERFACE,
+            Object::Session(..) => SESSION_INTERFACE,
+            Object::Prompt(..) => PROMPT_INTERFACE,
+        }
+    }
+}
+
+/// An open session of a client
+struct Session {
+    /// the unique bus name of the client that opened the session
+    owner: String,
+    encryption: Encryption,
+}
+
+struct Service<C> {
+    ctx: Arc<C>,
+    connection: Connection,
+    sessions: Mutex<HashMap<String, Session>>,
+
+    /// prompts that unlock databases, with the objects they were requested for
+    prompts: Mutex<HashMap<String, Vec<(Uuid, OwnedObjectPath)>>>,
+
+    next_id: AtomicU64,
+}
+
+/// Provide the Secret Service on the session bus in the background
+pub fn listen<C: SecretServiceContext + Send + Sync + 'static>(ctx: Arc<C>) -> Result<()> {
+    serve(Connection::session()?, ctx)
+}
+
+/// Provide the Secret Service on a bus connection in the background
+///
+/// Fails if another provider, like GNOME Keyring, already owns the service name.
+pub fn serve<C: SecretServiceContext + Send + Sync + 'static>(
+    connection: Connection,
+    ctx: Arc<C>,
+) -> Result<()> {
+    let messages = MessageIterator::from(&connection);
+
+    let reply = connection
+        .request_name_with_flags(SERVICE_NAME, RequestNameFlags::DoNotQueue.into())
+        .ok();
+
+    if reply != Some(RequestNameReply::PrimaryOwner) {
+        bail!("Another Secret Service provider is running, e.g. GNOME Keyring");
+    }
+
+    let service = Arc::new(Service {
+        ctx,
+        connection,
+        sessions: Mutex::new(HashMap::new()),
+        prompts: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+    });
+
+    std::thread::spawn(move || {
+        for message in messages {
+            let Ok(message) = message else {
+                continue;
+            };
+
+            if message.message_type() == MessageType::MethodCall {
+                service.dispatch(&message);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+impl<C: SecretServiceContext + Send + Sync + 'static> Service<C> {
+    /// Handle a method call and send the reply
+    fn dispatch(self: &Arc<Self>, message: &Message) {
+        let header = message.header();
+
+        // a failed reply means that the client is gone, so there is nobody to report it to
+        let _ = match self.handle(message) {
+            Ok(reply) => self.connection.send(&reply),
+            Err(e) => self.connection.reply_error(&header, e.name, &e.message),
+        };
+    }
+
+    fn handle(self: &Arc<Self>, message: &Message) -> ServiceResult<Message> {
+        let header = message.header();
+        let path = header.path().map(|p| p.as_str()).unwrap_or_default();
+        let member = header.member().map(|m| m.as_str()).unwrap_or_default();
+        let sender = header.sender().map(|s| s.to_string()).unwrap_or_default();
+
+        let object = self
+            .ctx
+            .with_state(|state| resolve(state, path))
+            .ok_or_else(ServiceError::no_such_object)?;
+
+        let interface = header
+            .interface()
+            .map(|i| i.as_str())
+            .unwrap_or_else(|| object.interface());
+
+        let reply = Message::method_return(&header)?;
+
+        let reply = match (&object, interface, member) {
+            (_, PROPERTIES_INTERFACE, "Get") => {
+                let (interface, name): (String, String) = args(message)?;
+                let value = self
+                    .properties(&object, &interface)?
+                    .remove(name.as_str())
+                    .ok_or_else(|| ServiceError::new(ERROR_INVALID_ARGS, "No such property"))?;
+                reply.build(&value)?
+            }
+            (_, PROPERTIES_INTERFACE, "GetAll") => {
+                let (interface,): (String,) = args(message)?;
+                reply.build(&(self.properties(&object, &interface)?,))?
+            }
+            (_, PROPERTIES_INTERFACE, "Set") => {
+                let (interface, name, value): (String, String, OwnedValue) = args(message)?;
+                self.set_property(&object, &interface, &name, value)?;
+                reply.build(&())?
+            }
+            (Object::Service, SERVICE_INTERFACE, "OpenSession") => {
+                let (algorithm, input): (String, OwnedValue) = args(message)?;
+                reply.build(&self.open_session(&sender, &algorithm, input)?)?
+            }
+            (Object::Service, SERVICE_INTERFACE, "CreateCollection") => {
+                let default = self.ctx.with_state(|state| default_collection(state));
+                let collection = default.ok_or_else(|| {
+                    ServiceError::new(ERROR_NOT_SUPPORTED, "Expose a database in OmniKee first")
+                })?;
+                reply.build(&(object_path(collection_path(&collection)), no_prompt()))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "SearchItems") => {
+                let (attributes,): (HashMap<String, String>,) = args(message)?;
+                let unlocked = self.ctx.with_state(|state| {
+                    state
+                        .databases
+                        .iter()
+                        .filter(|db| is_exposed(db))
+                        .flat_map(|db| search(db, &attributes))
+                        .collect::<Vec<_>>()
+                });
+
+                // the items of locked databases cannot be searched
+                reply.build(&(unlocked, Vec::<OwnedObjectPath>::new()))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "Unlock") => {
+                let (objects,): (Vec<OwnedObjectPath>,) = args(message)?;
+                reply.build(&self.unlock(objects))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "Lock") => {
+                let (objects,): (Vec<OwnedObjectPath>,) = args(message)?;
+                reply.build(&(self.lock(objects), no_prompt()))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "GetSecrets") => {
+                let (items, session): (Vec<OwnedObjectPath>, OwnedObjectPath) = args(message)?;
+
+                let mut secrets = HashMap::new();
+                for item in items {
+                    if let Ok(secret) = self.get_secret(&item, &session, &sender) {
+                        secrets.insert(item, secret);
+                    }
+                }
+
+                reply.build(&(secrets,))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "ReadAlias") => {
+                let (name,): (String,) = args(message)?;
+
+                let collection = match name.as_str() {
+                    DEFAULT_ALIAS => self.ctx.with_state(|state| default_collection(state)),
+                    _ => None,
+                };
+
+                let path = match collection {
+                    Some(id) => object_path(collection_path(&id)),
+                    None => no_prompt(),
+                };
+                reply.build(&(path,))?
+            }
+            (Object::Service, SERVICE_INTERFACE, "SetAlias") => {
+                return Err(ServiceError::new(
+                    ERROR_NOT_SUPPORTED,
+                    "The default collection is the first database exposed in OmniKee",
+                ));
+            }
+            (Object::Collection(..), COLLECTION_INTERFACE, "Delete") => {
+                return Err(ServiceError::new(
+                    ERROR_NOT_SUPPORTED,
+                    "Databases can only be closed in OmniKee",
+                ));
+            }
+            (Object::Collection(id), COLLECTION_INTERFACE, "SearchItems") => {
+                let (attributes,): (HashMap<String, String>,) = args(message)?;
+                let items = self.ctx.with_state(|state| {
+                    find_database(state, id).map(|(_, db)| search(db, &attributes))
+                })?;
+                reply.build(&(items,))?
+            }
+            (Object::Collection(id), COLLECTION_INTERFACE, "CreateItem") => {
+                let (properties, secret, replace): (HashMap<String, OwnedValue>, Secret, bool) =
+                    args(message)?;
+                let item = self.create_item(id, properties, &secret, replace, &sender)?;
+                reply.build(&(item, no_prompt()))?
+            }
+            (Object::Item(id, uuid), ITEM_INTERFACE, "Delete") => {
+                self.delete_item(id, uuid)?;
+                reply.build(&(no_prompt(),))?
+            }
+            (Object::Item(..), ITEM_INTERFACE, "GetSecret") => {
+                let (session,): (OwnedObjectPath,) = args(message)?;
+                let item = object_path(path.to_string());
+                reply.build(&(self.get_secret(&item, &session, &sender)?,))?
+            }
+            (Object::Item(id, uuid), ITEM_INTERFACE, "SetSecret") => {
+                let (secret,): (Secret,) = args(message)?;
+                let value = self.decode_secret(&secret, &sender)?;
+
+                self.change_item(id, uuid, |entry| {
+                    entry
+                        .fields
+                        .insert("Password".to_string(), KpValue::Protected(value.into()));
+                })?;
+                reply.build(&())?
+            }
+            (Object::Session(path), SESSION_INTERFACE, "Close") => {
+                let mut sessions = self.sessions.lock().unwrap();
+                if sessions.get(path).is_some_and(|s| s.owner == sender) {
+                    sessions.remove(path);
+                }
+                reply.build(&())?
+            }
+            (Object::Prompt(path), PROMPT_INTERFACE, "Prompt") => {
+                let objects = self.take_prompt(path)?;
+                self.run_prompt(path.clone(), sender, objects);
+                reply.build(&())?
+            }
+            (Object::Prompt(path), PROMPT_INTERFACE, "Dismiss") => {
+                self.take_prompt(path)?;
+                self.complete_prompt(path, &sender, true, Vec::new());
+                reply.build(&())?
+            }
+            _ => {
+                return Err(ServiceError::new(
+                    ERROR_UNKNOWN_METHOD,
+                    &format!("Unknown method {}.{}", interface, member),
+                ));
+            }
+        };
+
+        Ok(reply)
+    }
+
+    /// Get the properties of an object
+    fn properties(
+        &self,
+        object: &Object,
+        interface: &str,
+    ) -> ServiceResult<HashMap<&'static str, Value<'static>>> {
+        self.ctx.with_state(|state| match object {
+            Object::Service if interface == SERVICE_INTERFACE => {
+                let collections: Vec<_> = state
+                    .databases
+                    .iter()
+                    .filter(|db| is_exposed(db))
+                    .map(|db| object_path(collection_path(&db.id)))
+                    .collect();
+
+                Ok(HashMap::from([("Collections", Value::from(collections))]))
+            }
+            Object::Collection(id) if interface == COLLECTION_INTERFACE => {
+                let (_, db) = find_database(state, id)?;
+
+                let (items, created, modified) = match db.get_database() {
+                    Ok(database) => (
+                        super::items(database)
+                            .iter()
+                            .map(|entry| object_path(item_path(id, &entry.uuid)))
+                            .collect(),
+                        timestamp(database.root.times.get_creation()),
+                        timestamp(database.root.times.get_last_modification()),
+                    ),
+                    Err(_) => (Vec::new(), 0, 0),
+                };
+
+                Ok(HashMap::from([
+                    ("Items", Value::from(items)),
+                    ("Label", Value::from(db.get_name().to_string())),
+                    ("Locked", Value::from(db.get_database().is_err())),
+                    ("Created", Value::from(created)),
+                    ("Modified", Value::from(modified)),
+                ]))
+            }
+            Object::Item(id, uuid) if interface == ITEM_INTERFACE => {
+                let (_, db) = find_database(state, id)?;
+
+                let Ok(database) = db.get_database() else {
+                    return Ok(HashMap::from([
+                        ("Locked", Value::from(true)),
+                        ("Attributes", Value::from(HashMap::<String, String>::new())),
+                        ("Label", Value::from(String::new())),
+                        ("Created", Value::from(0u64)),
+                        ("Modified", Value::from(0u64)),
+                    ]));
+                };
+
+                let entry = super::item(database, uuid).ok_or_else(ServiceError::no_such_object)?;
+
+                Ok(HashMap::from([
+                    ("Locked", Value::from(false)),
+                    ("Attributes", Value::from(super::item_attributes(entry))),
+                    (
+                        "Label",
+                        Value::from(entry.get_title().unwrap_or_default().to_string()),
+                    ),
+                    (
+                        "Created",
+                        Value::from(timestamp(entry.times.get_creation())),
+                    ),
+                    (
+                        "Modified",
+                        Value::from(timestamp(entry.times.get_last_modification())),
+                    ),
+                ]))
+            }
+            Object::Session(..) | Object::Prompt(..) => Ok(HashMap::new()),
+            _ => Err(ServiceError::new(ERROR_INVALID_ARGS, "No such interface")),
+        })
+    }
+
+    /// Change a writable property of an object
+    fn set_property(
+        &self,
+        object: &Object,
+        interface: &str,
+        name: &str,
+        value: OwnedValue,
+    ) -> ServiceResult<()> {
+        let invalid = |_| ServiceError::new(ERROR_INVALID_ARGS, "Invalid property value");
+
+        match (object, interface, name) {
+            (Object::Item(id, uuid), ITEM_INTERFACE, "Label") => {
+                let label = String::try_from(value).map_err(invalid)?;
+                self.change_item(id, uuid, |entry| {
+                    entry
+                        .fields
+                        .insert("Title".to_string(), KpValue::Unprotected(label));
+                })
+            }
+            (Object::Item(id, uuid), ITEM_INTERFACE, "Attributes") => {
+                let attributes = HashMap::<String, String>::try_from(value).map_err(invalid)?;
+                self.change_item(id, uuid, |entry| {
+                    super::set_item_attributes(entry, &attributes)
+                })
+            }
+            _ => Err(ServiceError::new(
+                ERROR_NOT_SUPPORTED,
+                "The property cannot be changed",
+            )),
+        }
+    }
+
+    fn open_session(
+        &self,
+        sender: &str,
+        algorithm: &str,
+        input: OwnedValue,
+    ) -> ServiceResult<(Value<'static>, OwnedObjectPath)> {
+        let (encryption, output) = match algorithm {
+            session::PLAIN => (Encryption::Plain, Value::from("")),
+            session::DH_AES => {
+                let input = Vec::<u8>::try_from(input)
+                    .map_err(|_| ServiceError::new(ERROR_INVALID_ARGS, "Expected a public key"))?;
+
+                let (encryption, public) = Encryption::dh_aes(&input)
+                    .map_err(|e| ServiceError::new(ERROR_INVALID_ARGS, &format!("{}", e)))?;
+
+                (encryption, Value::from(public))
+            }
+            _ => {
+                return Err(ServiceError::new(
+                    ERROR_NOT_SUPPORTED,
+                    "Unsupported algorithm",
+                ));
+            }
+        };
+
+        let path = format!("{SESSION_PREFIX}{}", self.next_id());
+
+        self.sessions.lock().unwrap().insert(
+            path.clone(),
+            Session {
+                owner: sender.to_string(),
+                encryption,
+            },
+        );
+
+        Ok((output, object_path(path)))
+    }
+
+    /// Get the secret of an item, encrypted for a session
+    fn get_secret(
+        &self,
+        item: &OwnedObjectPath,
+        session: &OwnedObjectPath,
+        sender: &str,
+    ) -> ServiceResult<Secret> {
+        let password = self.ctx.with_state(|state| {
+            let Some(Object::Item(id, uuid)) = resolve(state, item.as_str()) else {
+                return Err(ServiceError::no_such_object());
+            };
+
+            let (_, db) = find_database(state, &id)?;
+            let database = db.get_database().map_err(|_| ServiceError::is_locked())?;
+
+            let entry = super::item(database, &uuid).ok_or_else(ServiceError::no_such_object)?;
+
+            Ok(entry.get_password().unwrap_or_default().to_string())
+        })?;
+
+        let sessions = self.sessions.lock().unwrap();
+        let encryption = &sessions
+            .get(session.as_str())
+            .filter(|s| s.owner == sender)
+            .ok_or_else(|| ServiceError::new(ERROR_NO_SESSION, "No such session"))?
+            .encryption;
+
+        let (parameters, value) = encryption
+            .encrypt(password.as_bytes())
+            .map_err(|e| ServiceError::new(ERROR_FAILED, &format!("{}", e)))?;
+
+        Ok((session.clone(), parameters, value, CONTENT_TYPE.to_string()))
+    }
+
+    /// Decrypt a secret sent by a client
+    fn decode_secret(&self, secret: &Secret, sender: &str) -> ServiceResult<String> {
+        let (session, parameters, value, _) = secret;
+
+        let sessions = self.sessions.lock().unwrap();
+        let encryption = &sessions
+            .get(session.as_str())
+            .filter(|s| s.owner == sender)
+            .ok_or_else(|| ServiceError::new(ERROR_NO_SESSION, "No such session"))?
+            .encryption;
+
+        let value = encryption
+            .decrypt(parameters, value)
+            .map_err(|e| ServiceError::new(ERROR_INVALID_ARGS, &format!("{}", e)))?;
+
+        String::from_utf8(value)
+            .map_err(|_| ServiceError::new(ERROR_NOT_SUPPORTED, "Only text secrets are supported"))
+    }
+
+    fn create_item(
+        &self,
+        id: &Uuid,
+        mut properties: HashMap<String, OwnedValue>,
+        secret: &Secret,
+        replace: bool,
+        sender: &str,
+    ) -> ServiceResult<OwnedObjectPath> {
+        let invalid = |_| ServiceError::new(ERROR_INVALID_ARGS, "Invalid item properties");
+
+        let label = match properties.remove(LABEL_PROPERTY) {
+            Some(label) => String::try_from(label).map_err(invalid)?,
+            None => String::new(),
+        };
+
+        let attributes = match properties.remove(ATTRIBUTES_PROPERTY) {
+            Some(attributes) => HashMap::<String, String>::try_from(attributes).map_err(invalid)?,
+            None => HashMap::new(),
+        };
+
+        let password = self.decode_secret(secret, sender)?;
+
+        let (idx, uuid, created) = self.ctx.with_state(|state| -> ServiceResult<_> {
+            let (idx, database) = unlocked_database_mut(state, id)?;
+
+            let existing = super::items(database)
+                .into_iter()
+                .find(|entry| replace && super::item_attributes(entry) == attributes)
+                .map(|entry| entry.uuid);
+
+            let entry = match existing {
+                Some(uuid) => {
+                    super::item_mut(database, &uuid).ok_or_else(ServiceError::no_such_object)?
+                }
+                None => {
+                    let group = super::exposed_group_mut(database);
+                    group.children.push(Node::Entry(KpEntry::new()));
+
+                    match group.children.last_mut() {
+                        Some(Node::Entry(entry)) => entry,
+                        _ => unreachable!(),
+                    }
+                }
+            };
+
+            entry
+                .fields
+                .insert("Title".to_string(), KpValue::Unprotected(label));
+            entry
+                .fields
+                .insert("Password".to_string(), KpValue::Protected(password.into()));
+            super::set_item_attributes(entry, &attributes);
+            entry
+                .times
+                .set_last_modification(chrono::Utc::now().naive_utc());
+
+            Ok((idx, entry.uuid, existing.is_none()))
+        })?;
+
+        self.ctx.database_changed(idx);
+
+        let signal = if created {
+            "ItemCreated"
+        } else {
+            "ItemChanged"
+        };
+        let item = object_path(item_path(id, &uuid));
+        self.emit_collection_signal(id, signal, &item);
+
+        Ok(item)
+    }
+
+    fn delete_item(&self, id: &Uuid, uuid: &Uuid) -> ServiceResult<()> {
+        let idx = self.ctx.with_state(|state| -> ServiceResult<_> {
+            let (idx, database) = unlocked_database_mut(state, id)?;
+
+            if !super::remove_item(database, uuid) {
+                return Err(ServiceError::no_such_object());
+            }
+
+            Ok(idx)
+        })?;
+
+        self.ctx.database_changed(idx);
+        self.emit_collection_signal(id, "ItemDeleted", &object_path(item_path(id, uuid)));
+
+        Ok(())
+    }
+
+    /// Apply a change to the entry of an item
+    fn change_item(
+        &self,
+        id: &Uuid,
+        uuid: &Uuid,
+        f: impl FnOnce(&mut KpEntry),
+    ) -> ServiceResult<()> {
+        let idx = self.ctx.with_state(|state| -> ServiceResult<_> {
+            let (idx, database) = unlocked_database_mut(state, id)?;
+
+            let entry = super::item_mut(database, uuid).ok_or_else(ServiceError::no_such_object)?;
+            f(entry);
+            entry
+                .times
+                .set_last_modification(chrono::Utc::now().naive_utc());
+
+            Ok(idx)
+        })?;
+
+        self.ctx.database_changed(idx);
+        self.emit_collection_signal(id, "ItemChanged", &object_path(item_path(id, uuid)));
+
+        Ok(())
+    }
+
+    /// Unlock objects, returning those that are already unlocked and a prompt for the others
+    fn unlock(&self, objects: Vec<OwnedObjectPath>) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+        let (unlocked, locked): (Vec<_>, Vec<_>) = self.ctx.with_state(|state| {
+            objects
+                .into_iter()
+                .filter_map(|path| match resolve(state, path.as_str()) {
+                    Some(Object::Collection(id) | Object::Item(id, _)) => {
+                        let (_, db) = find_database(state, &id).ok()?;
+                        Some((id, path, db.get_database().is_ok()))
+                    }
+                    _ => None,
+                })
+                .partition(|(_, _, unlocked)| *unlocked)
+        });
+
+        let unlocked = unlocked.into_iter().map(|(_, path, _)| path).collect();
+
+        if locked.is_empty() {
+            return (unlocked, no_prompt());
+        }
+
+        let path = format!("{PROMPT_PREFIX}{}", self.next_id());
+        let locked = locked.into_iter().map(|(id, path, _)| (id, path)).collect();
+        self.prompts.lock().unwrap().insert(path.clone(), locked);
+
+        (unlocked, object_path(path))
+    }
+
+    /// Lock the databases of objects, returning the objects that were locked
+    fn lock(&self, objects: Vec<OwnedObjectPath>) -> Vec<OwnedObjectPath> {
+        self.ctx.with_state(|state| {
+            objects
+                .into_iter()
+                .filter(|path| match resolve(state, path.as_str()) {
+                    Some(Object::Collection(id) | Object::Item(id, _)) => {
+                        match find_database(state, &id) {
+                            Ok((idx, _)) => state.lock_database(idx).is_ok(),
+                            Err(_) => false,
+                        }
+                    }
+                    _ => false,
+                })
+                .collect()
+        })
+    }
+
+    fn take_prompt(&self, path: &str) -> ServiceResult<Vec<(Uuid, OwnedObjectPath)>> {
+        self.prompts
+            .lock()
+            .unwrap()
+            .remove(path)
+            .ok_or_else(ServiceError::no_such_object)
+    }
+
+    /// Ask the user to unlock the databases of a prompt in the background
+    fn run_prompt(
+        self: &Arc<Self>,
+        path: String,
+        caller: String,
+        objects: Vec<(Uuid, OwnedObjectPath)>,
+    ) {
+        let service = self.clone();
+
+        std::thread::spawn(move || {
+            let mut ids: Vec<Uuid> = objects.iter().map(|(id, _)| *id).collect();
+            ids.dedup();
+
+            for id in ids {
+                let name = service.ctx.with_state(|state| {
+                    find_database(state, &id)
+                        .ok()
+                        .filter(|(_, db)| db.get_database().is_err())
+                        .map(|(_, db)| db.get_name().to_string())
+                });
+
+                if let Some(name) = name {
+                    service.ctx.request_unlock(&name);
+                }
+            }
+
+            let unlocked: Vec<OwnedObjectPath> = service.ctx.with_state(|state| {
+                objects
+                    .into_iter()
+                    .filter(|(id, _)| {
+                        find_database(state, id).is_ok_and(|(_, db)| db.get_database().is_ok())
+                    })
+                    .map(|(_, path)| path)
+                    .collect()
+            });
+
+            service.complete_prompt(&path, &caller, unlocked.is_empty(), unlocked);
+        });
+    }
+
+    fn complete_prompt(
+        &self,
+        path: &str,
+        caller: &str,
+        dismissed: bool,
+        result: Vec<OwnedObjectPath>,
+    ) {
+        let _ = self.connection.emit_signal(
+            Some(caller),
+            path,
+            PROMPT_INTERFACE,
+            "Completed",
+            &(dismissed, Value::from(result)),
+        );
+    }
+
+    fn emit_collection_signal(&self, id: &Uuid, signal: &str, item: &OwnedObjectPath) {
+        let _ = self.connection.emit_signal(
+            None::<&str>,
+            collection_path(id).as_str(),
+            COLLECTION_INTERFACE,
+            signal,
+            &(item,),
+        );
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Find the object that a path refers to
+fn resolve(state: &AppState, path: &str) -> Option<Object> {
+    if path == SERVICE_PATH {
+        return Some(Object::Service);
+    }
+
+    if path.starts_with(SESSION_PREFIX) {
+        return Some(Object::Session(path.to_string()));
+    }
+
+    if path.starts_with(PROMPT_PREFIX) {
+        return Some(Object::Prompt(path.to_string()));
+    }
+
+    let (collection, item) = if let Some(rest) = path.strip_prefix(COLLECTION_PREFIX) {
+        let (collection, item) = split_item(rest);
+        (Uuid::try_parse(collection).ok()?, item)
+    } else if let Some(rest) = path.strip_prefix(ALIAS_PREFIX) {
+        match split_item(rest) {
+            (DEFAULT_ALIAS, item) => (default_collection(state)?, item),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    find_database(state, &collection).ok()?;
+
+    match item {
+        Some(item) => Some(Object::Item(collection, Uuid::try_parse(item).ok()?)),
+        None => Some(Object::Collection(collection)),
+    }
+}
+
+fn split_item(path: &str) -> (&str, Option<&str>) {
+    match path.split_once('/') {
+        Some((collection, item)) => (collection, Some(item)),
+        None => (path, None),
+    }
+}
+
+/// Check whether a database is opted in to be a collection
+fn is_exposed(db: &Database) -> bool {
+    match db.get_database() {
+        Ok(database) => super::is_enabled(database),
+        Err(_) => db.exposed_while_locked,
+    }
+}
+
+fn default_collection(state: &AppState) -> Option<Uuid> {
+    state
+        .databases
+        .iter()
+        .find(|db| is_exposed(db))
+        .map(|db| db.id)
+}
+
+fn find_database<'a>(state: &'a AppState, id: &Uuid) -> ServiceResult<(usize, &'a Database)> {
+    state
+        .databases
+        .iter()
+        .enumerate()
+        .find(|(_, db)| &db.id == id && is_exposed(db))
+        .ok_or_else(ServiceError::no_such_object)
+}
+
+fn unlocked_database_mut<'a>(
+    state: &'a mut AppState,
+    id: &Uuid,
+) -> ServiceResult<(usize, &'a mut KpDatabase)> {
+    let (idx, db) = state
+        .databases
+        .iter_mut()
+        .enumerate()
+        .find(|(_, db)| &db.id == id && is_exposed(db))
+        .ok_or_else(ServiceError::no_such_object)?;
+
+    let database = db
+        .get_database_mut()
+        .map_err(|_| ServiceError::is_locked())?;
+
+    Ok((idx, database))
+}
+
+/// Find the items of a database with the given attributes
+fn search(db: &Database, attributes: &HashMap<String, String>) -> Vec<OwnedObjectPath> {
+    let Ok(database) = db.get_database() else {
+        return Vec::new();
+    };
+
+    super::items(database)
+        .into_iter()
+        .filter(|entry| super::matches_attributes(entry, attributes))
+        .map(|entry| object_path(item_path(&db.id, &entry.uuid)))
+        .collect()
+}
+
+fn collection_path(id: &Uuid) -> String {
+    format!("{COLLECTION_PREFIX}{}", id.simple())
+}
+
+fn item_path(id: &Uuid, uuid: &Uuid) -> String {
+    format!("{}/{}", collection_path(id), uuid.simple())
+}
+
+fn object_path(path: String) -> OwnedObjectPath {
+    OwnedObjectPath::try_from(path).expect("valid object path")
+}
+
+/// The path that stands for no object, e.g. when no prompt is necessary
+fn no_prompt() -> OwnedObjectPath {
+    object_path("/".to_string())
+}
+
+fn timestamp(time: Option<&NaiveDateTime>) -> u64 {
+    time.map(|t| t.and_utc().timestamp().max(0) as u64)
+        .unwrap_or_default()
+}
+
+/// Deserialize the arguments of a method call
+fn args<T>(message: &Message) -> ServiceResult<T>
+where
+    T: serde::de::DeserializeOwned + zbus::zvariant::Type,
+{
+    message
+        .body()
+        .deserialize()
+        .map_err(|e| ServiceError::new(ERROR_INVALID_ARGS, &format!("{}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    use num_bigint::BigUint;
+    use zbus::MatchRule;
+
+    use super::*;
+    use crate::source::block_on;
+
+    /// A private session bus that is shut down when dropped
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        /// Start a bus, which requires `dbus-daemon` to be installed
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon is required to test the Secret Service");
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            zbus::blocking::connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    struct TestContext {
+        state: Mutex<AppState>,
+        changed: Mutex<Vec<usize>>,
+    }
+
+    impl SecretServiceContext for TestContext {
+        fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+            f(&mut self.state.lock().unwrap())
+        }
+
+        fn request_unlock(&self, _database_name: &str) {
+            let password = Some("demopass".to_string());
+            block_on(AppState::unlock_database(&self.state, 0, password, None)).unwrap();
+
+            self.with_state(|state| {
+                let database = state.databases[0].get_database_mut().unwrap();
+                crate::secret_service::set_enabled(database, true);
+            });
+        }
+
+        fn database_changed(&self, database_idx: usize) {
+            self.changed.lock().unwrap().push(database_idx);
+        }
+    }
+
+    impl TestContext {
+        /// A context with an unlocked database containing a mail password
+        fn unlocked() -> Self {
+            let mut entry = KpEntry::new();
+            for (field, value) in [("Title", "Mail"), ("service", "imap"), ("user", "alice")] {
+                entry
+                    .fields
+                    .insert(field.to_string(), KpValue::Unprotected(value.to_string()));
+            }
+            entry.fields.insert(
+                "Password".to_string(),
+                KpValue::Protected("hunter2".as_bytes().into()),
+            );
+
+            let mut database = KpDatabase::new(Default::default());
+            database.root.children.push(Node::Entry(entry));
+            crate::secret_service::set_enabled(&mut database, true);
+
+            let mut state = AppState::default();
+            state.databases.push(Database::unlocked(database));
+
+            Self {
+                state: Mutex::new(state),
+                changed: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// A context with the locked demo database, as if it had been exposed before locking
+        fn locked() -> Self {
+            let mut state = AppState::default();
+            state
+                .load_database_buffer("demo.kdbx".to_string(), include_bytes!("../demo.kdbx"))
+                .unwrap();
+            state.databases[0].exposed_while_locked = true;
+
+            Self {
+                state: Mutex::new(state),
+                changed: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    fn call<B, R>(client: &Connection, path: &str, interface: &str, method: &str, body: &B) -> R
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+        R: serde::de::DeserializeOwned + zbus::zvariant::Type,
+    {
+        client
+            .call_method(Some(SERVICE_NAME), path, Some(interface), method, body)
+            .unwrap()
+            .body()
+            .deserialize()
+            .unwrap()
+    }
+
+    fn start(ctx: TestContext) -> (TestBus, Connection, Arc<TestContext>) {
+        let bus = TestBus::start();
+
+        let ctx = Arc::new(ctx);
+        serve(bus.connect(), ctx.clone()).unwrap();
+
+        let client = bus.connect();
+        (bus, client, ctx)
+    }
+
+    #[test]
+    fn test_opt_in() {
+        let ctx = TestContext::unlocked();
+        let mut state = ctx.state.into_inner().unwrap();
+        let id = state.databases[0].id;
+        let path = collection_path(&id);
+
+        assert_eq!(default_collection(&state), Some(id));
+        assert!(matches!(
+            resolve(&state, &path),
+            Some(Object::Collection(..))
+        ));
+
+        // an opted-in database stays exposed while it is locked
+        state.lock_database(0).unwrap();
+        assert!(matches!(
+            resolve(&state, &path),
+            Some(Object::Collection(..))
+        ));
+
+        // the demo database has not been opted in
+        state
+            .load_database_buffer("demo.kdbx".to_string(), include_bytes!("../demo.kdbx"))
+            .unwrap();
+        let demo = collection_path(&state.databases[1].id);
+        assert!(resolve(&state, &demo).is_none());
+
+        state.databases.remove(0);
+        assert_eq!(default_collection(&state), None);
+        assert!(resolve(&state, &path).is_none());
+    }
+
+    #[test]
+    fn test_store_and_lookup() {
+        let (_bus, client, ctx) = start(TestContext::unlocked());
+
+        let (_, session): (OwnedValue, OwnedObjectPath) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "OpenSession",
+            &("plain", Value::from("")),
+        );
+
+        let query = HashMap::from([("service", "imap")]);
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "SearchItems",
+            &(&query,),
+        );
+        assert_eq!(unlocked.len(), 1);
+        assert!(locked.is_empty());
+
+        let (secrets,): (HashMap<OwnedObjectPath, Secret>,) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "GetSecrets",
+            &(&unlocked, &session),
+        );
+        assert_eq!(secrets[&unlocked[0]].2, b"hunter2");
+
+        let label: OwnedValue = call(
+            &client,
+            unlocked[0].as_str(),
+            PROPERTIES_INTERFACE,
+            "Get",
+            &(ITEM_INTERFACE, "Label"),
+        );
+        assert_eq!(String::try_from(label).unwrap(), "Mail");
+
+        // store a new password in the default collection, as libsecret does
+        let properties = HashMap::from([
+            (LABEL_PROPERTY, Value::from("Chat")),
+            (
+                ATTRIBUTES_PROPERTY,
+                Value::from(HashMap::from([("service", "xmpp")])),
+            ),
+        ]);
+        let secret = (&session, Vec::<u8>::new(), b"s3cret".to_vec(), CONTENT_TYPE);
+
+        let default = format!("{ALIAS_PREFIX}{DEFAULT_ALIAS}");
+        let (item, prompt): (OwnedObjectPath, OwnedObjectPath) = call(
+            &client,
+            &default,
+            COLLECTION_INTERFACE,
+            "CreateItem",
+            &(&properties, &secret, true),
+        );
+        assert_eq!(prompt.as_str(), "/");
+        assert_eq!(ctx.changed.lock().unwrap().as_slice(), &[0]);
+
+        // storing again with the same attributes replaces the item
+        let (replaced, _): (OwnedObjectPath, OwnedObjectPath) = call(
+            &client,
+            &default,
+            COLLECTION_INTERFACE,
+            "CreateItem",
+            &(&properties, &secret, true),
+        );
+        assert_eq!(replaced, item);
+
+        let (found,): (Vec<OwnedObjectPath>,) = call(
+            &client,
+            &default,
+            COLLECTION_INTERFACE,
+            "SearchItems",
+            &(HashMap::from([("service", "xmpp")]),),
+        );
+        assert_eq!(found, vec![item.clone()]);
+
+        ctx.with_state(|state| {
+            let database = state.databases[0].get_database().unwrap();
+            assert_eq!(crate::secret_service::items(database).len(), 2);
+        });
+
+        let (_,): (OwnedObjectPath,) = call(&client, item.as_str(), ITEM_INTERFACE, "Delete", &());
+
+        ctx.with_state(|state| {
+            let database = state.databases[0].get_database().unwrap();
+            assert_eq!(crate::secret_service::items(database).len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_encrypted_session() {
+        let (bus, client, _ctx) = start(TestContext::unlocked());
+
+        let private = BigUint::from_bytes_be(&[0x17; 64]);
+        let public = BigUint::from(session::GENERATOR).modpow(&private, &session::prime());
+
+        let (output, session): (OwnedValue, OwnedObjectPath) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "OpenSession",
+            &(session::DH_AES, Value::from(session::pad(&public))),
+        );
+
+        let service_public = BigUint::from_bytes_be(&Vec::<u8>::try_from(output).unwrap());
+        let shared = service_public.modpow(&private, &session::prime());
+        let encryption = Encryption::Aes {
+            key: session::derive_key(&shared).unwrap(),
+        };
+
+        let (unlocked, _): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "SearchItems",
+            &(HashMap::from([("user", "alice")]),),
+        );
+
+        let ((_, parameters, value, _),): (Secret,) = call(
+            &client,
+            unlocked[0].as_str(),
+            ITEM_INTERFACE,
+            "GetSecret",
+            &(&session,),
+        );
+        assert_ne!(value, b"hunter2");
+        assert_eq!(encryption.decrypt(&parameters, &value).unwrap(), b"hunter2");
+
+        // sessions cannot be used by other clients
+        let other = bus.connect();
+        let res = other.call_method(
+            Some(SERVICE_NAME),
+            unlocked[0].as_str(),
+            Some(ITEM_INTERFACE),
+            "GetSecret",
+            &(&session,),
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_unlock_with_prompt() {
+        let (_bus, client, _ctx) = start(TestContext::locked());
+
+        let collections: OwnedValue = call(
+            &client,
+            SERVICE_PATH,
+            PROPERTIES_INTERFACE,
+            "Get",
+            &(SERVICE_INTERFACE, "Collections"),
+        );
+        let collections = Vec::<OwnedObjectPath>::try_from(collections).unwrap();
+        assert_eq!(collections.len(), 1);
+
+        let locked: OwnedValue = call(
+            &client,
+            collections[0].as_str(),
+            PROPERTIES_INTERFACE,
+            "Get",
+            &(COLLECTION_INTERFACE, "Locked"),
+        );
+        assert!(bool::try_from(locked).unwrap());
+
+        let (unlocked, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) = call(
+            &client,
+            SERVICE_PATH,
+            SERVICE_INTERFACE,
+            "Unlock",
+            &(&collections,),
+        );
+        assert!(unlocked.is_empty());
+        assert_ne!(prompt.as_str(), "/");
+
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(PROMPT_INTERFACE)
+            .unwrap()
+            .member("Completed")
+            .unwrap()
+            .build();
+        let mut signals = MessageIterator::for_match_rule(rule, &client, None).unwrap();
+
+        let () = call(&client, prompt.as_str(), PROMPT_INTERFACE, "Prompt", &("",));
+
+        let signal = signals.next().unwrap().unwrap();
+        let (dismissed, result): (bool, OwnedValue) = signal.body().deserialize().unwrap();
+        assert!(!dismissed);
+        assert_eq!(
+            Vec::<OwnedObjectPath>::try_from(result).unwrap(),
+            collections
+        );
+
+        let locked: OwnedValue = call(
+            &client,
+            collections[0].as_str(),
+            PROPERTIES_INTERFACE,
+            "Get",
+            &(COLLECTION_INTERFACE, "Locked"),
+        );
+        assert!(!bool::try_from(locked).unwrap());
+    }
+}
@@ -0,0 +1,146 @@
+//! Transfer encryption of secrets between the service and its clients
+//!
+//! Besides `plain`, clients can negotiate `dh-ietf1024-sha256-aes128-cbc-pkcs7`: a Diffie-Hellman
+//! exchange in the 1024-bit MODP group of RFC 2409, whose shared secret is turned into an AES-128
+//! key with HKDF-SHA256. Secrets are then encrypted with AES-128-CBC, with the IV as parameters.
+
+use aes::Aes128;
+use anyhow::{Result, anyhow, bail};
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use sha2::Sha256;
+
+/// Name of the algorithm that transfers secrets unencrypted
+pub const PLAIN: &str = "plain";
+
+/// Name of the algorithm that encrypts secrets with a key agreed by Diffie-Hellman
+pub const DH_AES: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+/// The prime of the Second Oakley Group of RFC 2409
+const PRIME: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF";
+
+pub(super) const GENERATOR: u32 = 2;
+
+/// Size of the prime, public keys and shared secret in bytes
+const KEY_SIZE: usize = 128;
+
+const IV_SIZE: usize = 16;
+
+/// How secrets are encrypted within a session
+pub enum Encryption {
+    Plain,
+    Aes { key: [u8; 16] },
+}
+
+impl Encryption {
+    /// Agree on a key with the public key of a client, returning our public key for the client
+    pub fn dh_aes(client_public: &[u8]) -> Result<(Self, Vec<u8>)> {
+        let prime = prime();
+        let client_public = BigUint::from_bytes_be(client_public);
+
+        if client_public <= BigUint::from(1u32) || client_public >= &prime - 1u32 {
+            bail!("Invalid public key");
+        }
+
+        let mut private = [0u8; KEY_SIZE];
+        getrandom::fill(&mut private).map_err(|e| anyhow!("{}", e))?;
+        let private = BigUint::from_bytes_be(&private);
+
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime);
+        let shared = client_public.modpow(&private, &prime);
+
+        let key = derive_key(&shared)?;
+
+        Ok((Self::Aes { key }, pad(&public)))
+    }
+
+    /// Encrypt a secret, returning the parameters and the encrypted value
+    pub fn encrypt(&self, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        match self {
+            Self::Plain => Ok((Vec::new(), secret.to_vec())),
+            Self::Aes { key } => {
+                let mut iv = [0u8; IV_SIZE];
+                getrandom::fill(&mut iv).map_err(|e| anyhow!("{}", e))?;
+
+                let value = cbc::Encryptor::<Aes128>::new(key.into(), &iv.into())
+                    .encrypt_padded_vec_mut::<Pkcs7>(secret);
+
+                Ok((iv.to_vec(), value))
+            }
+        }
+    }
+
+    /// Decrypt a secret sent by a client
+    pub fn decrypt(&self, parameters: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Plain => Ok(value.to_vec()),
+            Self::Aes { key } => cbc::Decryptor::<Aes128>::new_from_slices(key, parameters)
+                .map_err(|_| anyhow!("Invalid parameters"))?
+                .decrypt_padded_vec_mut::<Pkcs7>(value)
+                .map_err(|_| anyhow!("Cannot decrypt the secret")),
+        }
+    }
+}
+
+pub(super) fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME.as_bytes(), 16).expect("valid prime")
+}
+
+/// Encode a number as big-endian bytes of the size of the prime
+pub(super) fn pad(value: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+
+    let mut out = vec![0u8; KEY_SIZE.saturating_sub(bytes.len())];
+    out.extend(bytes);
+    out
+}
+
+/// Derive the AES key from the shared secret, like libsecret and gnome-keyring do
+pub(super) fn derive_key(shared: &BigUint) -> Result<[u8; 16]> {
+    let mut key = [0u8; 16];
+
+    Hkdf::<Sha256>::new(None, &pad(shared))
+        .expand(&[], &mut key)
+        .map_err(|e| anyhow!("{}", e))?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dh_aes_agrees_with_client() {
+        let client_private = BigUint::from_bytes_be(&[0x42; 64]);
+        let client_public = BigUint::from(GENERATOR).modpow(&client_private, &prime());
+
+        let (encryption, service_public) = Encryption::dh_aes(&pad(&client_public)).unwrap();
+        assert_eq!(service_public.len(), KEY_SIZE);
+
+        let shared = BigUint::from_bytes_be(&service_public).modpow(&client_private, &prime());
+        let client = Encryption::Aes {
+            key: derive_key(&shared).unwrap(),
+        };
+
+        let (iv, value) = encryption.encrypt(b"hunter2").unwrap();
+        assert_eq!(iv.len(), IV_SIZE);
+        assert_ne!(value, b"hunter2");
+        assert_eq!(client.decrypt(&iv, &value).unwrap(), b"hunter2");
+
+        let (iv, value) = client.encrypt(b"correct horse").unwrap();
+        assert_eq!(encryption.decrypt(&iv, &value).unwrap(), b"correct horse");
+    }
+
+    #[test]
+    fn test_rejects_weak_public_keys() {
+        assert!(Encryption::dh_aes(&[1]).is_err());
+        assert!(Encryption::dh_aes(&pad(&(prime() - 1u32))).is_err());
+    }
+}
@@ -1,43 +1,183 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::io::{Cursor, Read, Write};
+use std::pin::Pin;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+
+mod http;
+mod s3;
+#[cfg(feature = "tauri")]
+mod sftp;
+#[cfg(any(test, all(not(feature = "tauri"), target_arch = "wasm32")))]
+mod storage;
+mod webdav;
+
+pub use http::default_client;
+pub use s3::{S3DatabaseSource, S3Location};
+#[cfg(feature = "tauri")]
+pub use sftp::{SftpDatabaseSource, SftpLocation, SftpLogin};
+#[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+pub use storage::{
+    FileHandleDatabaseSource, StoredDatabaseSource, link_database_file, list_stored_databases,
+    remove_stored_database, store_database,
+};
+pub use webdav::WebDavDatabaseSource;
+
+/// The result of an operation of a [`DatabaseSource`] that may have to wait
+///
+/// Sources in the browser wait for promises, which cannot be sent to other threads, so these
+/// futures are not `Send` on any platform.
+pub type SourceFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
 
 /// A way to load a KeePass database
 ///
-/// This is to abstract over whether the database is loaded from a Vec<u8> buffer or a path into
-/// the filesystem.
+/// This is to abstract over where the database is stored, like in a Vec<u8> buffer, at a path in
+/// the filesystem or on a server. Sources that never wait can implement the simpler
+/// [`BlockingDatabaseSource`] instead and be wrapped in [`Blocking`].
 pub trait DatabaseSource: Send {
+    /// Read the whole database from the source
+    fn open(&self) -> SourceFuture<'_, Vec<u8>>;
+
+    /// Replace the database at the source
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()>;
+
+    /// If applicable, send the internal data as a buffer
+    fn send_saved(&self) -> Option<Vec<u8>>;
+
+    /// Get a name describing the source (like a file name)
+    fn get_name(&self) -> &str;
+
+    /// Read another file next to the database, like a KeeShare container
+    ///
+    /// Relative paths are resolved against the location of the database. Returns `None` if the
+    /// file does not exist.
+    fn read_sibling<'a>(&'a self, _path: &'a str) -> SourceFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async { bail!("This database source does not support other files") })
+    }
+
+    /// Write another file next to the database, replacing any previous contents
+    fn write_sibling<'a>(&'a mut self, _path: &'a str, _data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async { bail!("This database source does not support other files") })
+    }
+}
+
+/// A database source whose operations complete right away, like reading a local file
+///
+/// Wrap it in [`Blocking`] to use it as a [`DatabaseSource`].
+pub trait BlockingDatabaseSource: Send {
     /// Get a read handle from the database source
-    fn open(&self, #[cfg(feature = "tauri")] app: tauri::AppHandle) -> Result<Box<dyn Read>>;
+    fn open(&self) -> Result<Box<dyn Read>>;
 
     /// Get a write handle for the database source
-    fn save(
-        &mut self,
-        #[cfg(feature = "tauri")] app: tauri::AppHandle,
-    ) -> Result<Box<dyn Write + '_>>;
+    fn save(&mut self) -> Result<Box<dyn Write + '_>>;
 
     /// If applicable, send the internal data as a buffer
     fn send_saved(&self) -> Option<Vec<u8>>;
 
     /// Get a name describing the source (like a file name)
     fn get_name(&self) -> &str;
+
+    /// Read another file next to the database, see [`DatabaseSource::read_sibling`]
+    fn read_sibling(&self, _path: &str) -> Result<Option<Vec<u8>>> {
+        bail!("This database source does not support other files")
+    }
+
+    /// Write another file next to the database, see [`DatabaseSource::write_sibling`]
+    fn write_sibling(&mut self, _path: &str, _data: &[u8]) -> Result<()> {
+        bail!("This database source does not support other files")
+    }
+}
+
+/// Adapts a [`BlockingDatabaseSource`] to a [`DatabaseSource`], running every operation as soon
+/// as its future is polled
+pub struct Blocking<S>(pub S);
+
+impl<S: BlockingDatabaseSource> DatabaseSource for Blocking<S> {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let mut data = Vec::new();
+            self.0.open()?.read_to_end(&mut data)?;
+
+            Ok(data)
+        })
+    }
+
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            let mut writer = self.0.save()?;
+
+            writer.write_all(data)?;
+            writer.flush()?;
+
+            Ok(())
+        })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        self.0.send_saved()
+    }
+
+    fn get_name(&self) -> &str {
+        self.0.get_name()
+    }
+
+    fn read_sibling<'a>(&'a self, path: &'a str) -> SourceFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { self.0.read_sibling(path) })
+    }
+
+    fn write_sibling<'a>(&'a mut self, path: &'a str, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move { self.0.write_sibling(path, data) })
+    }
+}
+
+/// Run a future that never waits, like those of [`Blocking`] sources and test stand-ins
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// The user name and password to log in to a remote database source with
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
 }
 
 /// A database loaded from an in-memory buffer (for web deploys)
 pub struct BufferDatabaseSource {
     pub name: String,
     pub buffer: Vec<u8>,
+
+    /// other files by their path, which are uploaded and downloaded separately
+    pub siblings: HashMap<String, Vec<u8>>,
+}
+
+impl BufferDatabaseSource {
+    /// An empty source without siblings, for tests
+    #[cfg(test)]
+    pub(crate) fn empty(name: &str) -> Blocking<Self> {
+        Blocking(Self {
+            name: name.to_string(),
+            buffer: Vec::new(),
+            siblings: Default::default(),
+        })
+    }
 }
 
-impl DatabaseSource for BufferDatabaseSource {
-    fn open(&self, #[cfg(feature = "tauri")] _: tauri::AppHandle) -> Result<Box<dyn Read>> {
+impl BlockingDatabaseSource for BufferDatabaseSource {
+    fn open(&self) -> Result<Box<dyn Read>> {
         Ok(Box::new(Cursor::new(self.buffer.clone())))
     }
 
-    fn save(
-        &mut self,
-        #[cfg(feature = "tauri")] _: tauri::AppHandle,
-    ) -> Result<Box<dyn Write + '_>> {
+    fn save(&mut self) -> Result<Box<dyn Write + '_>> {
         self.buffer.clear();
         Ok(Box::new(&mut self.buffer))
     }
@@ -49,30 +189,43 @@ impl DatabaseSource for BufferDatabaseSource {
     fn get_name(&self) -> &str {
         &self.name
     }
+
+    fn read_sibling(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.siblings.get(path).cloned())
+    }
+
+    fn write_sibling(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.siblings.insert(path.to_string(), data.to_vec());
+        Ok(())
+    }
 }
 
 /// A database loaded from the filesystem (for Tauri deploys)
+///
+/// Files are opened through the file system plugin of the app, which also handles content URIs on
+/// Android.
 #[cfg(feature = "tauri")]
 pub struct FilesystemDatabaseSource {
     pub path: tauri_plugin_fs::FilePath,
+    pub app: tauri::AppHandle,
 }
 
 #[cfg(feature = "tauri")]
-impl DatabaseSource for FilesystemDatabaseSource {
-    fn open(&self, app: tauri::AppHandle) -> Result<Box<dyn Read>> {
+impl BlockingDatabaseSource for FilesystemDatabaseSource {
+    fn open(&self) -> Result<Box<dyn Read>> {
         use tauri_plugin_fs::{FsExt, OpenOptions};
 
         let options = OpenOptions::new().read(true).clone();
 
-        Ok(Box::new(app.fs().open(self.path.clone(), options)?))
+        Ok(Box::new(self.app.fs().open(self.path.clone(), options)?))
     }
 
-    fn save(&mut self, app: tauri::AppHandle) -> Result<Box<dyn Write>> {
+    fn save(&mut self) -> Result<Box<dyn Write>> {
         use tauri_plugin_fs::{FsExt, OpenOptions};
 
         let options = OpenOptions::new().write(true).create(true).clone();
 
-        Ok(Box::new(app.fs().open(self.path.clone(), options)?))
+        Ok(Box::new(self.app.fs().open(self.path.clone(), options)?))
     }
 
     fn send_saved(&self) -> Option<Vec<u8>> {
@@ -91,4 +244,127 @@ impl DatabaseSource for FilesystemDatabaseSource {
                 .unwrap_or_default(),
         }
     }
+
+    fn read_sibling(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        use tauri_plugin_fs::{FsExt, OpenOptions};
+
+        let options = OpenOptions::new().read(true).clone();
+
+        let mut file = match self.app.fs().open(self.sibling_path(path)?, options) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Ok(Some(data))
+    }
+
+    fn write_sibling(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        use tauri_plugin_fs::{FsExt, OpenOptions};
+
+        let options = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .clone();
+
+        let mut file = self.app.fs().open(self.sibling_path(path)?, options)?;
+        file.write_all(data)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tauri")]
+impl FilesystemDatabaseSource {
+    /// Resolve the path of another file relative to the directory of the database
+    fn sibling_path(&self, path: &str) -> Result<tauri_plugin_fs::FilePath> {
+        use tauri_plugin_fs::FilePath;
+
+        let resolved = match &self.path {
+            FilePath::Url(url) => FilePath::Url(url.join(path)?),
+            FilePath::Path(database) => {
+                let path = std::path::Path::new(path);
+
+                match database.parent() {
+                    Some(dir) if path.is_relative() => FilePath::Path(dir.join(path)),
+                    _ => FilePath::Path(path.to_path_buf()),
+                }
+            }
+        };
+
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source that cannot be read or written
+    struct Broken;
+
+    impl BlockingDatabaseSource for Broken {
+        fn open(&self) -> Result<Box<dyn Read>> {
+            bail!("cannot read")
+        }
+
+        fn save(&mut self) -> Result<Box<dyn Write + '_>> {
+            Ok(Box::new(BrokenWriter))
+        }
+
+        fn send_saved(&self) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn get_name(&self) -> &str {
+            "broken.kdbx"
+        }
+    }
+
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("cannot write"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_blocking() {
+        let mut source = BufferDatabaseSource::empty("test.kdbx");
+
+        block_on(source.save(b"database")).unwrap();
+        assert_eq!(block_on(source.open()).unwrap(), b"database");
+        assert_eq!(source.send_saved(), Some(b"database".to_vec()));
+        assert_eq!(source.get_name(), "test.kdbx");
+
+        block_on(source.write_sibling("share.kdbx", b"container")).unwrap();
+        assert_eq!(
+            block_on(source.read_sibling("share.kdbx")).unwrap(),
+            Some(b"container".to_vec())
+        );
+        assert_eq!(block_on(source.read_sibling("missing.kdbx")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_blocking_errors() {
+        let mut source = Blocking(Broken);
+
+        let error = block_on(source.open()).unwrap_err();
+        assert_eq!(error.to_string(), "cannot read");
+
+        let error = block_on(source.save(b"database")).unwrap_err();
+        assert_eq!(error.to_string(), "cannot write");
+
+        assert!(block_on(source.read_sibling("share.kdbx")).is_err());
+        assert!(block_on(source.write_sibling("share.kdbx", b"container")).is_err());
+    }
 }
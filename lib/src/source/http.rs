@@ -0,0 +1,233 @@
+//! A minimal HTTP client abstraction for remote database sources
+//!
+//! Remote sources build [`HttpRequest`]s and leave sending them to an [`HttpClient`], which is a
+//! native client in the Tauri build and the browser in the web build. Tests answer requests with
+//! a stand-in client instead.
+
+use anyhow::Result;
+use url::Url;
+
+use super::SourceFuture;
+
+/// An HTTP request to send
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: &'static str,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl HttpRequest {
+    pub fn new(method: &'static str, url: Url) -> Self {
+        Self {
+            method,
+            url,
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(body);
+        self
+    }
+}
+
+/// The response to an [`HttpRequest`]
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponse {
+    pub status: u16,
+
+    /// header names are lowercase
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Get the first value of a header by its name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get all values of a header that may occur more than once
+    pub fn headers_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends HTTP requests on behalf of a database source
+pub trait HttpClient: Send {
+    /// Send a request and wait for the complete response
+    ///
+    /// Only failures to get a response are errors, HTTP error statuses are returned as responses.
+    fn send<'a>(&'a self, request: &'a HttpRequest) -> SourceFuture<'a, HttpResponse>;
+}
+
+/// Create the HTTP client of the current platform
+#[cfg(feature = "tauri")]
+pub fn default_client() -> Result<Box<dyn HttpClient>> {
+    Ok(Box::new(NativeHttpClient::new()?))
+}
+
+/// Create the HTTP client of the current platform
+#[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+pub fn default_client() -> Result<Box<dyn HttpClient>> {
+    Ok(Box::new(BrowserHttpClient))
+}
+
+/// Create the HTTP client of the current platform
+#[cfg(all(not(feature = "tauri"), not(target_arch = "wasm32")))]
+pub fn default_client() -> Result<Box<dyn HttpClient>> {
+    anyhow::bail!("Remote databases are not supported on this platform")
+}
+
+/// An [`HttpClient`] that uses a native HTTP client
+///
+/// Requests run on the Tokio runtime of Tauri, so its futures have to be awaited there.
+#[cfg(feature = "tauri")]
+pub struct NativeHttpClient {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "tauri")]
+impl NativeHttpClient {
+    pub fn new() -> Result<Self> {
+        use anyhow::Context;
+
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("OmniKee/", env!("CARGO_PKG_VERSION")))
+            .timeout(std::time::Duration::from_secs(60))
+            .build()
+            .context("Creating HTTP client")?;
+
+        Ok(Self { client })
+    }
+}
+
+#[cfg(feature = "tauri")]
+impl HttpClient for NativeHttpClient {
+    fn send<'a>(&'a self, request: &'a HttpRequest) -> SourceFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            use anyhow::Context;
+
+            let method = reqwest::Method::from_bytes(request.method.as_bytes())?;
+            let mut builder = self.client.request(method, request.url.clone());
+
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+
+            if let Some(body) = &request.body {
+                builder = builder.body(body.clone());
+            }
+
+            let res = builder.send().await.with_context(|| {
+                format!("Sending {} request to {}", request.method, request.url)
+            })?;
+
+            let status = res.status().as_u16();
+            let headers = res
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect();
+            let body = res.bytes().await.context("Reading response")?.to_vec();
+
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
+
+/// An [`HttpClient`] that uses the Fetch API of the browser
+///
+/// Responses are never taken from the cache of the browser, as the ETags of remote databases have
+/// to be current to detect conflicts.
+#[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+pub struct BrowserHttpClient;
+
+#[cfg(all(not(feature = "tauri"), target_arch = "wasm32"))]
+impl HttpClient for BrowserHttpClient {
+    fn send<'a>(&'a self, request: &'a HttpRequest) -> SourceFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            use anyhow::anyhow;
+            use wasm_bindgen::JsCast;
+            use wasm_bindgen_futures::JsFuture;
+
+            let js_error = |e: wasm_bindgen::JsValue| anyhow!("{:?}", e);
+
+            let headers = web_sys::Headers::new().map_err(js_error)?;
+            for (name, value) in &request.headers {
+                headers.append(name, value).map_err(js_error)?;
+            }
+
+            let init = web_sys::RequestInit::new();
+            init.set_method(request.method);
+            init.set_headers(&headers);
+            init.set_cache(web_sys::RequestCache::NoStore);
+
+            if let Some(body) = &request.body {
+                init.set_body(&js_sys::Uint8Array::from(body.as_slice()));
+            }
+
+            let window = web_sys::window().ok_or(anyhow!("Requests need a browser window"))?;
+            let response: web_sys::Response =
+                JsFuture::from(window.fetch_with_str_and_init(request.url.as_str(), &init))
+                    .await
+                    .map_err(|e| {
+                        anyhow!(
+                            "Sending {} request to {}: {:?}",
+                            request.method,
+                            request.url,
+                            e
+                        )
+                    })?
+                    .dyn_into()
+                    .map_err(js_error)?;
+
+            let status = response.status();
+
+            let headers = js_sys::try_iter(&response.headers())
+                .map_err(js_error)?
+                .ok_or(anyhow!("The response headers cannot be iterated"))?
+                .filter_map(|entry| {
+                    let entry = entry.ok()?.dyn_into::<js_sys::Array>().ok()?;
+                    Some((entry.get(0).as_string()?, entry.get(1).as_string()?))
+                })
+                .collect();
+
+            let body = JsFuture::from(response.array_buffer().map_err(js_error)?)
+                .await
+                .map_err(js_error)?;
+            let body = js_sys::Uint8Array::new(&body).to_vec();
+
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
@@ -0,0 +1,728 @@
+//! Databases stored on a server reachable over SSH
+//!
+//! Every access opens a new SSH connection and SFTP session, which is closed again afterwards.
+//! The host key of the server has to be in the user's `known_hosts` file or match a fingerprint
+//! that was confirmed before. Saving uploads the database into a temporary file next to it and
+//! renames that over the database, so that an interrupted upload never leaves a truncated
+//! database behind. The size and modification time of the database are remembered when opening,
+//! and saving is refused if they have changed on the server in the meantime.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, anyhow, bail};
+use russh::client;
+use russh::keys::{HashAlg, PrivateKeyWithHashAlg};
+use russh_sftp::client::RawSftpSession;
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+
+use super::{DatabaseSource, SourceFuture};
+
+/// Size of the chunks that files are read and written in, which all servers accept
+const CHUNK_SIZE: u32 = 32 * 1024;
+
+/// Extension of OpenSSH to rename a file over an existing one
+const POSIX_RENAME: &str = "posix-rename@openssh.com";
+
+/// How to log in to the server
+pub enum SftpLogin {
+    Password(String),
+    Key(Box<ssh_key::PrivateKey>),
+}
+
+/// Where a database is stored on a server
+pub struct SftpLocation {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+
+    /// the path of the database, relative to the home directory unless absolute
+    pub path: String,
+
+    /// the SHA-256 fingerprint of a host key to trust even if it is not in `known_hosts`
+    pub host_key_fingerprint: Option<String>,
+}
+
+/// The version of the database on the server that was opened or saved last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Version {
+    size: Option<u64>,
+    mtime: Option<u32>,
+}
+
+/// A database stored on a server reachable over SSH (for Tauri deploys)
+pub struct SftpDatabaseSource {
+    location: SftpLocation,
+    login: SftpLogin,
+    name: String,
+    version: Mutex<Option<Version>>,
+}
+
+/// Checks the host key of the server when connecting
+struct HostKeyCheck {
+    host: String,
+    port: u16,
+    fingerprint: Option<String>,
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(&mut self, key: &russh::keys::PublicKey) -> Result<bool> {
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+
+        match russh::keys::check_known_hosts(&self.host, self.port, key) {
+            Ok(true) => Ok(true),
+            Err(russh::keys::Error::KeyChanged { line }) => bail!(
+                "The host key of {} does not match line {} of the known hosts, it is {}",
+                self.host,
+                line,
+                fingerprint
+            ),
+            _ if self.fingerprint.as_deref() == Some(fingerprint.as_str()) => Ok(true),
+            _ => bail!(
+                "The host key of {} is unknown, confirm that its fingerprint is {} to trust it",
+                self.host,
+                fingerprint
+            ),
+        }
+    }
+}
+
+/// An SFTP session with its SSH connection
+struct Connection {
+    ssh: client::Handle<HostKeyCheck>,
+    session: Session,
+}
+
+/// An SFTP session, which runs over any stream so that it can be tested without a server
+struct Session {
+    sftp: RawSftpSession,
+    posix_rename: bool,
+}
+
+/// Request of the POSIX rename extension, with both paths encoded as SFTP strings
+struct PosixRenameExtension {
+    oldpath: String,
+    newpath: String,
+}
+
+impl From<PosixRenameExtension> for Vec<u8> {
+    fn from(extension: PosixRenameExtension) -> Self {
+        let mut data = Vec::new();
+        for path in [extension.oldpath, extension.newpath] {
+            data.extend((path.len() as u32).to_be_bytes());
+            data.extend(path.into_bytes());
+        }
+        data
+    }
+}
+
+impl SftpDatabaseSource {
+    pub fn new(location: SftpLocation, login: SftpLogin) -> Result<Self> {
+        let name = location
+            .path
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .ok_or(anyhow!("The path does not point to a file"))?
+            .to_string();
+
+        Ok(Self {
+            location,
+            login,
+            name,
+            version: Mutex::new(None),
+        })
+    }
+
+    /// Resolve the path of another file relative to the directory of the database
+    fn sibling_path(&self, path: &str) -> String {
+        match self.location.path.rfind('/') {
+            Some(end) if !path.starts_with('/') => {
+                format!("{}{}", &self.location.path[..=end], path)
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// Connect to the server, log in and start an SFTP session
+    async fn connect(&self) -> Result<Connection> {
+        let location = &self.location;
+
+        let config = Arc::new(client::Config::default());
+        let check = HostKeyCheck {
+            host: location.host.clone(),
+            port: location.port,
+            fingerprint: location.host_key_fingerprint.clone(),
+        };
+
+        let mut ssh = client::connect(config, (location.host.as_str(), location.port), check)
+            .await
+            .with_context(|| format!("Connecting to {}:{}", location.host, location.port))?;
+
+        let result = match &self.login {
+            SftpLogin::Password(password) => {
+                ssh.authenticate_password(&location.username, password)
+                    .await?
+            }
+            SftpLogin::Key(key) => {
+                // russh parses keys itself, so hand the key over in the OpenSSH format
+                let pem = key.to_openssh(ssh_key::LineEnding::LF)?;
+                let key = russh::keys::decode_secret_key(&pem, None)?;
+                let hash = ssh.best_supported_rsa_hash().await?.flatten();
+
+                ssh.authenticate_publickey(
+                    &location.username,
+                    PrivateKeyWithHashAlg::new(Arc::new(key), hash),
+                )
+                .await?
+            }
+        };
+
+        if !result.success() {
+            bail!(
+                "The server {} did not accept the login of {}",
+                location.host,
+                location.username
+            );
+        }
+
+        let channel = ssh.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+
+        let session = Session::start(RawSftpSession::new(channel.into_stream())).await?;
+
+        Ok(Connection { ssh, session })
+    }
+
+    /// Run an operation on a new connection to the server, closing it afterwards
+    ///
+    /// The SSH connection runs on the Tokio runtime of Tauri, so this has to be awaited there.
+    async fn with_connection<T>(
+        &self,
+        operation: impl AsyncFnOnce(&Session) -> Result<T>,
+    ) -> Result<T> {
+        let connection = self.connect().await?;
+        let result = operation(&connection.session).await;
+
+        let _ = connection.session.sftp.close_session();
+        let _ = connection
+            .ssh
+            .disconnect(russh::Disconnect::ByApplication, "", "")
+            .await;
+
+        result
+    }
+}
+
+impl Session {
+    /// Negotiate the protocol version and the supported extensions
+    async fn start(sftp: RawSftpSession) -> Result<Self> {
+        let version = sftp.init().await.context("Starting SFTP session")?;
+        let posix_rename = version.extensions.contains_key(POSIX_RENAME);
+
+        Ok(Self { sftp, posix_rename })
+    }
+
+    /// Get the size and modification time of a file, or `None` if it does not exist
+    async fn version(&self, path: &str) -> Result<Option<Version>> {
+        match self.sftp.stat(path).await {
+            Ok(attrs) => Ok(Some(Version {
+                size: attrs.attrs.size,
+                mtime: attrs.attrs.mtime,
+            })),
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                Ok(None)
+            }
+            Err(e) => Err(e).with_context(|| format!("Getting the attributes of {}", path)),
+        }
+    }
+
+    /// Read a whole file, or `None` if it does not exist
+    async fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let handle = match self
+            .sftp
+            .open(path, OpenFlags::READ, FileAttributes::default())
+            .await
+        {
+            Ok(handle) => handle.handle,
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e).with_context(|| format!("Opening {}", path)),
+        };
+
+        let mut data = Vec::new();
+        let result = loop {
+            match self
+                .sftp
+                .read(handle.as_str(), data.len() as u64, CHUNK_SIZE)
+                .await
+            {
+                Ok(chunk) => data.extend(chunk.data),
+                Err(SftpError::Status(status)) if status.status_code == StatusCode::Eof => {
+                    break Ok(Some(data));
+                }
+                Err(e) => break Err(e).with_context(|| format!("Reading {}", path)),
+            }
+        };
+
+        self.sftp.close(handle).await?;
+
+        result
+    }
+
+    /// Write a whole file, replacing it if it exists
+    async fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let flags = OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE;
+        let handle = self
+            .sftp
+            .open(path, flags, FileAttributes::default())
+            .await
+            .with_context(|| format!("Creating {}", path))?
+            .handle;
+
+        let mut result = Ok(());
+        for (i, chunk) in data.chunks(CHUNK_SIZE as usize).enumerate() {
+            let offset = i as u64 * CHUNK_SIZE as u64;
+
+            if let Err(e) = self
+                .sftp
+                .write(handle.as_str(), offset, chunk.to_vec())
+                .await
+            {
+                result = Err(e).with_context(|| format!("Writing {}", path));
+                break;
+            }
+        }
+
+        self.sftp.close(handle).await?;
+
+        result
+    }
+
+    /// Rename a file over another one
+    ///
+    /// Plain SFTP renames fail if the target exists, so servers without the POSIX rename
+    /// extension of OpenSSH have the target removed first, which is not atomic.
+    async fn replace(&self, from: &str, to: &str) -> Result<()> {
+        if self.posix_rename {
+            let data = PosixRenameExtension {
+                oldpath: from.to_string(),
+                newpath: to.to_string(),
+            }
+            .into();
+
+            match self.sftp.extended(POSIX_RENAME, data).await? {
+                russh_sftp::protocol::Packet::Status(status)
+                    if status.status_code == StatusCode::Ok => {}
+                packet => bail!("Renaming {} to {} failed: {:?}", from, to, packet),
+            }
+        } else {
+            match self.sftp.remove(to).await {
+                Ok(_) => {}
+                Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {}
+                Err(e) => return Err(e).with_context(|| format!("Removing {}", to)),
+            }
+
+            self.sftp
+                .rename(from, to)
+                .await
+                .with_context(|| format!("Renaming {} to {}", from, to))?;
+        }
+
+        Ok(())
+    }
+
+    /// Upload a database through a temporary file, unless it was changed since the version
+    /// that was opened, and return the new version
+    async fn upload(
+        &self,
+        path: &str,
+        data: &[u8],
+        opened: Option<Version>,
+    ) -> Result<Option<Version>> {
+        let current = self.version(path).await?;
+
+        // a database that did not exist yet may be created
+        if current.is_some() && current != opened {
+            bail!(
+                "The database was changed on the server since it was opened. Reopen it to merge the changes before saving."
+            );
+        }
+
+        let temporary = format!("{}.{}.tmp", path, uuid::Uuid::new_v4().simple());
+        let uploaded = match self.write(&temporary, data).await {
+            Ok(()) => self.replace(&temporary, path).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = uploaded {
+            let _ = self.sftp.remove(temporary.as_str()).await;
+            return Err(e);
+        }
+
+        self.version(path).await
+    }
+}
+
+impl DatabaseSource for SftpDatabaseSource {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let path = &self.location.path;
+
+            let (data, version) = self
+                .with_connection(async |session| {
+                    let version = session.version(path).await?;
+                    let data = session
+                        .read(path)
+                        .await?
+                        .ok_or(anyhow!("The database does not exist on the server"))?;
+
+                    Ok((data, version))
+                })
+                .await?;
+
+            *self.version.lock().unwrap() = version;
+
+            Ok(data)
+        })
+    }
+
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            let path = &self.location.path;
+            let opened = *self.version.lock().unwrap();
+
+            let version = self
+                .with_connection(async |session| session.upload(path, data, opened).await)
+                .await?;
+
+            *self.version.lock().unwrap() = version;
+
+            Ok(())
+        })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_sibling<'a>(&'a self, path: &'a str) -> SourceFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let path = self.sibling_path(path);
+            self.with_connection(async |session| session.read(&path).await)
+                .await
+        })
+    }
+
+    fn write_sibling<'a>(&'a mut self, path: &'a str, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.sibling_path(path);
+            self.with_connection(async |session| session.write(&path, data).await)
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use russh_sftp::protocol::{
+        Attrs, Data, FileAttributes, Handle, OpenFlags, Packet, Status, StatusCode,
+    };
+    use russh_sftp::server::{self, Handler};
+
+    use super::*;
+
+    /// Files on the stand-in server, with a modification time that increases on every change
+    #[derive(Default)]
+    struct Files {
+        files: HashMap<String, (Vec<u8>, u32)>,
+        time: u32,
+    }
+
+    impl Files {
+        fn put(&mut self, path: &str, data: Vec<u8>) {
+            self.time += 1;
+            self.files.insert(path.to_string(), (data, self.time));
+        }
+    }
+
+    /// An SFTP server keeping its files in memory
+    struct Server {
+        files: Arc<Mutex<Files>>,
+        handles: HashMap<String, String>,
+        posix_rename: bool,
+    }
+
+    fn ok(id: u32) -> Status {
+        Status {
+            id,
+            status_code: StatusCode::Ok,
+            error_message: "Ok".to_string(),
+            language_tag: "en-US".to_string(),
+        }
+    }
+
+    impl Handler for Server {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            StatusCode::OpUnsupported
+        }
+
+        async fn init(
+            &mut self,
+            _: u32,
+            _: HashMap<String, String>,
+        ) -> Result<russh_sftp::protocol::Version, Self::Error> {
+            let mut version = russh_sftp::protocol::Version::new();
+            if self.posix_rename {
+                version
+                    .extensions
+                    .insert(POSIX_RENAME.to_string(), "1".to_string());
+            }
+            Ok(version)
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            filename: String,
+            pflags: OpenFlags,
+            _: FileAttributes,
+        ) -> Result<Handle, Self::Error> {
+            let mut files = self.files.lock().unwrap();
+            let exists = files.files.contains_key(&filename);
+
+            if pflags.contains(OpenFlags::CREATE) {
+                if !exists || pflags.contains(OpenFlags::TRUNCATE) {
+                    files.put(&filename, Vec::new());
+                }
+            } else if !exists {
+                return Err(StatusCode::NoSuchFile);
+            }
+
+            let handle = self.handles.len().to_string();
+            self.handles.insert(handle.clone(), filename);
+            Ok(Handle { id, handle })
+        }
+
+        async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+            self.handles.remove(&handle).ok_or(StatusCode::Failure)?;
+            Ok(ok(id))
+        }
+
+        async fn read(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            len: u32,
+        ) -> Result<Data, Self::Error> {
+            let files = self.files.lock().unwrap();
+            let (data, _) = &files.files[&self.handles[&handle]];
+
+            let start = offset as usize;
+            if start >= data.len() {
+                return Err(StatusCode::Eof);
+            }
+            let end = data.len().min(start + len as usize);
+
+            Ok(Data {
+                id,
+                data: data[start..end].to_vec(),
+            })
+        }
+
+        async fn write(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            chunk: Vec<u8>,
+        ) -> Result<Status, Self::Error> {
+            let mut files = self.files.lock().unwrap();
+            let path = &self.handles[&handle];
+
+            let mut data = files.files[path].0.clone();
+            data.truncate(offset as usize);
+            data.resize(offset as usize, 0);
+            data.extend(chunk);
+            files.put(path, data);
+
+            Ok(ok(id))
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            let files = self.files.lock().unwrap();
+            let (data, mtime) = files.files.get(&path).ok_or(StatusCode::NoSuchFile)?;
+
+            let mut attrs = FileAttributes::empty();
+            attrs.size = Some(data.len() as u64);
+            attrs.mtime = Some(*mtime);
+            Ok(Attrs { id, attrs })
+        }
+
+        async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+            let mut files = self.files.lock().unwrap();
+            files
+                .files
+                .remove(&filename)
+                .ok_or(StatusCode::NoSuchFile)?;
+            Ok(ok(id))
+        }
+
+        async fn rename(
+            &mut self,
+            id: u32,
+            oldpath: String,
+            newpath: String,
+        ) -> Result<Status, Self::Error> {
+            let mut files = self.files.lock().unwrap();
+
+            // like plain SFTP servers, refuse to rename over an existing file
+            if files.files.contains_key(&newpath) {
+                return Err(StatusCode::Failure);
+            }
+            let file = files.files.remove(&oldpath).ok_or(StatusCode::NoSuchFile)?;
+            files.files.insert(newpath, file);
+
+            Ok(ok(id))
+        }
+
+        async fn extended(
+            &mut self,
+            id: u32,
+            request: String,
+            data: Vec<u8>,
+        ) -> Result<Packet, Self::Error> {
+            if !self.posix_rename || request != POSIX_RENAME {
+                return Err(StatusCode::OpUnsupported);
+            }
+
+            let mut paths = Vec::new();
+            let mut rest = data.as_slice();
+            while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+                let (path, tail) = tail.split_at(u32::from_be_bytes(*len) as usize);
+                paths.push(String::from_utf8(path.to_vec()).unwrap());
+                rest = tail;
+            }
+
+            let mut files = self.files.lock().unwrap();
+            let file = files
+                .files
+                .remove(&paths[0])
+                .ok_or(StatusCode::NoSuchFile)?;
+            files.files.insert(paths[1].clone(), file);
+
+            Ok(Packet::Status(ok(id)))
+        }
+    }
+
+    /// Start an SFTP session with a stand-in server over an in-memory stream
+    async fn session(files: &Arc<Mutex<Files>>, posix_rename: bool) -> Session {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        server::run(
+            server,
+            Server {
+                files: files.clone(),
+                handles: HashMap::new(),
+                posix_rename,
+            },
+        )
+        .await;
+
+        Session::start(RawSftpSession::new(client)).await.unwrap()
+    }
+
+    #[test]
+    fn test_posix_rename_extension() {
+        let data: Vec<u8> = PosixRenameExtension {
+            oldpath: "a.tmp".to_string(),
+            newpath: "b".to_string(),
+        }
+        .into();
+
+        assert_eq!(data, b"\0\0\0\x05a.tmp\0\0\0\x01b");
+    }
+
+    #[tokio::test]
+    async fn test_read_write() {
+        let files = Arc::default();
+        let session = session(&files, true).await;
+        assert!(session.posix_rename);
+
+        // spans several chunks, and does not end on a chunk boundary
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3 + 100).map(|i| i as u8).collect();
+        session.write("db.kdbx", &data).await.unwrap();
+
+        assert_eq!(session.read("db.kdbx").await.unwrap(), Some(data.clone()));
+        assert_eq!(
+            session.version("db.kdbx").await.unwrap().unwrap().size,
+            Some(data.len() as u64)
+        );
+
+        // writing again truncates the file
+        session.write("db.kdbx", b"short").await.unwrap();
+        assert_eq!(
+            session.read("db.kdbx").await.unwrap(),
+            Some(b"short".to_vec())
+        );
+
+        assert_eq!(session.read("missing.kdbx").await.unwrap(), None);
+        assert_eq!(session.version("missing.kdbx").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_replace() {
+        for posix_rename in [true, false] {
+            let files: Arc<Mutex<Files>> = Arc::default();
+            files.lock().unwrap().put("db.kdbx", b"old".to_vec());
+            files.lock().unwrap().put("db.kdbx.tmp", b"new".to_vec());
+
+            let session = session(&files, posix_rename).await;
+            assert_eq!(session.posix_rename, posix_rename);
+            session.replace("db.kdbx.tmp", "db.kdbx").await.unwrap();
+
+            let files = files.lock().unwrap();
+            assert_eq!(files.files["db.kdbx"].0, b"new");
+            assert!(!files.files.contains_key("db.kdbx.tmp"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let files: Arc<Mutex<Files>> = Arc::default();
+        let session = session(&files, false).await;
+
+        // a new database is created
+        let version = session.upload("db.kdbx", b"first", None).await.unwrap();
+        assert!(version.is_some());
+
+        let version = session.upload("db.kdbx", b"second", version).await.unwrap();
+        assert_eq!(files.lock().unwrap().files["db.kdbx"].0, b"second");
+
+        // somebody else saves the database in the meantime
+        files.lock().unwrap().put("db.kdbx", b"theirs".to_vec());
+
+        let error = session
+            .upload("db.kdbx", b"third", version)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("changed on the server"));
+
+        // the changes on the server are kept, and no temporary files are left behind
+        let files = files.lock().unwrap();
+        assert_eq!(files.files["db.kdbx"].0, b"theirs");
+        assert_eq!(files.files.len(), 1);
+    }
+}
@@ -0,0 +1,220 @@
+//! Databases kept by the browser between sessions
+//!
+//! Copies of databases are stored in the Origin Private File System, or in IndexedDB in browsers
+//! that cannot write files there. Each copy is a file named like the database, with its KeeShare
+//! containers next to it. Alternatively, the browser can be given access to a database file on
+//! disk through the File System Access API, which is then read and written in place. Its handle is
+//! remembered in IndexedDB, so that the file can be opened again in later sessions once the user
+//! allows it.
+//!
+//! Objects of the browser cannot be kept in a [`DatabaseSource`](super::DatabaseSource), so
+//! sources only remember names and look up their files on every access. Which names are allowed,
+//! and when the user is asked for access, is decided here, apart from the browser.
+
+use anyhow::{Result, bail};
+
+use crate::exchange::StoredDatabase;
+
+#[cfg(target_arch = "wasm32")]
+mod browser;
+
+#[cfg(target_arch = "wasm32")]
+pub use browser::{
+    FileHandleDatabaseSource, StoredDatabaseSource, link_database_file, list_stored_databases,
+    remove_stored_database, store_database,
+};
+
+/// The steps of getting access to a database file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Permission {
+    /// ask whether access was allowed before
+    Query,
+
+    /// ask the user to allow access
+    Request,
+
+    Granted,
+    Denied,
+}
+
+impl Permission {
+    /// Continue after the browser answered a step with the state of the permission, or `None` if
+    /// it does not check permissions
+    fn answered(self, state: Option<&str>) -> Self {
+        match (self, state) {
+            (Self::Query | Self::Request, None | Some("granted")) => Self::Granted,
+            (Self::Query, Some(_)) => Self::Request,
+            (Self::Request, Some(_)) => Self::Denied,
+            (done, _) => done,
+        }
+    }
+}
+
+/// Check that a database is stored at the top level, where databases are listed
+fn database_name(name: &str) -> Result<&str> {
+    match segments(name)?[..] {
+        [segment] if segment == name => Ok(name),
+        _ => bail!("{:?} is not the name of a stored database", name),
+    }
+}
+
+/// Make sure that storing a new database does not replace one that is stored already
+fn ensure_unused(name: &str, stored: &[String]) -> Result<()> {
+    if stored.iter().any(|stored| stored == name) {
+        bail!("A database named {} is already stored in the browser", name);
+    }
+
+    Ok(())
+}
+
+/// Whether a stored file is a database, rather than a KeeShare container in a directory
+fn is_database_path(path: &str) -> bool {
+    !path.contains('/')
+}
+
+/// Describe the copies of databases in the storage of the browser, by their names, and the
+/// database files it remembers, by their IDs and names
+fn stored_databases(copies: Vec<String>, linked: Vec<(String, String)>) -> Vec<StoredDatabase> {
+    let mut databases = copies
+        .into_iter()
+        .map(|name| StoredDatabase {
+            id: name.clone(),
+            name,
+            linked: false,
+        })
+        .chain(linked.into_iter().map(|(id, name)| StoredDatabase {
+            id,
+            name,
+            linked: true,
+        }))
+        .collect::<Vec<_>>();
+
+    databases.sort_by(|a, b| a.name.cmp(&b.name).then(a.linked.cmp(&b.linked)));
+
+    databases
+}
+
+/// Split a path relative to the stored databases into its names, which must not leave the
+/// storage of the browser
+fn segments(path: &str) -> Result<Vec<&str>> {
+    let segments = path
+        .split(['/', '\\'])
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect::<Vec<_>>();
+
+    if segments.is_empty() || segments.contains(&"..") {
+        bail!("{:?} is not a path in the storage of the browser", path);
+    }
+
+    Ok(segments)
+}
+
+/// The key of a file in IndexedDB, which is its normalized path
+fn idb_key(path: &str) -> Result<String> {
+    Ok(segments(path)?.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission() {
+        // browsers without permission checks allow access right away
+        assert_eq!(Permission::Query.answered(None), Permission::Granted);
+
+        // access allowed in this session is not asked for again
+        assert_eq!(
+            Permission::Query.answered(Some("granted")),
+            Permission::Granted
+        );
+
+        // otherwise the user is asked, once
+        let permission = Permission::Query.answered(Some("prompt"));
+        assert_eq!(permission, Permission::Request);
+        assert_eq!(permission.answered(Some("granted")), Permission::Granted);
+        assert_eq!(permission.answered(Some("denied")), Permission::Denied);
+        assert_eq!(permission.answered(Some("")), Permission::Denied);
+
+        assert_eq!(
+            Permission::Denied.answered(Some("granted")),
+            Permission::Denied
+        );
+    }
+
+    #[test]
+    fn test_database_name() {
+        assert_eq!(database_name("Passwords.kdbx").unwrap(), "Passwords.kdbx");
+
+        for name in [
+            "",
+            "/",
+            "..",
+            "shares/team.kdbx",
+            "/Passwords.kdbx",
+            "./Passwords.kdbx",
+        ] {
+            assert!(database_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_ensure_unused() {
+        let stored = vec!["Passwords.kdbx".to_string()];
+
+        assert!(ensure_unused("Passwords.kdbx", &stored).is_err());
+        assert!(ensure_unused("passwords.kdbx", &stored).is_ok());
+        assert!(ensure_unused("Work.kdbx", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(idb_key("shares/team.kdbx").unwrap(), "shares/team.kdbx");
+        assert_eq!(idb_key("./shares//team.kdbx").unwrap(), "shares/team.kdbx");
+        assert_eq!(idb_key("shares\\team.kdbx").unwrap(), "shares/team.kdbx");
+
+        assert!(idb_key("../team.kdbx").is_err());
+        assert!(idb_key("shares/../../team.kdbx").is_err());
+        assert!(idb_key("").is_err());
+
+        assert!(is_database_path("Passwords.kdbx"));
+        assert!(!is_database_path(&idb_key("shares/team.kdbx").unwrap()));
+    }
+
+    #[test]
+    fn test_stored_databases() {
+        let databases = stored_databases(
+            vec!["Work.kdbx".to_string(), "Home.kdbx".to_string()],
+            vec![
+                ("1234".to_string(), "Work.kdbx".to_string()),
+                ("5678".to_string(), "Archive.kdbx".to_string()),
+            ],
+        );
+
+        // sorted by name, with the copy of a database before a file of the same name
+        let listed = databases
+            .iter()
+            .map(|database| (database.id.as_str(), database.linked))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            [
+                ("5678", true),
+                ("Home.kdbx", false),
+                ("Work.kdbx", false),
+                ("1234", true),
+            ]
+        );
+
+        // the records are handed to the frontend and back to load or remove a database
+        let json = serde_json::to_value(&databases[0]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"id": "5678", "name": "Archive.kdbx", "linked": true})
+        );
+
+        let database: StoredDatabase = serde_json::from_value(json).unwrap();
+        assert_eq!(database.id, "5678");
+        assert!(database.linked);
+    }
+}
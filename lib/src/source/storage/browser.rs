@@ -0,0 +1,650 @@
+//! Access to the Origin Private File System, IndexedDB and the File System Access API
+
+use anyhow::{Result, anyhow, bail};
+use js_sys::{Promise, Uint8Array};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemHandle, FileSystemHandleKind,
+    IdbDatabase, IdbRequest, IdbTransaction, IdbTransactionMode,
+};
+
+use super::{Permission, database_name, ensure_unused, idb_key, is_database_path, segments};
+use crate::exchange::StoredDatabase;
+use crate::source::{DatabaseSource, SourceFuture};
+
+/// Directory of the Origin Private File System that copies of databases are stored in
+const DIRECTORY: &str = "databases";
+
+/// Name of the IndexedDB database
+const IDB_NAME: &str = "omnikee";
+
+/// Object store of the copies of databases by their path, if they are kept in IndexedDB
+const FILES: &str = "files";
+
+/// Object store of the File System Access handles of database files by their ID
+const HANDLES: &str = "handles";
+
+/// A copy of a database in the storage of the browser (for web deploys)
+pub struct StoredDatabaseSource {
+    name: String,
+}
+
+/// A database file on disk that the browser was given access to (for web deploys)
+pub struct FileHandleDatabaseSource {
+    id: String,
+    name: String,
+}
+
+/// Where copies of databases are stored
+enum Storage {
+    Opfs(FileSystemDirectoryHandle),
+    IndexedDb(IdbDatabase),
+}
+
+impl StoredDatabaseSource {
+    /// Create a source for a copy of a database that is already stored
+    pub fn new(name: &str) -> Result<Self> {
+        Ok(Self {
+            name: database_name(name)?.to_string(),
+        })
+    }
+}
+
+impl FileHandleDatabaseSource {
+    /// Create a source for a database file that the browser remembers
+    pub fn new(id: &str, name: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Look up the handle of the file and make sure that it may be read and written
+    async fn handle(&self) -> Result<FileSystemFileHandle> {
+        let database = open_idb().await?;
+
+        let transaction = database
+            .transaction_with_str_and_mode(HANDLES, IdbTransactionMode::Readonly)
+            .map_err(js_error)?;
+        let handle = transaction
+            .object_store(HANDLES)
+            .and_then(|store| store.get(&JsValue::from_str(&self.id)))
+            .map_err(js_error)?;
+
+        let handle = request(&handle).await?;
+        if handle.is_undefined() {
+            bail!(
+                "The browser does not remember the file {} anymore",
+                self.name
+            );
+        }
+
+        let handle: FileSystemFileHandle = handle.unchecked_into();
+        allow_writing(&handle).await?;
+
+        Ok(handle)
+    }
+}
+
+impl DatabaseSource for StoredDatabaseSource {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            Storage::open()
+                .await?
+                .read(&self.name)
+                .await?
+                .ok_or(anyhow!("The database is not stored in the browser anymore"))
+        })
+    }
+
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move { Storage::open().await?.write(&self.name, data).await })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_sibling<'a>(&'a self, path: &'a str) -> SourceFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Storage::open().await?.read(path).await })
+    }
+
+    fn write_sibling<'a>(&'a mut self, path: &'a str, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move { Storage::open().await?.write(path, data).await })
+    }
+}
+
+impl DatabaseSource for FileHandleDatabaseSource {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let handle = self.handle().await?;
+            read_file(&handle).await
+        })
+    }
+
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            let handle = self.handle().await?;
+            write_file(&handle, data).await
+        })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// List the copies of databases in the storage of the browser and the database files it
+/// remembers
+pub async fn list_stored_databases() -> Result<Vec<StoredDatabase>> {
+    let copies = Storage::open().await?.list().await?;
+
+    let (ids, handles) = list_handles(&open_idb().await?).await?;
+    let linked = ids
+        .into_iter()
+        .zip(handles.iter().map(|handle| handle.name()))
+        .collect();
+
+    Ok(super::stored_databases(copies, linked))
+}
+
+/// Keep a copy of a new database in the storage of the browser
+pub async fn store_database(name: &str, data: &[u8]) -> Result<StoredDatabase> {
+    let name = database_name(name)?;
+    let storage = Storage::open().await?;
+
+    ensure_unused(name, &storage.list().await?)?;
+
+    storage.write(name, data).await?;
+    persist().await;
+
+    Ok(StoredDatabase {
+        id: name.to_string(),
+        name: name.to_string(),
+        linked: false,
+    })
+}
+
+/// Remember a database file that the user picked, to read and write it in place
+pub async fn link_database_file(handle: FileSystemFileHandle) -> Result<StoredDatabase> {
+    let database = open_idb().await?;
+
+    let (ids, handles) = list_handles(&database).await?;
+    for (id, known) in ids.into_iter().zip(handles) {
+        if wait(handle.is_same_entry(&known)).await?.is_truthy() {
+            return Ok(StoredDatabase {
+                id,
+                name: handle.name(),
+                linked: true,
+            });
+        }
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+
+    let transaction = database
+        .transaction_with_str_and_mode(HANDLES, IdbTransactionMode::Readwrite)
+        .map_err(js_error)?;
+    let committed = committed(&transaction);
+
+    transaction
+        .object_store(HANDLES)
+        .and_then(|store| store.put_with_key(&handle, &JsValue::from_str(&id)))
+        .map_err(js_error)?;
+
+    committed
+        .await
+        .map_err(|_| anyhow!("Remembering the database file failed"))?;
+    persist().await;
+
+    Ok(StoredDatabase {
+        id,
+        name: handle.name(),
+        linked: true,
+    })
+}
+
+/// Delete a copy of a database from the storage of the browser, or forget a database file
+///
+/// The KeeShare containers of a copy are kept, as they may be shared with other databases.
+pub async fn remove_stored_database(database: &StoredDatabase) -> Result<()> {
+    if !database.linked {
+        let name = database_name(&database.id)?;
+        return Storage::open().await?.remove(name).await;
+    }
+
+    let transaction = open_idb()
+        .await?
+        .transaction_with_str_and_mode(HANDLES, IdbTransactionMode::Readwrite)
+        .map_err(js_error)?;
+    let committed = committed(&transaction);
+
+    transaction
+        .object_store(HANDLES)
+        .and_then(|store| store.delete(&JsValue::from_str(&database.id)))
+        .map_err(js_error)?;
+
+    committed
+        .await
+        .map_err(|_| anyhow!("Forgetting the database file failed"))?;
+
+    Ok(())
+}
+
+impl Storage {
+    /// Open the storage of the browser, preferring the Origin Private File System
+    async fn open() -> Result<Self> {
+        match opfs_directory().await {
+            Some(directory) => Ok(Self::Opfs(directory)),
+            None => Ok(Self::IndexedDb(open_idb().await?)),
+        }
+    }
+
+    /// Read a file, or `None` if it does not exist
+    async fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Opfs(directory) => match opfs_file(directory, path, false).await? {
+                Some(handle) => Ok(Some(read_file(&handle).await?)),
+                None => Ok(None),
+            },
+            Self::IndexedDb(database) => {
+                let key = JsValue::from_str(&idb_key(path)?);
+
+                let transaction = database
+                    .transaction_with_str_and_mode(FILES, IdbTransactionMode::Readonly)
+                    .map_err(js_error)?;
+                let data = transaction
+                    .object_store(FILES)
+                    .and_then(|store| store.get(&key))
+                    .map_err(js_error)?;
+
+                let data = request(&data).await?;
+                if data.is_undefined() {
+                    return Ok(None);
+                }
+
+                Ok(Some(Uint8Array::new(&data).to_vec()))
+            }
+        }
+    }
+
+    /// Write a file, replacing any previous contents
+    async fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Opfs(directory) => {
+                let handle = opfs_file(directory, path, true)
+                    .await?
+                    .ok_or(anyhow!("Creating {} failed", path))?;
+
+                write_file(&handle, data).await
+            }
+            Self::IndexedDb(database) => {
+                let key = JsValue::from_str(&idb_key(path)?);
+
+                let transaction = database
+                    .transaction_with_str_and_mode(FILES, IdbTransactionMode::Readwrite)
+                    .map_err(js_error)?;
+                let committed = committed(&transaction);
+
+                transaction
+                    .object_store(FILES)
+                    .and_then(|store| store.put_with_key(&Uint8Array::from(data), &key))
+                    .map_err(js_error)?;
+
+                committed
+                    .await
+                    .map_err(|_| anyhow!("Writing {} to IndexedDB failed", path))?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Delete a file if it exists
+    async fn remove(&self, path: &str) -> Result<()> {
+        match self {
+            Self::Opfs(directory) => {
+                let segments = segments(path)?;
+                let (name, parents) = segments.split_last().expect("paths are not empty");
+
+                let mut directory = directory.clone();
+                for parent in parents {
+                    match optional(directory.get_directory_handle(parent)).await? {
+                        Some(handle) => directory = handle.unchecked_into(),
+                        None => return Ok(()),
+                    }
+                }
+
+                optional(directory.remove_entry(name)).await?;
+
+                Ok(())
+            }
+            Self::IndexedDb(database) => {
+                let key = JsValue::from_str(&idb_key(path)?);
+
+                let transaction = database
+                    .transaction_with_str_and_mode(FILES, IdbTransactionMode::Readwrite)
+                    .map_err(js_error)?;
+                let committed = committed(&transaction);
+
+                transaction
+                    .object_store(FILES)
+                    .and_then(|store| store.delete(&key))
+                    .map_err(js_error)?;
+
+                committed
+                    .await
+                    .map_err(|_| anyhow!("Deleting {} from IndexedDB failed", path))?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// List the names of the files at the top level, which are the databases
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        match self {
+            Self::Opfs(directory) => {
+                let entries = directory.values();
+
+                loop {
+                    let next = wait(entries.next().map_err(js_error)?).await?;
+                    let next: js_sys::IteratorNext = next.unchecked_into();
+
+                    if next.done() {
+                        break;
+                    }
+
+                    let handle: FileSystemHandle = next.value().unchecked_into();
+                    if handle.kind() == FileSystemHandleKind::File {
+                        names.push(handle.name());
+                    }
+                }
+            }
+            Self::IndexedDb(database) => {
+                let transaction = database
+                    .transaction_with_str_and_mode(FILES, IdbTransactionMode::Readonly)
+                    .map_err(js_error)?;
+                let keys = transaction
+                    .object_store(FILES)
+                    .and_then(|store| store.get_all_keys())
+                    .map_err(js_error)?;
+
+                let keys: js_sys::Array = request(&keys).await?.unchecked_into();
+                names.extend(
+                    keys.iter()
+                        .filter_map(|key| key.as_string())
+                        .filter(|key| is_database_path(key)),
+                );
+            }
+        }
+
+        Ok(names)
+    }
+}
+
+/// Open the directory of databases in the Origin Private File System, if the browser can write
+/// files there
+async fn opfs_directory() -> Option<FileSystemDirectoryHandle> {
+    // some browsers offered the Origin Private File System long before writing to it from pages
+    let writable = js_sys::Reflect::get(&js_sys::global(), &"FileSystemFileHandle".into())
+        .and_then(|class| js_sys::Reflect::get(&class, &"prototype".into()))
+        .and_then(|prototype| js_sys::Reflect::has(&prototype, &"createWritable".into()))
+        .unwrap_or(false);
+
+    if !writable {
+        return None;
+    }
+
+    let storage = web_sys::window()?.navigator().storage();
+    let root: FileSystemDirectoryHandle = JsFuture::from(storage.get_directory())
+        .await
+        .ok()?
+        .dyn_into()
+        .ok()?;
+
+    let options = web_sys::FileSystemGetDirectoryOptions::new();
+    options.set_create(true);
+
+    JsFuture::from(root.get_directory_handle_with_options(DIRECTORY, &options))
+        .await
+        .ok()?
+        .dyn_into()
+        .ok()
+}
+
+/// Get a file in the Origin Private File System, or `None` if it does not exist and is not
+/// created
+async fn opfs_file(
+    directory: &FileSystemDirectoryHandle,
+    path: &str,
+    create: bool,
+) -> Result<Option<FileSystemFileHandle>> {
+    let segments = segments(path)?;
+    let (name, parents) = segments.split_last().expect("paths are not empty");
+
+    let directory_options = web_sys::FileSystemGetDirectoryOptions::new();
+    directory_options.set_create(create);
+
+    let mut directory = directory.clone();
+    for parent in parents {
+        let handle = directory.get_directory_handle_with_options(parent, &directory_options);
+
+        match optional(handle).await? {
+            Some(handle) => directory = handle.unchecked_into(),
+            None => return Ok(None),
+        }
+    }
+
+    let file_options = web_sys::FileSystemGetFileOptions::new();
+    file_options.set_create(create);
+
+    let handle = optional(directory.get_file_handle_with_options(name, &file_options)).await?;
+
+    Ok(handle.map(|handle| handle.unchecked_into()))
+}
+
+/// Read a whole file
+async fn read_file(handle: &FileSystemFileHandle) -> Result<Vec<u8>> {
+    let file: web_sys::File = wait(handle.get_file()).await?.unchecked_into();
+    let data = wait(file.array_buffer()).await?;
+
+    Ok(Uint8Array::new(&data).to_vec())
+}
+
+/// Replace the contents of a file
+///
+/// The browser writes into a temporary file that only replaces the file when it is closed, so an
+/// interrupted write leaves the previous contents behind.
+async fn write_file(handle: &FileSystemFileHandle, data: &[u8]) -> Result<()> {
+    let stream: web_sys::FileSystemWritableFileStream =
+        wait(handle.create_writable()).await?.unchecked_into();
+
+    // copy the data, as the browser may write it after the memory of a view was reused
+    let written = match stream.write_with_buffer_source(&Uint8Array::from(data)) {
+        Ok(promise) => wait(promise).await,
+        Err(e) => Err(js_error(e)),
+    };
+
+    if let Err(e) = written {
+        let _ = wait(stream.abort()).await;
+        return Err(e);
+    }
+
+    wait(stream.close()).await?;
+
+    Ok(())
+}
+
+/// Make sure that a file on disk may be read and written, asking the user if needed
+///
+/// Browsers forget permissions between sessions, and can only ask the user right after an
+/// interaction like a click. Browsers without these permission checks always allow access.
+async fn allow_writing(handle: &FileSystemFileHandle) -> Result<()> {
+    let descriptor = js_sys::Object::new();
+    js_sys::Reflect::set(&descriptor, &"mode".into(), &"readwrite".into()).map_err(js_error)?;
+
+    let mut permission = Permission::Query;
+
+    loop {
+        let method = match permission {
+            Permission::Query => "queryPermission",
+            Permission::Request => "requestPermission",
+            Permission::Granted => return Ok(()),
+            Permission::Denied => bail!("Access to the file {} was not allowed", handle.name()),
+        };
+
+        let state = match js_sys::Reflect::get(handle, &method.into())
+            .and_then(|function| function.dyn_into::<js_sys::Function>())
+        {
+            Ok(function) => {
+                let state = function.call1(handle, &descriptor).map_err(js_error)?;
+                Some(
+                    wait(Promise::resolve(&state))
+                        .await?
+                        .as_string()
+                        .unwrap_or_default(),
+                )
+            }
+            Err(_) => None,
+        };
+
+        permission = permission.answered(state.as_deref());
+    }
+}
+
+/// Ask the browser not to evict stored databases when it runs low on space
+///
+/// Browsers may decline this silently, in which case databases are only kept as long as space
+/// allows.
+async fn persist() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+
+    if let Ok(promise) = window.navigator().storage().persist() {
+        let _ = JsFuture::from(promise).await;
+    }
+}
+
+/// Open the IndexedDB database, creating its object stores on first use
+async fn open_idb() -> Result<IdbDatabase> {
+    let factory = web_sys::window()
+        .ok_or(anyhow!("Storing databases needs a browser window"))?
+        .indexed_db()
+        .map_err(js_error)?
+        .ok_or(anyhow!("The browser does not allow storing databases"))?;
+
+    let open = factory.open_with_u32(IDB_NAME, 1).map_err(js_error)?;
+
+    let upgrade = {
+        let open = open.clone();
+
+        Closure::<dyn FnMut()>::new(move || {
+            let Ok(database) = open.result() else {
+                return;
+            };
+
+            let database: IdbDatabase = database.unchecked_into();
+            for store in [FILES, HANDLES] {
+                if !database.object_store_names().contains(store) {
+                    let _ = database.create_object_store(store);
+                }
+            }
+        })
+    };
+
+    open.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+    let database = request(&open).await;
+    open.set_onupgradeneeded(None);
+
+    Ok(database?.unchecked_into())
+}
+
+/// List the IDs and handles of the database files that the browser remembers
+async fn list_handles(database: &IdbDatabase) -> Result<(Vec<String>, Vec<FileSystemFileHandle>)> {
+    let transaction = database
+        .transaction_with_str_and_mode(HANDLES, IdbTransactionMode::Readonly)
+        .map_err(js_error)?;
+    let store = transaction.object_store(HANDLES).map_err(js_error)?;
+
+    // both are ordered by key
+    let keys = store.get_all_keys().map_err(js_error)?;
+    let values = store.get_all().map_err(js_error)?;
+
+    let keys: js_sys::Array = request(&keys).await?.unchecked_into();
+    let values: js_sys::Array = request(&values).await?.unchecked_into();
+
+    Ok((
+        keys.iter().filter_map(|key| key.as_string()).collect(),
+        values.iter().map(|value| value.unchecked_into()).collect(),
+    ))
+}
+
+/// Wait for an IndexedDB request to succeed, returning its result
+async fn request(request: &IdbRequest) -> Result<JsValue> {
+    let done = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+
+    if JsFuture::from(done).await.is_err() {
+        return Err(match request.error() {
+            Ok(Some(e)) => anyhow!("{}", e.message()),
+            _ => anyhow!("Accessing IndexedDB failed"),
+        });
+    }
+
+    request.result().map_err(js_error)
+}
+
+/// Wait for an IndexedDB transaction to be committed
+///
+/// This has to be called before making requests, so that the transaction cannot complete before
+/// it is waited for.
+fn committed(transaction: &IdbTransaction) -> JsFuture {
+    JsFuture::from(Promise::new(&mut |resolve, reject| {
+        transaction.set_oncomplete(Some(&resolve));
+        transaction.set_onerror(Some(&reject));
+        transaction.set_onabort(Some(&reject));
+    }))
+}
+
+/// Wait for a promise
+async fn wait(promise: Promise) -> Result<JsValue> {
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+/// Wait for a promise that fails if a file does not exist, returning `None` in that case
+async fn optional(promise: Promise) -> Result<Option<JsValue>> {
+    match JsFuture::from(promise).await {
+        Ok(value) => Ok(Some(value)),
+        Err(e)
+            if e.dyn_ref::<web_sys::DomException>()
+                .is_some_and(|e| e.name() == "NotFoundError") =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(js_error(e)),
+    }
+}
+
+/// Describe an error thrown by the browser
+fn js_error(e: JsValue) -> anyhow::Error {
+    match e.dyn_ref::<js_sys::Error>() {
+        Some(e) => anyhow!("{}", String::from(e.message())),
+        None => anyhow!("{:?}", e),
+    }
+}
@@ -0,0 +1,824 @@
+//! Databases stored on a WebDAV server, like Nextcloud
+//!
+//! The database is downloaded with GET and uploaded with PUT. The ETag of the last known version
+//! is sent along as `If-Match` when saving, so that the server rejects the upload instead of
+//! overwriting changes that someone else made in the meantime. Saving a database that was not
+//! downloaded sends `If-None-Match: *` instead, and saving is refused if the server did not send
+//! an ETag along with the database. Basic and digest authentication are supported, chosen by the
+//! challenge of the server on the first request.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{Result, anyhow, bail};
+use sha2::Digest;
+use url::Url;
+
+use super::http::{HttpClient, HttpRequest, HttpResponse};
+use super::{Credentials, DatabaseSource, SourceFuture};
+
+/// How requests are authenticated, as learned from the server
+enum Auth {
+    /// no challenge received yet
+    None,
+
+    Basic,
+
+    Digest {
+        challenge: DigestChallenge,
+
+        /// the number of requests sent with the current nonce
+        count: u32,
+    },
+}
+
+/// A digest authentication challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: String,
+
+    /// whether the server offers the `auth` quality of protection
+    qop_auth: bool,
+
+    /// whether the challenge only renews an expired nonce
+    stale: bool,
+}
+
+/// The version of the database on the server that was last downloaded or uploaded
+enum Version {
+    /// nothing was downloaded yet, so saving creates the database
+    Unknown,
+
+    /// the server did not send an ETag, so changes by someone else cannot be detected
+    Untagged,
+
+    Tagged(String),
+}
+
+impl From<Option<&str>> for Version {
+    fn from(etag: Option<&str>) -> Self {
+        match etag {
+            Some(etag) => Self::Tagged(etag.to_string()),
+            None => Self::Untagged,
+        }
+    }
+}
+
+/// What an upload may replace on the server
+enum Precondition<'a> {
+    /// only the version with the given ETag
+    Version(&'a str),
+
+    /// nothing, the file must not exist yet
+    Absent,
+
+    /// any file, or none
+    Any,
+}
+
+/// State that changes while talking to the server
+struct Remote {
+    auth: Auth,
+    version: Version,
+}
+
+/// A database stored on a WebDAV server
+pub struct WebDavDatabaseSource {
+    url: Url,
+    name: String,
+    credentials: Option<Credentials>,
+    client: Box<dyn HttpClient>,
+    remote: Mutex<Remote>,
+}
+
+impl WebDavDatabaseSource {
+    pub fn new(
+        url: &str,
+        credentials: Option<Credentials>,
+        client: Box<dyn HttpClient>,
+    ) -> Result<Self> {
+        let url = Url::parse(url)?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("WebDAV databases need an http or https URL");
+        }
+
+        let name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|name| percent_encoding::percent_decode_str(name).decode_utf8_lossy())
+            .filter(|name| !name.is_empty())
+            .ok_or(anyhow!("The URL does not point to a file"))?
+            .to_string();
+
+        Ok(Self {
+            url,
+            name,
+            credentials,
+            client,
+            remote: Mutex::new(Remote {
+                auth: Auth::None,
+                version: Version::Unknown,
+            }),
+        })
+    }
+
+    /// Send a request, answering an authentication challenge of the server if needed
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let mut retried = false;
+        loop {
+            let mut request = request.clone();
+
+            let authorization =
+                self.authorization(&mut self.remote.lock().unwrap().auth, &request)?;
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+
+            let response = self.client.send(&request).await?;
+            if response.status != 401 {
+                return Ok(response);
+            }
+
+            let Some(credentials) = &self.credentials else {
+                bail!("The WebDAV server requires a user name and password");
+            };
+
+            let challenges = response
+                .headers_named("www-authenticate")
+                .flat_map(parse_challenges)
+                .collect::<Vec<_>>();
+
+            let mut remote = self.remote.lock().unwrap();
+
+            let auth = if let Some(challenge) = challenges
+                .iter()
+                .find(|(scheme, _)| scheme == "digest")
+                .map(|(_, params)| DigestChallenge::from_params(params))
+                .transpose()?
+            {
+                // a stale nonce is renewed without the credentials being wrong
+                if retried && !challenge.stale {
+                    bail!(
+                        "The WebDAV server rejected the password of {:?}",
+                        credentials.username
+                    );
+                }
+
+                Auth::Digest {
+                    challenge,
+                    count: 0,
+                }
+            } else if challenges.iter().any(|(scheme, _)| scheme == "basic") {
+                if retried || matches!(remote.auth, Auth::Basic) {
+                    bail!(
+                        "The WebDAV server rejected the password of {:?}",
+                        credentials.username
+                    );
+                }
+
+                Auth::Basic
+            } else {
+                bail!("The WebDAV server asks for an unsupported kind of authentication");
+            };
+
+            remote.auth = auth;
+            retried = true;
+        }
+    }
+
+    /// Get the `Authorization` header for a request, if the server asked for one
+    fn authorization(&self, auth: &mut Auth, request: &HttpRequest) -> Result<Option<String>> {
+        use base64::prelude::*;
+
+        let Some(credentials) = &self.credentials else {
+            return Ok(None);
+        };
+
+        let authorization = match auth {
+            Auth::None => return Ok(None),
+            Auth::Basic => format!(
+                "Basic {}",
+                BASE64_STANDARD
+                    .encode(format!("{}:{}", credentials.username, credentials.password))
+            ),
+            Auth::Digest { challenge, count } => {
+                *count += 1;
+
+                let mut uri = request.url.path().to_string();
+                if let Some(query) = request.url.query() {
+                    uri = format!("{uri}?{query}");
+                }
+
+                let cnonce = uuid::Uuid::new_v4().simple().to_string();
+                challenge.authorization(credentials, request.method, &uri, *count, &cnonce)?
+            }
+        };
+
+        Ok(Some(authorization))
+    }
+
+    /// Download a file, returning `None` if it does not exist
+    async fn get(&self, url: &Url) -> Result<Option<HttpResponse>> {
+        let response = self.send(HttpRequest::new("GET", url.clone())).await?;
+
+        match response.status {
+            404 => Ok(None),
+            _ if response.is_success() => Ok(Some(response)),
+            status => bail!("Downloading {} failed with HTTP status {}", url, status),
+        }
+    }
+
+    /// Upload a file, as long as the server holds what the precondition expects
+    async fn put(
+        &self,
+        url: &Url,
+        data: &[u8],
+        precondition: Precondition<'_>,
+    ) -> Result<HttpResponse> {
+        let mut request = HttpRequest::new("PUT", url.clone())
+            .header("Content-Type", "application/octet-stream")
+            .body(data.to_vec());
+
+        request = match precondition {
+            Precondition::Version(etag) => request.header("If-Match", etag),
+            Precondition::Absent => request.header("If-None-Match", "*"),
+            Precondition::Any => request,
+        };
+
+        let response = self.send(request).await?;
+
+        match response.status {
+            412 if matches!(precondition, Precondition::Absent) => bail!(
+                "The database was created on the server in the meantime. Open it to merge the changes before saving."
+            ),
+            412 => bail!(
+                "The database was changed on the server since it was opened. Reopen it to merge the changes before saving."
+            ),
+            _ if response.is_success() => Ok(response),
+            status => bail!("Uploading {} failed with HTTP status {}", url, status),
+        }
+    }
+}
+
+impl DatabaseSource for WebDavDatabaseSource {
+    fn open(&self) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let response = self
+                .get(&self.url)
+                .await?
+                .ok_or(anyhow!("The database does not exist on the server"))?;
+
+            self.remote.lock().unwrap().version = response.header("etag").into();
+
+            Ok(response.body)
+        })
+    }
+
+    fn save<'a>(&'a mut self, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            let etag = match &self.remote.lock().unwrap().version {
+                Version::Unknown => None,
+                Version::Untagged => bail!(
+                    "The WebDAV server does not report versions of the database, so saving could overwrite changes made by someone else"
+                ),
+                Version::Tagged(etag) => Some(etag.clone()),
+            };
+
+            let precondition = match &etag {
+                Some(etag) => Precondition::Version(etag),
+                None => Precondition::Absent,
+            };
+            let response = self.put(&self.url, data, precondition).await?;
+
+            // not all servers return the new ETag on upload, so ask for it separately
+            let version = match response.header("etag") {
+                Some(etag) => Version::Tagged(etag.to_string()),
+                None => {
+                    let response = self
+                        .send(HttpRequest::new("HEAD", self.url.clone()))
+                        .await?;
+                    response.header("etag").into()
+                }
+            };
+
+            self.remote.lock().unwrap().version = version;
+
+            Ok(())
+        })
+    }
+
+    fn send_saved(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_sibling<'a>(&'a self, path: &'a str) -> SourceFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.get(&self.url.join(path)?).await?.map(|r| r.body)) })
+    }
+
+    fn write_sibling<'a>(&'a mut self, path: &'a str, data: &'a [u8]) -> SourceFuture<'a, ()> {
+        Box::pin(async move {
+            self.put(&self.url.join(path)?, data, Precondition::Any)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+impl DigestChallenge {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self> {
+        let param = |name: &str| {
+            params.get(name).cloned().ok_or(anyhow!(
+                "The digest challenge of the server has no {}",
+                name
+            ))
+        };
+
+        Ok(Self {
+            realm: param("realm")?,
+            nonce: param("nonce")?,
+            opaque: params.get("opaque").cloned(),
+            algorithm: params
+                .get("algorithm")
+                .cloned()
+                .unwrap_or_else(|| "MD5".to_string()),
+            qop_auth: params
+                .get("qop")
+                .is_some_and(|qop| qop.split(',').any(|q| q.trim() == "auth")),
+            stale: params
+                .get("stale")
+                .is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Answer the challenge for a request (RFC 7616)
+    fn authorization(
+        &self,
+        credentials: &Credentials,
+        method: &str,
+        uri: &str,
+        count: u32,
+        cnonce: &str,
+    ) -> Result<String> {
+        let (hash, session): (fn(&str) -> String, bool) =
+            match self.algorithm.to_ascii_uppercase().as_str() {
+                "MD5" => (hex_hash::<md5::Md5>, false),
+                "MD5-SESS" => (hex_hash::<md5::Md5>, true),
+                "SHA-256" => (hex_hash::<sha2::Sha256>, false),
+                "SHA-256-SESS" => (hex_hash::<sha2::Sha256>, true),
+                other => bail!("Unsupported digest algorithm {:?}", other),
+            };
+
+        let nc = format!("{:08x}", count);
+
+        let mut ha1 = hash(&format!(
+            "{}:{}:{}",
+            credentials.username, self.realm, credentials.password
+        ));
+        if session {
+            ha1 = hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+
+        let ha2 = hash(&format!("{}:{}", method, uri));
+
+        let response = if self.qop_auth {
+            hash(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            hash(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut authorization = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(&credentials.username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            self.algorithm,
+            response
+        );
+
+        if self.qop_auth {
+            authorization.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+
+        if let Some(opaque) = &self.opaque {
+            authorization.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+
+        Ok(authorization)
+    }
+}
+
+fn hex_hash<D: Digest>(data: &str) -> String {
+    data_encoding::HEXLOWER.encode(&D::digest(data.as_bytes()))
+}
+
+/// Escape a value for a quoted string in a header
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parse a `WWW-Authenticate` header into its challenges, with lowercase schemes and parameter
+/// names
+fn parse_challenges(header: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges: Vec<(String, HashMap<String, String>)> = Vec::new();
+    let mut rest = header.trim_start();
+
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+            .unwrap_or(rest.len());
+
+        if end == 0 {
+            // stray separator
+            rest = rest[1..].trim_start();
+            continue;
+        }
+
+        let token = rest[..end].to_lowercase();
+        rest = rest[end..].trim_start();
+
+        match (rest.strip_prefix('='), challenges.last_mut()) {
+            (Some(value), Some((_, params))) => {
+                let value = value.trim_start();
+
+                let (value, remaining) = match value.strip_prefix('"') {
+                    Some(quoted) => {
+                        let mut unquoted = String::new();
+                        let mut end = quoted.len();
+                        let mut chars = quoted.char_indices();
+
+                        while let Some((i, c)) = chars.next() {
+                            match c {
+                                '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                                '"' => {
+                                    end = i + 1;
+                                    break;
+                                }
+                                c => unquoted.push(c),
+                            }
+                        }
+
+                        (unquoted, &quoted[end..])
+                    }
+                    None => {
+                        let end = value.find(',').unwrap_or(value.len());
+                        (value[..end].trim().to_string(), &value[end..])
+                    }
+                };
+
+                params.insert(token, value);
+                rest = remaining;
+            }
+            _ => challenges.push((token, HashMap::new())),
+        }
+
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    challenges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::source::block_on;
+
+    /// A stand-in for a WebDAV server holding a single file
+    #[derive(Default)]
+    struct Server {
+        data: Vec<u8>,
+        version: u32,
+
+        /// the credentials that the server requires, and whether it asks for digest authentication
+        login: Option<(Credentials, bool)>,
+
+        /// whether the server leaves out ETags
+        untagged: bool,
+
+        requests: Vec<HttpRequest>,
+    }
+
+    #[derive(Clone, Default)]
+    struct StubClient(Arc<Mutex<Server>>);
+
+    impl StubClient {
+        fn with_file(data: &[u8]) -> Self {
+            let client = Self::default();
+            {
+                let mut server = client.0.lock().unwrap();
+                server.data = data.to_vec();
+                server.version = 1;
+            }
+            client
+        }
+
+        fn etag(server: &Server) -> String {
+            format!("\"{}\"", server.version)
+        }
+
+        /// Answer a request like the server would
+        fn answer(&self, request: &HttpRequest) -> Result<HttpResponse> {
+            let mut server = self.0.lock().unwrap();
+            server.requests.push(request.clone());
+
+            let header = |name: &str| {
+                request
+                    .headers
+                    .iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.clone())
+            };
+
+            if let Some((credentials, digest)) = &server.login {
+                let challenge = DigestChallenge {
+                    realm: "files".to_string(),
+                    nonce: "abc".to_string(),
+                    opaque: None,
+                    algorithm: "SHA-256".to_string(),
+                    qop_auth: false,
+                    stale: false,
+                };
+
+                let expected = if *digest {
+                    challenge.authorization(
+                        credentials,
+                        request.method,
+                        request.url.path(),
+                        1,
+                        "",
+                    )?
+                } else {
+                    use base64::prelude::*;
+                    format!(
+                        "Basic {}",
+                        BASE64_STANDARD
+                            .encode(format!("{}:{}", credentials.username, credentials.password))
+                    )
+                };
+
+                if header("Authorization") != Some(expected) {
+                    let scheme = if *digest {
+                        r#"Digest realm="files", nonce="abc", algorithm=SHA-256"#
+                    } else {
+                        r#"Basic realm="files""#
+                    };
+
+                    return Ok(HttpResponse {
+                        status: 401,
+                        headers: vec![("www-authenticate".to_string(), scheme.to_string())],
+                        body: Vec::new(),
+                    });
+                }
+            }
+
+            let etag = if server.untagged {
+                Vec::new()
+            } else {
+                vec![("etag".to_string(), Self::etag(&server))]
+            };
+
+            let response = match request.method {
+                "GET" if server.version == 0 => HttpResponse {
+                    status: 404,
+                    ..Default::default()
+                },
+                "GET" => HttpResponse {
+                    status: 200,
+                    headers: etag,
+                    body: server.data.clone(),
+                },
+                "HEAD" => HttpResponse {
+                    status: 200,
+                    headers: etag,
+                    body: Vec::new(),
+                },
+                "PUT" => {
+                    let exists = server.version != 0;
+                    if header("If-Match").is_some_and(|e| e != Self::etag(&server))
+                        || header("If-None-Match").is_some_and(|e| e == "*" && exists)
+                    {
+                        HttpResponse {
+                            status: 412,
+                            ..Default::default()
+                        }
+                    } else {
+                        server.data = request.body.clone().unwrap_or_default();
+                        server.version += 1;
+
+                        HttpResponse {
+                            status: 204,
+                            ..Default::default()
+                        }
+                    }
+                }
+                _ => HttpResponse {
+                    status: 405,
+                    ..Default::default()
+                },
+            };
+
+            Ok(response)
+        }
+    }
+
+    impl HttpClient for StubClient {
+        fn send<'a>(&'a self, request: &'a HttpRequest) -> SourceFuture<'a, HttpResponse> {
+            Box::pin(async move { self.answer(request) })
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    fn read(source: &WebDavDatabaseSource) -> Vec<u8> {
+        block_on(source.open()).unwrap()
+    }
+
+    fn write(source: &mut WebDavDatabaseSource, data: &[u8]) -> Result<()> {
+        block_on(source.save(data))
+    }
+
+    #[test]
+    fn test_open_and_save() {
+        let client = StubClient::with_file(b"first");
+        let url = "https://cloud.example.com/remote.php/dav/files/alice/My%20Vault.kdbx";
+        let mut source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+        assert_eq!(source.get_name(), "My Vault.kdbx");
+
+        assert_eq!(read(&source), b"first");
+
+        write(&mut source, b"second").unwrap();
+        write(&mut source, b"third").unwrap();
+
+        let server = client.0.lock().unwrap();
+        assert_eq!(server.data, b"third");
+
+        let if_match = server
+            .requests
+            .iter()
+            .filter(|r| r.method == "PUT")
+            .map(|r| {
+                r.headers
+                    .iter()
+                    .find(|(n, _)| n == "If-Match")
+                    .unwrap()
+                    .1
+                    .as_str()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(if_match, ["\"1\"", "\"2\""]);
+    }
+
+    #[test]
+    fn test_conflict() {
+        let client = StubClient::with_file(b"first");
+        let url = "http://localhost:8080/vault.kdbx";
+        let mut source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+        read(&source);
+
+        // someone else saves in the meantime
+        {
+            let mut server = client.0.lock().unwrap();
+            server.data = b"theirs".to_vec();
+            server.version += 1;
+        }
+
+        assert!(write(&mut source, b"ours").is_err());
+        assert_eq!(client.0.lock().unwrap().data, b"theirs");
+
+        // after reopening, saving works again
+        assert_eq!(read(&source), b"theirs");
+        write(&mut source, b"ours").unwrap();
+        assert_eq!(client.0.lock().unwrap().data, b"ours");
+    }
+
+    #[test]
+    fn test_create() {
+        let client = StubClient::default();
+        let url = "http://localhost:8080/vault.kdbx";
+        let mut source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+
+        write(&mut source, b"new").unwrap();
+        {
+            let server = client.0.lock().unwrap();
+            assert_eq!(server.data, b"new");
+
+            let put = &server.requests[0];
+            assert!(
+                put.headers
+                    .contains(&("If-None-Match".to_string(), "*".to_string()))
+            );
+        }
+
+        // another source that did not download the database must not replace it
+        let mut other = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+        assert!(write(&mut other, b"other").is_err());
+        assert_eq!(client.0.lock().unwrap().data, b"new");
+
+        write(&mut source, b"newer").unwrap();
+        assert_eq!(client.0.lock().unwrap().data, b"newer");
+    }
+
+    #[test]
+    fn test_untagged() {
+        let client = StubClient::with_file(b"first");
+        client.0.lock().unwrap().untagged = true;
+
+        let url = "http://localhost:8080/vault.kdbx";
+        let mut source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+        assert_eq!(read(&source), b"first");
+
+        assert!(write(&mut source, b"second").is_err());
+        assert_eq!(client.0.lock().unwrap().data, b"first");
+    }
+
+    #[test]
+    fn test_authentication() {
+        for digest in [false, true] {
+            let client = StubClient::with_file(b"vault");
+            client.0.lock().unwrap().login = Some((credentials(), digest));
+
+            let url = "http://localhost:8080/vault.kdbx";
+
+            let source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+            assert!(block_on(source.open()).is_err());
+
+            let wrong = Credentials {
+                password: "wrong".to_string(),
+                ..credentials()
+            };
+            let source =
+                WebDavDatabaseSource::new(url, Some(wrong), Box::new(client.clone())).unwrap();
+            assert!(block_on(source.open()).is_err());
+
+            let mut source =
+                WebDavDatabaseSource::new(url, Some(credentials()), Box::new(client.clone()))
+                    .unwrap();
+
+            client.0.lock().unwrap().requests.clear();
+            assert_eq!(read(&source), b"vault");
+            assert_eq!(client.0.lock().unwrap().requests.len(), 2);
+
+            // later requests are authenticated right away, uploading and asking for the ETag
+            client.0.lock().unwrap().requests.clear();
+            write(&mut source, b"changed").unwrap();
+            assert_eq!(client.0.lock().unwrap().requests.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_siblings() {
+        let client = StubClient::with_file(b"vault");
+        let url = "http://localhost:8080/vaults/vault.kdbx";
+        let mut source = WebDavDatabaseSource::new(url, None, Box::new(client.clone())).unwrap();
+
+        block_on(source.write_sibling("shared.kdbx", b"shared")).unwrap();
+        let server = client.0.lock().unwrap();
+        let put = server.requests.last().unwrap();
+        assert_eq!(put.url.as_str(), "http://localhost:8080/vaults/shared.kdbx");
+        assert!(!put.headers.iter().any(|(n, _)| n == "If-Match"));
+    }
+
+    #[test]
+    fn test_digest_authorization() {
+        // the example of RFC 2617, section 3.5
+        let challenge = DigestChallenge::from_params(
+            &parse_challenges(
+                r#"Basic realm="other", Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+            )[1]
+            .1,
+        )
+        .unwrap();
+
+        let credentials = Credentials {
+            username: "Mufasa".to_string(),
+            password: "Circle Of Life".to_string(),
+        };
+
+        let authorization = challenge
+            .authorization(&credentials, "GET", "/dir/index.html", 1, "0a4f113b")
+            .unwrap();
+
+        assert_eq!(
+            authorization,
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", qop=auth, nc=00000001, cnonce="0a4f113b", opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! PuTTY private key files (`.ppk`), versions 2 and 3
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use cbc::cipher::block_padding::NoPadding;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use ssh_encoding::Decode;
+use ssh_key::PrivateKey;
+use ssh_key::private::KeypairData;
+
+use super::{read_string, write_string};
+
+/// Parse a PuTTY key file, decrypting it with a passphrase if necessary
+pub(super) fn parse(text: &str, passphrase: &str) -> Result<PrivateKey> {
+    let mut lines = text.lines().map(str::trim);
+
+    let mut headers: Vec<(&str, &str)> = Vec::new();
+    let mut public = Vec::new();
+    let mut private = Vec::new();
+
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once(": ")
+            .ok_or_else(|| anyhow!("Malformed PuTTY key file"))?;
+
+        match name {
+            "Public-Lines" | "Private-Lines" => {
+                let count: usize = value.parse().context("Invalid line count")?;
+                let data: String = (&mut lines).take(count).collect();
+                let data = BASE64.decode(data).context("Invalid key data")?;
+
+                match name {
+                    "Public-Lines" => public = data,
+                    _ => private = data,
+                }
+            }
+            _ => headers.push((name, value)),
+        }
+    }
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    };
+
+    let (version, algorithm) = headers
+        .first()
+        .and_then(|(name, value)| Some((name.strip_prefix("PuTTY-User-Key-File-")?, *value)))
+        .ok_or_else(|| anyhow!("Not a PuTTY key file"))?;
+
+    let encryption = header("Encryption").unwrap_or("none");
+    let comment = header("Comment").unwrap_or_default();
+    let expected_mac = header("Private-MAC").ok_or_else(|| anyhow!("The key has no MAC"))?;
+
+    let encrypted = match encryption {
+        "none" => false,
+        "aes256-cbc" => true,
+        _ => bail!("Unsupported key encryption {}", encryption),
+    };
+
+    let mac = match version {
+        "2" => {
+            if encrypted {
+                let mut key = Vec::with_capacity(40);
+                for counter in [0u32, 1] {
+                    let mut hash = Sha1::new();
+                    hash.update(counter.to_be_bytes());
+                    hash.update(passphrase);
+                    key.extend(hash.finalize());
+                }
+
+                decrypt(&key[..32], &[0; 16], &mut private)?;
+            }
+
+            let mut hash = Sha1::new();
+            hash.update("putty-private-key-file-mac-key");
+            if encrypted {
+                hash.update(passphrase);
+            }
+
+            let mut mac = Hmac::<Sha1>::new_from_slice(&hash.finalize())?;
+            mac.update(&mac_data(algorithm, encryption, comment, &public, &private));
+            mac.finalize().into_bytes().to_vec()
+        }
+        "3" => {
+            let mut mac_key = Vec::new();
+
+            if encrypted {
+                let flavor = match header("Key-Derivation") {
+                    Some("Argon2id") => argon2::Algorithm::Argon2id,
+                    Some("Argon2i") => argon2::Algorithm::Argon2i,
+                    Some("Argon2d") => argon2::Algorithm::Argon2d,
+                    _ => bail!("Unsupported key derivation"),
+                };
+
+                let number = |name: &str| -> Result<u32> {
+                    header(name)
+                        .and_then(|v| v.parse().ok())
+                        .ok_or_else(|| anyhow!("Missing or invalid {}", name))
+                };
+
+                let salt = HEXLOWER_PERMISSIVE
+                    .decode(header("Argon2-Salt").unwrap_or_default().as_bytes())
+                    .context("Invalid Argon2 salt")?;
+
+                let params = argon2::Params::new(
+                    number("Argon2-Memory")?,
+                    number("Argon2-Passes")?,
+                    number("Argon2-Parallelism")?,
+                    Some(80),
+                )
+                .map_err(|e| anyhow!("{}", e))?;
+
+                let mut output = [0u8; 80];
+                argon2::Argon2::new(flavor, argon2::Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), &salt, &mut output)
+                    .map_err(|e| anyhow!("{}", e))?;
+
+                decrypt(&output[..32], &output[32..48], &mut private)?;
+                mac_key.extend(&output[48..]);
+            }
+
+            let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key)?;
+            mac.update(&mac_data(algorithm, encryption, comment, &public, &private));
+            mac.finalize().into_bytes().to_vec()
+        }
+        _ => bail!("Unsupported PuTTY key file version {}", version),
+    };
+
+    if HEXLOWER_PERMISSIVE.encode(&mac) != expected_mac.to_ascii_lowercase() {
+        match encrypted {
+            true => bail!("Cannot decrypt the private key, is the password correct?"),
+            false => bail!("The private key is corrupted"),
+        }
+    }
+
+    let keypair = openssh_keypair(algorithm, &public, &private)?;
+    let keypair = KeypairData::decode(&mut &keypair[..]).context("Invalid key data")?;
+
+    Ok(PrivateKey::new(keypair, comment)?)
+}
+
+fn decrypt(key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<()> {
+    cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv)
+        .map_err(|e| anyhow!("{}", e))?
+        .decrypt_padded_mut::<NoPadding>(data)
+        .map_err(|_| anyhow!("Invalid encrypted key length"))?;
+
+    Ok(())
+}
+
+fn mac_data(
+    algorithm: &str,
+    encryption: &str,
+    comment: &str,
+    public: &[u8],
+    private: &[u8],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    write_string(&mut out, algorithm.as_bytes());
+    write_string(&mut out, encryption.as_bytes());
+    write_string(&mut out, comment.as_bytes());
+    write_string(&mut out, public);
+    write_string(&mut out, private);
+
+    out
+}
+
+/// Rearrange the public and private blobs of a PuTTY key into the OpenSSH keypair encoding
+fn openssh_keypair(algorithm: &str, public: &[u8], private: &[u8]) -> Result<Vec<u8>> {
+    let mut public = public;
+    let mut private = private;
+
+    if read_string(&mut public)? != algorithm.as_bytes() {
+        bail!("The public key does not match the key algorithm");
+    }
+
+    let mut out = Vec::new();
+    write_string(&mut out, algorithm.as_bytes());
+
+    match algorithm {
+        "ssh-rsa" => {
+            let e = read_string(&mut public)?;
+            let n = read_string(&mut public)?;
+            let d = read_string(&mut private)?;
+            let p = read_string(&mut private)?;
+            let q = read_string(&mut private)?;
+            let iqmp = read_string(&mut private)?;
+
+            for value in [n, e, d, iqmp, p, q] {
+                write_string(&mut out, value);
+            }
+        }
+        "ssh-ed25519" => {
+            let public_key = read_string(&mut public)?;
+            let private_key = read_string(&mut private)?;
+
+            write_string(&mut out, public_key);
+            write_string(&mut out, &[private_key, public_key].concat());
+        }
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => {
+            let curve = read_string(&mut public)?;
+            let point = read_string(&mut public)?;
+            let scalar = read_string(&mut private)?;
+
+            for value in [curve, point, scalar] {
+                write_string(&mut out, value);
+            }
+        }
+        _ => bail!("Unsupported key algorithm {}", algorithm),
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ssh_key::PublicKey;
+
+    // generated from the same Ed25519 key, encrypted keys with the passphrase "correct horse"
+    const V2_ENCRYPTED: &str = "\
+PuTTY-User-Key-File-2: ssh-ed25519
+Encryption: aes256-cbc
+Comment: deploy@example.com
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIJqX0Y/ds7iun2qnAiCY0Y+jiVzYGYsz7lnqW08d
+WzAg
+Private-Lines: 1
+LwHZn4WN5hWh2U5yxJQp/6S8k10rXpfRQIOo4Sh7lVfhVAcCu9LiokedJ01f8ZM4
+Private-MAC: ef8ef2e8bf603935fb95413d5ae9d4c2555e9123
+";
+
+    const V2: &str = "\
+PuTTY-User-Key-File-2: ssh-ed25519
+Encryption: none
+Comment: deploy@example.com
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIJqX0Y/ds7iun2qnAiCY0Y+jiVzYGYsz7lnqW08d
+WzAg
+Private-Lines: 1
+AAAAIE45KGn1PDOqsPsBqeMMIzoTUMnrXapzkVroGS7suxEC
+Private-MAC: 596c2666bf73151c71afcd42c2d9f33cb0e64b73
+";
+
+    const V3_ENCRYPTED: &str = "\
+PuTTY-User-Key-File-3: ssh-ed25519
+Encryption: aes256-cbc
+Comment: deploy@example.com
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIJqX0Y/ds7iun2qnAiCY0Y+jiVzYGYsz7lnqW08d
+WzAg
+Key-Derivation: Argon2id
+Argon2-Memory: 256
+Argon2-Passes: 1
+Argon2-Parallelism: 1
+Argon2-Salt: 7e5a1b9c03d4f6e8a2b1c0d9e8f70615
+Private-Lines: 1
+wKEGLJigLMC2Ncgcroe4yHCc5QyuRq0AFE4WXH+tdYrDCgfb9T2o8fV9lO2a/ftz
+Private-MAC: 2155a4cb02fb58e46830e1c4a1457e990c6a71958274e46a73d50aa8488dfd52
+";
+
+    const V3: &str = "\
+PuTTY-User-Key-File-3: ssh-ed25519
+Encryption: none
+Comment: deploy@example.com
+Public-Lines: 2
+AAAAC3NzaC1lZDI1NTE5AAAAIJqX0Y/ds7iun2qnAiCY0Y+jiVzYGYsz7lnqW08d
+WzAg
+Private-Lines: 1
+AAAAIE45KGn1PDOqsPsBqeMMIzoTUMnrXapzkVroGS7suxEC
+Private-MAC: f84eced1c83cdc5efab6242f0b305955de259bd87b6290c3622b1f5e1ce901d4
+";
+
+    const PUBLIC: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJqX0Y/ds7iun2qnAiCY0Y+jiVzYGYsz7lnqW08dWzAg";
+
+    fn check(key: PrivateKey) {
+        let expected = PublicKey::from_openssh(PUBLIC).unwrap();
+        assert_eq!(key.public_key().key_data(), expected.key_data());
+        assert_eq!(key.comment(), "deploy@example.com");
+    }
+
+    #[test]
+    fn test_version_2() {
+        check(parse(V2, "").unwrap());
+        check(parse(V2_ENCRYPTED, "correct horse").unwrap());
+
+        let error = parse(V2_ENCRYPTED, "wrong").unwrap_err();
+        assert!(error.to_string().contains("is the password correct"));
+    }
+
+    #[test]
+    fn test_version_3() {
+        check(parse(V3, "").unwrap());
+        check(parse(V3_ENCRYPTED, "correct horse").unwrap());
+
+        let error = parse(V3_ENCRYPTED, "wrong").unwrap_err();
+        assert!(error.to_string().contains("is the password correct"));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let tampered = V3.replace(
+            "Comment: deploy@example.com",
+            "Comment: mallory@example.com",
+        );
+        let error = parse(&tampered, "").unwrap_err();
+        assert_eq!(error.to_string(), "The private key is corrupted");
+    }
+}
@@ -0,0 +1,680 @@
+//! Matching entries against the URL of a web page
+//!
+//! Entries match by their `URL` field and by additional URLs in `KP2A_URL*` fields, as used by
+//! Keepass2Android and KeePassXC. URLs prefixed with `regex:` are matched as regular expressions
+//! against the whole page URL. Results are ranked by how closely the URLs match.
+
+use anyhow::{Result, bail};
+use keepass::db::{Database as KpDatabase, Entry as KpEntry, NodeRef, Value as KpValue};
+use regex::RegexBuilder;
+use url::{Host, Url};
+
+use crate::exchange::{UrlMatch, UrlMatchLevel};
+use crate::placeholder::Resolver;
+
+/// Prefix of fields holding additional URLs of an entry
+pub const ADDITIONAL_URL_PREFIX: &str = "KP2A_URL";
+
+/// Key in the custom data of an entry that hides it from browser integration, as in KeePassXC
+pub const HIDE_ENTRY_KEY: &str = "BrowserHideEntry";
+
+/// Legacy KeePassXC field with a JSON object listing allowed and denied hosts
+pub const BROWSER_SETTINGS_FIELD: &str = "KeePassXC-Browser Settings";
+
+/// Prefix of URLs that are regular expressions
+pub const REGEX_PREFIX: &str = "regex:";
+
+/// Matches entry URLs against a page URL
+pub struct UrlMatcher {
+    page: Url,
+    host: String,
+}
+
+/// An entry that matches a page
+pub struct EntryMatch<'a> {
+    pub database_idx: usize,
+    pub database: &'a KpDatabase,
+    pub entry: &'a KpEntry,
+    pub level: UrlMatchLevel,
+
+    /// the URL of the entry that matched, with placeholders resolved
+    pub url: String,
+}
+
+impl UrlMatcher {
+    /// Create a matcher for a page URL. URLs without a scheme are assumed to be HTTPS.
+    pub fn new(page_url: &str) -> Result<Self> {
+        let page_url = page_url.trim();
+
+        let page = if page_url.contains("://") {
+            Url::parse(page_url)?
+        } else {
+            Url::parse(&format!("https://{page_url}"))?
+        };
+
+        let Some(host) = page.host_str() else {
+            bail!("The URL {} has no host", page);
+        };
+        let host = normalize_host(host);
+
+        Ok(Self { page, host })
+    }
+
+    /// Check how closely an entry URL matches the page
+    pub fn match_url(&self, entry_url: &str) -> Option<UrlMatchLevel> {
+        let entry_url = entry_url.trim();
+
+        if entry_url.is_empty() {
+            return None;
+        }
+
+        if entry_url.len() > REGEX_PREFIX.len()
+            && entry_url[..REGEX_PREFIX.len()].eq_ignore_ascii_case(REGEX_PREFIX)
+        {
+            let matches = RegexBuilder::new(&entry_url[REGEX_PREFIX.len()..])
+                .case_insensitive(true)
+                .build()
+                .is_ok_and(|re| re.is_match(self.page.as_str()));
+
+            return matches.then_some(UrlMatchLevel::Regex);
+        }
+
+        let (scheme, rest) = match entry_url.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, entry_url),
+        };
+
+        // `*.example.com` matches subdomains only
+        let (wildcard, rest) = match rest.strip_prefix("*.") {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+
+        let url = Url::parse(&format!(
+            "{}://{}",
+            scheme.as_deref().unwrap_or(self.page.scheme()),
+            rest
+        ))
+        .ok()?;
+
+        // never send credentials for HTTPS entries to insecure pages, but allow upgrades
+        if scheme.is_some()
+            && url.scheme() != self.page.scheme()
+            && !(url.scheme() == "http" && self.page.scheme() == "https")
+        {
+            return None;
+        }
+
+        let port_matches = match url.port() {
+            Some(port) => Some(port) == self.page.port_or_known_default(),
+            None => self.page.port().is_none(),
+        };
+        if !port_matches {
+            return None;
+        }
+
+        let entry_host = normalize_host(url.host_str()?);
+
+        if wildcard {
+            return is_subdomain(&self.host, &entry_host).then_some(UrlMatchLevel::Subdomain);
+        }
+
+        if entry_host != self.host {
+            // only registrable names can have subdomains, not IP addresses or bare TLDs
+            let is_domain = matches!(url.host(), Some(Host::Domain(_))) && entry_host.contains('.');
+
+            return (is_domain && is_subdomain(&self.host, &entry_host))
+                .then_some(UrlMatchLevel::Subdomain);
+        }
+
+        let entry_path = url.path().trim_end_matches('/');
+        let page_path = self.page.path().trim_end_matches('/');
+
+        let same_query = url.query().unwrap_or_default() == self.page.query().unwrap_or_default();
+
+        if entry_path == page_path && same_query {
+            return Some(UrlMatchLevel::Exact);
+        }
+
+        if !entry_path.is_empty()
+            && (page_path == entry_path || page_path.starts_with(&format!("{entry_path}/")))
+        {
+            return Some(UrlMatchLevel::Path);
+        }
+
+        Some(UrlMatchLevel::Host)
+    }
+
+    /// Find the best match of the URLs of an entry, returning the match level and matching URL
+    pub fn match_entry(
+        &self,
+        resolver: &Resolver,
+        entry: &KpEntry,
+    ) -> Option<(UrlMatchLevel, String)> {
+        if is_hidden(entry) || self.is_denied(entry) {
+            return None;
+        }
+
+        let mut additional: Vec<&String> = entry
+            .fields
+            .keys()
+            .filter(|name| name.starts_with(ADDITIONAL_URL_PREFIX))
+            .collect();
+        additional.sort();
+
+        std::iter::once("URL")
+            .chain(additional.into_iter().map(String::as_str))
+            .filter_map(|field| resolver.field(entry, field))
+            .filter_map(|url| self.match_url(&url).map(|level| (level, url)))
+            // keep the first of equally good matches
+            .fold(None, |best, (level, url)| match best {
+                Some((best_level, _)) if best_level >= level => best,
+                _ => Some((level, url)),
+            })
+    }
+
+    /// Check whether the legacy browser settings of an entry deny the page host
+    fn is_denied(&self, entry: &KpEntry) -> bool {
+        let Some(settings) = entry
+            .get(BROWSER_SETTINGS_FIELD)
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        else {
+            return false;
+        };
+
+        let host_port = match self.page.port() {
+            Some(port) => format!("{}:{}", self.host, port),
+            None => self.host.clone(),
+        };
+
+        settings["Deny"]
+            .as_array()
+            .map(|deny| {
+                deny.iter()
+                    .filter_map(|d| d.as_str())
+                    .map(normalize_host)
+                    .any(|d| d == self.host || d == host_port)
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Find entries matching a page in a set of databases, best matches first
+pub fn find_entries<'a>(
+    databases: impl IntoIterator<Item = (usize, &'a KpDatabase)>,
+    matcher: &UrlMatcher,
+) -> Vec<EntryMatch<'a>> {
+    let mut out = Vec::new();
+
+    for (database_idx, database) in databases {
+        let resolver = Resolver::new(database);
+
+        for node in database.root.iter() {
+            let NodeRef::Entry(entry) = node else {
+                continue;
+            };
+
+            if is_recycled(database, entry) {
+                continue;
+            }
+
+            if let Some((level, url)) = matcher.match_entry(&resolver, entry) {
+                out.push(EntryMatch {
+                    database_idx,
+                    database,
+                    entry,
+                    level,
+                    url,
+                });
+            }
+        }
+    }
+
+    // stable sort keeps database order within the same level
+    out.sort_by_key(|m| std::cmp::Reverse(m.level));
+    out
+}
+
+/// Find entries matching a page URL in a set of databases, best matches first
+pub fn find_matches<'a>(
+    databases: impl IntoIterator<Item = (usize, &'a KpDatabase)>,
+    page_url: &str,
+) -> Result<Vec<UrlMatch>> {
+    let matcher = UrlMatcher::new(page_url)?;
+
+    let out = find_entries(databases, &matcher)
+        .into_iter()
+        .map(|m| {
+            let resolver = Resolver::new(m.database);
+
+            UrlMatch {
+                database_idx: m.database_idx,
+                entry_uuid: m.entry.uuid,
+                name: resolver.field(m.entry, "Title"),
+                user_name: resolver.field(m.entry, "UserName"),
+                url: m.url,
+                level: m.level,
+            }
+        })
+        .collect();
+
+    Ok(out)
+}
+
+/// Check whether an entry is hidden from browser integration
+pub fn is_hidden(entry: &KpEntry) -> bool {
+    entry
+        .custom_data
+        .items
+        .get(HIDE_ENTRY_KEY)
+        .and_then(|item| item.value.as_ref())
+        .is_some_and(|value| matches!(value, KpValue::Unprotected(v) if v == "true"))
+}
+
+/// Check whether an entry is in the recycle bin of a database
+pub(crate) fn is_recycled(database: &KpDatabase, entry: &KpEntry) -> bool {
+    let Some(bin) = database.meta.recyclebin_uuid else {
+        return false;
+    };
+
+    Resolver::new(database)
+        .parent_groups(entry)
+        .unwrap_or_default()
+        .iter()
+        .any(|group| group.uuid == bin)
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn is_subdomain(host: &str, domain: &str) -> bool {
+    host.len() > domain.len()
+        && host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use keepass::db::{CustomDataItem, Group as KpGroup, Node};
+
+    fn level(page: &str, entry: &str) -> Option<UrlMatchLevel> {
+        UrlMatcher::new(page).unwrap().match_url(entry)
+    }
+
+    fn entry(fields: &[(&str, &str)]) -> KpEntry {
+        let mut entry = KpEntry::new();
+        for (name, value) in fields {
+            entry
+                .fields
+                .insert(name.to_string(), KpValue::Unprotected(value.to_string()));
+        }
+        entry
+    }
+
+    fn database(entries: Vec<KpEntry>) -> KpDatabase {
+        let mut database = KpDatabase::new(Default::default());
+        database
+            .root
+            .children
+            .extend(entries.into_iter().map(Node::Entry));
+        database
+    }
+
+    #[test]
+    fn exact_match() {
+        let page = "https://example.com/login";
+        assert_eq!(
+            level(page, "https://example.com/login"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level(page, "https://example.com/login/"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level(page, "https://EXAMPLE.com/login"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level("https://example.com/", "example.com"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level("https://example.com", "https://example.com/"),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn fragment_is_ignored() {
+        assert_eq!(
+            level(
+                "https://example.com/app#/settings",
+                "https://example.com/app"
+            ),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn query_must_match_for_exact() {
+        assert_eq!(
+            level(
+                "https://example.com/login?next=/",
+                "https://example.com/login"
+            ),
+            Some(UrlMatchLevel::Path)
+        );
+        assert_eq!(
+            level(
+                "https://example.com/login?a=1",
+                "https://example.com/login?a=1"
+            ),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn path_match() {
+        let page = "https://example.com/app/login";
+        assert_eq!(
+            level(page, "https://example.com/app"),
+            Some(UrlMatchLevel::Path)
+        );
+        assert_eq!(
+            level(page, "https://example.com/app/"),
+            Some(UrlMatchLevel::Path)
+        );
+        assert_eq!(level(page, "example.com/app"), Some(UrlMatchLevel::Path));
+    }
+
+    #[test]
+    fn path_prefix_respects_segments() {
+        assert_eq!(
+            level("https://example.com/application", "https://example.com/app"),
+            Some(UrlMatchLevel::Host)
+        );
+    }
+
+    #[test]
+    fn host_match() {
+        let page = "https://example.com/other";
+        assert_eq!(
+            level(page, "https://example.com/login"),
+            Some(UrlMatchLevel::Host)
+        );
+        assert_eq!(level(page, "example.com"), Some(UrlMatchLevel::Host));
+        assert_eq!(level(page, "example.com."), Some(UrlMatchLevel::Host));
+    }
+
+    #[test]
+    fn different_host() {
+        let page = "https://example.com/";
+        assert_eq!(level(page, "https://example.org/"), None);
+        assert_eq!(level(page, "https://notexample.com/"), None);
+        assert_eq!(level(page, "https://www.example.com/"), None);
+    }
+
+    #[test]
+    fn subdomain_match() {
+        let page = "https://login.example.com/";
+        assert_eq!(
+            level(page, "https://example.com/"),
+            Some(UrlMatchLevel::Subdomain)
+        );
+        assert_eq!(level(page, "example.com"), Some(UrlMatchLevel::Subdomain));
+        assert_eq!(
+            level("https://a.b.example.com/", "example.com"),
+            Some(UrlMatchLevel::Subdomain)
+        );
+    }
+
+    #[test]
+    fn subdomain_requires_label_boundary() {
+        assert_eq!(level("https://evilexample.com/", "example.com"), None);
+    }
+
+    #[test]
+    fn no_subdomain_match_for_tlds_and_ips() {
+        assert_eq!(level("https://example.com/", "com"), None);
+        assert_eq!(level("https://1.2.3.4/", "2.3.4"), None);
+        assert_eq!(
+            level("https://10.0.0.1/", "10.0.0.1"),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn wildcard_matches_subdomains_only() {
+        assert_eq!(
+            level("https://login.example.com/", "https://*.example.com"),
+            Some(UrlMatchLevel::Subdomain)
+        );
+        assert_eq!(level("https://example.com/", "https://*.example.com"), None);
+        assert_eq!(
+            level("https://login.example.com/", "*.example.com"),
+            Some(UrlMatchLevel::Subdomain)
+        );
+    }
+
+    #[test]
+    fn scheme_rules() {
+        assert_eq!(level("http://example.com/", "https://example.com/"), None);
+        assert_eq!(
+            level("https://example.com/", "http://example.com/"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level("http://example.com/", "example.com"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(level("ftp://example.com/", "https://example.com/"), None);
+    }
+
+    #[test]
+    fn port_rules() {
+        assert_eq!(
+            level("https://example.com:8443/", "https://example.com:8443/"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level("https://example.com:8443/", "https://example.com/"),
+            None
+        );
+        assert_eq!(
+            level("https://example.com/", "https://example.com:8443/"),
+            None
+        );
+        assert_eq!(
+            level("https://example.com/", "https://example.com:443/"),
+            Some(UrlMatchLevel::Exact)
+        );
+        assert_eq!(
+            level("https://example.com:8443/", "example.com:8443"),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn regex_match() {
+        let page = "https://eu.example.com/login";
+        assert_eq!(
+            level(page, r"regex:^https://(eu|us)\.example\.com/"),
+            Some(UrlMatchLevel::Regex)
+        );
+        assert_eq!(
+            level(page, r"REGEX:EXAMPLE\.COM"),
+            Some(UrlMatchLevel::Regex)
+        );
+        assert_eq!(level(page, r"regex:^https://us\.example\.com/"), None);
+        assert_eq!(level(page, "regex:("), None);
+    }
+
+    #[test]
+    fn empty_and_invalid_urls() {
+        let page = "https://example.com/";
+        assert_eq!(level(page, ""), None);
+        assert_eq!(level(page, "   "), None);
+        assert_eq!(level(page, "https://"), None);
+        assert!(UrlMatcher::new("").is_err());
+        assert!(UrlMatcher::new("file:///etc/passwd").is_err());
+    }
+
+    #[test]
+    fn page_without_scheme() {
+        assert_eq!(
+            level("example.com/login", "https://example.com/login"),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn levels_are_ranked() {
+        assert!(UrlMatchLevel::Exact > UrlMatchLevel::Path);
+        assert!(UrlMatchLevel::Path > UrlMatchLevel::Host);
+        assert!(UrlMatchLevel::Host > UrlMatchLevel::Subdomain);
+        assert!(UrlMatchLevel::Subdomain > UrlMatchLevel::Regex);
+    }
+
+    #[test]
+    fn additional_urls() {
+        let e = entry(&[
+            ("URL", "https://other.org/"),
+            ("KP2A_URL", "https://example.com/"),
+            ("KP2A_URL_1", "https://example.com/login"),
+        ]);
+        let db = database(vec![]);
+        let resolver = Resolver::new(&db);
+
+        let matcher = UrlMatcher::new("https://example.com/login").unwrap();
+        assert_eq!(
+            matcher.match_entry(&resolver, &e),
+            Some((
+                UrlMatchLevel::Exact,
+                "https://example.com/login".to_string()
+            ))
+        );
+
+        let matcher = UrlMatcher::new("https://other.org/").unwrap();
+        assert_eq!(
+            matcher.match_entry(&resolver, &e),
+            Some((UrlMatchLevel::Exact, "https://other.org/".to_string()))
+        );
+
+        let matcher = UrlMatcher::new("https://unrelated.net/").unwrap();
+        assert_eq!(matcher.match_entry(&resolver, &e), None);
+    }
+
+    #[test]
+    fn placeholders_in_urls_are_resolved() {
+        let e = entry(&[("URL", "https://{S:Domain}/"), ("Domain", "example.com")]);
+        let db = database(vec![]);
+        let resolver = Resolver::new(&db);
+
+        let matcher = UrlMatcher::new("https://example.com/").unwrap();
+        assert_eq!(
+            matcher.match_entry(&resolver, &e).map(|(level, _)| level),
+            Some(UrlMatchLevel::Exact)
+        );
+    }
+
+    #[test]
+    fn hidden_entries_never_match() {
+        let mut e = entry(&[("URL", "https://example.com/")]);
+        e.custom_data.items.insert(
+            HIDE_ENTRY_KEY.to_string(),
+            CustomDataItem {
+                value: Some(KpValue::Unprotected("true".to_string())),
+                last_modification_time: None,
+            },
+        );
+        let db = database(vec![]);
+        let resolver = Resolver::new(&db);
+
+        let matcher = UrlMatcher::new("https://example.com/").unwrap();
+        assert!(is_hidden(&e));
+        assert_eq!(matcher.match_entry(&resolver, &e), None);
+    }
+
+    #[test]
+    fn denied_hosts_never_match() {
+        let e = entry(&[
+            ("URL", "example.com"),
+            (
+                BROWSER_SETTINGS_FIELD,
+                r#"{"Allow":[],"Deny":["login.example.com"]}"#,
+            ),
+        ]);
+        let db = database(vec![]);
+        let resolver = Resolver::new(&db);
+
+        let denied = UrlMatcher::new("https://login.example.com/").unwrap();
+        assert_eq!(denied.match_entry(&resolver, &e), None);
+
+        let allowed = UrlMatcher::new("https://www.example.com/").unwrap();
+        assert_eq!(
+            allowed.match_entry(&resolver, &e).map(|(level, _)| level),
+            Some(UrlMatchLevel::Subdomain)
+        );
+    }
+
+    #[test]
+    fn find_matches_ranks_results() {
+        let db = database(vec![
+            entry(&[("Title", "Parent"), ("URL", "https://example.com/")]),
+            entry(&[("Title", "Unrelated"), ("URL", "https://example.org/")]),
+            entry(&[
+                ("Title", "Parent path"),
+                ("URL", "https://example.com/login"),
+            ]),
+            entry(&[
+                ("Title", "Other"),
+                ("URL", "https://other.org/"),
+                ("KP2A_URL", "example.com"),
+            ]),
+        ]);
+
+        let other = database(vec![entry(&[
+            ("Title", "Path"),
+            ("URL", "https://login.example.com/login"),
+        ])]);
+
+        let matches = find_matches(
+            [(0, &db), (1, &other)],
+            "https://login.example.com/login/step",
+        )
+        .unwrap();
+
+        let titles: Vec<_> = matches
+            .iter()
+            .map(|m| (m.name.clone().unwrap(), m.level, m.database_idx))
+            .collect();
+
+        assert_eq!(
+            titles,
+            vec![
+                ("Path".to_string(), UrlMatchLevel::Path, 1),
+                ("Parent".to_string(), UrlMatchLevel::Subdomain, 0),
+                ("Parent path".to_string(), UrlMatchLevel::Subdomain, 0),
+                ("Other".to_string(), UrlMatchLevel::Subdomain, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn recycled_entries_never_match() {
+        let mut bin = KpGroup::new("Recycle Bin");
+        bin.children
+            .push(Node::Entry(entry(&[("URL", "https://example.com/")])));
+
+        let mut db = database(vec![]);
+        db.meta.recyclebin_uuid = Some(bin.uuid);
+        db.root.children.push(Node::Group(bin));
+
+        let matches = find_matches([(0, &db)], "https://example.com/").unwrap();
+        assert!(matches.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+//! Native messaging host for the KeePassXC-Browser extension
+//!
+//! The browser starts this program and exchanges length-prefixed JSON messages with it over
+//! stdin/stdout. Messages are relayed unchanged over a local socket to the running OmniKee
+//! application, which handles the protocol and encryption.
+
+#[cfg(unix)]
+use omnikee_lib::browser::{read_message, socket_path, write_message};
+
+/// Answer a request with a KeePassXC-style error when OmniKee cannot be reached
+fn not_connected(request: &str) -> String {
+    let request = serde_json::from_str::<serde_json::Value>(request).unwrap_or_default();
+    let action = request["action"].as_str().unwrap_or_default();
+
+    serde_json::json!({
+        "action": action,
+        "errorCode": "5",
+        "error": "OmniKee is not running or browser integration is disabled",
+    })
+    .to_string()
+}
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    use std::os::unix::net::UnixStream;
+
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+
+    let mut socket: Option<UnixStream> = None;
+
+    while let Some(request) = read_message(&mut stdin)? {
+        // (re)connect lazily so that OmniKee can be started after the browser
+        if socket.is_none() {
+            socket = UnixStream::connect(socket_path()).ok();
+        }
+
+        let response = match socket.as_mut() {
+            Some(stream) => {
+                match write_message(stream, &request).and_then(|_| read_message(stream)) {
+                    Ok(Some(response)) => response,
+                    _ => {
+                        socket = None;
+                        not_connected(&request)
+                    }
+                }
+            }
+            None => not_connected(&request),
+        };
+
+        write_message(&mut stdout, &response)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("omnikee-proxy is not supported on this platform yet");
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_action_of_unanswered_requests() {
+        let response = not_connected(r#"{"action":"get-\"logins\\","nonce":"AAAA"}"#);
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+
+        assert_eq!(response["action"], "get-\"logins\\");
+        assert_eq!(response["errorCode"], "5");
+
+        let response = not_connected("not json");
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+
+        assert_eq!(response["action"], "");
+    }
+}
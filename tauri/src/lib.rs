@@ -1,8 +1,19 @@
 use std::sync::Mutex;
 
-use omnikee_lib::{AppState, DatabaseOverview, Entry, OTPResponse, ValueSet};
+use omnikee_lib::browser::BrowserContext;
+use omnikee_lib::favicon::HttpFaviconFetcher;
+#[cfg(target_os = "linux")]
+use omnikee_lib::secret_service::SecretServiceContext;
+use omnikee_lib::ssh_agent::AgentContext;
+use omnikee_lib::{
+    AppState, AutoTypeAction, AutoTypeMatch, CsvFormat, CsvMapping, CsvPreview, CustomIcon,
+    DatabaseOverview, Entry, ExportFormat, FaviconSummary, GroupExportOptions, IconSet,
+    ImportSummary, ImportedDatabase, OTPResponse, OTPSettings, OTPSetup, RemoteCredentials,
+    ReportOptions, ResolvedValue, S3Settings, SftpSettings, Share, ShareReference, SshKey,
+    UrlMatch, ValueSet,
+};
 use tauri::{AppHandle, Manager};
-use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
 
 type State<'a> = tauri::State<'a, Mutex<AppState>>;
 
@@ -12,18 +23,31 @@ fn list_databases(state: State<'_>) -> Vec<DatabaseOverview> {
     state.list_databases()
 }
 
+/// Wait for a database source in a blocking thread, without locking the state
+///
+/// The futures of database sources cannot be sent to other threads, so they cannot be awaited in
+/// a command and are run with [`tauri::async_runtime::block_on`] instead.
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, String> {
+    tauri::async_runtime::spawn_blocking(f)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
-fn load_demo(app: AppHandle, state: State<'_>) -> Result<DatabaseOverview, String> {
+fn load_demo(state: State<'_>) -> Result<DatabaseOverview, String> {
     let mut state = state.lock().unwrap();
-    state.load_demo(app)
+    state.load_demo()
 }
 
 #[tauri::command]
 async fn load_database(app: AppHandle, state: State<'_>) -> Result<DatabaseOverview, String> {
-    let app = app.clone();
+    let app_thread = app.clone();
 
     let path = tauri::async_runtime::spawn_blocking(move || {
-        app.dialog()
+        app_thread
+            .dialog()
             .file()
             .add_filter("KeePass Databases", &["kdbx"])
             .blocking_pick_file()
@@ -34,46 +58,73 @@ async fn load_database(app: AppHandle, state: State<'_>) -> Result<DatabaseOverv
 
     if let Some(path) = path {
         let mut state = state.lock().unwrap();
-        return state.load_database_path(&path);
+        return state.load_database_path(&path, app);
     }
 
     Err("Loading aborted".into())
 }
 
 #[tauri::command]
-fn unlock_database(
-    app: AppHandle,
+fn load_database_webdav(
     state: State<'_>,
-    database_idx: usize,
-    password: Option<String>,
-    keyfile: Option<Vec<u8>>,
+    url: String,
+    credentials: Option<RemoteCredentials>,
 ) -> Result<DatabaseOverview, String> {
     let mut state = state.lock().unwrap();
-    state.unlock_database(database_idx, password, keyfile, app)
+    state.load_database_webdav(&url, credentials)
 }
 
 #[tauri::command]
-fn lock_database(state: State<'_>, database_idx: usize) -> Result<DatabaseOverview, String> {
+fn load_database_s3(state: State<'_>, settings: S3Settings) -> Result<DatabaseOverview, String> {
     let mut state = state.lock().unwrap();
-    state.lock_database(database_idx)
+    state.load_database_s3(settings)
 }
 
 #[tauri::command]
-fn save_database(
-    app: AppHandle,
+fn load_database_sftp(
     state: State<'_>,
-    database_idx: usize,
-) -> Result<Option<Vec<u8>>, String> {
+    settings: SftpSettings,
+) -> Result<DatabaseOverview, String> {
     let mut state = state.lock().unwrap();
-    state.save_database(database_idx, app)
+    state.load_database_sftp(settings)
 }
 
 #[tauri::command]
-async fn save_database_as(
+async fn unlock_database(
     app: AppHandle,
-    state: State<'_>,
     database_idx: usize,
-) -> Result<(), String> {
+    password: Option<String>,
+    keyfile: Option<Vec<u8>>,
+) -> Result<DatabaseOverview, String> {
+    run_blocking(move || {
+        let state = app.state::<Mutex<AppState>>();
+        tauri::async_runtime::block_on(AppState::unlock_database(
+            &*state,
+            database_idx,
+            password,
+            keyfile,
+        ))
+    })
+    .await?
+}
+
+#[tauri::command]
+fn lock_database(state: State<'_>, database_idx: usize) -> Result<DatabaseOverview, String> {
+    let mut state = state.lock().unwrap();
+    state.lock_database(database_idx)
+}
+
+#[tauri::command]
+async fn save_database(app: AppHandle, database_idx: usize) -> Result<Option<Vec<u8>>, String> {
+    run_blocking(move || {
+        let state = app.state::<Mutex<AppState>>();
+        tauri::async_runtime::block_on(AppState::save_database(&*state, database_idx))
+    })
+    .await?
+}
+
+#[tauri::command]
+async fn save_database_as(app: AppHandle, database_idx: usize) -> Result<(), String> {
     let app_thread = app.clone();
 
     let path = tauri::async_runtime::spawn_blocking(move || {
@@ -87,10 +138,16 @@ async fn save_database_as(
     .map_err(|e| e.to_string())?;
 
     if let Some(path) = path {
-        let mut state = state.lock().unwrap();
-        state.save_database_as(database_idx, path, app)?;
-
-        return Ok(());
+        return run_blocking(move || {
+            let state = app.state::<Mutex<AppState>>();
+            tauri::async_runtime::block_on(AppState::save_database_as(
+                &*state,
+                database_idx,
+                path,
+                app.clone(),
+            ))
+        })
+        .await?;
     }
 
     Err("Loading aborted".into())
@@ -123,6 +180,105 @@ fn reveal_protected(
     state.reveal_protected(database_idx, &entry_uuid, &field_name)
 }
 
+#[tauri::command]
+fn get_attachment(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    name: String,
+) -> Result<Vec<u8>, String> {
+    let state = state.lock().unwrap();
+    state.get_attachment(database_idx, &entry_uuid, &name)
+}
+
+#[tauri::command]
+fn resolve_field(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    field_name: String,
+) -> Result<ResolvedValue, String> {
+    let state = state.lock().unwrap();
+    state.resolve_field(database_idx, &entry_uuid, &field_name)
+}
+
+#[tauri::command]
+fn resolve_placeholders(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    text: String,
+) -> Result<String, String> {
+    let state = state.lock().unwrap();
+    state.resolve_placeholders(database_idx, &entry_uuid, &text)
+}
+
+#[tauri::command]
+fn find_entries_for_url(state: State<'_>, url: String) -> Result<Vec<UrlMatch>, String> {
+    let state = state.lock().unwrap();
+    state.find_entries_for_url(&url)
+}
+
+#[tauri::command]
+fn get_browser_auto_save(state: State<'_>, database_idx: usize) -> Result<bool, String> {
+    let state = state.lock().unwrap();
+    state.get_browser_auto_save(database_idx)
+}
+
+#[tauri::command]
+fn set_browser_auto_save(
+    state: State<'_>,
+    database_idx: usize,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_browser_auto_save(database_idx, enabled)
+}
+
+#[tauri::command]
+fn get_auto_type_plan(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    window_title: Option<String>,
+) -> Result<Vec<AutoTypeAction>, String> {
+    let state = state.lock().unwrap();
+    state.get_auto_type_plan(database_idx, &entry_uuid, window_title)
+}
+
+#[tauri::command]
+fn find_auto_type_matches(
+    state: State<'_>,
+    database_idx: usize,
+    window_title: String,
+) -> Result<Vec<AutoTypeMatch>, String> {
+    let state = state.lock().unwrap();
+    state.find_auto_type_matches(database_idx, &window_title)
+}
+
+#[tauri::command]
+async fn perform_auto_type(
+    window: tauri::Window,
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+) -> Result<(), String> {
+    let actions = {
+        let state = state.lock().unwrap();
+        state.get_auto_type_plan(database_idx, &entry_uuid, None)?
+    };
+
+    // return focus to the previously active window before typing
+    window.minimize().map_err(|e| e.to_string())?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        omnikee_lib::autotype::perform_native(&actions).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 #[tauri::command]
 fn get_otp(
     state: State<'_>,
@@ -130,10 +286,57 @@ fn get_otp(
     entry_uuid: String,
     time: u64,
 ) -> Result<OTPResponse, String> {
-    let state = state.lock().unwrap();
+    let mut state = state.lock().unwrap();
     state.get_otp(database_idx, &entry_uuid, time)
 }
 
+#[tauri::command]
+fn get_otp_settings(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+) -> Result<Option<OTPSettings>, String> {
+    let state = state.lock().unwrap();
+    state.get_otp_settings(database_idx, &entry_uuid)
+}
+
+#[tauri::command]
+fn set_otp(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    setup: OTPSetup,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_otp(database_idx, &entry_uuid, setup)
+}
+
+#[tauri::command]
+fn remove_otp(state: State<'_>, database_idx: usize, entry_uuid: String) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.remove_otp(database_idx, &entry_uuid)
+}
+
+#[tauri::command]
+fn get_otp_uri(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+) -> Result<String, String> {
+    let state = state.lock().unwrap();
+    state.get_otp_uri(database_idx, &entry_uuid)
+}
+
+#[tauri::command]
+fn get_otp_qr_code(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+) -> Result<String, String> {
+    let state = state.lock().unwrap();
+    state.get_otp_qr_code(database_idx, &entry_uuid)
+}
+
 #[tauri::command]
 fn set_group_name(
     state: State<'_>,
@@ -157,6 +360,531 @@ fn set_field(
     state.set_field(database_idx, &entry_uuid, field_name, value)
 }
 
+#[tauri::command]
+fn list_custom_icons(state: State<'_>, database_idx: usize) -> Result<Vec<CustomIcon>, String> {
+    let state = state.lock().unwrap();
+    state.list_custom_icons(database_idx)
+}
+
+#[tauri::command]
+fn add_custom_icon(state: State<'_>, database_idx: usize, data: Vec<u8>) -> Result<String, String> {
+    let mut state = state.lock().unwrap();
+    state.add_custom_icon(database_idx, &data)
+}
+
+#[tauri::command]
+fn replace_custom_icon(
+    state: State<'_>,
+    database_idx: usize,
+    icon_uuid: String,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.replace_custom_icon(database_idx, &icon_uuid, &data)
+}
+
+#[tauri::command]
+fn deduplicate_custom_icons(state: State<'_>, database_idx: usize) -> Result<usize, String> {
+    let mut state = state.lock().unwrap();
+    state.deduplicate_custom_icons(database_idx)
+}
+
+#[tauri::command]
+fn purge_unused_custom_icons(state: State<'_>, database_idx: usize) -> Result<usize, String> {
+    let mut state = state.lock().unwrap();
+    state.purge_unused_custom_icons(database_idx)
+}
+
+#[tauri::command]
+fn set_group_icon(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    icon: IconSet,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_group_icon(database_idx, &group_uuid, icon)
+}
+
+#[tauri::command]
+fn set_entry_icon(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+    icon: IconSet,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_entry_icon(database_idx, &entry_uuid, icon)
+}
+
+#[tauri::command]
+fn get_favicon_download(state: State<'_>, database_idx: usize) -> Result<bool, String> {
+    let state = state.lock().unwrap();
+    state.get_favicon_download(database_idx)
+}
+
+#[tauri::command]
+fn set_favicon_download(
+    state: State<'_>,
+    database_idx: usize,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_favicon_download(database_idx, enabled)
+}
+
+#[tauri::command]
+async fn download_favicons(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuids: Vec<String>,
+) -> Result<FaviconSummary, String> {
+    // the database is looked up again by its id afterwards, as it may have been closed meanwhile
+    let (database_id, requests) = {
+        let state = state.lock().unwrap();
+        (
+            state.database_id(database_idx)?,
+            state.list_favicon_requests(database_idx, entry_uuids)?,
+        )
+    };
+
+    // don't hold the state lock while waiting for the network
+    let results = tauri::async_runtime::spawn_blocking(move || {
+        let fetcher = HttpFaviconFetcher::new().map_err(|e| e.to_string())?;
+
+        let results: Vec<_> = requests
+            .into_iter()
+            .map(|request| {
+                let icon = omnikee_lib::favicon::fetch_favicon(&fetcher, &request.url);
+                (request.entry_uuid, icon)
+            })
+            .collect();
+
+        Ok::<_, String>(results)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let mut state = state.lock().unwrap();
+    let database_idx = state.database_index(&database_id)?;
+
+    // the database may have been opted out while waiting
+    if !state.get_favicon_download(database_idx)? {
+        return Err("Favicon downloads are not enabled for this database".to_string());
+    }
+
+    let mut summary = FaviconSummary::default();
+
+    for (entry_uuid, icon) in results {
+        match icon {
+            Ok(Some(data)) => {
+                match state.set_entry_favicon(database_idx, &entry_uuid.to_string(), data) {
+                    Ok(()) => summary.downloaded += 1,
+                    Err(e) => {
+                        summary.failed.insert(entry_uuid, e);
+                    }
+                }
+            }
+            Ok(None) => summary.not_found += 1,
+            Err(e) => {
+                summary.failed.insert(entry_uuid, e.to_string());
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[tauri::command]
+fn list_ssh_keys(state: State<'_>) -> Vec<SshKey> {
+    let state = state.lock().unwrap();
+    state.list_ssh_keys()
+}
+
+#[tauri::command]
+fn add_ssh_key(
+    state: State<'_>,
+    database_idx: usize,
+    entry_uuid: String,
+) -> Result<SshKey, String> {
+    let mut state = state.lock().unwrap();
+    state.add_ssh_key(database_idx, &entry_uuid)
+}
+
+#[tauri::command]
+fn remove_ssh_key(state: State<'_>, entry_uuid: String) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.remove_ssh_key(&entry_uuid)
+}
+
+#[tauri::command]
+fn get_secret_service_enabled(state: State<'_>, database_idx: usize) -> Result<bool, String> {
+    let state = state.lock().unwrap();
+    state.get_secret_service_enabled(database_idx)
+}
+
+#[tauri::command]
+fn set_secret_service_enabled(
+    state: State<'_>,
+    database_idx: usize,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_secret_service_enabled(database_idx, enabled)
+}
+
+#[tauri::command]
+fn get_secret_service_group(
+    state: State<'_>,
+    database_idx: usize,
+) -> Result<Option<String>, String> {
+    let state = state.lock().unwrap();
+    state.get_secret_service_group(database_idx)
+}
+
+#[tauri::command]
+fn set_secret_service_group(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: Option<String>,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_secret_service_group(database_idx, group_uuid)
+}
+
+#[tauri::command]
+fn preview_csv_import(
+    state: State<'_>,
+    data: Vec<u8>,
+    format: Option<CsvFormat>,
+) -> Result<CsvPreview, String> {
+    let state = state.lock().unwrap();
+    state.preview_csv_import(&data, format)
+}
+
+#[tauri::command]
+fn import_csv(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    data: Vec<u8>,
+    format: CsvFormat,
+    mapping: CsvMapping,
+) -> Result<ImportSummary, String> {
+    let mut state = state.lock().unwrap();
+    state.import_csv(database_idx, &group_uuid, &data, format, mapping)
+}
+
+#[tauri::command]
+fn import_bitwarden(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    data: Vec<u8>,
+    password: Option<String>,
+) -> Result<ImportSummary, String> {
+    let mut state = state.lock().unwrap();
+    state.import_bitwarden(database_idx, &group_uuid, &data, password)
+}
+
+#[tauri::command]
+fn import_onepassword(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    data: Vec<u8>,
+) -> Result<ImportSummary, String> {
+    let mut state = state.lock().unwrap();
+    state.import_onepassword(database_idx, &group_uuid, &data)
+}
+
+#[tauri::command]
+fn import_lastpass(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    data: Vec<u8>,
+) -> Result<ImportSummary, String> {
+    let mut state = state.lock().unwrap();
+    state.import_lastpass(database_idx, &group_uuid, &data)
+}
+
+#[tauri::command]
+fn import_kdb(
+    state: State<'_>,
+    name: String,
+    data: Vec<u8>,
+    password: Option<String>,
+    keyfile: Option<Vec<u8>>,
+) -> Result<ImportedDatabase, String> {
+    let mut state = state.lock().unwrap();
+    state.import_kdb(name, &data, password, keyfile)
+}
+
+#[tauri::command]
+fn import_keepass_xml(
+    state: State<'_>,
+    name: String,
+    data: Vec<u8>,
+    password: Option<String>,
+    keyfile: Option<Vec<u8>>,
+) -> Result<ImportedDatabase, String> {
+    let mut state = state.lock().unwrap();
+    state.import_keepass_xml(name, &data, password, keyfile)
+}
+
+#[tauri::command]
+async fn export_database(
+    app: AppHandle,
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: Option<String>,
+    format: ExportFormat,
+    include_protected: bool,
+) -> Result<(), String> {
+    let app_thread = app.clone();
+
+    let path = tauri::async_runtime::spawn_blocking(move || {
+        app_thread
+            .dialog()
+            .file()
+            .add_filter(format.extension().to_uppercase(), &[format.extension()])
+            .blocking_save_file()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(path) = path {
+        let state = state.lock().unwrap();
+        state.export_database_as(
+            database_idx,
+            group_uuid,
+            format,
+            include_protected,
+            path,
+            app,
+        )?;
+
+        return Ok(());
+    }
+
+    Err("Export aborted".into())
+}
+
+#[tauri::command]
+async fn export_group_as_database(
+    app: AppHandle,
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    password: Option<String>,
+    keyfile: Option<Vec<u8>>,
+    options: GroupExportOptions,
+) -> Result<(), String> {
+    let app_thread = app.clone();
+
+    let path = tauri::async_runtime::spawn_blocking(move || {
+        app_thread
+            .dialog()
+            .file()
+            .add_filter("KeePass Databases", &["kdbx"])
+            .blocking_save_file()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(path) = path {
+        let data = state.lock().unwrap().export_group_as_database(
+            database_idx,
+            &group_uuid,
+            password,
+            keyfile,
+            options,
+        )?;
+
+        AppState::write_export(&data, path, app)?;
+
+        return Ok(());
+    }
+
+    Err("Export aborted".into())
+}
+
+#[tauri::command]
+fn list_shares(state: State<'_>, database_idx: usize) -> Result<Vec<Share>, String> {
+    let state = state.lock().unwrap();
+    state.list_shares(database_idx)
+}
+
+#[tauri::command]
+fn set_share(
+    state: State<'_>,
+    database_idx: usize,
+    group_uuid: String,
+    reference: Option<ShareReference>,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+    state.set_share(database_idx, &group_uuid, reference)
+}
+
+#[tauri::command]
+async fn import_shares(app: AppHandle, database_idx: usize) -> Result<(), String> {
+    run_blocking(move || {
+        let state = app.state::<Mutex<AppState>>();
+        tauri::async_runtime::block_on(AppState::import_shares(&*state, database_idx))
+    })
+    .await?
+}
+
+#[tauri::command]
+async fn save_entry_report(
+    app: AppHandle,
+    state: State<'_>,
+    database_idx: usize,
+    options: ReportOptions,
+) -> Result<(), String> {
+    let app_thread = app.clone();
+
+    let path = tauri::async_runtime::spawn_blocking(move || {
+        app_thread
+            .dialog()
+            .file()
+            .add_filter("HTML", &["html"])
+            .blocking_save_file()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(path) = path {
+        let state = state.lock().unwrap();
+        state.save_entry_report(database_idx, options, path, app)?;
+
+        return Ok(());
+    }
+
+    Err("Saving aborted".into())
+}
+
+#[tauri::command]
+async fn save_emergency_sheet(
+    app: AppHandle,
+    state: State<'_>,
+    database_idx: usize,
+    keyfile_hint: Option<String>,
+) -> Result<(), String> {
+    let app_thread = app.clone();
+
+    let path = tauri::async_runtime::spawn_blocking(move || {
+        app_thread
+            .dialog()
+            .file()
+            .add_filter("HTML", &["html"])
+            .blocking_save_file()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(path) = path {
+        let state = state.lock().unwrap();
+        state.save_emergency_sheet(database_idx, keyfile_hint, path, app)?;
+
+        return Ok(());
+    }
+
+    Err("Saving aborted".into())
+}
+
+/// Gives browser extension requests access to the application
+struct BrowserBridge {
+    app: AppHandle,
+}
+
+impl BrowserContext for BrowserBridge {
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+        let state = self.app.state::<Mutex<AppState>>();
+        let mut state = state.lock().unwrap();
+        f(&mut state)
+    }
+
+    fn confirm_association(&self, database_name: &str) -> bool {
+        self.app
+            .dialog()
+            .message(format!(
+                "A browser extension requests access to the database \"{database_name}\". Do you want to allow it?"
+            ))
+            .title("Browser integration")
+            .buttons(MessageDialogButtons::OkCancel)
+            .blocking_show()
+    }
+
+    fn database_changed(&self, database_idx: usize) {
+        let state = self.app.state::<Mutex<AppState>>();
+        let res = tauri::async_runtime::block_on(AppState::save_database(&*state, database_idx));
+
+        if let Err(e) = res {
+            log::error!("Saving database after browser request: {}", e);
+        }
+    }
+}
+
+/// Lets the user confirm the use of SSH keys added with a confirm constraint
+struct SshAgentBridge {
+    app: AppHandle,
+}
+
+impl AgentContext for SshAgentBridge {
+    fn confirm_sign(&self, comment: &str) -> bool {
+        self.app
+            .dialog()
+            .message(format!(
+                "An application requests a signature with the SSH key \"{comment}\". Do you want to allow it?"
+            ))
+            .title("SSH agent")
+            .buttons(MessageDialogButtons::OkCancel)
+            .blocking_show()
+    }
+}
+
+/// Gives Secret Service clients access to the application
+#[cfg(target_os = "linux")]
+struct SecretServiceBridge {
+    app: AppHandle,
+}
+
+#[cfg(target_os = "linux")]
+impl SecretServiceContext for SecretServiceBridge {
+    fn with_state<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+        let state = self.app.state::<Mutex<AppState>>();
+        let mut state = state.lock().unwrap();
+        f(&mut state)
+    }
+
+    fn request_unlock(&self, database_name: &str) {
+        if let Some(window) = self.app.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+
+        self.app
+            .dialog()
+            .message(format!(
+                "An application requests secrets from the locked database \"{database_name}\". Unlock it in OmniKee, then press OK."
+            ))
+            .title("Secret Service")
+            .buttons(MessageDialogButtons::Ok)
+            .blocking_show();
+    }
+
+    fn database_changed(&self, database_idx: usize) {
+        let state = self.app.state::<Mutex<AppState>>();
+        let res = tauri::async_runtime::block_on(AppState::save_database(&*state, database_idx));
+
+        if let Err(e) = res {
+            log::error!("Saving database after Secret Service request: {}", e);
+        }
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let state: AppState = Default::default();
@@ -165,10 +893,18 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
+        .register_uri_scheme_protocol("omnikee-icon", |ctx, request| {
+            let state = ctx.app_handle().state::<Mutex<AppState>>();
+            let state = state.lock().unwrap();
+            state.custom_icon_response(&request)
+        })
         .invoke_handler(tauri::generate_handler![
             list_databases,
             load_demo,
             load_database,
+            load_database_webdav,
+            load_database_s3,
+            load_database_sftp,
             unlock_database,
             lock_database,
             save_database,
@@ -176,13 +912,93 @@ pub fn run() {
             close_database,
             list_entries,
             reveal_protected,
+            get_attachment,
+            resolve_field,
+            resolve_placeholders,
+            find_entries_for_url,
+            get_browser_auto_save,
+            set_browser_auto_save,
+            get_auto_type_plan,
+            find_auto_type_matches,
+            perform_auto_type,
             set_group_name,
             set_field,
             get_otp,
+            get_otp_settings,
+            set_otp,
+            remove_otp,
+            get_otp_uri,
+            get_otp_qr_code,
+            list_custom_icons,
+            add_custom_icon,
+            replace_custom_icon,
+            deduplicate_custom_icons,
+            purge_unused_custom_icons,
+            set_group_icon,
+            set_entry_icon,
+            get_favicon_download,
+            set_favicon_download,
+            download_favicons,
+            list_ssh_keys,
+            add_ssh_key,
+            remove_ssh_key,
+            get_secret_service_enabled,
+            set_secret_service_enabled,
+            get_secret_service_group,
+            set_secret_service_group,
+            preview_csv_import,
+            import_csv,
+            import_bitwarden,
+            import_onepassword,
+            import_lastpass,
+            import_kdb,
+            import_keepass_xml,
+            export_database,
+            export_group_as_database,
+            save_entry_report,
+            save_emergency_sheet,
+            list_shares,
+            set_share,
+            import_shares,
         ])
         .setup(|app| {
             app.manage(Mutex::new(state));
 
+            #[cfg(desktop)]
+            {
+                let bridge = std::sync::Arc::new(BrowserBridge {
+                    app: app.handle().clone(),
+                });
+
+                if let Err(e) = omnikee_lib::browser::listen(bridge) {
+                    log::warn!("Browser integration is unavailable: {}", e);
+                }
+
+                let ssh_keys = app
+                    .state::<Mutex<AppState>>()
+                    .lock()
+                    .unwrap()
+                    .ssh_key_store();
+                let bridge = std::sync::Arc::new(SshAgentBridge {
+                    app: app.handle().clone(),
+                });
+
+                if let Err(e) = omnikee_lib::ssh_agent::listen(ssh_keys, bridge) {
+                    log::warn!("SSH agent is unavailable: {}", e);
+                }
+
+                #[cfg(target_os = "linux")]
+                {
+                    let bridge = std::sync::Arc::new(SecretServiceBridge {
+                        app: app.handle().clone(),
+                    });
+
+                    if let Err(e) = omnikee_lib::secret_service::listen(bridge) {
+                        log::warn!("Secret Service is unavailable: {}", e);
+                    }
+                }
+            }
+
             if cfg!(debug_assertions) {
                 app.handle().plugin(
                     tauri_plugin_log::Builder::default()
@@ -1,5 +1,5 @@
 
-import {type OTPResponse, type DatabaseOverview, type Entry, type ValueSet} from 'omnikee-wasm'
+import {type OTPResponse, type OTPSettings, type OTPSetup, type DatabaseOverview, type Entry, type ValueSet, type CustomIcon, type IconSet, type FaviconSummary, type ResolvedValue, type AutoTypeAction, type AutoTypeMatch, type UrlMatch, type SshKey, type CsvFormat, type CsvMapping, type CsvPreview, type ImportSummary, type ImportedDatabase, type ExportFormat, type GroupExportOptions, type ReportOptions, type Share, type ShareReference, type RemoteCredentials, type S3Settings, type SftpSettings, type StoredDatabase} from 'omnikee-wasm'
 
 import {saveAs} from 'file-saver'
 
@@ -9,6 +9,14 @@ export interface OmniKee {
 
   loadDemo(): Promise<DatabaseOverview>,
   loadDatabase(): Promise<DatabaseOverview>,
+  loadDatabaseWebDav(url: string, credentials: RemoteCredentials | null): Promise<DatabaseOverview>,
+  loadDatabaseS3(settings: S3Settings): Promise<DatabaseOverview>,
+  loadDatabaseSftp(settings: SftpSettings): Promise<DatabaseOverview>,
+  openDatabaseFile(): Promise<DatabaseOverview>,
+  listStoredDatabases(): Promise<StoredDatabase[]>,
+  storeDatabase(): Promise<DatabaseOverview>,
+  loadDatabaseStored(database: StoredDatabase): Promise<DatabaseOverview>,
+  removeStoredDatabase(database: StoredDatabase): Promise<void>,
   saveDatabase(databaseIdx: number): Promise<void>,
   saveDatabaseAs(databaseIdx: number): Promise<void>,
 
@@ -19,10 +27,65 @@ export interface OmniKee {
 
   listEntries(databaseIdx: number, groupUuid: string): Promise<Entry[]>,
   revealProtected(databaseIdx: number, entryUuid: string, fieldName: string): Promise<string | undefined>,
+  getAttachment(databaseIdx: number, entryUuid: string, name: string): Promise<Uint8Array>,
+  resolveField(databaseIdx: number, entryUuid: string, fieldName: string): Promise<ResolvedValue>,
+  resolvePlaceholders(databaseIdx: number, entryUuid: string, text: string): Promise<string>,
   getOtp(databaseIdx: number, entryUuid: string, time: bigint): Promise<OTPResponse>,
+  getOtpSettings(databaseIdx: number, entryUuid: string): Promise<OTPSettings | undefined>,
+  setOtp(databaseIdx: number, entryUuid: string, setup: OTPSetup): Promise<void>,
+  removeOtp(databaseIdx: number, entryUuid: string): Promise<void>,
+  getOtpUri(databaseIdx: number, entryUuid: string): Promise<string>,
+  getOtpQrCode(databaseIdx: number, entryUuid: string): Promise<string>,
+  customIconUrl(databaseIdx: number, iconUuid: string): string,
+  listCustomIcons(databaseIdx: number): Promise<CustomIcon[]>,
+  addCustomIcon(databaseIdx: number, data: Uint8Array): Promise<string>,
+  replaceCustomIcon(databaseIdx: number, iconUuid: string, data: Uint8Array): Promise<void>,
+  deduplicateCustomIcons(databaseIdx: number): Promise<number>,
+  purgeUnusedCustomIcons(databaseIdx: number): Promise<number>,
+
+  getFaviconDownload(databaseIdx: number): Promise<boolean>,
+  setFaviconDownload(databaseIdx: number, enabled: boolean): Promise<void>,
+  downloadFavicons(databaseIdx: number, entryUuids: string[]): Promise<FaviconSummary>,
+
+  findEntriesForUrl(url: string): Promise<UrlMatch[]>,
+  getBrowserAutoSave(databaseIdx: number): Promise<boolean>,
+  setBrowserAutoSave(databaseIdx: number, enabled: boolean): Promise<void>,
+
+  getAutoTypePlan(databaseIdx: number, entryUuid: string, windowTitle: string | null): Promise<AutoTypeAction[]>,
+  findAutoTypeMatches(databaseIdx: number, windowTitle: string): Promise<AutoTypeMatch[]>,
+  performAutoType(databaseIdx: number, entryUuid: string): Promise<void>,
+
+  listSshKeys(): Promise<SshKey[]>,
+  addSshKey(databaseIdx: number, entryUuid: string): Promise<SshKey>,
+  removeSshKey(entryUuid: string): Promise<void>,
+
+  getSecretServiceEnabled(databaseIdx: number): Promise<boolean>,
+  setSecretServiceEnabled(databaseIdx: number, enabled: boolean): Promise<void>,
+  getSecretServiceGroup(databaseIdx: number): Promise<string | null>,
+  setSecretServiceGroup(databaseIdx: number, groupUuid: string | null): Promise<void>,
+
+  previewCsvImport(data: Uint8Array, format: CsvFormat | null): Promise<CsvPreview>,
+  importCsv(databaseIdx: number, groupUuid: string, data: Uint8Array, format: CsvFormat, mapping: CsvMapping): Promise<ImportSummary>,
+  importBitwarden(databaseIdx: number, groupUuid: string, data: Uint8Array, password: string | null): Promise<ImportSummary>,
+  importOnePassword(databaseIdx: number, groupUuid: string, data: Uint8Array): Promise<ImportSummary>,
+  importLastPass(databaseIdx: number, groupUuid: string, data: Uint8Array): Promise<ImportSummary>,
+  importKdb(name: string, data: Uint8Array, password: string | null, keyfile: Uint8Array | null): Promise<ImportedDatabase>,
+  importKeePassXml(name: string, data: Uint8Array, password: string | null, keyfile: Uint8Array | null): Promise<ImportedDatabase>,
+  exportDatabase(databaseIdx: number, groupUuid: string | null, format: ExportFormat, includeProtected: boolean): Promise<void>,
+  exportGroupAsDatabase(databaseIdx: number, groupUuid: string, password: string | null, keyfile: Uint8Array | null, options: GroupExportOptions): Promise<void>,
+  saveEntryReport(databaseIdx: number, options: ReportOptions): Promise<void>,
+  saveEmergencySheet(databaseIdx: number, keyfileHint: string | null): Promise<void>,
+
+  listShares(databaseIdx: number): Promise<Share[]>,
+  setShare(databaseIdx: number, groupUuid: string, reference: ShareReference | null): Promise<void>,
+  importShares(databaseIdx: number): Promise<void>,
+  addShareContainer(databaseIdx: number, path: string, data: Uint8Array): Promise<void>,
+  downloadShareContainer(databaseIdx: number, path: string): Promise<void>,
 
   setGroupName(databaseIdx: number, groupUuid: string, name: string): Promise<void>,
   setField(databaseIdx: number, entryUuid: string, fieldName: string, value: ValueSet): Promise<void>,
+  setGroupIcon(databaseIdx: number, groupUuid: string, icon: IconSet): Promise<void>,
+  setEntryIcon(databaseIdx: number, entryUuid: string, icon: IconSet): Promise<void>,
 
   openExternalLink(url: string): Promise<void>,
   setWindowTitle(title: string): Promise<void>,
@@ -40,6 +103,14 @@ if (process.env.TAURI_ENV_PLATFORM === 'web') {
 
   const state = ok.AppState.new()
 
+  // object URLs for custom icons, keyed by `${databaseIdx}/${iconUuid}`
+  const iconUrls = new Map<string, string>()
+
+  function clearIconUrls() {
+    for (const url of iconUrls.values()) {URL.revokeObjectURL(url)}
+    iconUrls.clear()
+  }
+
 
   function promptFileOpen(): Promise<File> {
     return new Promise((resolve, reject) => {
@@ -75,33 +146,215 @@ if (process.env.TAURI_ENV_PLATFORM === 'web') {
       return Promise.resolve(state.load_database_buffer(file.name, data))
     },
 
-    saveDatabase(databaseIdx) {
-      const desc = state.list_databases()[databaseIdx]
-      if (!desc) {return Promise.reject(new Error("No database with that index"))}
+    loadDatabaseWebDav(url, credentials) {return Promise.resolve(state.load_database_webdav(url, credentials ?? undefined))},
+    loadDatabaseS3(settings) {return Promise.resolve(state.load_database_s3(settings))},
+    loadDatabaseSftp() {return Promise.reject(new Error("SFTP is not supported in the browser"))},
 
-      const data = state.save_database(databaseIdx)
-      if (!data) {return Promise.reject(new Error("Did not get data from the backend"))}
+    async openDatabaseFile() {
+      // the File System Access API is not part of the DOM types yet
+      const picker = (window as unknown as {showOpenFilePicker?: (options: object) => Promise<FileSystemFileHandle[]>}).showOpenFilePicker
+      if (!picker) {throw new Error("This browser cannot save to files on disk")}
 
-      saveAs(new Blob([data], {type: "application/x-keepass"}), desc.file_name)
+      const [fileHandle] = await picker({
+        types: [{description: "KeePass Databases", accept: {"application/octet-stream": [".kdbx"]}}],
+      })
 
-      return Promise.resolve()
+      return state.load_database_stored(await ok.AppState.link_database_file(fileHandle))
+    },
+
+    async listStoredDatabases() {return await ok.AppState.list_stored_databases()},
+
+    async storeDatabase() {
+      const file = await promptFileOpen()
+      const data = new Uint8Array(await file.arrayBuffer())
+
+      return state.load_database_stored(await ok.AppState.store_database(file.name, data))
+    },
+
+    loadDatabaseStored(database) {return Promise.resolve(state.load_database_stored(database))},
+    async removeStoredDatabase(database) {await ok.AppState.remove_stored_database(database)},
+
+    async saveDatabase(databaseIdx) {
+      const desc = state.list_databases()[databaseIdx]
+      if (!desc) {throw new Error("No database with that index")}
+
+      // databases on a server are uploaded by the backend and don't return their data, and the
+      // state stays usable while waiting for them
+      const pending = state.prepare_save(databaseIdx)
+      const data = state.finish_save(await pending.run())
+      if (data) {saveAs(new Blob([data], {type: "application/x-keepass"}), desc.file_name)}
     },
 
     async saveDatabaseAs(databaseIdx) {
       await this.saveDatabase(databaseIdx)
     },
 
-    unlockDatabase(databaseIdx, password, keyfile) {return Promise.resolve(state.unlock_database(databaseIdx, password, keyfile))},
+    async unlockDatabase(databaseIdx, password, keyfile) {
+      const pending = state.prepare_unlock(databaseIdx, password, keyfile)
+      return state.finish_unlock(await pending.run())
+    },
     lockDatabase(databaseIdx) {return Promise.resolve(state.lock_database(databaseIdx))},
 
-    closeDatabase(databaseIdx) {return Promise.resolve(state.close_database(databaseIdx))},
+    closeDatabase(databaseIdx) {
+      // database indices shift when closing, so cached icons may now point to the wrong database
+      clearIconUrls()
+      return Promise.resolve(state.close_database(databaseIdx))
+    },
 
     listEntries(databaseIdx, groupUuid) {return Promise.resolve(state.list_entries(databaseIdx, groupUuid))},
     revealProtected(databaseIdx, entryUuid, fieldName) {return Promise.resolve(state.reveal_protected(databaseIdx, entryUuid, fieldName))},
+    getAttachment(databaseIdx, entryUuid, name) {return Promise.resolve(state.get_attachment(databaseIdx, entryUuid, name))},
+    resolveField(databaseIdx, entryUuid, fieldName) {return Promise.resolve(state.resolve_field(databaseIdx, entryUuid, fieldName))},
+    resolvePlaceholders(databaseIdx, entryUuid, text) {return Promise.resolve(state.resolve_placeholders(databaseIdx, entryUuid, text))},
     getOtp(databaseIdx, entryUuid, time) {return Promise.resolve(state.get_otp(databaseIdx, entryUuid, time))},
+    getOtpSettings(databaseIdx, entryUuid) {return Promise.resolve(state.get_otp_settings(databaseIdx, entryUuid))},
+    setOtp(databaseIdx, entryUuid, setup) {return Promise.resolve(state.set_otp(databaseIdx, entryUuid, setup))},
+    removeOtp(databaseIdx, entryUuid) {return Promise.resolve(state.remove_otp(databaseIdx, entryUuid))},
+    getOtpUri(databaseIdx, entryUuid) {return Promise.resolve(state.get_otp_uri(databaseIdx, entryUuid))},
+    getOtpQrCode(databaseIdx, entryUuid) {return Promise.resolve(state.get_otp_qr_code(databaseIdx, entryUuid))},
+
+    customIconUrl(databaseIdx, iconUuid) {
+      const key = `${databaseIdx}/${iconUuid}`
+      let url = iconUrls.get(key)
+
+      if (!url) {
+        const data = state.get_custom_icon(databaseIdx, iconUuid)
+        url = URL.createObjectURL(new Blob([data]))
+        iconUrls.set(key, url)
+      }
+
+      return url
+    },
+
+    listCustomIcons(databaseIdx) {return Promise.resolve(state.list_custom_icons(databaseIdx))},
+    addCustomIcon(databaseIdx, data) {return Promise.resolve(state.add_custom_icon(databaseIdx, data))},
+    replaceCustomIcon(databaseIdx, iconUuid, data) {
+      clearIconUrls()
+      return Promise.resolve(state.replace_custom_icon(databaseIdx, iconUuid, data))
+    },
+    deduplicateCustomIcons(databaseIdx) {return Promise.resolve(state.deduplicate_custom_icons(databaseIdx))},
+    purgeUnusedCustomIcons(databaseIdx) {return Promise.resolve(state.purge_unused_custom_icons(databaseIdx))},
+
+    getFaviconDownload(databaseIdx) {return Promise.resolve(state.get_favicon_download(databaseIdx))},
+    setFaviconDownload(databaseIdx, enabled) {return Promise.resolve(state.set_favicon_download(databaseIdx, enabled))},
+
+    downloadFavicons() {
+      // browsers block cross-origin requests to arbitrary websites
+      return Promise.reject(new Error("Downloading favicons is not supported in the browser"))
+    },
+
+    findEntriesForUrl(url) {return Promise.resolve(state.find_entries_for_url(url))},
+    getBrowserAutoSave(databaseIdx) {return Promise.resolve(state.get_browser_auto_save(databaseIdx))},
+    setBrowserAutoSave(databaseIdx, enabled) {return Promise.resolve(state.set_browser_auto_save(databaseIdx, enabled))},
+
+    getAutoTypePlan(databaseIdx, entryUuid, windowTitle) {
+      return Promise.resolve(state.get_auto_type_plan(databaseIdx, entryUuid, windowTitle ?? undefined))
+    },
+    findAutoTypeMatches(databaseIdx, windowTitle) {return Promise.resolve(state.find_auto_type_matches(databaseIdx, windowTitle))},
+    performAutoType() {
+      // browsers cannot send keystrokes to other applications
+      return Promise.reject(new Error("Auto-Type is not supported in the browser"))
+    },
+
+    // there is no SSH agent socket in the browser
+    listSshKeys() {return Promise.resolve([])},
+    addSshKey() {return Promise.reject(new Error("The SSH agent is not supported in the browser"))},
+    removeSshKey() {return Promise.resolve()},
+
+    // the settings are stored in the database, but only the desktop app provides the Secret Service
+    getSecretServiceEnabled(databaseIdx) {return Promise.resolve(state.get_secret_service_enabled(databaseIdx))},
+    setSecretServiceEnabled(databaseIdx, enabled) {return Promise.resolve(state.set_secret_service_enabled(databaseIdx, enabled))},
+    getSecretServiceGroup(databaseIdx) {return Promise.resolve(state.get_secret_service_group(databaseIdx) ?? null)},
+    setSecretServiceGroup(databaseIdx, groupUuid) {
+      return Promise.resolve(state.set_secret_service_group(databaseIdx, groupUuid ?? undefined))
+    },
+
+    previewCsvImport(data, format) {return Promise.resolve(state.preview_csv_import(data, format ?? undefined))},
+    importCsv(databaseIdx, groupUuid, data, format, mapping) {
+      return Promise.resolve(state.import_csv(databaseIdx, groupUuid, data, format, mapping))
+    },
+    importBitwarden(databaseIdx, groupUuid, data, password) {
+      return Promise.resolve(state.import_bitwarden(databaseIdx, groupUuid, data, password ?? undefined))
+    },
+    importOnePassword(databaseIdx, groupUuid, data) {
+      return Promise.resolve(state.import_onepassword(databaseIdx, groupUuid, data))
+    },
+    importLastPass(databaseIdx, groupUuid, data) {
+      return Promise.resolve(state.import_lastpass(databaseIdx, groupUuid, data))
+    },
+    importKdb(name, data, password, keyfile) {
+      return Promise.resolve(state.import_kdb(name, data, password ?? undefined, keyfile ?? undefined))
+    },
+    importKeePassXml(name, data, password, keyfile) {
+      return Promise.resolve(state.import_keepass_xml(name, data, password ?? undefined, keyfile ?? undefined))
+    },
+
+    exportDatabase(databaseIdx, groupUuid, format, includeProtected) {
+      const desc = state.list_databases()[databaseIdx]
+      if (!desc) {return Promise.reject(new Error("No database with that index"))}
+
+      const data = state.export_database(databaseIdx, groupUuid ?? undefined, format, includeProtected)
+      const types = {Csv: "text/csv", Json: "application/json", Xml: "application/xml"}
+      const name = desc.file_name.replace(/\.kdbx$/i, "")
+
+      saveAs(new Blob([data], {type: types[format]}), `${name}.${format.toLowerCase()}`)
+
+      return Promise.resolve()
+    },
+
+    exportGroupAsDatabase(databaseIdx, groupUuid, password, keyfile, options) {
+      const data = state.export_group_as_database(databaseIdx, groupUuid, password ?? undefined, keyfile ?? undefined, options)
+      const name = options.name ?? "export"
+
+      saveAs(new Blob([data], {type: "application/x-keepass"}), `${name}.kdbx`)
+
+      return Promise.resolve()
+    },
+
+    saveEntryReport(databaseIdx, options) {
+      const desc = state.list_databases()[databaseIdx]
+      if (!desc) {return Promise.reject(new Error("No database with that index"))}
+
+      const html = state.entry_report(databaseIdx, options)
+      saveAs(new Blob([html], {type: "text/html"}), `${desc.name}.html`)
+
+      return Promise.resolve()
+    },
+
+    saveEmergencySheet(databaseIdx, keyfileHint) {
+      const desc = state.list_databases()[databaseIdx]
+      if (!desc) {return Promise.reject(new Error("No database with that index"))}
+
+      const html = state.emergency_sheet(databaseIdx, keyfileHint ?? undefined)
+      saveAs(new Blob([html], {type: "text/html"}), `${desc.name} Emergency Sheet.html`)
+
+      return Promise.resolve()
+    },
+
+    listShares(databaseIdx) {return Promise.resolve(state.list_shares(databaseIdx))},
+    setShare(databaseIdx, groupUuid, reference) {return Promise.resolve(state.set_share(databaseIdx, groupUuid, reference ?? undefined))},
+    async importShares(databaseIdx) {
+      const pending = state.prepare_import_shares(databaseIdx)
+      state.finish_import_shares(await pending.run())
+    },
+    async addShareContainer(databaseIdx, path, data) {
+      const pending = state.prepare_add_share_container(databaseIdx, path, data)
+      state.finish_share_container(await pending.run())
+    },
+
+    async downloadShareContainer(databaseIdx, path) {
+      const pending = state.prepare_get_share_container(databaseIdx, path)
+      const data = state.finish_share_container(await pending.run())
+      if (!data) {throw new Error("The container has not been saved yet")}
+
+      const name = path.split(/[\\/]/).pop() || "share.kdbx"
+      saveAs(new Blob([data], {type: "application/x-keepass"}), name)
+    },
 
     setGroupName(databaseIdx, groupUuid, name) {return Promise.resolve(state.set_group_name(databaseIdx, groupUuid, name))},
     setField(databaseIdx, entryUuid, fieldName, value) {return Promise.resolve(state.set_field(databaseIdx, entryUuid, fieldName, value))},
+    setGroupIcon(databaseIdx, groupUuid, icon) {return Promise.resolve(state.set_group_icon(databaseIdx, groupUuid, icon))},
+    setEntryIcon(databaseIdx, entryUuid, icon) {return Promise.resolve(state.set_entry_icon(databaseIdx, entryUuid, icon))},
 
     openExternalLink(url) {
       window.open(url)
@@ -124,6 +377,11 @@ if (process.env.TAURI_ENV_PLATFORM === 'web') {
 
   console.log(`Tauri on mobile: ${isMobile}`)
 
+  // custom URI scheme protocols are served from a http:// origin on Windows and Android
+  const iconBase = (platform.includes("windows") || platform.includes("android"))
+    ? 'http://omnikee-icon.localhost'
+    : 'omnikee-icon://localhost'
+
   handle = {
     async listDatabases() {return await invoke('list_databases')},
 
@@ -131,6 +389,14 @@ if (process.env.TAURI_ENV_PLATFORM === 'web') {
 
     // file picking is done from the Tauri backend
     async loadDatabase() {return await invoke('load_database')},
+    async loadDatabaseWebDav(url, credentials) {return await invoke('load_database_webdav', {url, credentials})},
+    async loadDatabaseS3(settings) {return await invoke('load_database_s3', {settings})},
+    async loadDatabaseSftp(settings) {return await invoke('load_database_sftp', {settings})},
+    async openDatabaseFile() {return await invoke('load_database')},
+    listStoredDatabases() {return Promise.resolve([])},
+    storeDatabase() {return Promise.reject(new Error("Databases are only stored by the browser in the web version"))},
+    loadDatabaseStored() {return Promise.reject(new Error("Databases are only stored by the browser in the web version"))},
+    removeStoredDatabase() {return Promise.reject(new Error("Databases are only stored by the browser in the web version"))},
     async saveDatabase(databaseIdx) {return await invoke('save_database', {databaseIdx})},
     async saveDatabaseAs(databaseIdx) {return await invoke('save_database_as', {databaseIdx})},
 
@@ -142,10 +408,101 @@ if (process.env.TAURI_ENV_PLATFORM === 'web') {
     async revealProtected(databaseIdx, entryUuid, fieldName) {
       return await invoke<string | undefined>("reveal_protected", {databaseIdx, entryUuid, fieldName})
     },
+    async getAttachment(databaseIdx, entryUuid, name) {
+      return new Uint8Array(await invoke<number[]>("get_attachment", {databaseIdx, entryUuid, name}))
+    },
+    async resolveField(databaseIdx, entryUuid, fieldName) {return await invoke("resolve_field", {databaseIdx, entryUuid, fieldName})},
+    async resolvePlaceholders(databaseIdx, entryUuid, text) {
+      return await invoke("resolve_placeholders", {databaseIdx, entryUuid, text})
+    },
     async getOtp(databaseIdx, entryUuid, time) {return await invoke("get_otp", {databaseIdx, entryUuid, time: Number(time)})},
+    async getOtpSettings(databaseIdx, entryUuid) {
+      return await invoke<OTPSettings | null>("get_otp_settings", {databaseIdx, entryUuid}) ?? undefined
+    },
+    async setOtp(databaseIdx, entryUuid, setup) {return await invoke("set_otp", {databaseIdx, entryUuid, setup})},
+    async removeOtp(databaseIdx, entryUuid) {return await invoke("remove_otp", {databaseIdx, entryUuid})},
+    async getOtpUri(databaseIdx, entryUuid) {return await invoke("get_otp_uri", {databaseIdx, entryUuid})},
+    async getOtpQrCode(databaseIdx, entryUuid) {return await invoke("get_otp_qr_code", {databaseIdx, entryUuid})},
+
+    customIconUrl(databaseIdx, iconUuid) {return `${iconBase}/${databaseIdx}/${iconUuid}`},
+    async listCustomIcons(databaseIdx) {return await invoke("list_custom_icons", {databaseIdx})},
+    async addCustomIcon(databaseIdx, data) {return await invoke("add_custom_icon", {databaseIdx, data: Array.from(data)})},
+    async replaceCustomIcon(databaseIdx, iconUuid, data) {
+      return await invoke("replace_custom_icon", {databaseIdx, iconUuid, data: Array.from(data)})
+    },
+    async deduplicateCustomIcons(databaseIdx) {return await invoke("deduplicate_custom_icons", {databaseIdx})},
+    async purgeUnusedCustomIcons(databaseIdx) {return await invoke("purge_unused_custom_icons", {databaseIdx})},
+
+    async getFaviconDownload(databaseIdx) {return await invoke("get_favicon_download", {databaseIdx})},
+    async setFaviconDownload(databaseIdx, enabled) {return await invoke("set_favicon_download", {databaseIdx, enabled})},
+    async downloadFavicons(databaseIdx, entryUuids) {return await invoke("download_favicons", {databaseIdx, entryUuids})},
+
+    async findEntriesForUrl(url) {return await invoke("find_entries_for_url", {url})},
+    async getBrowserAutoSave(databaseIdx) {return await invoke("get_browser_auto_save", {databaseIdx})},
+    async setBrowserAutoSave(databaseIdx, enabled) {return await invoke("set_browser_auto_save", {databaseIdx, enabled})},
+
+    async getAutoTypePlan(databaseIdx, entryUuid, windowTitle) {
+      return await invoke("get_auto_type_plan", {databaseIdx, entryUuid, windowTitle})
+    },
+    async findAutoTypeMatches(databaseIdx, windowTitle) {return await invoke("find_auto_type_matches", {databaseIdx, windowTitle})},
+    async performAutoType(databaseIdx, entryUuid) {await invoke("perform_auto_type", {databaseIdx, entryUuid})},
+
+    async listSshKeys() {return await invoke("list_ssh_keys")},
+    async addSshKey(databaseIdx, entryUuid) {return await invoke("add_ssh_key", {databaseIdx, entryUuid})},
+    async removeSshKey(entryUuid) {await invoke("remove_ssh_key", {entryUuid})},
+
+    async getSecretServiceEnabled(databaseIdx) {return await invoke("get_secret_service_enabled", {databaseIdx})},
+    async setSecretServiceEnabled(databaseIdx, enabled) {await invoke("set_secret_service_enabled", {databaseIdx, enabled})},
+    async getSecretServiceGroup(databaseIdx) {return await invoke("get_secret_service_group", {databaseIdx})},
+    async setSecretServiceGroup(databaseIdx, groupUuid) {await invoke("set_secret_service_group", {databaseIdx, groupUuid})},
+
+    async previewCsvImport(data, format) {return await invoke("preview_csv_import", {data: Array.from(data), format})},
+    async importCsv(databaseIdx, groupUuid, data, format, mapping) {
+      return await invoke("import_csv", {databaseIdx, groupUuid, data: Array.from(data), format, mapping})
+    },
+    async importBitwarden(databaseIdx, groupUuid, data, password) {
+      return await invoke("import_bitwarden", {databaseIdx, groupUuid, data: Array.from(data), password})
+    },
+    async importOnePassword(databaseIdx, groupUuid, data) {
+      return await invoke("import_onepassword", {databaseIdx, groupUuid, data: Array.from(data)})
+    },
+    async importLastPass(databaseIdx, groupUuid, data) {
+      return await invoke("import_lastpass", {databaseIdx, groupUuid, data: Array.from(data)})
+    },
+    async importKdb(name, data, password, keyfile) {
+      return await invoke("import_kdb", {name, data: Array.from(data), password, keyfile: keyfile && Array.from(keyfile)})
+    },
+    async importKeePassXml(name, data, password, keyfile) {
+      return await invoke("import_keepass_xml", {name, data: Array.from(data), password, keyfile: keyfile && Array.from(keyfile)})
+    },
+
+    async exportDatabase(databaseIdx, groupUuid, format, includeProtected) {
+      return await invoke("export_database", {databaseIdx, groupUuid, format, includeProtected})
+    },
+
+    async exportGroupAsDatabase(databaseIdx, groupUuid, password, keyfile, options) {
+      return await invoke("export_group_as_database", {databaseIdx, groupUuid, password, keyfile: keyfile && Array.from(keyfile), options})
+    },
+
+    async saveEntryReport(databaseIdx, options) {
+      return await invoke("save_entry_report", {databaseIdx, options})
+    },
+
+    async saveEmergencySheet(databaseIdx, keyfileHint) {
+      return await invoke("save_emergency_sheet", {databaseIdx, keyfileHint})
+    },
+
+    async listShares(databaseIdx) {return await invoke("list_shares", {databaseIdx})},
+    async setShare(databaseIdx, groupUuid, reference) {return await invoke("set_share", {databaseIdx, groupUuid, reference})},
+    async importShares(databaseIdx) {return await invoke("import_shares", {databaseIdx})},
+
+    addShareContainer() {return Promise.reject(new Error("KeeShare containers are read from files next to the database"))},
+    downloadShareContainer() {return Promise.reject(new Error("KeeShare containers are written to files next to the database"))},
 
     async setGroupName(databaseIdx, groupUuid, name) {return await invoke("set_group_name", {databaseIdx, groupUuid, name})},
     async setField(databaseIdx, entryUuid, fieldName, value) {return await invoke("set_field", {databaseIdx, entryUuid, fieldName, value})},
+    async setGroupIcon(databaseIdx, groupUuid, icon) {return await invoke("set_group_icon", {databaseIdx, groupUuid, icon})},
+    async setEntryIcon(databaseIdx, entryUuid, icon) {return await invoke("set_entry_icon", {databaseIdx, entryUuid, icon})},
 
     async openExternalLink(url) {
       const {openUrl} = await import('@tauri-apps/plugin-opener')